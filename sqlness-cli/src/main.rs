@@ -115,11 +115,12 @@ fn main() {
         .build()
         .expect("build config");
 
-    block_on(async {
+    let report = block_on(async {
         let ctrl = CliController::new(db_config, args.db_type);
         let runner = Runner::new(config, ctrl);
-        runner.run().await.expect("run testcase")
+        runner.run_with_report().await.expect("run testcase")
     });
 
-    println!("Test run finish");
+    report.print_summary();
+    std::process::exit(report.exit_code());
 }
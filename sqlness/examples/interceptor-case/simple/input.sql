@@ -64,5 +64,12 @@ INSERT INTO t (c) VALUES
 2
 2;
 
+-- SQLNESS SORT_RESULT 1 1 BY 2 1
+id,name
+3,b
+2,a
+1,b
+;
+
 -- Test case for https://github.com/CeresDB/sqlness/issues/68
 INSERT INTO timestamp VALUES ('1900-1-1 00;00;00');
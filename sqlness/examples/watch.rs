@@ -0,0 +1,49 @@
+// Copyright 2024 CeresDB Project Authors. Licensed under Apache-2.0.
+
+use std::{fmt::Display, path::Path};
+
+use async_trait::async_trait;
+use sqlness::{ConfigBuilder, Database, EnvController, QueryContext, Runner};
+
+struct MyController;
+struct MyDB;
+
+#[async_trait]
+impl Database for MyDB {
+    async fn query(&self, _context: QueryContext, query: String) -> Box<dyn Display> {
+        Box::new(query)
+    }
+}
+
+#[async_trait]
+impl EnvController for MyController {
+    type DB = MyDB;
+
+    async fn start(&self, env: &str, _config: Option<&Path>) -> Self::DB {
+        println!("Start, env:{env}.");
+        MyDB
+    }
+
+    async fn stop(&self, env: &str, _database: Self::DB) {
+        println!("Stop, env:{env}.");
+    }
+}
+
+#[tokio::main]
+async fn main() {
+    let config = ConfigBuilder::default()
+        .case_dir("examples/basic-case".to_string())
+        .build()
+        .unwrap();
+    let runner = Runner::new(config, MyController);
+
+    println!("Edit a case under examples/basic-case to rerun it, Ctrl-C to exit...");
+
+    // Environments are stopped before returning on Ctrl-C.
+    runner
+        .watch(async {
+            tokio::signal::ctrl_c().await.unwrap();
+        })
+        .await
+        .unwrap();
+}
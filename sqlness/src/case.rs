@@ -1,84 +1,460 @@
 // Copyright 2022 CeresDB Project Authors. Licensed under Apache-2.0.
 
 use std::{
+    borrow::Cow,
     collections::HashMap,
     fmt::Display,
     fs::File,
     io::{BufRead, BufReader, Write},
-    path::Path,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::{Duration, Instant},
 };
 
+use futures::{future, stream, StreamExt};
+use regex::Regex;
+
 use crate::{
     config::Config,
     error::Result,
-    interceptor::{InterceptorRef, Registry},
-    Database, SqlnessError,
+    format::{self, ResultFormat},
+    interceptor::{
+        contains, require, shell, skip_if::SkipCondition, strip_ansi, txn::TxnEnd, InterceptorRef,
+        Registry,
+    },
+    report::QueryDuration,
+    util, Database, QueryResult, SqlnessError,
 };
 
-const COMMENT_PREFIX: &str = "--";
 const QUERY_DELIMITER: char = ';';
+/// Directive changing the delimiter, eg. `-- SQLNESS DELIMITER //`.
+const DELIMITER_COMMAND: &str = "DELIMITER";
+/// Directive including queries of another file, eg. `-- SQLNESS INCLUDE common/setup.sql`.
+///
+/// The path is relative to the including file. Included queries are executed in
+/// place but not recorded in the result file, like `HIDE` without the marker.
+/// Cyclic includes fail the case with [`SqlnessError::CyclicInclude`]. Note that an
+/// included file with [`Config::test_case_extension`] is also run as a case itself.
+const INCLUDE_COMMAND: &str = "INCLUDE";
+/// Directive executing queries of another file, eg. `-- SQLNESS SOURCE gen/data.sql`.
+///
+/// Unlike `INCLUDE`, sourced queries are recorded in place along with their results,
+/// as if they were written in the case, so their results are compared. The path is
+/// relative to the sourcing file. The directive and the comments before it are
+/// recorded before the first sourced query, and it can't be used between
+/// interceptors and their query.
+const SOURCE_COMMAND: &str = "SOURCE";
+/// Directive restricting the case to some environments, eg. `-- SQLNESS ONLY local remote`.
+///
+/// It must be placed before the first query. In other environments the case isn't
+/// run but reported as skipped, and its result file is left untouched. Multiple
+/// `ONLY` lines add up.
+const ONLY_COMMAND: &str = "ONLY";
+/// Directive tagging the case, eg. `-- SQLNESS TAG smoke slow`.
+///
+/// Cases are selected by [`Config::include_tags`] and [`Config::exclude_tags`] as a
+/// whole, so tags written before the first query apply to the file, and tags of a
+/// query are added to the case as well.
+const TAG_COMMAND: &str = "TAG";
+/// Written in place of the result of a query skipped by `SKIP_IF`.
+const SKIPPED_MARKER: &str = "-- SKIPPED";
+/// Written in place of the result of a query hidden by `HIDE`.
+const HIDDEN_MARKER: &str = "-- HIDDEN";
+/// Written in place of the result of a query executed by `WARMUP`.
+const WARMUP_MARKER: &str = "-- WARMUP";
+/// Written in place of the error of a query expected to fail by `EXPECT_ERROR`.
+const EXPECTED_ERROR_MARKER: &str = "-- EXPECTED ERROR";
+/// Ends the result of a query read by `STREAM` until the first difference.
+const STREAM_STOPPED_MARKER: &str = "... (stream stopped at the first difference)";
+/// Starts the line of query duration written by `TIMING`.
+const TIMING_MARKER: &str = "-- elapsed: ";
+/// Separates candidate results of a query annotated with `ALLOW_VARIANTS`.
+const VARIANT_SEPARATOR: &str = "-- VARIANT\n\n";
 
 pub(crate) struct TestCase {
     name: String,
     queries: Vec<Query>,
+    query_timeout: Option<Duration>,
+    /// `SKIP_IF` conditions in the file header, which apply to the whole case.
+    skip_conditions: Vec<SkipCondition>,
+    /// Features required by `REQUIRE` in the file header, for the whole case.
+    required_features: Vec<String>,
+    /// Environments allowed by `ONLY`, empty for all environments.
+    only_envs: Vec<String>,
+    /// Tags added by `TAG`, without duplicates.
+    tags: Vec<String>,
+    /// Metadata of the environment, see [`EnvController::metadata`].
+    ///
+    /// [`EnvController::metadata`]: crate::EnvController::metadata
+    metadata: Arc<HashMap<String, String>>,
+    /// Values captured by `CAPTURE`, seeded by previous cases with
+    /// [`Config::persistent_context`].
+    variables: HashMap<String, String>,
 }
 
 impl TestCase {
     pub(crate) fn from_file<P: AsRef<Path>>(path: P, cfg: &Config) -> Result<Self> {
-        let file = File::open(path.as_ref()).map_err(|e| SqlnessError::ReadPath {
+        Self::parse_file(path.as_ref(), cfg, &mut vec![])
+    }
+
+    /// `include_stack` contains files including this one, to detect cyclic includes.
+    fn parse_file(path: &Path, cfg: &Config, include_stack: &mut Vec<PathBuf>) -> Result<Self> {
+        let file = File::open(path).map_err(|e| SqlnessError::ReadPath {
             source: e,
-            path: path.as_ref().to_path_buf(),
+            path: path.to_path_buf(),
         })?;
 
+        Self::parse(path, BufReader::new(file), cfg, include_stack)
+    }
+
+    /// Parse a case from `reader`. `path` is only used to name the case and
+    /// resolve included files.
+    pub(crate) fn from_reader<R: BufRead>(path: &Path, reader: R, cfg: &Config) -> Result<Self> {
+        Self::parse(path, reader, cfg, &mut vec![])
+    }
+
+    fn parse<R: BufRead>(
+        path: &Path,
+        reader: R,
+        cfg: &Config,
+        include_stack: &mut Vec<PathBuf>,
+    ) -> Result<Self> {
+        include_stack.push(path.canonicalize().unwrap_or_else(|_| path.to_path_buf()));
         let mut queries = vec![];
-        let mut query = Query::with_interceptor_factories(cfg.interceptor_registry.clone());
+        let options = Arc::new(QueryOptions {
+            args: Arc::new(cfg.args.clone()),
+            result_filters: cfg.result_filters.clone(),
+            result_format: cfg.result_format,
+            catch_panic: cfg.catch_panic,
+            csv_delimiter: cfg.csv_delimiter,
+            connection_retries: cfg.connection_retries,
+            retry_backoff: cfg.retry_backoff,
+            update_result: cfg.update_result,
+            max_result_bytes: cfg.max_result_bytes,
+            warn_oversized_results: cfg.warn_oversized_results,
+            strip_ansi: cfg.strip_ansi,
+            allow_shell: cfg.allow_shell,
+        });
+        let new_query = || Query::new(cfg.interceptor_registry.clone(), options.clone());
+        let mut query = new_query();
+        // `SKIP_IF` conditions and `REQUIRE` features seen before the first query, they become case level
+        // once the header ends with an empty line.
+        let mut header_conditions = vec![];
+        let mut skip_conditions = vec![];
+        let mut header_features = vec![];
+        let mut required_features = vec![];
+        let mut delimiter = cfg.delimiter.clone();
+        let mut only_envs = vec![];
+        let mut tags: Vec<String> = vec![];
 
-        let reader = BufReader::new(file);
-        for line in reader.lines() {
+        for (line_no, line) in reader.lines().enumerate() {
             let line = line?;
 
-            // record comment
-            if line.starts_with(COMMENT_PREFIX) {
+            // record comment, interceptors are also comments
+            if line.starts_with(&cfg.comment_prefix) || line.starts_with(&cfg.interceptor_prefix) {
                 query.push_comment(line.clone());
 
                 // intercept command start with INTERCEPTOR_PREFIX
-                if line.starts_with(&cfg.interceptor_prefix) {
-                    query.push_interceptor(&cfg.interceptor_prefix, line)?;
+                if let Some(command) = line.strip_prefix(&cfg.interceptor_prefix) {
+                    let parse_error = |e| SqlnessError::ParseCase {
+                        source: Box::new(e),
+                        path: path.to_path_buf(),
+                        line: line_no + 1,
+                    };
+                    let (name, ctx) = command
+                        .trim()
+                        .split_once(' ')
+                        .unwrap_or((command.trim(), ""));
+
+                    // It's a directive of the parser rather than an interceptor.
+                    if name == DELIMITER_COMMAND {
+                        let ctx = ctx.trim();
+                        if ctx.is_empty() {
+                            return Err(parse_error(SqlnessError::InvalidContext {
+                                prefix: DELIMITER_COMMAND.to_string(),
+                                msg: "Expect a delimiter".to_string(),
+                            }));
+                        }
+                        delimiter = ctx.to_string();
+                        continue;
+                    }
+                    if name == ONLY_COMMAND {
+                        if ctx.trim().is_empty() {
+                            return Err(parse_error(SqlnessError::InvalidContext {
+                                prefix: ONLY_COMMAND.to_string(),
+                                msg: "Expect at least one environment".to_string(),
+                            }));
+                        }
+                        if !queries.is_empty() || !query.display_query.is_empty() {
+                            return Err(parse_error(SqlnessError::InvalidContext {
+                                prefix: ONLY_COMMAND.to_string(),
+                                msg: "Expect to be placed before the first query".to_string(),
+                            }));
+                        }
+                        only_envs.extend(ctx.split_whitespace().map(str::to_string));
+                        continue;
+                    }
+                    if name == TAG_COMMAND {
+                        if ctx.trim().is_empty() {
+                            return Err(parse_error(SqlnessError::InvalidContext {
+                                prefix: TAG_COMMAND.to_string(),
+                                msg: "Expect at least one tag".to_string(),
+                            }));
+                        }
+                        for tag in ctx.split_whitespace() {
+                            if !tags.iter().any(|t| t == tag) {
+                                tags.push(tag.to_string());
+                            }
+                        }
+                        continue;
+                    }
+                    if name == INCLUDE_COMMAND {
+                        let mut included = Self::include(path, ctx.trim(), cfg, include_stack)
+                            .map_err(parse_error)?;
+                        for query in &mut included {
+                            query.included = true;
+                        }
+                        queries.extend(included);
+                        continue;
+                    }
+                    if name == SOURCE_COMMAND {
+                        if !query.interceptors.is_empty() || !query.display_query.is_empty() {
+                            return Err(parse_error(SqlnessError::InvalidContext {
+                                prefix: SOURCE_COMMAND.to_string(),
+                                msg: "Expect not to be placed in a query or after interceptors"
+                                    .to_string(),
+                            }));
+                        }
+                        let mut sourced = Self::include(path, ctx.trim(), cfg, include_stack)
+                            .map_err(parse_error)?;
+                        // Record the directive before the sourced queries
+                        if let Some(first) = sourced.iter_mut().find(|q| !q.included) {
+                            let mut comments = std::mem::take(&mut query.comment_lines);
+                            comments.append(&mut first.comment_lines);
+                            first.comment_lines = comments;
+                        }
+                        queries.extend(sourced);
+                        continue;
+                    }
+
+                    if name == crate::interceptor::skip_if::PREFIX
+                        && queries.is_empty()
+                        && query.display_query.is_empty()
+                    {
+                        header_conditions.push(SkipCondition::parse(ctx).map_err(parse_error)?);
+                    }
+                    if name == crate::interceptor::require::PREFIX
+                        && queries.is_empty()
+                        && query.display_query.is_empty()
+                    {
+                        header_features
+                            .append(&mut require::parse_features(ctx).map_err(parse_error)?);
+                    }
+                    query
+                        .push_interceptor(&cfg.interceptor_prefix, line)
+                        .map_err(parse_error)?;
                 }
                 continue;
             }
 
             // ignore empty line
             if line.is_empty() {
+                if queries.is_empty() && query.display_query.is_empty() {
+                    skip_conditions.append(&mut header_conditions);
+                    required_features.append(&mut header_features);
+                }
                 continue;
             }
 
+            // A passthrough line is a query on its own, eg. psql's `\d t`
+            if let Some(prefix) = &cfg.passthrough_prefix {
+                if query.display_query.is_empty() && line.starts_with(prefix.as_str()) {
+                    query.append_query_line(&line);
+                    query.verbatim = true;
+                    queries.push(query);
+                    query = new_query();
+                    continue;
+                }
+            }
+
             query.append_query_line(&line);
 
-            // SQL statement ends with ';'
-            if line.ends_with(QUERY_DELIMITER) {
+            // SQL statement ends with the delimiter
+            if line.ends_with(delimiter.as_str()) {
+                if delimiter != QUERY_DELIMITER.to_string() {
+                    query.strip_delimiter(&delimiter);
+                }
                 queries.push(query);
-                query = Query::with_interceptor_factories(cfg.interceptor_registry.clone());
+                query = new_query();
             } else {
                 query.append_query_line("\n");
             }
         }
 
+        include_stack.pop();
         Ok(Self {
-            name: path.as_ref().to_str().unwrap().to_string(),
+            name: path.to_str().unwrap().to_string(),
             queries,
+            query_timeout: cfg.query_timeout,
+            skip_conditions,
+            required_features,
+            only_envs,
+            tags,
+            metadata: Arc::default(),
+            variables: HashMap::new(),
         })
     }
 
-    pub(crate) async fn execute<W>(&mut self, db: &dyn Database, writer: &mut W) -> Result<()>
+    /// Parse queries of `target` included or sourced by `path`.
+    fn include(
+        path: &Path,
+        target: &str,
+        cfg: &Config,
+        include_stack: &mut Vec<PathBuf>,
+    ) -> Result<Vec<Query>> {
+        let target = path.parent().unwrap_or(Path::new("")).join(target);
+        let canonical = target.canonicalize().map_err(|e| SqlnessError::ReadPath {
+            source: e,
+            path: target.clone(),
+        })?;
+        if include_stack.contains(&canonical) {
+            return Err(SqlnessError::CyclicInclude { path: target });
+        }
+
+        Ok(Self::parse_file(&target, cfg, include_stack)?.queries)
+    }
+
+    /// Returns the environments allowed by `ONLY` if `env` isn't one of them.
+    pub(crate) fn only_envs(&self, env: &str) -> Option<&[String]> {
+        if self.only_envs.is_empty() || self.only_envs.iter().any(|e| e == env) {
+            None
+        } else {
+            Some(&self.only_envs)
+        }
+    }
+
+    /// Why the case isn't selected by `include_tags` and `exclude_tags`, or `None`
+    /// if it's selected.
+    pub(crate) fn unselected_reason(
+        &self,
+        include_tags: &[String],
+        exclude_tags: &[String],
+    ) -> Option<String> {
+        if let Some(tag) = self.tags.iter().find(|t| exclude_tags.contains(t)) {
+            Some(format!("tag {tag} is in exclude_tags"))
+        } else if !include_tags.is_empty() && !self.tags.iter().any(|t| include_tags.contains(t)) {
+            Some("no tag in include_tags".to_string())
+        } else {
+            None
+        }
+    }
+
+    /// Set the metadata of the environment to run in.
+    pub(crate) fn set_metadata(&mut self, metadata: Arc<HashMap<String, String>>) {
+        self.metadata = metadata;
+    }
+
+    /// Set values captured by previous cases, see [`Config::persistent_context`].
+    pub(crate) fn set_variables(&mut self, variables: HashMap<String, String>) {
+        self.variables = variables;
+    }
+
+    /// Values captured by `CAPTURE`, including the ones set by
+    /// [`Self::set_variables`].
+    pub(crate) fn variables(&self) -> &HashMap<String, String> {
+        &self.variables
+    }
+
+    /// Returns the first case level `SKIP_IF` condition that holds.
+    pub(crate) fn skip_condition(&self) -> Option<&SkipCondition> {
+        self.skip_conditions
+            .iter()
+            .find(|c| c.holds(&self.metadata))
+    }
+
+    /// Returns the first feature required by case level `REQUIRE` that the
+    /// environment doesn't have.
+    pub(crate) fn missing_feature(&self) -> Option<&str> {
+        require::missing_feature(&self.required_features, &self.metadata)
+    }
+
+    /// Execute all queries and write their result to `writer`. `expected` is the
+    /// old result, used by interceptors that depends on the comparison like `RETRY`.
+    /// It's empty for a new case, then no query has a recorded result.
+    ///
+    /// Returns the first query whose result differs from `expected`, if any.
+    pub(crate) async fn execute<W>(
+        &mut self,
+        db: &dyn Database,
+        writer: &mut W,
+        expected: &str,
+    ) -> Result<Option<String>>
     where
         W: Write,
     {
-        for query in &mut self.queries {
-            query.execute(db, writer).await?;
+        let mut expected = Some(expected);
+        let mut mismatched = None;
+        // Values captured by `CAPTURE`, which can shadow the metadata.
+        let mut variables = (*self.metadata).clone();
+        variables.extend(self.variables.clone());
+        for i in 0..self.queries.len() {
+            // Where the block of this query ends in the result file, used to find
+            // the variants of `ALLOW_VARIANTS`.
+            let next_block = self.queries[i + 1..]
+                .iter()
+                .find(|query| !query.included)
+                .map(Query::render_query);
+            let block = self.queries[i]
+                .execute(
+                    db,
+                    self.query_timeout,
+                    // Nothing is recorded for this query if the old result ends here.
+                    expected.filter(|expected| !expected.is_empty()),
+                    next_block.as_deref(),
+                    &self.metadata,
+                    &mut variables,
+                )
+                .await;
+            // Keep captured values even if the query fails, the metadata is set again
+            // for each case.
+            for (name, value) in &variables {
+                if self.metadata.get(name) != Some(value) {
+                    self.variables.insert(name.clone(), value.clone());
+                }
+            }
+            let block = block.map_err(|e| match e {
+                SqlnessError::Timeout { query, elapsed, .. } => SqlnessError::Timeout {
+                    case: self.name.clone(),
+                    query,
+                    elapsed,
+                },
+                SqlnessError::Panic { query, message, .. } => SqlnessError::Panic {
+                    case: self.name.clone(),
+                    query,
+                    message,
+                },
+                e => e,
+            })?;
+            // `expected` has no timing, see `strip_timing`.
+            let compared = strip_timing(&block);
+            let matched = expected.and_then(|expected| expected.strip_prefix(compared.as_ref()));
+            if expected.is_some() && matched.is_none() {
+                mismatched = Some(self.queries[i].display_query.concat());
+            }
+            expected = matched;
+            writer.write_all(block.as_bytes())?;
         }
 
-        Ok(())
+        Ok(mismatched)
+    }
+
+    /// Durations measured by `MAX_DURATION` in the last execution, in the order of
+    /// queries. A query failed by it is also included.
+    pub(crate) fn query_durations(&self) -> Vec<QueryDuration> {
+        self.queries
+            .iter()
+            .filter_map(|query| query.duration.clone())
+            .collect()
     }
 }
 
@@ -92,6 +468,214 @@ impl Display for TestCase {
 #[derive(Default, Debug, Clone)]
 pub struct QueryContext {
     pub context: HashMap<String, String>,
+    /// Settings of builtin interceptors, consumed by sqlness itself.
+    pub(crate) settings: QuerySettings,
+    /// The result of the statement while [`Interceptor::after_execute`] runs.
+    ///
+    /// [`Interceptor::after_execute`]: crate::interceptor::Interceptor::after_execute
+    pub(crate) result: Option<StatementResult>,
+    /// Why the result isn't expected, set by the first interceptor failing it in
+    /// [`Interceptor::after_execute`].
+    ///
+    /// [`Interceptor::after_execute`]: crate::interceptor::Interceptor::after_execute
+    pub(crate) failure: Option<String>,
+}
+
+impl QueryContext {
+    /// Execution options set by the `OPT` interceptor, see
+    /// [`Database::query_with_opts`].
+    ///
+    /// [`Database::query_with_opts`]: crate::Database::query_with_opts
+    pub fn options(&self) -> &HashMap<String, String> {
+        &self.settings.options
+    }
+
+    /// Database to run the query against, set by the `DB` interceptor. `None` for
+    /// the default one, see [`Database::supports_databases`].
+    ///
+    /// [`Database::supports_databases`]: crate::Database::supports_databases
+    pub fn database(&self) -> Option<&str> {
+        self.settings.database.as_deref()
+    }
+
+    /// Apply `f` to the rows of the formatted `result` and format them again, for
+    /// interceptors working on rows in [`Interceptor::after_execute`]. If `f` fails,
+    /// the case fails with [`SqlnessError::UnexpectedResult`].
+    ///
+    /// Once `result` is changed as text, eg. by `REPLACE`, its rows are not known
+    /// any more, and `f` gets the text as [`QueryResult::Raw`].
+    ///
+    /// [`Interceptor::after_execute`]: crate::interceptor::Interceptor::after_execute
+    pub(crate) fn map_rows<F>(&mut self, result: &mut String, f: F)
+    where
+        F: FnOnce(QueryResult) -> std::result::Result<QueryResult, String>,
+    {
+        if self.failure.is_some() {
+            return;
+        }
+        let state = self
+            .result
+            .get_or_insert_with(|| StatementResult::raw(result));
+        let rows = if *result == state.rendered {
+            std::mem::replace(&mut state.rows, QueryResult::Raw(String::new()))
+        } else {
+            QueryResult::Raw(result.clone())
+        };
+        match f(rows) {
+            Ok(rows) => {
+                *result = render_result(&rows, &self.settings, &state.options);
+                state.rendered = result.clone();
+                state.rows = rows;
+            }
+            Err(msg) => self.failure = Some(msg),
+        }
+    }
+}
+
+#[cfg(test)]
+impl QueryContext {
+    /// Run `after_execute` of `interceptor` on `rows`, return the rows left or why
+    /// it failed.
+    pub(crate) fn after_execute_rows(
+        interceptor: &dyn crate::interceptor::Interceptor,
+        rows: QueryResult,
+    ) -> std::result::Result<QueryResult, String> {
+        let mut context = QueryContext::default();
+        let options = Arc::<QueryOptions>::default();
+        let mut result = render_result(&rows, &context.settings, &options);
+        context.result = Some(StatementResult {
+            rows,
+            rendered: result.clone(),
+            options,
+        });
+        interceptor.after_execute(&mut result, &mut context);
+        match context.failure {
+            Some(msg) => Err(msg),
+            None => Ok(context.result.unwrap().rows),
+        }
+    }
+}
+
+/// A statement's result, as rows and as formatted, see [`QueryContext::map_rows`].
+#[derive(Debug, Clone)]
+pub(crate) struct StatementResult {
+    /// Rows left by the interceptors run so far.
+    rows: QueryResult,
+    /// `rows` formatted, the result was changed as text if it differs.
+    rendered: String,
+    options: Arc<QueryOptions>,
+}
+
+impl StatementResult {
+    /// A result only known as text.
+    fn raw(result: &str) -> Self {
+        Self {
+            rows: QueryResult::Raw(result.to_string()),
+            rendered: result.to_string(),
+            options: Arc::default(),
+        }
+    }
+}
+
+/// Format `result`, then remove ANSI escape sequences with [`Config::strip_ansi`]
+/// and apply [`Config::result_filters`].
+fn render_result(result: &QueryResult, settings: &QuerySettings, options: &QueryOptions) -> String {
+    let result_format = settings.format.unwrap_or(options.result_format);
+    let null = settings.null_as.as_deref().unwrap_or(format::NULL);
+    let mut result = format::render_with_null(result, result_format, options.csv_delimiter, null);
+    if options.strip_ansi {
+        result = strip_ansi::strip_ansi(&result);
+    }
+    for (pattern, replacement) in &options.result_filters {
+        result = pattern.replace_all(&result, replacement).to_string();
+    }
+    result
+}
+
+/// Per-query execution settings set by builtin interceptors.
+#[derive(Default, Debug, Clone)]
+pub(crate) struct QuerySettings {
+    /// Overrides [`Config::query_timeout`].
+    pub(crate) timeout: Option<Duration>,
+    /// Max attempts and the delay between them, see `RETRY` interceptor.
+    pub(crate) retry: Option<(usize, Duration)>,
+    /// Labels of queries separated by the template delimiter, written before
+    /// each result. See `TEMPLATE` interceptor.
+    pub(crate) labels: Vec<String>,
+    /// Don't execute the query, see `SKIP_IF` interceptor.
+    pub(crate) skip: bool,
+    /// Token NULL cells are rendered as, see `NULL_AS` interceptor.
+    pub(crate) null_as: Option<String>,
+    /// Overrides [`Config::result_format`], see `FORMAT` interceptor.
+    pub(crate) format: Option<ResultFormat>,
+    /// How many times to execute the query, see `REPEAT` interceptor.
+    pub(crate) repeat: Option<usize>,
+    /// Don't record the result, see `HIDE` interceptor.
+    pub(crate) hide: bool,
+    /// Ignore the result and errors, see `WARMUP` interceptor.
+    pub(crate) warmup: bool,
+    /// Longest duration allowed, see `MAX_DURATION` interceptor.
+    pub(crate) max_duration: Option<Duration>,
+    /// The query should fail with an error containing this, see `EXPECT_ERROR`
+    /// interceptor.
+    pub(crate) expect_error: Option<String>,
+    /// Database to run the query against, see `DB` interceptor.
+    pub(crate) database: Option<String>,
+    /// Options passed to the database, see `OPT` interceptor.
+    pub(crate) options: HashMap<String, String>,
+    /// Values from [`Config::args`], see `ARG` interceptor.
+    pub(crate) args: Arc<HashMap<String, String>>,
+    /// Accept any of the recorded results, see `ALLOW_VARIANTS` interceptor.
+    pub(crate) allow_variants: bool,
+    /// Store the result into this variable, see `CAPTURE` interceptor.
+    pub(crate) capture: Option<String>,
+    /// Record the duration of the query, see `TIMING` interceptor.
+    pub(crate) timing: bool,
+    /// Run the query in a transaction ending like this, see `TXN` interceptor.
+    pub(crate) transaction: Option<TxnEnd>,
+    /// Compare the result as it's streamed, see `STREAM` interceptor.
+    pub(crate) stream: bool,
+    /// Commands to run before the query, see `SHELL` interceptor.
+    pub(crate) shell: Vec<String>,
+    /// Substrings the result must contain, see `CONTAINS` interceptor.
+    pub(crate) contains: Vec<String>,
+    /// Substrings the result must not contain, see `NOT_CONTAINS` interceptor.
+    pub(crate) not_contains: Vec<String>,
+    /// Ignore letter case in comparison, see `CASE_INSENSITIVE` interceptor.
+    pub(crate) case_insensitive: bool,
+    /// Stop at the first failed statement, see `SPLIT` interceptor.
+    pub(crate) split: bool,
+    /// Metadata of the environment, see `SKIP_IF` interceptor.
+    pub(crate) metadata: Arc<HashMap<String, String>>,
+}
+
+/// Options of queries from [`Config`], shared by all queries of a case.
+#[derive(Default, Debug)]
+struct QueryOptions {
+    /// See [`Config::args`].
+    args: Arc<HashMap<String, String>>,
+    /// See [`Config::result_filters`].
+    result_filters: Vec<(Regex, String)>,
+    /// See [`Config::result_format`].
+    result_format: ResultFormat,
+    /// See [`Config::catch_panic`].
+    catch_panic: bool,
+    /// See [`Config::csv_delimiter`].
+    csv_delimiter: char,
+    /// See [`Config::connection_retries`].
+    connection_retries: usize,
+    /// See [`Config::retry_backoff`].
+    retry_backoff: Duration,
+    /// See [`Config::update_result`].
+    update_result: bool,
+    /// See [`Config::max_result_bytes`].
+    max_result_bytes: Option<usize>,
+    /// See [`Config::warn_oversized_results`].
+    warn_oversized_results: bool,
+    /// See [`Config::strip_ansi`].
+    strip_ansi: bool,
+    /// See [`Config::allow_shell`].
+    allow_shell: bool,
 }
 
 #[derive(Default)]
@@ -103,12 +687,24 @@ struct Query {
     execute_query: Vec<String>,
     interceptor_registry: Registry,
     interceptors: Vec<InterceptorRef>,
+    /// Names of `interceptors`, like `REPLACE`.
+    interceptor_names: Vec<String>,
+    options: Arc<QueryOptions>,
+    /// Whether the query is included from another file, its result is not recorded.
+    included: bool,
+    /// Whether the query is executed as is, without appending `;`. It's set for
+    /// queries ended by a delimiter other than `;`, which is stripped, and for lines
+    /// passed through by [`Config::passthrough_prefix`].
+    verbatim: bool,
+    /// Duration measured by `MAX_DURATION` in the last execution.
+    duration: Option<QueryDuration>,
 }
 
 impl Query {
-    pub fn with_interceptor_factories(interceptor_registry: Registry) -> Self {
+    pub fn new(interceptor_registry: Registry, options: Arc<QueryOptions>) -> Self {
         Self {
             interceptor_registry,
+            options,
             ..Default::default()
         }
     }
@@ -118,10 +714,12 @@ impl Query {
         interceptor_prefix: &str,
         interceptor_line: String,
     ) -> Result<()> {
-        if let Some((_, remaining)) = interceptor_line.split_once(interceptor_prefix) {
+        if let Some(remaining) = interceptor_line.strip_prefix(interceptor_prefix) {
             let interceptor = self.interceptor_registry.create(remaining)?;
             self.interceptors.push(interceptor);
-            Ok(())
+            let name = remaining.split_whitespace().next().unwrap_or_default();
+            self.interceptor_names.push(name.to_string());
+            self.check_stream()
         } else {
             Err(SqlnessError::MissingPrefix {
                 line: interceptor_line,
@@ -129,6 +727,41 @@ impl Query {
         }
     }
 
+    /// Check the result of a `STREAM` query isn't supposed to be changed, as it's
+    /// compared as the database yields it.
+    fn check_stream(&self) -> Result<()> {
+        if !self
+            .interceptor_names
+            .iter()
+            .any(|name| name == crate::interceptor::stream::PREFIX)
+        {
+            return Ok(());
+        }
+        let invalid = |msg: String| {
+            Err(SqlnessError::InvalidContext {
+                prefix: crate::interceptor::stream::PREFIX.to_string(),
+                msg,
+            })
+        };
+        if let Some(name) = self
+            .interceptor_names
+            .iter()
+            .find(|name| crate::interceptor::stream::RESULT_INTERCEPTORS.contains(&name.as_str()))
+        {
+            return invalid(format!("Can't be combined with {name}"));
+        }
+        if !self.options.result_filters.is_empty() {
+            return invalid("Can't be used with result_filters".to_string());
+        }
+        if self.options.strip_ansi {
+            return invalid("Can't be used with strip_ansi".to_string());
+        }
+        if self.options.max_result_bytes.is_some() {
+            return invalid("Can't be used with max_result_bytes".to_string());
+        }
+        Ok(())
+    }
+
     fn push_comment(&mut self, comment_line: String) {
         self.comment_lines.push(comment_line);
     }
@@ -138,44 +771,584 @@ impl Query {
         self.execute_query.push(line.to_string());
     }
 
-    async fn execute<W>(&mut self, db: &dyn Database, writer: &mut W) -> Result<()>
-    where
-        W: Write,
-    {
-        let context = self.before_execute_intercept().await;
+    /// Strip the custom delimiter from the query to be executed.
+    fn strip_delimiter(&mut self, delimiter: &str) {
+        if let Some(last) = self.execute_query.last_mut() {
+            last.truncate(last.len() - delimiter.len());
+        }
+        self.verbatim = true;
+    }
+
+    /// Execute this query and render its block in result file.
+    ///
+    /// `expected` is the remaining part of the old result, or None if previous queries
+    /// already mismatched. It's used to decide whether the query should be retried.
+    ///
+    /// `metadata` is the metadata of the environment, and `variables` are values
+    /// captured by `CAPTURE` from previous queries.
+    ///
+    /// The timeout error returned doesn't contain the case name.
+    async fn execute(
+        &mut self,
+        db: &dyn Database,
+        timeout: Option<Duration>,
+        expected: Option<&str>,
+        next_block: Option<&str>,
+        metadata: &Arc<HashMap<String, String>>,
+        variables: &mut HashMap<String, String>,
+    ) -> Result<String> {
+        let mut context = self.before_execute_intercept(metadata).await;
+        if self.included {
+            if !context.settings.skip {
+                context.settings.hide = true;
+                self.execute_once(db, &context, timeout, variables).await?;
+            }
+            return Ok(String::new());
+        }
+        if context.settings.skip {
+            return Ok(self.render_skipped());
+        }
+        let timeout = context.settings.timeout.or(timeout);
+        if context.settings.warmup {
+            context.settings.hide = true;
+            match self.execute_once(db, &context, timeout, variables).await {
+                Ok(_)
+                | Err(SqlnessError::UnexpectedResult { .. } | SqlnessError::Timeout { .. }) => {}
+                Err(e) => return Err(e),
+            }
+            let mut block = self.render_query();
+            block.push_str(WARMUP_MARKER);
+            block.push_str("\n\n");
+            return Ok(block);
+        }
+        if context.settings.stream {
+            return self
+                .execute_stream(db, &context, timeout, expected, next_block, variables)
+                .await;
+        }
+        let (attempts, delay) = context.settings.retry.unwrap_or((1, Duration::ZERO));
+
+        if let Some(times) = context.settings.repeat {
+            let mut hidden = context.clone();
+            hidden.settings.hide = true;
+            for iteration in 1..times {
+                self.execute_once(db, &hidden, timeout, variables)
+                    .await
+                    .map_err(|e| match e {
+                        SqlnessError::UnexpectedResult { query, msg } => {
+                            SqlnessError::UnexpectedResult {
+                                query,
+                                msg: format!("{msg}, at iteration {iteration} of {times}"),
+                            }
+                        }
+                        e => e,
+                    })?;
+            }
+        }
+
+        let mut attempt = 1;
+        loop {
+            let (mut block, elapsed) = self.execute_once(db, &context, timeout, variables).await?;
+            if context.settings.allow_variants && !context.settings.hide {
+                block = self.merge_variants(block, expected, next_block);
+            }
+            if context.settings.case_insensitive && !context.settings.hide {
+                block = self.keep_recorded_case(block, expected, next_block);
+            }
+            let checked =
+                !context.settings.contains.is_empty() || !context.settings.not_contains.is_empty();
+            if checked && !context.settings.hide && !self.options.update_result {
+                block = self.keep_recorded(block, expected, next_block);
+            }
+            let matched =
+                expected.is_none_or(|expected| expected.starts_with(strip_timing(&block).as_ref()));
+            if matched || attempt >= attempts {
+                if let Some(limit) = context.settings.max_duration {
+                    self.check_duration(elapsed, limit)?;
+                }
+                return Ok(block);
+            }
+
+            attempt += 1;
+            util::sleep(delay).await;
+        }
+    }
+
+    /// Execute the query with [`Database::query_stream`] and compare chunks with the
+    /// result recorded in `expected` as they arrive, see `STREAM` interceptor.
+    async fn execute_stream(
+        &self,
+        db: &dyn Database,
+        context: &QueryContext,
+        timeout: Option<Duration>,
+        expected: Option<&str>,
+        next_block: Option<&str>,
+        variables: &HashMap<String, String>,
+    ) -> Result<String> {
+        let mut sql = self.query_with_variables(variables);
+        if !self.verbatim && !sql.ends_with(QUERY_DELIMITER) {
+            sql.push(QUERY_DELIMITER);
+        }
+        let recorded = if self.options.update_result {
+            None
+        } else {
+            self.recorded_result(expected, next_block)
+        };
+
+        let start = Instant::now();
+        let read = async {
+            let mut chunks = match db.query_stream(context.clone(), sql.clone()) {
+                Some(chunks) => chunks,
+                None => {
+                    let result = self.query_db(db, context, sql.clone()).await?;
+                    let result = render_result(&result, &context.settings, &self.options);
+                    stream::once(future::ready(result)).boxed()
+                }
+            };
+            // A matching result is the prefix of `recorded`, so only its length
+            // is kept while reading.
+            let mut result = String::new();
+            let mut matched_len = 0;
+            while let Some(chunk) = chunks.next().await {
+                let Some(recorded) = recorded else {
+                    result.push_str(&chunk);
+                    continue;
+                };
+                let end = matched_len + chunk.len();
+                if recorded.get(matched_len..end) == Some(chunk.as_str()) {
+                    matched_len = end;
+                    continue;
+                }
+                let diverged = &recorded[..matched_len];
+                return Ok(format!("{diverged}{chunk}\n{STREAM_STOPPED_MARKER}"));
+            }
+            if let Some(recorded) = recorded {
+                result = recorded[..matched_len].to_string();
+            }
+            Ok::<_, SqlnessError>(result)
+        };
+        let read = util::catch_panic(self.options.catch_panic, read);
+        let result = match timeout {
+            Some(timeout) => {
+                util::timeout(timeout, read)
+                    .await
+                    .ok_or_else(|| SqlnessError::Timeout {
+                        case: String::new(),
+                        query: sql.clone(),
+                        elapsed: start.elapsed(),
+                    })?
+            }
+            None => read.await,
+        };
+        let result = result.map_err(|message| SqlnessError::Panic {
+            case: String::new(),
+            query: sql.clone(),
+            message,
+        })??;
+
+        let mut block = self.render_query();
+        block.push_str(&result);
+        block.push_str("\n\n");
+        Ok(block)
+    }
+
+    /// Record the `elapsed` duration for the report, and fail if it exceeds `limit`,
+    /// see `MAX_DURATION` interceptor.
+    fn check_duration(&mut self, elapsed: Duration, limit: Duration) -> Result<()> {
+        let query = self.display_query.concat();
+        self.duration = Some(QueryDuration {
+            query: query.clone(),
+            duration: elapsed,
+            limit,
+        });
+        if elapsed > limit {
+            return Err(SqlnessError::SlowQuery {
+                query,
+                elapsed,
+                limit,
+            });
+        }
+
+        Ok(())
+    }
+
+    /// Merge the result in `block` into the variants recorded in `expected`.
+    ///
+    /// Old variants are kept as is when one of them is the same as the result,
+    /// otherwise the result is appended as a new variant.
+    fn merge_variants(
+        &self,
+        block: String,
+        expected: Option<&str>,
+        next_block: Option<&str>,
+    ) -> String {
+        let header = self.render_query();
+        let Some(recorded) = self.recorded_result(expected, next_block) else {
+            return block;
+        };
+
+        let result = &block[header.len()..];
+        if split_variants(recorded).contains(&result) {
+            return format!("{header}{recorded}");
+        }
+        format!("{header}{recorded}{VARIANT_SEPARATOR}{result}")
+    }
+
+    /// Keep the result recorded in `expected` if it only differs from the one in
+    /// `block` by letter case, see `CASE_INSENSITIVE` interceptor.
+    fn keep_recorded_case(
+        &self,
+        block: String,
+        expected: Option<&str>,
+        next_block: Option<&str>,
+    ) -> String {
+        let header = self.render_query();
+        match self.recorded_result(expected, next_block) {
+            Some(recorded) if recorded.to_lowercase() == block[header.len()..].to_lowercase() => {
+                format!("{header}{recorded}")
+            }
+            _ => block,
+        }
+    }
+
+    /// Keep the result recorded in `expected` in place of the one in `block`, see
+    /// `CONTAINS` interceptor.
+    fn keep_recorded(
+        &self,
+        block: String,
+        expected: Option<&str>,
+        next_block: Option<&str>,
+    ) -> String {
+        match self.recorded_result(expected, next_block) {
+            Some(recorded) => format!("{}{recorded}", self.render_query()),
+            None => block,
+        }
+    }
+
+    /// The result of this query recorded at the start of `expected`, which ends
+    /// before `next_block`, the rendered query after this one.
+    fn recorded_result<'a>(
+        &self,
+        expected: Option<&'a str>,
+        next_block: Option<&str>,
+    ) -> Option<&'a str> {
+        let header = self.render_query();
+        expected
+            .and_then(|expected| expected.strip_prefix(header.as_str()))
+            .and_then(|rest| match next_block {
+                Some(next_block) => rest
+                    .match_indices(next_block)
+                    .find(|(i, _)| rest[..*i].ends_with('\n'))
+                    .map(|(i, _)| &rest[..i]),
+                None => Some(rest),
+            })
+            .filter(|recorded| !recorded.is_empty())
+    }
+
+    fn render_skipped(&self) -> String {
+        let mut block = self.render_query();
+        block.push_str(SKIPPED_MARKER);
+        block.push_str("\n\n");
+        block
+    }
+
+    /// Render comments and the displayed query of this query's block.
+    fn render_query(&self) -> String {
+        let mut block = String::new();
         for comment in &self.comment_lines {
-            writer.write_all(comment.as_bytes())?;
-            writer.write_all("\n".as_bytes())?;
+            block.push_str(comment);
+            block.push('\n');
         }
         for comment in &self.display_query {
-            writer.write_all(comment.as_bytes())?;
+            block.push_str(comment);
         }
-        writer.write_all("\n\n".as_bytes())?;
+        block.push_str("\n\n");
+        block
+    }
 
-        let sql = self.concat_query_lines();
+    /// Execute statements of this query once, in a transaction if it's required by
+    /// `TXN` interceptor.
+    async fn execute_once(
+        &self,
+        db: &dyn Database,
+        context: &QueryContext,
+        timeout: Option<Duration>,
+        variables: &mut HashMap<String, String>,
+    ) -> Result<(String, Duration)> {
+        let Some(end) = context.settings.transaction else {
+            return self
+                .execute_statements(db, context, timeout, variables)
+                .await;
+        };
+
+        self.execute_control(db, context, "BEGIN;").await?;
+        let block = self
+            .execute_statements(db, context, timeout, variables)
+            .await;
+        match block {
+            Ok(block) => {
+                self.execute_control(db, context, end.statement()).await?;
+                Ok(block)
+            }
+            Err(e) => {
+                // The error of the query is more relevant than the one of rollback
+                let _ = self
+                    .execute_control(db, context, TxnEnd::Rollback.statement())
+                    .await;
+                Err(e)
+            }
+        }
+    }
+
+    /// Execute a statement controlling the transaction, its result isn't recorded.
+    async fn execute_control(
+        &self,
+        db: &dyn Database,
+        context: &QueryContext,
+        sql: &str,
+    ) -> Result<()> {
+        match self.query_db(db, context, sql.to_string()).await? {
+            QueryResult::Error(msg) => Err(SqlnessError::UnexpectedResult {
+                query: sql.to_string(),
+                msg: format!("query failed: {msg}"),
+            }),
+            _ => Ok(()),
+        }
+    }
+
+    /// Apply [`Config::max_result_bytes`] to the formatted `result` of `sql`.
+    fn limit_size(&self, result: &mut String, sql: &str) -> Result<()> {
+        let Some(limit) = self.options.max_result_bytes else {
+            return Ok(());
+        };
+        let size = result.len();
+        if size <= limit {
+            return Ok(());
+        }
+        if !self.options.warn_oversized_results {
+            return Err(SqlnessError::ResultTooLarge {
+                query: sql.to_string(),
+                size,
+                limit,
+            });
+        }
+
+        println!("Result of query {sql} is {size} bytes, truncated to max_result_bytes {limit}");
+        let mut end = limit;
+        while !result.is_char_boundary(end) {
+            end -= 1;
+        }
+        result.truncate(end);
+        if !result.ends_with('\n') {
+            result.push('\n');
+        }
+        result.push_str(&format!("... (truncated, {size} bytes)"));
+        Ok(())
+    }
+
+    /// Send one statement to the database, see [`Config::catch_panic`] and
+    /// [`Config::connection_retries`].
+    async fn query_db(
+        &self,
+        db: &dyn Database,
+        context: &QueryContext,
+        sql: String,
+    ) -> Result<QueryResult> {
+        if let Some(database) = context.database() {
+            if !db.supports_databases() {
+                return Err(SqlnessError::UnsupportedDatabase {
+                    query: sql,
+                    database: database.to_string(),
+                });
+            }
+        }
+        let mut delay = self.options.retry_backoff;
+        let mut retries = 0;
+        loop {
+            let result = db.query_result(context.clone(), sql.clone());
+            let result = util::catch_panic(self.options.catch_panic, result)
+                .await
+                .map_err(|message| SqlnessError::Panic {
+                    case: String::new(),
+                    query: sql.clone(),
+                    message,
+                })?;
+            match &result {
+                QueryResult::Error(error)
+                    if retries < self.options.connection_retries && db.is_retryable(error) =>
+                {
+                    retries += 1;
+                    println!("Query failed with {error}, retry {retries} in {delay:?}");
+                    util::sleep(delay).await;
+                    delay *= 2;
+                }
+                _ => return Ok(result),
+            }
+        }
+    }
+
+    async fn execute_statements(
+        &self,
+        db: &dyn Database,
+        context: &QueryContext,
+        timeout: Option<Duration>,
+        variables: &mut HashMap<String, String>,
+    ) -> Result<(String, Duration)> {
+        let mut block = self.render_query();
+        for command in &context.settings.shell {
+            if !self.options.allow_shell {
+                return Err(SqlnessError::ShellNotAllowed {
+                    command: command.clone(),
+                });
+            }
+            let command = command.clone();
+            let output = util::run_blocking(move || shell::run(&command, timeout)).await?;
+            if !context.settings.hide && !output.is_empty() {
+                block.push_str(output.trim_end_matches('\n'));
+                block.push_str("\n\n");
+            }
+        }
+
+        let sql = self.query_with_variables(variables);
+        // Duration of queries, see `TIMING` interceptor.
+        let mut elapsed = Duration::ZERO;
+        // Whether a statement failed, see `SPLIT` interceptor.
+        let mut failed = false;
         // An intercetor may generate multiple SQLs, so we need to split them.
-        for sql in sql.split(crate::interceptor::template::DELIMITER) {
+        for (i, sql) in sql
+            .split(crate::interceptor::template::DELIMITER)
+            .enumerate()
+        {
+            if failed && context.settings.split {
+                if !context.settings.hide {
+                    if let Some(label) = context.settings.labels.get(i) {
+                        block.push_str(&format!("-- {label}\n"));
+                    }
+                    block.push_str(SKIPPED_MARKER);
+                    block.push_str("\n\n");
+                }
+                continue;
+            }
             if !sql.trim().is_empty() {
-                let sql = if sql.ends_with(QUERY_DELIMITER) {
+                let sql = if self.verbatim || sql.ends_with(QUERY_DELIMITER) {
                     sql.to_string()
                 } else {
                     format!("{sql};")
                 };
-                let mut result = db.query(context.clone(), sql).await.to_string();
-                self.after_execute_intercept(&mut result).await;
-                self.write_result(writer, result)?;
+                let start = Instant::now();
+                let query = self.query_db(db, context, sql.clone());
+                let result = match timeout {
+                    Some(timeout) => util::timeout(timeout, query).await.ok_or_else(|| {
+                        SqlnessError::Timeout {
+                            case: String::new(),
+                            query: sql.clone(),
+                            elapsed: start.elapsed(),
+                        }
+                    })??,
+                    None => query.await?,
+                };
+                elapsed += start.elapsed();
+                let result = match (&context.settings.expect_error, result) {
+                    (None, QueryResult::Error(msg))
+                        if context.settings.hide && !context.settings.warmup =>
+                    {
+                        return Err(SqlnessError::UnexpectedResult {
+                            query: sql,
+                            msg: format!("query failed: {msg}"),
+                        })
+                    }
+                    (None, rows) => {
+                        failed = matches!(rows, QueryResult::Error(_));
+                        let mut result = render_result(&rows, &context.settings, &self.options);
+                        // Interceptors run on a copy of the context, with the rows
+                        // before `max_result_bytes` truncates the text.
+                        let mut post = context.clone();
+                        post.result = Some(StatementResult {
+                            rows,
+                            rendered: result.clone(),
+                            options: self.options.clone(),
+                        });
+                        self.limit_size(&mut result, &sql)?;
+                        self.after_execute_intercept(&mut result, &mut post).await;
+                        if let Some(msg) = post.failure {
+                            return Err(SqlnessError::UnexpectedResult { query: sql, msg });
+                        }
+                        if let Some(name) = &context.settings.capture {
+                            let rows = post
+                                .result
+                                .map_or(QueryResult::Raw(result.clone()), |r| r.rows);
+                            let value = capture_value(&rows).map_err(|msg| {
+                                SqlnessError::UnexpectedResult {
+                                    query: sql.clone(),
+                                    msg,
+                                }
+                            })?;
+                            variables.insert(name.clone(), value);
+                        }
+                        contains::check(
+                            &result,
+                            &context.settings.contains,
+                            &context.settings.not_contains,
+                        )
+                        .map_err(|msg| SqlnessError::UnexpectedResult {
+                            query: sql.clone(),
+                            msg,
+                        })?;
+                        result
+                    }
+                    (Some(pattern), QueryResult::Error(msg)) if msg.contains(pattern.as_str()) => {
+                        EXPECTED_ERROR_MARKER.to_string()
+                    }
+                    (Some(pattern), QueryResult::Error(msg)) => {
+                        return Err(SqlnessError::UnexpectedResult {
+                            query: sql,
+                            msg: format!("expect error containing {pattern}, got: {msg}"),
+                        })
+                    }
+                    (Some(_), result) => {
+                        return Err(SqlnessError::UnexpectedResult {
+                            query: sql,
+                            msg: format!("expect error, got: {result}"),
+                        })
+                    }
+                };
+                if context.settings.hide {
+                    continue;
+                }
+                if let Some(label) = context.settings.labels.get(i) {
+                    block.push_str(&format!("-- {label}\n"));
+                }
+                block.push_str(&result);
+                block.push_str("\n\n");
             }
         }
+        if context.settings.timing && !context.settings.hide {
+            // Put it right after the last result, before the empty line.
+            block.pop();
+            block.push_str(&format!(
+                "{TIMING_MARKER}{:.3}ms\n\n",
+                elapsed.as_secs_f64() * 1000.0
+            ));
+        }
+        if context.settings.hide {
+            block.push_str(HIDDEN_MARKER);
+            block.push_str("\n\n");
+        }
 
-        Ok(())
+        Ok((block, elapsed))
     }
 
     /// Run pre-execution interceptors.
     ///
     /// Interceptors may change either the query to be displayed or the query to be executed,
     /// so we need to return the query to caller.
-    async fn before_execute_intercept(&mut self) -> QueryContext {
+    async fn before_execute_intercept(
+        &mut self,
+        metadata: &Arc<HashMap<String, String>>,
+    ) -> QueryContext {
         let mut context = QueryContext::default();
+        context.settings.args = self.options.args.clone();
+        context.settings.metadata = metadata.clone();
 
         for interceptor in &self.interceptors {
             interceptor
@@ -186,12 +1359,43 @@ impl Query {
         context
     }
 
-    async fn after_execute_intercept(&mut self, result: &mut String) {
+    /// Run post-execution interceptors in the order they are declared.
+    async fn after_execute_intercept(&self, result: &mut String, context: &mut QueryContext) {
         for interceptor in &self.interceptors {
-            interceptor.after_execute_async(result).await;
+            interceptor.after_execute_async(result, context).await;
         }
     }
 
+    /// Concatenate the query lines, with `{{name}}` replaced by the values captured by
+    /// `CAPTURE` from previous queries.
+    ///
+    /// The query is scanned once from left to right, values are inserted as is even
+    /// if they contain `{{other}}`, and unknown names are kept.
+    fn query_with_variables(&self, variables: &HashMap<String, String>) -> String {
+        let sql = self.concat_query_lines();
+        let mut result = String::with_capacity(sql.len());
+        let mut rest = sql.as_str();
+        while let Some(start) = rest.find("{{") {
+            let Some(len) = rest[start + 2..].find("}}") else {
+                break;
+            };
+            let name = &rest[start + 2..start + 2 + len];
+            match variables.get(name) {
+                Some(value) => {
+                    result.push_str(&rest[..start]);
+                    result.push_str(value);
+                    rest = &rest[start + len + 4..];
+                }
+                None => {
+                    result.push_str(&rest[..start + 2]);
+                    rest = &rest[start + 2..];
+                }
+            }
+        }
+        result.push_str(rest);
+        result
+    }
+
     /// Concat the query to be executed to a single string.
     fn concat_query_lines(&self) -> String {
         self.execute_query
@@ -200,15 +1404,1439 @@ impl Query {
             .trim_start()
             .to_string()
     }
+}
 
-    #[allow(clippy::unused_io_amount)]
-    fn write_result<W>(&self, writer: &mut W, result: String) -> Result<()>
-    where
-        W: Write,
-    {
-        writer.write_all(result.as_bytes())?;
-        writer.write("\n\n".as_bytes())?;
+/// Remove lines written by `TIMING` from `result`, they are not compared.
+pub(crate) fn strip_timing(result: &str) -> Cow<'_, str> {
+    if !result.contains(TIMING_MARKER) {
+        return Cow::Borrowed(result);
+    }
+    result
+        .split_inclusive('\n')
+        .filter(|line| !line.starts_with(TIMING_MARKER))
+        .collect::<String>()
+        .into()
+}
 
-        Ok(())
+/// Get the single value in `result` for `CAPTURE`.
+fn capture_value(result: &QueryResult) -> std::result::Result<String, String> {
+    match result {
+        QueryResult::Rows { rows, .. } => match rows.as_slice() {
+            [row] if row.len() == 1 => Ok(row[0].clone().unwrap_or_else(|| "NULL".to_string())),
+            _ => Err(format!(
+                "expect a single value to capture, got {} rows of {} columns",
+                rows.len(),
+                rows.first().map_or(0, Vec::len)
+            )),
+        },
+        QueryResult::Raw(raw) => {
+            let raw = raw.trim();
+            if raw.lines().count() > 1 {
+                return Err(format!("expect a single value to capture, got: {raw}"));
+            }
+            Ok(raw.to_string())
+        }
+        QueryResult::Error(msg) => Err(format!("query failed: {msg}")),
+    }
+}
+
+/// Split recorded results of a query by [`VARIANT_SEPARATOR`] at the start of a line.
+fn split_variants(recorded: &str) -> Vec<&str> {
+    let mut variants = vec![];
+    let mut start = 0;
+    for (i, _) in recorded.match_indices(VARIANT_SEPARATOR) {
+        if i > start && recorded[..i].ends_with('\n') {
+            variants.push(&recorded[start..i]);
+            start = i + VARIANT_SEPARATOR.len();
+        }
+    }
+    variants.push(&recorded[start..]);
+    variants
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use async_trait::async_trait;
+
+    use super::*;
+    use crate::interceptor::{txn::TxnInterceptorFactory, InterceptorFactory};
+    use crate::ConfigBuilder;
+
+    /// Return how many times it's queried.
+    #[derive(Default)]
+    struct CountingDB {
+        count: AtomicUsize,
+    }
+
+    #[async_trait]
+    impl Database for CountingDB {
+        async fn query(&self, _: QueryContext, _: String) -> Box<dyn Display> {
+            Box::new(self.count.fetch_add(1, Ordering::SeqCst) + 1)
+        }
+    }
+
+    /// Return the query it received.
+    struct EchoDB;
+
+    #[async_trait]
+    impl Database for EchoDB {
+        async fn query(&self, _: QueryContext, query: String) -> Box<dyn Display> {
+            Box::new(query)
+        }
+    }
+
+    #[tokio::test]
+    async fn custom_prefix() {
+        let cfg = ConfigBuilder::default()
+            .case_dir(String::new())
+            .comment_prefix("#".to_string())
+            .interceptor_prefix("/* SQLNESS */".to_string())
+            .build()
+            .unwrap();
+        let input = "# comment
+/* SQLNESS */ REPLACE 3 x
+-- SELECT 3 is a query
+SELECT 3 /* SQLNESS */;
+";
+        let mut case = TestCase::from_reader(Path::new("test"), input.as_bytes(), &cfg).unwrap();
+
+        assert_eq!(
+            execute(&mut case, &EchoDB, "").await,
+            "# comment
+/* SQLNESS */ REPLACE 3 x
+-- SELECT 3 is a query
+SELECT 3 /* SQLNESS */;
+
+-- SELECT x is a query
+SELECT x /* SQLNESS */;
+
+"
+        );
+    }
+
+    /// Return the query in a table.
+    struct RowsDB;
+
+    #[async_trait]
+    impl Database for RowsDB {
+        async fn query(&self, _: QueryContext, _: String) -> Box<dyn Display> {
+            unreachable!()
+        }
+
+        async fn query_result(&self, _: QueryContext, query: String) -> QueryResult {
+            QueryResult::Rows {
+                columns: vec!["query".to_string()],
+                rows: vec![vec![Some(query)]],
+            }
+        }
+    }
+
+    /// Fail queries containing `error`.
+    struct FailingDB;
+
+    #[async_trait]
+    impl Database for FailingDB {
+        async fn query(&self, _: QueryContext, _: String) -> Box<dyn Display> {
+            unreachable!()
+        }
+
+        async fn query_result(&self, _: QueryContext, query: String) -> QueryResult {
+            if query.contains("error") {
+                QueryResult::Error("ERROR 42P01: relation does not exist".to_string())
+            } else {
+                QueryResult::Raw("ok".to_string())
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn split_statements() {
+        let mut case = parse(
+            "-- SQLNESS DELIMITER $$
+-- SQLNESS SPLIT
+SELECT 1; SELECT 'error';
+SELECT 2;$$
+",
+        );
+        assert_eq!(
+            execute(&mut case, &FailingDB, "").await,
+            "-- SQLNESS DELIMITER $$
+-- SQLNESS SPLIT
+SELECT 1; SELECT 'error';
+SELECT 2;$$
+
+-- statement 1
+ok
+
+-- statement 2
+ERROR 42P01: relation does not exist
+
+-- statement 3
+-- SKIPPED
+
+"
+        );
+    }
+
+    #[test]
+    fn substitute_variables() {
+        let mut query = Query::new(Registry::default(), Arc::default());
+        query.append_query_line("SELECT '{{a}}', '{{b}}', '{{c}}', '{{ {{a}}';");
+        let variables = [
+            ("a".to_string(), "{{b}}".to_string()),
+            ("b".to_string(), "2".to_string()),
+        ]
+        .into();
+        assert_eq!(
+            query.query_with_variables(&variables),
+            "SELECT '{{b}}', '2', '{{c}}', '{{ {{b}}';"
+        );
+    }
+
+    #[tokio::test]
+    async fn capture() {
+        let mut case = parse("-- SQLNESS CAPTURE v\nSELECT 1;\nSELECT '{{v}}', '{{w}}';\n");
+        assert_eq!(
+            execute(&mut case, &EchoDB, "").await,
+            "-- SQLNESS CAPTURE v
+SELECT 1;
+
+SELECT 1;
+
+SELECT '{{v}}', '{{w}}';
+
+SELECT 'SELECT 1;', '{{w}}';
+
+"
+        );
+
+        let mut case = parse("-- SQLNESS CAPTURE v\n-- SQLNESS HIDE\nSELECT 1;\nSELECT '{{v}}';\n");
+        assert_eq!(
+            execute(&mut case, &EchoDB, "").await,
+            "-- SQLNESS CAPTURE v
+-- SQLNESS HIDE
+SELECT 1;
+
+-- HIDDEN
+
+SELECT '{{v}}';
+
+SELECT 'SELECT 1;';
+
+"
+        );
+
+        let mut case = parse("-- SQLNESS CAPTURE v\nSELECT\n1;\n");
+        let err = case.execute(&EchoDB, &mut vec![], "").await.err().unwrap();
+        assert!(err.to_string().contains("single value"), "{err}");
+    }
+
+    #[tokio::test]
+    async fn custom_interceptor() {
+        use crate::interceptor::Interceptor;
+
+        /// Append the context to results.
+        struct SuffixInterceptor(String);
+
+        impl Interceptor for SuffixInterceptor {
+            fn after_execute(&self, result: &mut String, _: &mut QueryContext) {
+                result.push_str(&self.0);
+            }
+        }
+
+        struct SuffixInterceptorFactory;
+
+        impl InterceptorFactory for SuffixInterceptorFactory {
+            fn try_new(&self, ctx: &str) -> Result<InterceptorRef> {
+                Ok(Box::new(SuffixInterceptor(ctx.to_string())))
+            }
+        }
+
+        let cfg = ConfigBuilder::default()
+            .case_dir(String::new())
+            .with_interceptor("SUFFIX", Arc::new(SuffixInterceptorFactory))
+            .build()
+            .unwrap();
+        let input = "-- SQLNESS SUFFIX !\n-- SQLNESS REPLACE 1 2\nSELECT 1;\n";
+        let mut case = TestCase::from_reader(Path::new("test"), input.as_bytes(), &cfg).unwrap();
+        assert_eq!(
+            execute(&mut case, &EchoDB, "").await,
+            "-- SQLNESS SUFFIX !\n-- SQLNESS REPLACE 1 2\nSELECT 1;\n\nSELECT 2;!\n\n"
+        );
+    }
+
+    #[tokio::test]
+    async fn interceptors_in_declaration_order() {
+        let mut case = parse("-- SQLNESS REPLACE [0-9]\n-- SQLNESS SORT_RESULT\n3a\n1c\n2b;\n");
+        assert_eq!(
+            execute(&mut case, &EchoDB, "").await,
+            "-- SQLNESS REPLACE [0-9]\n-- SQLNESS SORT_RESULT\n3a\n1c\n2b;\n\na\nb;\nc\n\n"
+        );
+
+        let mut case = parse("-- SQLNESS SORT_RESULT\n-- SQLNESS REPLACE [0-9]\n3a\n1c\n2b;\n");
+        assert_eq!(
+            execute(&mut case, &EchoDB, "").await,
+            "-- SQLNESS SORT_RESULT\n-- SQLNESS REPLACE [0-9]\n3a\n1c\n2b;\n\nc\nb;\na\n\n"
+        );
+    }
+
+    #[tokio::test]
+    async fn distinct_in_declaration_order() {
+        let mut case = parse("-- SQLNESS DISTINCT\n-- SQLNESS LIMIT 2\nb\nb\nc\nd;\n");
+        assert_eq!(
+            execute(&mut case, &EchoDB, "").await,
+            "-- SQLNESS DISTINCT\n-- SQLNESS LIMIT 2\nb\nb\nc\nd;\n\nb\nc\n... (truncated)\n\n"
+        );
+
+        let mut case = parse("-- SQLNESS LIMIT 2\n-- SQLNESS DISTINCT\nb\nb\nc\nd;\n");
+        assert_eq!(
+            execute(&mut case, &EchoDB, "").await,
+            "-- SQLNESS LIMIT 2\n-- SQLNESS DISTINCT\nb\nb\nc\nd;\n\nb\n... (truncated)\n\n"
+        );
+    }
+
+    #[tokio::test]
+    async fn lines_in_declaration_order() {
+        let mut case = parse("-- SQLNESS LINES 1-2\n-- SQLNESS SORT_RESULT\nc\nb\na;\n");
+        assert_eq!(
+            execute(&mut case, &EchoDB, "").await,
+            "-- SQLNESS LINES 1-2\n-- SQLNESS SORT_RESULT\nc\nb\na;\n\nb\nc\n\n"
+        );
+
+        let mut case = parse("-- SQLNESS SORT_RESULT\n-- SQLNESS LINES 1-2\nc\nb\na;\n");
+        assert_eq!(
+            execute(&mut case, &EchoDB, "").await,
+            "-- SQLNESS SORT_RESULT\n-- SQLNESS LINES 1-2\nc\nb\na;\n\na;\nb\n\n"
+        );
+    }
+
+    #[tokio::test]
+    async fn rows_interceptors_in_declaration_order() {
+        let input = "-- SQLNESS COUNT_ROWS 1\n-- SQLNESS REPLACE 1 one\nSELECT 1;\n";
+        let mut case = parse(input);
+        assert_eq!(
+            execute(&mut case, &RowsDB, "").await,
+            format!("{input}\n-- rows: one\n\n")
+        );
+
+        // The table is only known as text after `REPLACE`, each line is counted.
+        let mut case = parse("-- SQLNESS REPLACE 1 one\n-- SQLNESS COUNT_ROWS 1\nSELECT 1;\n");
+        let err = case.execute(&RowsDB, &mut vec![], "").await.unwrap_err();
+        assert!(
+            matches!(&err, SqlnessError::UnexpectedResult { msg, .. } if msg == "expect rows: 1, got 5"),
+            "{err}"
+        );
+    }
+
+    /// Record queries it received, and fail those containing `error`.
+    #[derive(Default)]
+    struct RecordingDB {
+        queries: std::sync::Mutex<Vec<String>>,
+    }
+
+    #[async_trait]
+    impl Database for RecordingDB {
+        async fn query(&self, _: QueryContext, _: String) -> Box<dyn Display> {
+            unreachable!()
+        }
+
+        async fn query_result(&self, _: QueryContext, query: String) -> QueryResult {
+            self.queries.lock().unwrap().push(query.clone());
+            if query.contains("error") {
+                QueryResult::Error("failed".to_string())
+            } else {
+                QueryResult::Raw(query)
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn transaction() {
+        let db = RecordingDB::default();
+        let mut case = parse("-- SQLNESS TXN\nINSERT 1;\n-- SQLNESS TXN ROLLBACK\nDELETE 1;\n");
+        assert_eq!(
+            execute(&mut case, &db, "").await,
+            "-- SQLNESS TXN\nINSERT 1;\n\nINSERT 1;\n\n-- SQLNESS TXN ROLLBACK\nDELETE 1;\n\nDELETE 1;\n\n"
+        );
+        assert_eq!(
+            *db.queries.lock().unwrap(),
+            [
+                "BEGIN;",
+                "INSERT 1;",
+                "COMMIT;",
+                "BEGIN;",
+                "DELETE 1;",
+                "ROLLBACK;"
+            ]
+        );
+
+        // rollback when the query fails
+        let db = RecordingDB::default();
+        let mut case = parse("-- SQLNESS TXN\n-- SQLNESS HIDE\nINSERT error;\n");
+        assert!(case.execute(&db, &mut vec![], "").await.is_err());
+        assert_eq!(
+            *db.queries.lock().unwrap(),
+            ["BEGIN;", "INSERT error;", "ROLLBACK;"]
+        );
+
+        assert!(TxnInterceptorFactory.try_new("abort").is_err());
+    }
+
+    #[tokio::test]
+    async fn timing() {
+        let input = "-- SQLNESS TIMING\nSELECT 1;\nSELECT 2;\n";
+        let mut case = parse(input);
+        let output = execute(&mut case, &EchoDB, "").await;
+        let lines = output.lines().collect::<Vec<_>>();
+        assert_eq!(
+            lines[..4],
+            ["-- SQLNESS TIMING", "SELECT 1;", "", "SELECT 1;"]
+        );
+        assert!(lines[4].starts_with("-- elapsed: ") && lines[4].ends_with("ms"));
+        assert_eq!(lines[5..], ["", "SELECT 2;", "", "SELECT 2;", ""]);
+
+        // timing is ignored when compared to the old result
+        let expected = strip_timing(&output);
+        assert_eq!(
+            expected,
+            "-- SQLNESS TIMING\nSELECT 1;\n\nSELECT 1;\n\nSELECT 2;\n\nSELECT 2;\n\n"
+        );
+        let mut case = parse(&format!("-- SQLNESS RETRY 2\n{input}"));
+        let db = CountingDB::default();
+        let expected =
+            "-- SQLNESS RETRY 2\n-- SQLNESS TIMING\nSELECT 1;\n\n1\n\nSELECT 2;\n\n2\n\n";
+        let output = execute(&mut case, &db, expected).await;
+        assert_eq!(strip_timing(&output), expected);
+        assert_eq!(db.count.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn retry_without_result() {
+        let mut case = parse("-- SQLNESS RETRY 5 1s\nSELECT 1;\n");
+
+        // the result of a new case is empty
+        let db = CountingDB::default();
+        let start = Instant::now();
+        assert_eq!(
+            execute(&mut case, &db, "").await,
+            "-- SQLNESS RETRY 5 1s\nSELECT 1;\n\n1\n\n"
+        );
+        assert_eq!(db.count.load(Ordering::SeqCst), 1);
+        assert!(start.elapsed() < Duration::from_secs(1));
+    }
+
+    #[tokio::test]
+    async fn allow_two_variants() {
+        let input = "-- SQLNESS ALLOW_VARIANTS\nSELECT 1;\nSELECT 2;\n";
+        let expected = "-- SQLNESS ALLOW_VARIANTS
+SELECT 1;
+
+0
+
+-- VARIANT
+
+1
+
+SELECT 2;
+
+2
+
+";
+        let mut case = parse(input);
+        assert_eq!(
+            execute(&mut case, &CountingDB::default(), expected).await,
+            expected
+        );
+
+        // unmatched result is appended as a new variant
+        let mut case = parse(input);
+        let db = CountingDB::default();
+        db.count.store(2, Ordering::SeqCst);
+        assert_eq!(
+            execute(&mut case, &db, expected).await,
+            "-- SQLNESS ALLOW_VARIANTS
+SELECT 1;
+
+0
+
+-- VARIANT
+
+1
+
+-- VARIANT
+
+3
+
+SELECT 2;
+
+4
+
+"
+        );
+    }
+
+    #[tokio::test]
+    async fn allow_three_variants() {
+        let expected = "-- SQLNESS ALLOW_VARIANTS
+SELECT 1;
+
+1
+
+-- VARIANT
+
+2
+
+-- VARIANT
+
+3
+
+";
+        for count in 0..3 {
+            let mut case = parse("-- SQLNESS ALLOW_VARIANTS\nSELECT 1;\n");
+            let db = CountingDB::default();
+            db.count.store(count, Ordering::SeqCst);
+            assert_eq!(execute(&mut case, &db, expected).await, expected);
+        }
+
+        // no recorded variants
+        let mut case = parse("-- SQLNESS ALLOW_VARIANTS\nSELECT 1;\n");
+        assert_eq!(
+            execute(&mut case, &CountingDB::default(), "").await,
+            "-- SQLNESS ALLOW_VARIANTS\nSELECT 1;\n\n1\n\n"
+        );
+    }
+
+    #[tokio::test]
+    async fn case_insensitive() {
+        let input = "-- SQLNESS CASE_INSENSITIVE\nSELECT Name FROM t;\nSELECT Id;\n";
+        let expected = "-- SQLNESS CASE_INSENSITIVE
+SELECT Name FROM t;
+
+select NAME from T;
+
+SELECT Id;
+
+SELECT Id;
+
+";
+        let mut case = parse(input);
+        assert_eq!(execute(&mut case, &EchoDB, expected).await, expected);
+
+        // Other differences are written as is, and the next query isn't affected
+        let mut case = parse(input);
+        let expected = expected.replace("select NAME from T;", "select id from t;");
+        assert_eq!(
+            execute(&mut case, &EchoDB, &expected).await,
+            "-- SQLNESS CASE_INSENSITIVE
+SELECT Name FROM t;
+
+SELECT Name FROM t;
+
+SELECT Id;
+
+SELECT Id;
+
+"
+        );
+        // Without the interceptor letter case matters
+        let mut case = parse("SELECT Id;\n");
+        let expected = "SELECT Id;\n\nSELECT ID;\n\n";
+        assert_ne!(execute(&mut case, &EchoDB, expected).await, expected);
+    }
+
+    #[tokio::test]
+    async fn contains() {
+        let input = "-- SQLNESS CONTAINS IndexScan
+-- SQLNESS NOT_CONTAINS SeqScan
+SELECT 'IndexScan on t';
+SELECT 1;
+";
+        let recorded = "-- SQLNESS CONTAINS IndexScan
+-- SQLNESS NOT_CONTAINS SeqScan
+SELECT 'IndexScan on t';
+
+IndexScan on t (cost=1)
+
+SELECT 1;
+
+SELECT 1;
+
+";
+        // The recorded result is kept
+        let mut case = parse(input);
+        assert_eq!(execute(&mut case, &EchoDB, recorded).await, recorded);
+        // The full result is recorded for the first time
+        let mut case = parse(input);
+        assert_eq!(
+            execute(&mut case, &EchoDB, "").await,
+            recorded.replace("IndexScan on t (cost=1)", "SELECT 'IndexScan on t';")
+        );
+
+        for input in [
+            "-- SQLNESS CONTAINS SeqScan\nSELECT 'IndexScan';\n",
+            "-- SQLNESS NOT_CONTAINS Index\nSELECT 'IndexScan';\n",
+        ] {
+            let mut case = parse(input);
+            let err = case.execute(&EchoDB, &mut vec![], "").await.err().unwrap();
+            assert!(
+                matches!(err, SqlnessError::UnexpectedResult { .. }),
+                "{err}"
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn expect_error() {
+        let mut case = parse(
+            "-- SQLNESS EXPECT_ERROR\nSELECT error;\n-- SQLNESS EXPECT_ERROR 42P01\nSELECT error;\n",
+        );
+        assert_eq!(
+            execute(&mut case, &FailingDB, "").await,
+            "-- SQLNESS EXPECT_ERROR
+SELECT error;
+
+-- EXPECTED ERROR
+
+-- SQLNESS EXPECT_ERROR 42P01
+SELECT error;
+
+-- EXPECTED ERROR
+
+"
+        );
+
+        for input in [
+            "-- SQLNESS EXPECT_ERROR\nSELECT 1;\n",
+            "-- SQLNESS EXPECT_ERROR 42000\nSELECT error;\n",
+        ] {
+            let mut case = parse(input);
+            let err = case
+                .execute(&FailingDB, &mut vec![], "")
+                .await
+                .err()
+                .unwrap();
+            assert!(
+                matches!(err, SqlnessError::UnexpectedResult { .. }),
+                "{err}"
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn hide_result() {
+        let mut case = parse("-- SQLNESS HIDE\nINSERT 1;\nSELECT 1;\n");
+        assert_eq!(
+            execute(&mut case, &FailingDB, "").await,
+            "-- SQLNESS HIDE\nINSERT 1;\n\n-- HIDDEN\n\nSELECT 1;\n\nok\n\n"
+        );
+
+        let mut case = parse("-- SQLNESS HIDE\nINSERT error;\n");
+        let err = case
+            .execute(&FailingDB, &mut vec![], "")
+            .await
+            .err()
+            .unwrap();
+        assert!(err.to_string().contains("query failed"), "{err}");
+    }
+
+    #[tokio::test]
+    async fn warmup() {
+        let mut case = parse(
+            "-- SQLNESS WARMUP\nSELECT error;\n-- SQLNESS WARMUP\n-- SQLNESS EXPECT_ERROR\nSELECT 1;\nSELECT 2;\n",
+        );
+        assert_eq!(
+            execute(&mut case, &FailingDB, "").await,
+            "-- SQLNESS WARMUP\nSELECT error;\n\n-- WARMUP\n\n\
+             -- SQLNESS WARMUP\n-- SQLNESS EXPECT_ERROR\nSELECT 1;\n\n-- WARMUP\n\n\
+             SELECT 2;\n\nok\n\n"
+        );
+    }
+
+    #[tokio::test]
+    async fn max_duration() {
+        /// Sleep 50ms for each query.
+        struct SlowDB;
+
+        #[async_trait]
+        impl Database for SlowDB {
+            async fn query(&self, _: QueryContext, _: String) -> Box<dyn Display> {
+                tokio::time::sleep(Duration::from_millis(50)).await;
+                Box::new("ok")
+            }
+        }
+
+        let mut case = parse(
+            "-- SQLNESS MAX_DURATION 10s\nSELECT 1;\nSELECT 2;\n-- SQLNESS MAX_DURATION 10ms\nSELECT 3;\n",
+        );
+        let err = case.execute(&SlowDB, &mut vec![], "").await.err().unwrap();
+        assert!(
+            matches!(&err, SqlnessError::SlowQuery { query, limit, .. }
+                if query == "SELECT 3;" && *limit == Duration::from_millis(10)),
+            "{err}"
+        );
+
+        let durations = case.query_durations();
+        assert_eq!(
+            durations
+                .iter()
+                .map(|d| d.query.as_str())
+                .collect::<Vec<_>>(),
+            vec!["SELECT 1;", "SELECT 3;"]
+        );
+        assert!(durations
+            .iter()
+            .all(|d| d.duration >= Duration::from_millis(50)));
+    }
+
+    #[tokio::test]
+    async fn format_result() {
+        let mut case = parse("-- SQLNESS FORMAT csv\nSELECT 1;\nSELECT 2;\n");
+        assert_eq!(
+            execute(&mut case, &RowsDB, "").await,
+            "-- SQLNESS FORMAT csv
+SELECT 1;
+
+query
+SELECT 1;
+
+SELECT 2;
+
++-----------+
+| query     |
++-----------+
+| SELECT 2; |
++-----------+
+
+"
+        );
+    }
+
+    #[tokio::test]
+    async fn repeat_query() {
+        let mut case = parse("-- SQLNESS REPEAT 3\nSELECT 1;\n");
+        let db = CountingDB::default();
+        assert_eq!(
+            execute(&mut case, &db, "").await,
+            "-- SQLNESS REPEAT 3\nSELECT 1;\n\n3\n\n"
+        );
+
+        let mut case = parse("-- SQLNESS REPEAT 3\nSELECT error;\n");
+        let err = case
+            .execute(&FailingDB, &mut vec![], "")
+            .await
+            .err()
+            .unwrap();
+        assert!(err.to_string().contains("at iteration 1 of 3"), "{err}");
+    }
+
+    #[tokio::test]
+    async fn structured_result() {
+        let mut case = parse("SELECT 1;\n");
+        assert_eq!(
+            execute(&mut case, &RowsDB, "").await,
+            "SELECT 1;
+
++-----------+
+| query     |
++-----------+
+| SELECT 1; |
++-----------+
+
+"
+        );
+    }
+
+    #[tokio::test]
+    async fn result_filters() {
+        let cfg = ConfigBuilder::default()
+            .case_dir(String::new())
+            .result_filters(vec![
+                (
+                    Regex::new(r"\d{4}-\d{2}-\d{2}").unwrap(),
+                    "<DATE>".to_string(),
+                ),
+                (Regex::new("<DATE>").unwrap(), "<TIME>".to_string()),
+            ])
+            .build()
+            .unwrap();
+        let input = "-- SQLNESS REPLACE TIME NOW\nSELECT '2024-01-01';\n";
+        let mut case = TestCase::from_reader(Path::new("test"), input.as_bytes(), &cfg).unwrap();
+
+        // global filters are applied in order, then the local one
+        assert_eq!(
+            execute(&mut case, &EchoDB, "").await,
+            "-- SQLNESS REPLACE TIME NOW\nSELECT '2024-01-01';\n\nSELECT '<NOW>';\n\n"
+        );
+    }
+
+    /// Write files under a clean temp directory and return its path.
+    fn write_files(name: &str, files: &[(&str, &str)]) -> PathBuf {
+        let root = std::env::temp_dir().join(format!("sqlness-case-{name}-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&root);
+        for (path, content) in files {
+            let path = root.join(path);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, content).unwrap();
+        }
+        root
+    }
+
+    #[tokio::test]
+    async fn include_file() {
+        let root = write_files(
+            "include",
+            &[
+                (
+                    "main.sql",
+                    "-- SQLNESS INCLUDE common/setup.sql\nSELECT 2;\n",
+                ),
+                (
+                    "common/setup.sql",
+                    "CREATE 1;\n-- SQLNESS INCLUDE data.sql\n",
+                ),
+                ("common/data.sql", "INSERT 1;\n"),
+            ],
+        );
+        let cfg = ConfigBuilder::default()
+            .case_dir(String::new())
+            .build()
+            .unwrap();
+        let mut case = TestCase::from_file(root.join("main.sql"), &cfg).unwrap();
+
+        // included queries are executed but not recorded
+        let db = CountingDB::default();
+        assert_eq!(
+            execute(&mut case, &db, "").await,
+            "-- SQLNESS INCLUDE common/setup.sql\nSELECT 2;\n\n3\n\n"
+        );
+    }
+
+    #[tokio::test]
+    async fn source_file() {
+        let root = write_files(
+            "source",
+            &[
+                (
+                    "main.sql",
+                    "CREATE 1;\n\n-- load data\n-- SQLNESS SOURCE gen/data.sql\nSELECT 2;\n",
+                ),
+                (
+                    "gen/data.sql",
+                    "-- SQLNESS INCLUDE setup.sql\nINSERT 1;\nINSERT 2;\n",
+                ),
+                ("gen/setup.sql", "SET 1;\n"),
+                (
+                    "bad.sql",
+                    "-- SQLNESS HIDE\n-- SQLNESS SOURCE gen/data.sql\nSELECT 1;\n",
+                ),
+                ("missing.sql", "-- SQLNESS SOURCE none.sql\n"),
+            ],
+        );
+        let cfg = ConfigBuilder::default()
+            .case_dir(String::new())
+            .build()
+            .unwrap();
+        let mut case = TestCase::from_file(root.join("main.sql"), &cfg).unwrap();
+
+        // sourced queries are recorded with their results
+        let db = CountingDB::default();
+        assert_eq!(
+            execute(&mut case, &db, "").await,
+            "CREATE 1;\n\n1\n\n-- load data\n-- SQLNESS SOURCE gen/data.sql\n\
+             -- SQLNESS INCLUDE setup.sql\nINSERT 1;\n\n3\n\nINSERT 2;\n\n4\n\nSELECT 2;\n\n5\n\n"
+        );
+
+        let err = TestCase::from_file(root.join("bad.sql"), &cfg)
+            .err()
+            .unwrap();
+        assert!(err.to_string().contains("SOURCE"), "{err}");
+        let err = TestCase::from_file(root.join("missing.sql"), &cfg)
+            .err()
+            .unwrap();
+        let msg = err.to_string();
+        assert!(
+            msg.contains("missing.sql") && msg.contains("none.sql"),
+            "{msg}"
+        );
+    }
+
+    #[test]
+    fn include_errors() {
+        let root = write_files(
+            "include-errors",
+            &[
+                ("a.sql", "-- SQLNESS INCLUDE b.sql\n"),
+                ("b.sql", "SELECT 1;\n-- SQLNESS INCLUDE a.sql\n"),
+                ("missing.sql", "-- SQLNESS INCLUDE none.sql\n"),
+            ],
+        );
+        let cfg = ConfigBuilder::default()
+            .case_dir(String::new())
+            .build()
+            .unwrap();
+
+        let err = TestCase::from_file(root.join("a.sql"), &cfg).err().unwrap();
+        assert!(err.to_string().contains("Cyclic include"), "{err}");
+        let err = TestCase::from_file(root.join("missing.sql"), &cfg)
+            .err()
+            .unwrap();
+        assert!(err.to_string().contains("none.sql"), "{err}");
+    }
+
+    #[tokio::test]
+    async fn stream_stops_at_first_difference() {
+        /// Stream lines of numbers and count how many are read.
+        #[derive(Default)]
+        struct StreamDB {
+            read: std::sync::atomic::AtomicUsize,
+        }
+
+        #[async_trait]
+        impl Database for StreamDB {
+            async fn query(&self, _: QueryContext, _: String) -> Box<dyn Display> {
+                unreachable!()
+            }
+
+            fn query_stream(
+                &self,
+                _: QueryContext,
+                _: String,
+            ) -> Option<futures::stream::BoxStream<'_, String>> {
+                let lines = futures::stream::iter(1..=1000).map(|i| {
+                    self.read.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+                    if i == 1000 {
+                        i.to_string()
+                    } else {
+                        format!("{i}\n")
+                    }
+                });
+                Some(lines.boxed())
+            }
+        }
+
+        let all = (1..=1000)
+            .map(|i| i.to_string())
+            .collect::<Vec<_>>()
+            .join("\n");
+        let header = "-- SQLNESS STREAM\nSELECT n;\n\n";
+        let run = |expected: String| async move {
+            let mut case = parse(header);
+            let db = StreamDB::default();
+            let result = execute(&mut case, &db, &expected).await;
+            (result, db.read.into_inner())
+        };
+
+        let expected = format!("{header}{all}\n\n");
+        assert_eq!(run(expected.clone()).await, (expected, 1000));
+
+        let (result, read) = run(format!("{header}{}\n\n", all.replacen("3\n", "x\n", 1))).await;
+        assert_eq!(
+            result,
+            format!("{header}1\n2\n3\n\n... (stream stopped at the first difference)\n\n")
+        );
+        assert_eq!(read, 3);
+    }
+
+    #[test]
+    fn stream_with_result_interceptors() {
+        let parse_err = |input: &str, cfg: &Config| match TestCase::from_reader(
+            Path::new("test"),
+            input.as_bytes(),
+            cfg,
+        ) {
+            Err(SqlnessError::ParseCase { source, .. }) => source.to_string(),
+            Err(err) => panic!("unexpected error: {err}"),
+            Ok(_) => panic!("expect {input} to fail"),
+        };
+        let cfg = ConfigBuilder::default()
+            .case_dir(String::new())
+            .build()
+            .unwrap();
+        for input in [
+            "-- SQLNESS STREAM\n-- SQLNESS REPLACE a b\nSELECT 1;\n",
+            "-- SQLNESS SORT_RESULT\n-- SQLNESS STREAM\nSELECT 1;\n",
+        ] {
+            let err = parse_err(input, &cfg);
+            assert!(
+                err.contains("STREAM") && err.contains("Can't be combined"),
+                "{err}"
+            );
+        }
+        parse("-- SQLNESS STREAM\n-- SQLNESS TIMEOUT 1s\nSELECT 1;\n\n-- SQLNESS REPLACE a b\nSELECT 2;\n");
+
+        let cfg = ConfigBuilder::default()
+            .case_dir(String::new())
+            .max_result_bytes(Some(10))
+            .build()
+            .unwrap();
+        let err = parse_err("-- SQLNESS STREAM\nSELECT 1;\n", &cfg);
+        assert!(err.contains("max_result_bytes"), "{err}");
+    }
+
+    #[tokio::test]
+    async fn target_database() {
+        /// Return the database a query runs against.
+        struct MultiDB(bool);
+
+        #[async_trait]
+        impl Database for MultiDB {
+            async fn query(&self, context: QueryContext, _: String) -> Box<dyn Display> {
+                Box::new(context.database().unwrap_or("default").to_string())
+            }
+
+            fn supports_databases(&self) -> bool {
+                self.0
+            }
+        }
+
+        let cfg = ConfigBuilder::default()
+            .case_dir(String::new())
+            .build()
+            .unwrap();
+        let input = "-- SQLNESS DB analytics\nSELECT 1;\n\nSELECT 2;\n";
+        let mut case = TestCase::from_reader(Path::new("test"), input.as_bytes(), &cfg).unwrap();
+        assert_eq!(
+            execute(&mut case, &MultiDB(true), "").await,
+            "-- SQLNESS DB analytics\nSELECT 1;\n\nanalytics\n\nSELECT 2;\n\ndefault\n\n"
+        );
+
+        let mut case = TestCase::from_reader(Path::new("test"), input.as_bytes(), &cfg).unwrap();
+        let mut output = vec![];
+        let err = case
+            .execute(&MultiDB(false), &mut output, "")
+            .await
+            .unwrap_err();
+        assert!(
+            matches!(&err, SqlnessError::UnsupportedDatabase { database, .. } if database == "analytics"),
+            "{err}"
+        );
+    }
+
+    #[tokio::test]
+    async fn query_options() {
+        /// Return options of the query in key order.
+        struct OptDB;
+
+        #[async_trait]
+        impl Database for OptDB {
+            async fn query(&self, _: QueryContext, _: String) -> Box<dyn Display> {
+                unreachable!()
+            }
+
+            async fn query_with_opts(
+                &self,
+                _: QueryContext,
+                _: String,
+                opts: HashMap<String, String>,
+            ) -> Box<dyn Display> {
+                let mut opts = opts.into_iter().collect::<Vec<_>>();
+                opts.sort();
+                Box::new(format!("{opts:?}"))
+            }
+        }
+
+        let cfg = ConfigBuilder::default()
+            .case_dir(String::new())
+            .build()
+            .unwrap();
+        let input =
+            "-- SQLNESS OPT group=etl tag=a\n-- SQLNESS OPT tag=b\nSELECT 1;\n\nSELECT 2;\n";
+        let mut case = TestCase::from_reader(Path::new("test"), input.as_bytes(), &cfg).unwrap();
+        assert_eq!(
+            execute(&mut case, &OptDB, "").await,
+            "-- SQLNESS OPT group=etl tag=a\n-- SQLNESS OPT tag=b\nSELECT 1;\n\n\
+             [(\"group\", \"etl\"), (\"tag\", \"b\")]\n\nSELECT 2;\n\n[]\n\n"
+        );
+    }
+
+    #[tokio::test]
+    async fn connection_retries() {
+        /// Fail the first `failures` queries with a retryable error.
+        struct FlakyDB {
+            failures: usize,
+            calls: std::sync::atomic::AtomicUsize,
+        }
+
+        #[async_trait]
+        impl Database for FlakyDB {
+            async fn query(&self, _: QueryContext, _: String) -> Box<dyn Display> {
+                let calls = self
+                    .calls
+                    .fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+                if calls < self.failures {
+                    Box::new("ERROR: connection reset")
+                } else {
+                    Box::new("ok")
+                }
+            }
+
+            async fn query_result(&self, context: QueryContext, query: String) -> QueryResult {
+                match self.query(context, query).await.to_string() {
+                    error if error.starts_with("ERROR") => QueryResult::Error(error),
+                    result => QueryResult::Raw(result),
+                }
+            }
+
+            fn is_retryable(&self, error: &str) -> bool {
+                error.contains("connection")
+            }
+        }
+
+        let run = |failures, retries| async move {
+            let cfg = ConfigBuilder::default()
+                .case_dir(String::new())
+                .connection_retries(retries)
+                .retry_backoff(Duration::from_millis(1))
+                .build()
+                .unwrap();
+            let mut case =
+                TestCase::from_reader(Path::new("test"), "SELECT 1;\n".as_bytes(), &cfg).unwrap();
+            let db = FlakyDB {
+                failures,
+                calls: Default::default(),
+            };
+            let result = execute(&mut case, &db, "").await;
+            (result, db.calls.into_inner())
+        };
+
+        assert_eq!(run(2, 2).await, ("SELECT 1;\n\nok\n\n".to_string(), 3));
+        assert_eq!(
+            run(3, 2).await,
+            ("SELECT 1;\n\nERROR: connection reset\n\n".to_string(), 3)
+        );
+        // Not retried by default
+        assert_eq!(
+            run(1, 0).await,
+            ("SELECT 1;\n\nERROR: connection reset\n\n".to_string(), 1)
+        );
+    }
+
+    #[tokio::test]
+    async fn max_result_bytes() {
+        /// Return 100 lines of `x`s.
+        struct HugeDB;
+
+        #[async_trait]
+        impl Database for HugeDB {
+            async fn query(&self, _: QueryContext, _: String) -> Box<dyn Display> {
+                Box::new(vec!["xxxxxxxxx"; 100].join("\n"))
+            }
+        }
+
+        let parse = |warn| {
+            let cfg = ConfigBuilder::default()
+                .case_dir(String::new())
+                .max_result_bytes(Some(25))
+                .warn_oversized_results(warn)
+                .build()
+                .unwrap();
+            TestCase::from_reader(Path::new("test"), "SELECT x;\n".as_bytes(), &cfg).unwrap()
+        };
+
+        let err = parse(false)
+            .execute(&HugeDB, &mut vec![], "")
+            .await
+            .err()
+            .unwrap();
+        assert!(
+            matches!(
+                err,
+                SqlnessError::ResultTooLarge {
+                    size: 999,
+                    limit: 25,
+                    ..
+                }
+            ),
+            "{err}"
+        );
+
+        assert_eq!(
+            execute(&mut parse(true), &HugeDB, "").await,
+            "SELECT x;\n\nxxxxxxxxx\nxxxxxxxxx\nxxxxx\n... (truncated, 999 bytes)\n\n"
+        );
+    }
+
+    #[tokio::test]
+    async fn strip_ansi() {
+        /// Return a colored status.
+        struct ColorDB;
+
+        #[async_trait]
+        impl Database for ColorDB {
+            async fn query(&self, _: QueryContext, _: String) -> Box<dyn Display> {
+                Box::new("\x1b[1;32mrunning\x1b[0m (1 job)")
+            }
+        }
+
+        let mut case = parse("-- SQLNESS STRIP_ANSI\nSELECT status;\n\nSELECT status;\n");
+        assert_eq!(
+            execute(&mut case, &ColorDB, "").await,
+            "-- SQLNESS STRIP_ANSI\nSELECT status;\n\nrunning (1 job)\n\n\
+             SELECT status;\n\n\x1b[1;32mrunning\x1b[0m (1 job)\n\n"
+        );
+
+        let cfg = ConfigBuilder::default()
+            .case_dir(String::new())
+            .strip_ansi(true)
+            .build()
+            .unwrap();
+        let mut case =
+            TestCase::from_reader(Path::new("test"), "SELECT status;\n".as_bytes(), &cfg).unwrap();
+        assert_eq!(
+            execute(&mut case, &ColorDB, "").await,
+            "SELECT status;\n\nrunning (1 job)\n\n"
+        );
+    }
+
+    #[tokio::test]
+    async fn shell_command() {
+        let input = "-- SQLNESS SHELL printf 'a\\nb\\n'\nSELECT 1;\n";
+        let mut case = parse(input);
+        let err = case.execute(&EchoDB, &mut vec![], "").await.unwrap_err();
+        assert!(matches!(err, SqlnessError::ShellNotAllowed { .. }), "{err}");
+
+        let cfg = ConfigBuilder::default()
+            .case_dir(String::new())
+            .allow_shell(true)
+            .build()
+            .unwrap();
+        let parse =
+            |input: &str| TestCase::from_reader(Path::new("test"), input.as_bytes(), &cfg).unwrap();
+        let mut case = parse(input);
+        assert_eq!(
+            execute(&mut case, &EchoDB, "").await,
+            format!("{input}\na\nb\n\nSELECT 1;\n\n")
+        );
+
+        let mut case = parse("-- SQLNESS SHELL echo oops >&2; false\nSELECT 1;\n");
+        let err = case.execute(&EchoDB, &mut vec![], "").await.unwrap_err();
+        assert!(
+            matches!(&err, SqlnessError::ShellCommand { msg, .. } if msg.contains("oops")),
+            "{err}"
+        );
+
+        let mut case =
+            parse("-- SQLNESS TIMEOUT 100ms\n-- SQLNESS SHELL exec sleep 5\nSELECT 1;\n");
+        let err = case.execute(&EchoDB, &mut vec![], "").await.unwrap_err();
+        assert!(
+            matches!(&err, SqlnessError::Timeout { query, .. } if query == "exec sleep 5"),
+            "{err}"
+        );
+    }
+
+    fn parse(case: &str) -> TestCase {
+        let cfg = ConfigBuilder::default()
+            .case_dir(String::new())
+            .build()
+            .unwrap();
+        TestCase::from_reader(Path::new("test"), case.as_bytes(), &cfg).unwrap()
+    }
+
+    async fn execute(case: &mut TestCase, db: &dyn Database, expected: &str) -> String {
+        let mut output = vec![];
+        case.execute(db, &mut output, expected).await.unwrap();
+        String::from_utf8(output).unwrap()
+    }
+
+    #[tokio::test]
+    async fn matrix_template_result() {
+        let mut case = parse("-- SQLNESS TEMPLATE v a,b\nSELECT '{v}';\n");
+
+        let db = CountingDB::default();
+        assert_eq!(
+            execute(&mut case, &db, "").await,
+            "-- SQLNESS TEMPLATE v a,b\nSELECT '{v}';\n\n-- v=a\n1\n\n-- v=b\n2\n\n"
+        );
+    }
+
+    #[tokio::test]
+    async fn passthrough() {
+        let cfg = ConfigBuilder::default()
+            .case_dir(String::new())
+            .passthrough_prefix(Some("\\".to_string()))
+            .build()
+            .unwrap();
+        let input = "\\d t\nSELECT 1,\n\\x;\n-- SQLNESS REPLACE t x\n\\dt t\n";
+        let mut case = TestCase::from_reader(Path::new("test"), input.as_bytes(), &cfg).unwrap();
+        assert_eq!(
+            execute(&mut case, &EchoDB, "").await,
+            "\\d t
+
+\\d t
+
+SELECT 1,
+\\x;
+
+SELECT 1,
+\\x;
+
+-- SQLNESS REPLACE t x
+\\dt t
+
+\\dx x
+
+"
+        );
+    }
+
+    #[tokio::test]
+    async fn custom_delimiter() {
+        let mut case = parse(
+            "-- SQLNESS DELIMITER //
+CREATE PROCEDURE p()
+BEGIN
+  SELECT 1;
+END//
+-- SQLNESS DELIMITER ;
+SELECT 2;
+",
+        );
+        assert_eq!(case.queries.len(), 2);
+
+        assert_eq!(
+            execute(&mut case, &EchoDB, "").await,
+            "-- SQLNESS DELIMITER //
+CREATE PROCEDURE p()
+BEGIN
+  SELECT 1;
+END//
+
+CREATE PROCEDURE p()
+BEGIN
+  SELECT 1;
+END
+
+-- SQLNESS DELIMITER ;
+SELECT 2;
+
+SELECT 2;
+
+"
+        );
+    }
+
+    #[test]
+    fn missing_delimiter() {
+        let cfg = ConfigBuilder::default()
+            .case_dir(String::new())
+            .build()
+            .unwrap();
+        let err = TestCase::from_reader(
+            Path::new("test"),
+            "SELECT 1;\n-- SQLNESS DELIMITER\n".as_bytes(),
+            &cfg,
+        )
+        .err()
+        .unwrap();
+        assert!(matches!(err, SqlnessError::ParseCase { line: 2, .. }));
+    }
+
+    #[tokio::test]
+    async fn skip_query() {
+        std::env::set_var("SQLNESS_TEST_SKIP_QUERY", "1");
+        let mut case =
+            parse("-- SQLNESS SKIP_IF SQLNESS_TEST_SKIP_QUERY\nSELECT 1;\n\nSELECT 2;\n");
+        assert!(case.skip_condition().is_none());
+
+        let db = CountingDB::default();
+        assert_eq!(
+            execute(&mut case, &db, "").await,
+            "-- SQLNESS SKIP_IF SQLNESS_TEST_SKIP_QUERY\nSELECT 1;\n\n-- SKIPPED\n\nSELECT 2;\n\n1\n\n"
+        );
+    }
+
+    #[test]
+    fn only_envs() {
+        let case = parse("-- SQLNESS ONLY local\n-- SQLNESS ONLY remote cluster\nSELECT 1;\n");
+        assert_eq!(case.only_envs("local"), None);
+        assert_eq!(case.only_envs("cluster"), None);
+        assert_eq!(
+            case.only_envs("standalone").unwrap(),
+            ["local", "remote", "cluster"]
+        );
+        assert_eq!(parse("SELECT 1;\n").only_envs("local"), None);
+
+        let cfg = ConfigBuilder::default()
+            .case_dir(String::new())
+            .build()
+            .unwrap();
+        for input in ["-- SQLNESS ONLY\n", "SELECT 1;\n-- SQLNESS ONLY local\n"] {
+            assert!(TestCase::from_reader(Path::new("test"), input.as_bytes(), &cfg).is_err());
+        }
+    }
+
+    #[test]
+    fn skip_case() {
+        std::env::set_var("SQLNESS_TEST_SKIP_CASE", "1");
+        let case = parse("-- SQLNESS SKIP_IF SQLNESS_TEST_SKIP_CASE=1\n\nSELECT 1;\n");
+        assert_eq!(
+            case.skip_condition().unwrap().to_string(),
+            "SQLNESS_TEST_SKIP_CASE=1"
+        );
+
+        let case = parse("-- SQLNESS SKIP_IF SQLNESS_TEST_SKIP_CASE=2\n\nSELECT 1;\n");
+        assert!(case.skip_condition().is_none());
+    }
+
+    #[tokio::test]
+    async fn require_features() {
+        let features = |features: &str| {
+            Arc::new([(require::FEATURES.to_string(), features.to_string())].into())
+        };
+        let mut case = parse("-- SQLNESS REQUIRE cte\n\nSELECT 1;\n");
+        case.set_metadata(features("json,cte"));
+        assert_eq!(case.missing_feature(), None);
+        case.set_metadata(features("json"));
+        assert_eq!(case.missing_feature(), Some("cte"));
+
+        let mut case = parse("-- SQLNESS REQUIRE cte\nSELECT 1;\n\nSELECT 2;\n");
+        case.set_metadata(features("json"));
+        assert_eq!(case.missing_feature(), None);
+        let db = CountingDB::default();
+        assert_eq!(
+            execute(&mut case, &db, "").await,
+            "-- SQLNESS REQUIRE cte\nSELECT 1;\n\n-- SKIPPED\n\nSELECT 2;\n\n1\n\n"
+        );
+    }
+
+    #[tokio::test]
+    async fn retry_until_matched() {
+        let mut case = parse("-- SQLNESS RETRY 5\nSELECT 1;\n");
+        let expected = "-- SQLNESS RETRY 5\nSELECT 1;\n\n3\n\n";
+
+        let db = CountingDB::default();
+        assert_eq!(execute(&mut case, &db, expected).await, expected);
+        assert_eq!(db.count.load(Ordering::SeqCst), 3);
+    }
+
+    #[tokio::test]
+    async fn retry_exhausted() {
+        let mut case = parse("-- SQLNESS RETRY 2\nSELECT 1;\n");
+        let expected = "-- SQLNESS RETRY 2\nSELECT 1;\n\n3\n\n";
+
+        let db = CountingDB::default();
+        assert_eq!(
+            execute(&mut case, &db, expected).await,
+            "-- SQLNESS RETRY 2\nSELECT 1;\n\n2\n\n"
+        );
+        assert_eq!(db.count.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn retry_only_annotated_query() {
+        let mut case = parse("SELECT 1;\n-- SQLNESS RETRY 5\nSELECT 2;\n");
+        let expected = "SELECT 1;\n\n1\n\n-- SQLNESS RETRY 5\nSELECT 2;\n\n4\n\n";
+
+        let db = CountingDB::default();
+        assert_eq!(execute(&mut case, &db, expected).await, expected);
+        assert_eq!(db.count.load(Ordering::SeqCst), 4);
+
+        // no retry once an earlier query mismatches
+        let db = CountingDB::default();
+        db.count.store(1, Ordering::SeqCst);
+        execute(&mut case, &db, expected).await;
+        assert_eq!(db.count.load(Ordering::SeqCst), 3);
     }
 }
@@ -1,7 +1,188 @@
 // Copyright 2022 CeresDB Project Authors. Licensed under Apache-2.0.
 
-use crate::interceptor::Registry;
+use std::{collections::HashMap, path::Path, time::Duration};
+
+use crate::{
+    error::Result,
+    interceptor::{InterceptorFactoryRef, Registry},
+    EventCallback, ResultFormat, SqlnessError,
+};
 use derive_builder::Builder;
+use regex::Regex;
+use serde::de::DeserializeOwned;
+
+/// Read an environment config file like `config.toml`, which is passed to
+/// [`EnvController::start`], and deserialize it.
+///
+/// `${VAR}` in the file is replaced by the environment variable `VAR` before
+/// parsing, or by `default` in the form of `${VAR:-default}` when `VAR` is unset.
+/// A variable without default must be set, otherwise
+/// [`SqlnessError::UnsetEnvVar`] is returned.
+///
+/// ``` toml
+/// addr = "${DB_HOST:-127.0.0.1}:${DB_PORT}"
+/// ```
+///
+/// [`EnvController::start`]: crate::EnvController::start
+pub fn load_env_config<T: DeserializeOwned>(path: &Path) -> Result<T> {
+    let content = std::fs::read_to_string(path).map_err(|source| SqlnessError::ReadPath {
+        source,
+        path: path.to_path_buf(),
+    })?;
+    let content = interpolate_env(&content).map_err(|name| SqlnessError::UnsetEnvVar {
+        name,
+        file: path.to_path_buf(),
+    })?;
+    toml::from_str(&content).map_err(|source| SqlnessError::ParseToml {
+        source,
+        file: path.to_path_buf(),
+    })
+}
+
+/// Replace `${VAR}` and `${VAR:-default}` by environment variables, return the
+/// name of the first unset variable without default.
+fn interpolate_env(content: &str) -> std::result::Result<String, String> {
+    let mut result = String::with_capacity(content.len());
+    let mut rest = content;
+    while let Some(start) = rest.find("${") {
+        let Some(len) = rest[start..].find('}') else {
+            break;
+        };
+        let reference = &rest[start + 2..start + len];
+        let (name, default) = match reference.split_once(":-") {
+            Some((name, default)) => (name, Some(default)),
+            None => (reference, None),
+        };
+        let value = match (std::env::var(name), default) {
+            (Ok(value), _) => value,
+            (Err(_), Some(default)) => default.to_string(),
+            (Err(_), None) => return Err(name.to_string()),
+        };
+        result.push_str(&rest[..start]);
+        result.push_str(&value);
+        rest = &rest[start + len + 1..];
+    }
+    result.push_str(rest);
+
+    Ok(result)
+}
+
+/// Settings of [`Config`] overridden by an environment config file, the other keys
+/// of the file are left to [`EnvController::start`].
+///
+/// ``` toml
+/// # cases of this environment are run 4 at a time
+/// parallelism = 4
+/// # features checked by `REQUIRE`
+/// features = ["json", "cte"]
+/// ```
+///
+/// Only `.toml` files are read, config files in other formats don't override
+/// anything. The file belongs to the [`EnvController`], so it's read leniently: a
+/// file that isn't valid TOML, even after replacing the environment variables that
+/// are set, overrides nothing and a warning is printed. Invalid values of these keys
+/// are errors.
+///
+/// [`EnvController::start`]: crate::EnvController::start
+/// [`EnvController`]: crate::EnvController
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub(crate) struct EnvOverrides {
+    /// See [`Config::parallelism`].
+    pub(crate) parallelism: Option<usize>,
+    /// Features of the environment, see [`RequireInterceptor`].
+    ///
+    /// [`RequireInterceptor`]: crate::interceptor::require::RequireInterceptor
+    pub(crate) features: Vec<String>,
+}
+
+impl EnvOverrides {
+    pub(crate) fn load(path: &Path) -> Result<Self> {
+        if path.extension().is_none_or(|ext| ext != "toml") {
+            return Ok(Self::default());
+        }
+        let content = std::fs::read_to_string(path).map_err(|source| SqlnessError::ReadPath {
+            source,
+            path: path.to_path_buf(),
+        })?;
+        // Variables the controller resolves its own way may be unset here.
+        let content = interpolate_env(&content).unwrap_or(content);
+        let config = match toml::from_str::<toml::Value>(&content) {
+            Ok(config) => config,
+            Err(e) => {
+                println!(
+                    "Config file {} isn't valid TOML, it doesn't override settings, error:{e}",
+                    path.display()
+                );
+                return Ok(Self::default());
+            }
+        };
+        let parallelism = match config.get("parallelism") {
+            None => None,
+            Some(value) => match value.as_integer() {
+                Some(parallelism) if parallelism > 0 => Some(parallelism as usize),
+                _ => {
+                    return Err(SqlnessError::InvalidEnvConfig {
+                        file: path.to_path_buf(),
+                        msg: format!("expect a positive integer for parallelism, got {value}"),
+                    })
+                }
+            },
+        };
+
+        let features = match config.get("features") {
+            None => vec![],
+            Some(value) => value
+                .as_array()
+                .and_then(|values| {
+                    values
+                        .iter()
+                        .map(|v| {
+                            v.as_str()
+                                .filter(|f| is_feature_name(f))
+                                .map(str::to_string)
+                        })
+                        .collect::<Option<Vec<_>>>()
+                })
+                .ok_or_else(|| SqlnessError::InvalidEnvConfig {
+                    file: path.to_path_buf(),
+                    msg: format!("expect an array of feature names for features, got {value}"),
+                })?,
+        };
+
+        Ok(Self {
+            parallelism,
+            features,
+        })
+    }
+}
+
+/// A feature name is not empty, and has no whitespace or `,`.
+fn is_feature_name(name: &str) -> bool {
+    !name.is_empty() && !name.contains(|c: char| c.is_whitespace() || c == ',')
+}
+
+/// How cases are isolated from fixtures left by a failed case, see
+/// [`Config::case_isolation`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum CaseIsolation {
+    /// Nothing is done, a failed case may leave tables created before the failure.
+    #[default]
+    None,
+    /// Run each case in a transaction, which is committed if the case passes and
+    /// rolled back otherwise, when [`Database::supports_transactions`]. Otherwise
+    /// it falls back to [`CaseIsolation::Cleanup`].
+    ///
+    /// Statements that can't run in a transaction, like DDL on some databases, or
+    /// queries with `TXN`, break the isolation.
+    ///
+    /// [`Database::supports_transactions`]: crate::Database::supports_transactions
+    Transaction,
+    /// Call [`EnvController::cleanup_after_case`] after a failed case, which drops
+    /// what the case created.
+    ///
+    /// [`EnvController::cleanup_after_case`]: crate::EnvController::cleanup_after_case
+    Cleanup,
+}
 
 /// Configurations of [`Runner`].
 ///
@@ -9,38 +190,405 @@ use derive_builder::Builder;
 #[derive(Builder)]
 pub struct Config {
     pub case_dir: String,
+    /// More directories of cases, run like [`Config::case_dir`] in the same run and
+    /// report.
+    ///
+    /// Environment directories with the same name are merged: the environment is
+    /// started once, with the config file of the first directory that has it, and
+    /// runs cases of all directories, sorted by their path relative to the
+    /// environment directory. Cases with the same relative path in several
+    /// directories are all run, and have the same name in the report. It's ignored
+    /// by [`Runner::new_with_cases`]. Default value: empty
+    ///
+    /// [`Runner::new_with_cases`]: crate::Runner::new_with_cases
+    #[builder(default = "Config::default_extra_case_dirs()")]
+    pub extra_case_dirs: Vec<String>,
     /// Default value: `sql`
     #[builder(default = "Config::default_test_case_extension()")]
     pub test_case_extension: String,
-    /// Default value: `result`
+    /// Extension of result files, eg. `out` or `expected`. Default value: `result`
     #[builder(default = "Config::default_result_extension()")]
     pub result_extension: String,
-    /// Default value: `-- SQLNESS`
+    /// Keep one result file for each environment, named like `basic.local.result`
+    /// for the case `basic.sql` in environment `local`, instead of `basic.result`.
+    /// Default `false`.
+    #[builder(default = "Config::default_per_env_results()")]
+    pub per_env_results: bool,
+    /// Lines starting with this are comments, which are kept in the result file
+    /// but not executed. Default value: `--`
+    #[builder(default = "Config::default_comment_prefix()")]
+    pub comment_prefix: String,
+    /// Lines starting with this declare interceptors, eg. `# sqlness` or
+    /// `/* SQLNESS */`. They are also treated as comments even if this doesn't
+    /// start with [`comment_prefix`]. Default value: `-- SQLNESS`
+    ///
+    /// Both prefixes are only recognized at the very start of a line, a prefix
+    /// appearing mid-line or after indentation is part of the query.
+    ///
+    /// [`comment_prefix`]: Config#structfield.comment_prefix
     #[builder(default = "Config::default_interceptor_prefix()")]
     pub interceptor_prefix: String,
-    /// Default value: `config.toml`
+    /// Name of the config file in each environment directory, which is passed to
+    /// [`EnvController::start`], eg. `config.ci.toml`. Default value: `config.toml`
+    ///
+    /// [`EnvController::start`]: crate::EnvController::start
     #[builder(default = "Config::default_env_config_file()")]
     pub env_config_file: String,
-    /// Fail this run as soon as one case fails if true
+    /// Whether every environment must have [`Config::env_config_file`]. If true, an
+    /// environment without it fails with [`SqlnessError::MissingEnvConfig`] instead
+    /// of starting with no config file. Default `false`.
+    ///
+    /// [`SqlnessError::MissingEnvConfig`]: crate::SqlnessError::MissingEnvConfig
+    #[builder(default = "Config::default_require_env_config()")]
+    pub require_env_config: bool,
+    /// Fail this run as soon as one environment can't be run if true, eg. its cases
+    /// can't be collected, the following environments aren't run then. Failed and
+    /// errored cases are still collected and reported. Default `true`.
+    ///
+    /// It's ignored with [`Config::parallel_envs`], all environments are already
+    /// running.
     #[builder(default = "Config::default_fail_fast()")]
     pub fail_fast: bool,
+    /// Stop this run at the first case whose result mismatches or which errors,
+    /// the following cases and environments aren't run. The environment is still
+    /// stopped before returning. Default `false`, all cases are run and reported.
+    ///
+    /// With [`Config::parallel_envs`] it only stops the cases of the failed
+    /// environment, the others are already running.
+    #[builder(default = "Config::default_fail_on_first_mismatch()")]
+    pub fail_on_first_mismatch: bool,
     /// Test only matched testcases, default `.*`
-    /// Env is prepended before filename, eg `{env}:{filename}`
+    /// Env is prepended before filename, eg `{env}:{filename}`. The path relative to
+    /// the environment directory is also tried, eg `{env}:{dir}/{filename}`.
+    /// File extension is not included. Unmatched cases are reported as filtered.
     #[builder(default = "Config::default_test_filter()")]
     pub test_filter: String,
+    /// Known flaky cases, matched like [`Config::test_filter`] against
+    /// `{env}:{dir}/{filename}`. They run normally, but a failure or error is
+    /// reported as [`CaseStatus::Quarantined`] and doesn't fail the run. A
+    /// quarantined case that passes is flagged by [`CaseReport::quarantined`] and
+    /// listed after its environment, to be removed from here. Default empty.
+    ///
+    /// [`CaseStatus::Quarantined`]: crate::CaseStatus::Quarantined
+    /// [`CaseReport::quarantined`]: crate::CaseReport#structfield.quarantined
+    #[builder(default = "Config::default_quarantine()")]
+    pub quarantine: Vec<Regex>,
+    /// Glob patterns of case files, relative to the environment directory. Patterns
+    /// starting with `!` exclude files, eg.
+    /// `["**/*.sql", "!helpers/**"]`. A file is a case if it has
+    /// [`Config::test_case_extension`], matches any including pattern and no
+    /// excluding one. `*` doesn't match `/`, while `**` matches any directories.
+    ///
+    /// Default empty, which is the same as `**/*.<test_case_extension>`, ie.
+    /// `**/*.sql`. Only excluding patterns is also relative to that.
+    #[builder(default = "Config::default_case_glob()")]
+    pub case_glob: Vec<String>,
     /// Test only matched env, default `.*`
     #[builder(default = "Config::default_env_filter()")]
     pub env_filter: String,
+    /// Only run these environments, empty for all of them. Default empty.
+    ///
+    /// Unlike [`Config::env_filter`], environments not included aren't started but
+    /// still reported, with all their cases skipped.
+    #[builder(default = "Config::default_include_envs()")]
+    pub include_envs: Vec<String>,
+    /// Don't run these environments, even if they are in [`Config::include_envs`].
+    /// Their cases are reported as skipped. Default empty.
+    #[builder(default = "Config::default_exclude_envs()")]
+    pub exclude_envs: Vec<String>,
+    /// Only run cases with one of these tags, empty for all cases. Default empty.
+    ///
+    /// Tags are added by `-- SQLNESS TAG <tag>...`. Cases not selected are reported
+    /// as skipped.
+    #[builder(default = "Config::default_include_tags()")]
+    pub include_tags: Vec<String>,
+    /// Don't run cases with any of these tags, even if they have a tag in
+    /// [`Config::include_tags`]. Default empty.
+    #[builder(default = "Config::default_exclude_tags()")]
+    pub exclude_tags: Vec<String>,
     /// Whether follow symbolic links when searching test case files.
     /// Defaults to "true" (follow symbolic links).
+    ///
+    /// When following, a link to a directory in [`Config::case_dir`] is an environment
+    /// as well, and inside an environment a directory is only walked once even if
+    /// it's linked multiple times, so link cycles are skipped.
     #[builder(default = "Config::default_follow_links()")]
     pub follow_links: bool,
+    /// Run cases of an environment in a random order instead of the sorted one, to
+    /// detect cases depending on each other. Default `false`.
+    ///
+    /// Cases are sorted by their path relative to the environment directory, and
+    /// shuffled then. Cases in the same directory are still run together.
+    #[builder(default = "Config::default_randomize()")]
+    pub randomize: bool,
+    /// Seed used to shuffle cases when [`Config::randomize`] is on. A random one is
+    /// picked if not set. The seed is printed at the start of a run, so the same
+    /// order can be reproduced by setting it here. Default `None`.
+    #[builder(default = "Config::default_seed()")]
+    pub seed: Option<u64>,
+    /// Only list cases to run, after applying [`Config::env_filter`] and
+    /// [`Config::test_filter`], without starting any environment. Every case is
+    /// reported as [`CaseStatus::Listed`], and no report file is written. Default
+    /// `false`.
+    ///
+    /// [`CaseStatus::Listed`]: crate::CaseStatus::Listed
+    #[builder(default = "Config::default_dry_run()")]
+    pub dry_run: bool,
+    /// Go on with other environments when one fails to start, ie. its config file
+    /// is missing (see [`Config::require_env_config`]), [`EnvController::start`]
+    /// panics (see [`Config::catch_panic`]) or it's not ready in
+    /// [`Config::startup_timeout`]. All its cases are reported as skipped, and
+    /// [`EnvReport::start_error`] tells the reason. Default `false`, the run
+    /// returns the error of starting it.
+    ///
+    /// [`EnvController::start`]: crate::EnvController::start
+    /// [`EnvReport::start_error`]: crate::EnvReport#structfield.start_error
+    #[builder(default = "Config::default_skip_unstartable_envs()")]
+    pub skip_unstartable_envs: bool,
     /// Interceptors used to pre-process input query and post-process query response
     #[builder(default = "Config::default_registry()")]
     pub interceptor_registry: Registry,
+    /// Accept new results instead of comparing them. Result files of mismatched
+    /// cases are overwritten and those cases are reported as updated rather than
+    /// failed. Default `false`.
+    #[builder(default = "Config::default_update_result()")]
+    pub update_result: bool,
+    /// Prompt `[u]pdate / [s]kip / [a]bort` after the diff of each mismatched case.
+    /// Its result file is only overwritten on update, and the case is reported as
+    /// updated. Otherwise the case fails with its result file untouched, and on
+    /// abort the remaining cases are skipped. Default `false`.
+    ///
+    /// It only takes effect when both stdin and stdout are terminals, otherwise
+    /// results are compared as usual so a CI run never waits for input.
+    #[builder(default = "Config::default_interactive()")]
+    pub interactive: bool,
+    /// Called with the progress of the run, like a case is started or finished, eg.
+    /// to show a progress bar. Default `None`.
+    ///
+    /// It's called in the task running cases, so it should return quickly, eg. by
+    /// sending the event to a channel, otherwise it blocks the run.
+    #[builder(default = "Config::default_on_event()")]
+    pub on_event: Option<EventCallback>,
+    /// Ignore trailing whitespace of each line and the difference between `\r\n`
+    /// and `\n` when comparing results. Both old and new results are normalized,
+    /// and the normalized result is written back. Default `false`.
+    #[builder(default = "Config::default_normalize_whitespace()")]
+    pub normalize_whitespace: bool,
+    /// Ignore lines starting with [`Config::comment_prefix`] when comparing results,
+    /// so human annotations can be added into result files. A matched result file
+    /// is left untouched to keep the annotations. Note that it also ignores comments
+    /// and interceptors of queries, and markers like `-- SKIPPED`. Default `false`.
+    #[builder(default = "Config::default_ignore_result_comments()")]
+    pub ignore_result_comments: bool,
+    /// Colorize the diff of mismatched results. Color is only emitted when stdout
+    /// is a terminal and `NO_COLOR` is not set, set this to `false` to disable it
+    /// unconditionally. Default `true`.
+    #[builder(default = "Config::default_diff_color()")]
+    pub diff_color: bool,
+    /// Number of unchanged lines shown around each change in the diff of
+    /// mismatched results. Default `3`.
+    #[builder(default = "Config::default_diff_context_lines()")]
+    pub diff_context_lines: usize,
+    /// Max duration of one query, the case fails with [`SqlnessError::Timeout`]
+    /// when exceeded. It can be overridden per query by the `TIMEOUT` interceptor.
+    /// Default `None` (no limit).
+    ///
+    /// Note that a [`Database`] blocking the thread inside `query` can't be interrupted.
+    ///
+    /// [`SqlnessError::Timeout`]: crate::SqlnessError::Timeout
+    /// [`Database`]: crate::Database
+    #[builder(default = "Config::default_query_timeout()")]
+    pub query_timeout: Option<Duration>,
+    /// Max duration to wait for [`Database::is_ready`] after an environment starts.
+    /// When exceeded, all cases of the environment error with
+    /// [`SqlnessError::NotReady`]. Default 30 seconds.
+    ///
+    /// [`Database::is_ready`]: crate::Database::is_ready
+    /// [`SqlnessError::NotReady`]: crate::SqlnessError::NotReady
+    #[builder(default = "Config::default_startup_timeout()")]
+    pub startup_timeout: Duration,
+    /// Max duration of [`EnvController::stop`]. When exceeded, the stop future is
+    /// dropped and the run goes on with other environments, but it finally returns
+    /// [`SqlnessError::ShutdownTimeout`]. Default `None` (no limit).
+    ///
+    /// [`EnvController::stop`]: crate::EnvController::stop
+    /// [`SqlnessError::ShutdownTimeout`]: crate::SqlnessError::ShutdownTimeout
+    #[builder(default = "Config::default_shutdown_timeout()")]
+    pub shutdown_timeout: Option<Duration>,
+    /// Catch panics from [`Database`] queries and [`EnvController::start`] /
+    /// [`EnvController::stop`]. A panicked query errors its case with
+    /// [`SqlnessError::Panic`], and other cases still run. A panicked environment
+    /// returns [`SqlnessError::EnvPanic`]. Set to `false` to let panics abort the
+    /// run. Default `true`.
+    ///
+    /// [`Database`]: crate::Database
+    /// [`EnvController::start`]: crate::EnvController::start
+    /// [`EnvController::stop`]: crate::EnvController::stop
+    /// [`SqlnessError::Panic`]: crate::SqlnessError::Panic
+    /// [`SqlnessError::EnvPanic`]: crate::SqlnessError::EnvPanic
+    #[builder(default = "Config::default_catch_panic()")]
+    pub catch_panic: bool,
+    /// How many times to retry a query failed with an error that
+    /// [`Database::is_retryable`] accepts, like a dropped connection. The error is
+    /// recorded as the result if it still fails after all retries. Default `0`.
+    ///
+    /// Unlike the `RETRY` interceptor, which retries a query whose result mismatches,
+    /// it applies to all queries and only on retryable errors.
+    ///
+    /// [`Database::is_retryable`]: crate::Database::is_retryable
+    #[builder(default = "Config::default_connection_retries()")]
+    pub connection_retries: usize,
+    /// Delay before the first retry of [`Config::connection_retries`], it's doubled
+    /// for each of the following retries. Default 100 milliseconds.
+    #[builder(default = "Config::default_retry_backoff()")]
+    pub retry_backoff: Duration,
+    /// Max size in bytes of the formatted result of one statement. A larger result
+    /// errors the case with [`SqlnessError::ResultTooLarge`], or is truncated if
+    /// [`Config::warn_oversized_results`] is on. Default `None` (no limit).
+    ///
+    /// [`SqlnessError::ResultTooLarge`]: crate::SqlnessError::ResultTooLarge
+    #[builder(default = "Config::default_max_result_bytes()")]
+    pub max_result_bytes: Option<usize>,
+    /// Truncate results larger than [`Config::max_result_bytes`] with a
+    /// `... (truncated, N bytes)` line and print a warning, instead of failing the
+    /// case. The truncated result is compared as usual. Default `false`.
+    #[builder(default = "Config::default_warn_oversized_results()")]
+    pub warn_oversized_results: bool,
+    /// Remove ANSI escape sequences, like colors written by a client, from all
+    /// results, like a `STRIP_ANSI` interceptor declared before the others of every
+    /// query. Default `false`.
+    #[builder(default = "Config::default_strip_ansi()")]
+    pub strip_ansi: bool,
+    /// Allow `SHELL` interceptors to run commands. Default `false`.
+    ///
+    /// Commands run with the privileges of the runner, and a case can run anything
+    /// with it, so only turn it on for cases you trust.
+    #[builder(default = "Config::default_allow_shell()")]
+    pub allow_shell: bool,
+    /// How many cases in one environment can be executed concurrently. Default `1`.
+    /// An environment can override it by `parallelism` in its config file, eg. to
+    /// query a remote cluster less concurrently than a local one.
+    ///
+    /// Cases are polled concurrently in the task calling [`Runner::run`] rather than
+    /// spawned, so [`Database`] doesn't need to be `Send` or `Sync`. But queries from
+    /// different cases are interleaved on the same [`Database`], which should handle
+    /// concurrent calls to `query` and not block the thread, otherwise cases are
+    /// effectively run one by one. Cases should not depend on each other when this is
+    /// greater than `1`.
+    ///
+    /// [`Runner::run`]: crate::Runner::run
+    /// [`Database`]: crate::Database
+    #[builder(default = "Config::default_parallelism()")]
+    pub parallelism: usize,
+    /// Keep values captured by `CAPTURE` across cases in one environment, so a case
+    /// can use `{{name}}` captured by a previous one. Default `false`, each case only
+    /// sees its own values.
+    ///
+    /// It makes cases depend on the order they run: cases run one by one in the
+    /// order of their paths (or shuffled by [`Config::seed`] with
+    /// [`Config::randomize`]), ignoring [`Config::parallelism`], and a filtered out
+    /// or skipped case doesn't capture anything for the next ones. Values are
+    /// cleared when the environment is started again.
+    #[builder(default = "Config::default_persistent_context()")]
+    pub persistent_context: bool,
+    /// How to keep a case failed partway from affecting the next ones, by a
+    /// transaction or a cleanup hook. Default [`CaseIsolation::None`].
+    ///
+    /// A case fails when it errors or its result mismatches, a case updated by
+    /// [`Config::update_result`] passes.
+    #[builder(default = "Config::default_case_isolation()")]
+    pub case_isolation: CaseIsolation,
+    /// Run environments concurrently. Default `false`, environments are run one by one.
+    ///
+    /// Like [`parallelism`], environments are polled concurrently in the task calling
+    /// [`Runner::run`], so [`EnvController`] should not block the thread. Environments
+    /// don't affect each other: a failed one doesn't stop the others even with
+    /// [`fail_fast`] or [`Config::fail_on_first_mismatch`], and reports are in the same order as running them one by one.
+    /// Output of different environments may interleave.
+    ///
+    /// [`parallelism`]: Config#structfield.parallelism
+    /// [`fail_fast`]: Config#structfield.fail_fast
+    /// [`Runner::run`]: crate::Runner::run
+    /// [`EnvController`]: crate::EnvController
+    #[builder(default = "Config::default_parallel_envs()")]
+    pub parallel_envs: bool,
+    /// Write a JUnit XML report to this path after the run. Each environment is
+    /// reported as a `<testsuite>` and each case as a `<testcase>`. Default `None`.
+    #[builder(default = "Config::default_junit_report()")]
+    pub junit_report: Option<String>,
+    /// Write a TAP (Test Anything Protocol) stream to this path after the run, with
+    /// one test point for each case. Default `None`.
+    #[builder(default = "Config::default_tap_report()")]
+    pub tap_report: Option<String>,
+    /// Write a JSON report to this path after the run, for custom tooling. Default
+    /// `None`. The schema is:
+    ///
+    /// ``` text
+    /// {
+    ///   "schema_version": 1,
+    ///   "environments": [{ "name", "backend", "filtered", "duration_ms" }],
+    ///   "cases": [{
+    ///     "path", "environment", "duration_ms",
+    ///     "status": "passed" | "failed" | "errored" | "updated" | "skipped" | "listed"
+    ///       | "quarantined",
+    ///     "diff": failed only, "error": errored or quarantined only,
+    ///     "reason": skipped only, "quarantined": true if it's run in quarantine,
+    ///     "query_durations": [{ "query", "duration_ms", "limit_ms" }], if measured
+    ///   }]
+    /// }
+    /// ```
+    ///
+    /// `path` is relative to the environment directory, without extension. Fields
+    /// may be added within the same `schema_version`.
+    #[builder(default = "Config::default_json_report()")]
+    pub json_report: Option<String>,
+    /// Delimiter ending a query in case files. Default `;`.
+    ///
+    /// It can be changed for the rest of a file by `-- SQLNESS DELIMITER <delimiter>`,
+    /// eg. for procedure bodies containing `;`. A delimiter other than `;` is
+    /// stripped from the query before execution.
+    #[builder(default = "Config::default_delimiter()")]
+    pub delimiter: String,
+    /// Lines starting with this are passed to [`Database`] verbatim as a query on
+    /// their own, eg. `\` for psql meta-commands like `\d t`, instead of being parsed
+    /// as SQL ended by the delimiter. Only lines starting a new query are passed
+    /// through. Default `None`.
+    ///
+    /// [`Database`]: crate::Database
+    #[builder(default = "Config::default_passthrough_prefix()")]
+    pub passthrough_prefix: Option<String>,
+    /// Values passed by the test driver, which can be filled in queries by the
+    /// `ARG` interceptor as `${name}`. Default empty.
+    #[builder(default = "Config::default_args()")]
+    pub args: HashMap<String, String>,
+    /// Replace all matches of each pattern in every query result with the paired
+    /// string, eg. to normalize timestamps or commit ids across all cases. Filters
+    /// are applied in order, before per-query interceptors like `REPLACE`, and the
+    /// replacement has the same syntax as `REPLACE`. Default empty.
+    #[builder(default = "Config::default_result_filters()")]
+    pub result_filters: Vec<(Regex, String)>,
+    /// How structured results from [`Database::query_result`] are rendered.
+    /// Default [`ResultFormat::Table`].
+    ///
+    /// [`Database::query_result`]: crate::Database#method.query_result
+    #[builder(default = "Config::default_result_format()")]
+    pub result_format: ResultFormat,
+    /// Delimiter of cells in [`ResultFormat::Csv`]. Default `,`.
+    #[builder(default = "Config::default_csv_delimiter()")]
+    pub csv_delimiter: char,
 }
 
 impl Config {
+    /// Register a custom interceptor to [`Config::interceptor_registry`], see
+    /// [`Registry::register`].
+    pub fn register_interceptor(&mut self, prefix: &str, factory: InterceptorFactoryRef) {
+        self.interceptor_registry.register(prefix, factory);
+    }
+
+    fn default_extra_case_dirs() -> Vec<String> {
+        vec![]
+    }
+
     fn default_test_case_extension() -> String {
         "sql".to_string()
     }
@@ -49,6 +597,14 @@ impl Config {
         "result".to_string()
     }
 
+    fn default_per_env_results() -> bool {
+        false
+    }
+
+    fn default_comment_prefix() -> String {
+        "--".to_string()
+    }
+
     fn default_interceptor_prefix() -> String {
         "-- SQLNESS".to_string()
     }
@@ -57,25 +613,204 @@ impl Config {
         "config.toml".to_string()
     }
 
+    fn default_require_env_config() -> bool {
+        false
+    }
+
     fn default_fail_fast() -> bool {
         true
     }
 
+    fn default_fail_on_first_mismatch() -> bool {
+        false
+    }
+
     fn default_test_filter() -> String {
         ".*".to_string()
     }
 
+    fn default_quarantine() -> Vec<Regex> {
+        vec![]
+    }
+
     fn default_env_filter() -> String {
         ".*".to_string()
     }
 
+    fn default_case_glob() -> Vec<String> {
+        vec![]
+    }
+
+    fn default_include_envs() -> Vec<String> {
+        vec![]
+    }
+
+    fn default_exclude_envs() -> Vec<String> {
+        vec![]
+    }
+
+    fn default_include_tags() -> Vec<String> {
+        vec![]
+    }
+
+    fn default_exclude_tags() -> Vec<String> {
+        vec![]
+    }
+
     fn default_follow_links() -> bool {
         true
     }
 
+    fn default_dry_run() -> bool {
+        false
+    }
+
+    fn default_skip_unstartable_envs() -> bool {
+        false
+    }
+
+    fn default_randomize() -> bool {
+        false
+    }
+
+    fn default_seed() -> Option<u64> {
+        None
+    }
+
     fn default_registry() -> Registry {
         Registry::default()
     }
+
+    fn default_on_event() -> Option<EventCallback> {
+        None
+    }
+
+    fn default_interactive() -> bool {
+        false
+    }
+
+    fn default_update_result() -> bool {
+        false
+    }
+
+    fn default_normalize_whitespace() -> bool {
+        false
+    }
+
+    fn default_ignore_result_comments() -> bool {
+        false
+    }
+
+    fn default_diff_color() -> bool {
+        true
+    }
+
+    fn default_diff_context_lines() -> usize {
+        3
+    }
+
+    fn default_query_timeout() -> Option<Duration> {
+        None
+    }
+
+    fn default_startup_timeout() -> Duration {
+        Duration::from_secs(30)
+    }
+
+    fn default_shutdown_timeout() -> Option<Duration> {
+        None
+    }
+
+    fn default_connection_retries() -> usize {
+        0
+    }
+
+    fn default_retry_backoff() -> Duration {
+        Duration::from_millis(100)
+    }
+
+    fn default_max_result_bytes() -> Option<usize> {
+        None
+    }
+
+    fn default_warn_oversized_results() -> bool {
+        false
+    }
+
+    fn default_strip_ansi() -> bool {
+        false
+    }
+
+    fn default_allow_shell() -> bool {
+        false
+    }
+
+    fn default_catch_panic() -> bool {
+        true
+    }
+
+    fn default_parallelism() -> usize {
+        1
+    }
+
+    fn default_persistent_context() -> bool {
+        false
+    }
+
+    fn default_case_isolation() -> CaseIsolation {
+        CaseIsolation::None
+    }
+
+    fn default_parallel_envs() -> bool {
+        false
+    }
+
+    fn default_junit_report() -> Option<String> {
+        None
+    }
+
+    fn default_tap_report() -> Option<String> {
+        None
+    }
+
+    fn default_json_report() -> Option<String> {
+        None
+    }
+
+    fn default_delimiter() -> String {
+        ";".to_string()
+    }
+
+    fn default_passthrough_prefix() -> Option<String> {
+        None
+    }
+
+    fn default_args() -> HashMap<String, String> {
+        HashMap::new()
+    }
+
+    fn default_result_filters() -> Vec<(Regex, String)> {
+        vec![]
+    }
+
+    fn default_result_format() -> ResultFormat {
+        ResultFormat::default()
+    }
+
+    fn default_csv_delimiter() -> char {
+        crate::format::CSV_DELIMITER
+    }
+}
+
+impl ConfigBuilder {
+    /// Register a custom interceptor on top of builtin ones, or the registry set by
+    /// [`ConfigBuilder::interceptor_registry`]. See [`Registry::register`].
+    pub fn with_interceptor(&mut self, prefix: &str, factory: InterceptorFactoryRef) -> &mut Self {
+        self.interceptor_registry
+            .get_or_insert_with(Config::default_registry)
+            .register(prefix, factory);
+        self
+    }
 }
 
 /// Config for DatabaseBuilder
@@ -87,3 +822,85 @@ pub struct DatabaseConfig {
     pub pass: Option<String>,
     pub db_name: Option<String>,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn interpolate_env_vars() {
+        std::env::set_var("SQLNESS_TEST_PORT", "4000");
+        std::env::remove_var("SQLNESS_TEST_UNSET");
+        assert_eq!(
+            interpolate_env("addr = \"${SQLNESS_TEST_UNSET:-localhost}:${SQLNESS_TEST_PORT}\""),
+            Ok("addr = \"localhost:4000\"".to_string())
+        );
+        assert_eq!(
+            interpolate_env("port = ${SQLNESS_TEST_UNSET}"),
+            Err("SQLNESS_TEST_UNSET".to_string())
+        );
+        assert_eq!(
+            interpolate_env("a = \"${b\""),
+            Ok("a = \"${b\"".to_string())
+        );
+    }
+
+    #[test]
+    fn load_env_overrides() {
+        let dir = std::env::temp_dir();
+        let load = |name: &str, content: &str| {
+            let path = dir.join(format!("sqlness-{}-{name}", std::process::id()));
+            std::fs::write(&path, content).unwrap();
+            EnvOverrides::load(&path)
+        };
+        assert_eq!(
+            load("overrides.toml", "addr = \"a\"\nparallelism = 4\n")
+                .unwrap()
+                .parallelism,
+            Some(4)
+        );
+        assert_eq!(
+            load("no-overrides.toml", "addr = \"a\"\n").unwrap(),
+            EnvOverrides::default()
+        );
+        assert_eq!(
+            load("overrides.yaml", "parallelism: 4\n").unwrap(),
+            EnvOverrides::default()
+        );
+        assert!(load("invalid.toml", "parallelism = 0\n").is_err());
+        assert_eq!(
+            load(
+                "not-toml.toml",
+                "addr = ${SQLNESS_TEST_UNSET}\nparallelism = 4\n"
+            )
+            .unwrap(),
+            EnvOverrides::default()
+        );
+        assert_eq!(
+            load(
+                "unset.toml",
+                "addr = \"${SQLNESS_TEST_UNSET}\"\nparallelism = 4\n"
+            )
+            .unwrap()
+            .parallelism,
+            Some(4)
+        );
+
+        assert_eq!(
+            load("features.toml", "features = [\"json\", \"cte\"]\n")
+                .unwrap()
+                .features,
+            vec!["json", "cte"]
+        );
+        assert!(load("invalid-features.toml", "features = \"json\"\n").is_err());
+        assert!(load("invalid-feature.toml", "features = [\"a,b\"]\n").is_err());
+    }
+
+    #[test]
+    fn load_config_file() {
+        let path = std::env::temp_dir().join(format!("sqlness-config-{}.toml", std::process::id()));
+        std::fs::write(&path, "port = ${SQLNESS_TEST_LOAD_PORT:-3306}\n").unwrap();
+        let config: toml::Value = load_env_config(&path).unwrap();
+        assert_eq!(config["port"].as_integer(), Some(3306));
+    }
+}
@@ -1,10 +1,14 @@
 // Copyright 2022 CeresDB Project Authors. Licensed under Apache-2.0.
 
-use std::fmt::Display;
+use std::{collections::HashMap, fmt::Display};
 
 use async_trait::async_trait;
+use futures::stream::BoxStream;
 
-use crate::case::QueryContext;
+use crate::{
+    case::QueryContext,
+    format::{self, ResultFormat},
+};
 
 /// Query executor.
 ///
@@ -12,9 +16,145 @@ use crate::case::QueryContext;
 /// execute query. The context parameter is a key-value pair map that
 /// usually comes from interceptor or config file.
 ///
+/// One database is started for each environment and shared by all its cases, so
+/// an implementation can keep one connection for the whole run, and session state
+/// persists across queries of a file. [`Database::reset`] is called after each
+/// case to clear the state before the next one.
+///
+/// The database is `Sync` for the same reason as [`EnvController`], the futures of
+/// its `async fn`s hold `&self` and are `Send`.
+///
 /// [`Runner`]: crate::Runner
+/// [`EnvController`]: crate::EnvController
 /// [`EnvController::start`]: crate::EnvController#tymethod.start
 #[async_trait]
-pub trait Database {
+pub trait Database: Sync {
     async fn query(&self, context: QueryContext, query: String) -> Box<dyn Display>;
+
+    /// Name of the backend, used in reports and errors to tell which backend ran
+    /// the case. The default implementation returns `None`, and the name of the
+    /// environment directory is used instead.
+    fn name(&self) -> Option<&str> {
+        None
+    }
+
+    /// Whether a query failed with `error`, the message of [`QueryResult::Error`],
+    /// can succeed if it's executed again, eg. on a dropped connection. Such queries
+    /// are retried up to [`Config::connection_retries`] times. The default
+    /// implementation returns `false`.
+    ///
+    /// [`Config::connection_retries`]: crate::Config#structfield.connection_retries
+    fn is_retryable(&self, error: &str) -> bool {
+        let _ = error;
+        false
+    }
+
+    /// Whether the database runs `BEGIN;`, `COMMIT;` and `ROLLBACK;`, to isolate
+    /// cases by [`CaseIsolation::Transaction`]. The default implementation returns
+    /// `false`.
+    ///
+    /// [`CaseIsolation::Transaction`]: crate::CaseIsolation::Transaction
+    fn supports_transactions(&self) -> bool {
+        false
+    }
+
+    /// Whether the database runs queries against [`QueryContext::database`], set by
+    /// the `DB` interceptor. The default implementation returns `false`, and a case
+    /// using `DB` errors.
+    fn supports_databases(&self) -> bool {
+        false
+    }
+
+    /// Execute the query with options set by the `OPT` interceptor, like query tags
+    /// or resource groups. `opts` is empty when the query has none. sqlness doesn't
+    /// interpret options, it's up to the backend to handle or reject unknown ones.
+    ///
+    /// The default implementation ignores `opts` and calls [`Database::query`].
+    async fn query_with_opts(
+        &self,
+        context: QueryContext,
+        query: String,
+        opts: HashMap<String, String>,
+    ) -> Box<dyn Display> {
+        let _ = opts;
+        self.query(context, query).await
+    }
+
+    /// Execute the query and return its result in structured form, which is
+    /// formatted by sqlness. See [`QueryResult`] and [`ResultFormat`].
+    ///
+    /// The default implementation wraps the output of
+    /// [`Database::query_with_opts`] as [`QueryResult::Raw`]. Options are in
+    /// [`QueryContext::options`] when it's overridden.
+    async fn query_result(&self, context: QueryContext, query: String) -> QueryResult {
+        let opts = context.options().clone();
+        let result = self.query_with_opts(context, query, opts).await;
+        QueryResult::Raw(result.to_string())
+    }
+
+    /// Execute the query and yield its result in chunks of text, which are
+    /// concatenated in order. It's used by the `STREAM` interceptor to compare a
+    /// large result as it arrives, and the stream is dropped at the first chunk
+    /// differing from the recorded result.
+    ///
+    /// The default implementation returns `None`, and the result of
+    /// [`Database::query_result`] is rendered and compared in one chunk.
+    fn query_stream(&self, context: QueryContext, query: String) -> Option<BoxStream<'_, String>> {
+        let _ = (context, query);
+        None
+    }
+
+    /// Whether the database is ready to run queries. [`Runner`] polls it after
+    /// [`EnvController::start`] until it returns `true` or
+    /// [`Config::startup_timeout`] is reached, before running any case.
+    ///
+    /// The default implementation returns `true`.
+    ///
+    /// [`Runner`]: crate::Runner
+    /// [`EnvController::start`]: crate::EnvController#tymethod.start
+    /// [`Config::startup_timeout`]: crate::Config#structfield.startup_timeout
+    async fn is_ready(&self) -> bool {
+        true
+    }
+
+    /// Clear session state left by a case, like temporary tables or session
+    /// variables. [`Runner`] calls it after each case file is run, so the next case
+    /// starts from a clean session on the same connection. It's not called for
+    /// skipped cases, and with [`Config::parallelism`] above 1 other cases may be
+    /// running on the database at the same time.
+    ///
+    /// The default implementation does nothing.
+    ///
+    /// [`Runner`]: crate::Runner
+    /// [`Config::parallelism`]: crate::Config#structfield.parallelism
+    async fn reset(&self) {}
+}
+
+/// Structured result of a query, see [`Database::query_result`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum QueryResult {
+    /// Result already formatted by the database, it's written as is.
+    Raw(String),
+    /// A table of rows, `None` cell stands for NULL. It's rendered in
+    /// [`Config::result_format`].
+    ///
+    /// [`Config::result_format`]: crate::Config#structfield.result_format
+    Rows {
+        columns: Vec<String>,
+        rows: Vec<Vec<Option<String>>>,
+    },
+    /// The query failed with this message, it's written as is. It's recognized by
+    /// the `EXPECT_ERROR` interceptor.
+    Error(String),
+}
+
+impl Display for QueryResult {
+    /// Render in [`ResultFormat::Table`].
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&format::render(
+            self,
+            ResultFormat::Table,
+            format::CSV_DELIMITER,
+        ))
+    }
 }
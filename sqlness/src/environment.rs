@@ -1,6 +1,6 @@
 // Copyright 2022 CeresDB Project Authors. Licensed under Apache-2.0.
 
-use std::path::Path;
+use std::{collections::HashMap, path::Path};
 
 use async_trait::async_trait;
 
@@ -17,18 +17,87 @@ use crate::database::Database;
 /// [`Self::start`] and [`Self::stop`]). Those names are extracted from the first-level
 /// directories of test case directory. Refer to crate level documentation for more information
 /// about directory organizaiton rules.
+///
+/// # Hooks
+/// Besides starting and stopping, a controller can hook into a run in this order:
+/// 1. [`Self::before_all`], once before any environment.
+/// 2. For each environment, [`Self::start`] and then [`Self::metadata`] once it's
+///    ready.
+/// 3. For each directory of cases, [`Self::before_dir`], the cases, and then
+///    [`Self::after_dir`]. [`Self::cleanup_after_case`] is called after a failed
+///    case with [`CaseIsolation::Cleanup`].
+/// 4. [`Self::stop`] the environment.
+/// 5. [`Self::after_all`], once after all environments are stopped.
+///
+/// Environments run concurrently with [`Config::parallel_envs`], so only the
+/// order inside one environment holds there.
+///
+/// The controller is `Sync` as the futures of its `async fn`s hold `&self` and are
+/// `Send`, which `#[async_trait]` requires of an implementation anyway.
+///
+/// [`Config::parallel_envs`]: crate::Config#structfield.parallel_envs
+/// [`CaseIsolation::Cleanup`]: crate::CaseIsolation::Cleanup
 #[async_trait]
-pub trait EnvController {
+pub trait EnvController: Sync {
     type DB: Database;
 
+    /// Called once before any environment is started, eg. to set up resources shared
+    /// by all environments. The default implementation does nothing.
+    async fn before_all(&self) {}
+
+    /// Called once after all environments are stopped, even if the run failed.
+    /// See [`Self::before_all`]. The default implementation does nothing.
+    async fn after_all(&self) {}
+
     /// Start a [`Database`] to run test queries.
     ///
     /// Two parameters are the mode of this environment, or environment's name.
     /// And the config file's path to this environment if it's find, it's defined
     /// by the `env_config_file` field in the root config toml, and the default
-    /// value is `config.toml`.
+    /// value is `config.toml`. It can be read by [`load_env_config`], which
+    /// resolves environment variables in it.
+    ///
+    /// [`load_env_config`]: crate::load_env_config
     async fn start(&self, env: &str, config: Option<&Path>) -> Self::DB;
 
     /// Stop one [`Database`].
     async fn stop(&self, env: &str, database: Self::DB);
+
+    /// Metadata of the [`Database`] returned by [`Self::start`], like
+    /// `backend_version`. It's fetched once the database is ready, and the default
+    /// implementation returns an empty map. `sqlness_env` is always set to the name
+    /// of the environment, overriding the one returned.
+    ///
+    /// Queries of the environment can read a value in the form of `{{name}}`, like
+    /// values captured by `CAPTURE`, and `SKIP_IF` can check it, eg.
+    /// `-- SQLNESS SKIP_IF {{backend_version}}=1.0`.
+    async fn metadata(&self, env: &str, database: &Self::DB) -> HashMap<String, String> {
+        let _ = (env, database);
+        HashMap::new()
+    }
+
+    /// Called before running cases directly under `dir`, eg. to prepare fixtures
+    /// shared by them. `dir` is the path of the directory, and `database` is the
+    /// one returned by [`Self::start`]. Sub-directories are entered separately rather
+    /// than nested in the parent. The default implementation does nothing.
+    async fn before_dir(&self, env: &str, dir: &Path, database: &Self::DB) {
+        let _ = (env, dir, database);
+    }
+
+    /// Called after running cases directly under `dir`, even if some of them failed.
+    /// See [`Self::before_dir`]. The default implementation does nothing.
+    async fn after_dir(&self, env: &str, dir: &Path, database: &Self::DB) {
+        let _ = (env, dir, database);
+    }
+
+    /// Called after a case failed with [`CaseIsolation::Cleanup`], eg. to drop the
+    /// tables it created so they don't affect the next cases. `case` is the path of
+    /// the case without extension. It's called before [`Database::reset`]. The
+    /// default implementation does nothing.
+    ///
+    /// [`CaseIsolation::Cleanup`]: crate::CaseIsolation::Cleanup
+    /// [`Database::reset`]: crate::Database::reset
+    async fn cleanup_after_case(&self, env: &str, case: &Path, database: &Self::DB) {
+        let _ = (env, case, database);
+    }
 }
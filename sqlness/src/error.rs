@@ -1,6 +1,8 @@
 // Copyright 2022 CeresDB Project Authors. Licensed under Apache-2.0.
 
+use std::fmt::Display;
 use std::path::PathBuf;
+use std::time::Duration;
 
 use thiserror::Error;
 
@@ -18,6 +20,9 @@ pub enum SqlnessError {
         file: PathBuf,
     },
 
+    #[error("Environment variable {name} used in {file} is not set")]
+    UnsetEnvVar { name: String, file: PathBuf },
+
     #[error("IO operation failed, source error: {0}")]
     IO(#[from] std::io::Error),
 
@@ -30,6 +35,12 @@ pub enum SqlnessError {
     #[error("Invalid regexp, source error: {0}")]
     Regex(#[from] regex::Error),
 
+    #[error("Invalid glob pattern {pattern}, error: {source}")]
+    InvalidGlob {
+        pattern: String,
+        source: glob::PatternError,
+    },
+
     #[error("Unknown interceptor prefix, value:{prefix}.")]
     UnknownInterceptor { prefix: String },
 
@@ -38,6 +49,115 @@ pub enum SqlnessError {
 
     #[error("Missing interceptor prefix, line:{line}.")]
     MissingPrefix { line: String },
+
+    #[error("Query timeout in case {case} after {elapsed:?}, query:{query}")]
+    Timeout {
+        case: String,
+        query: String,
+        elapsed: Duration,
+    },
+
+    #[error("Config file {path} of environment {env} is missing")]
+    MissingEnvConfig { env: String, path: PathBuf },
+
+    #[error("Invalid config file {file}, {msg}")]
+    InvalidEnvConfig { file: PathBuf, msg: String },
+
+    #[error("Case path {path} is not in an environment directory")]
+    InvalidCasePath { path: PathBuf },
+
+    #[error("Environment {env} is not ready after {timeout:?}")]
+    NotReady { env: String, timeout: Duration },
+
+    #[error("Stopping environment {env} timeout after {timeout:?}")]
+    ShutdownTimeout { env: String, timeout: Duration },
+
+    #[error("Panicked in case {case}, query:{query}, message:{message}")]
+    Panic {
+        case: String,
+        query: String,
+        message: String,
+    },
+
+    #[error("Environment {env} panicked, message:{message}")]
+    EnvPanic { env: String, message: String },
+
+    #[error("Query {query} took {elapsed:?}, exceeding MAX_DURATION {limit:?}")]
+    SlowQuery {
+        query: String,
+        elapsed: Duration,
+        limit: Duration,
+    },
+
+    #[error("Backend doesn't support running query {query} against database {database}")]
+    UnsupportedDatabase { query: String, database: String },
+
+    #[error("Shell command {command} isn't allowed, enable it by Config::allow_shell")]
+    ShellNotAllowed { command: String },
+
+    #[error("Shell command {command} failed, {msg}")]
+    ShellCommand { command: String, msg: String },
+
+    #[error("Unexpected result of query {query}, {msg}")]
+    UnexpectedResult { query: String, msg: String },
+
+    #[error("Result of query {query} is {size} bytes, exceeding max_result_bytes {limit}")]
+    ResultTooLarge {
+        query: String,
+        size: usize,
+        limit: usize,
+    },
+
+    /// The result of a case differs from the recorded one.
+    #[error("{0}")]
+    ResultMismatch(Box<ResultMismatch>),
+
+    #[error("Case {case} errored on backend {backend}, error: {source}")]
+    CaseErrored {
+        case: String,
+        backend: String,
+        source: Box<SqlnessError>,
+    },
+
+    #[error("Cyclic include of {path}")]
+    CyclicInclude { path: PathBuf },
+
+    #[error("Failed to parse case {path} at line {line}, error: {source}")]
+    ParseCase {
+        source: Box<SqlnessError>,
+        path: PathBuf,
+        line: usize,
+    },
 }
 
 pub(crate) type Result<T> = std::result::Result<T, SqlnessError>;
+
+/// Details of [`SqlnessError::ResultMismatch`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ResultMismatch {
+    /// Path of the case file.
+    pub case: String,
+    pub env: String,
+    /// The first query whose result differs, if it can be told.
+    pub query: Option<String>,
+    /// The recorded result.
+    pub expected: String,
+    /// The result of this run.
+    pub actual: String,
+    /// Unified diff from `expected` to `actual`.
+    pub diff: String,
+}
+
+impl Display for ResultMismatch {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Result of case {} mismatched in environment {}",
+            self.case, self.env
+        )?;
+        if let Some(query) = &self.query {
+            write!(f, ", query:{query}")?;
+        }
+        write!(f, "\n{}", self.diff)
+    }
+}
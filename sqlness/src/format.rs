@@ -0,0 +1,327 @@
+// Copyright 2024 CeresDB Project Authors. Licensed under Apache-2.0.
+
+//! Builtin renderers of [`QueryResult`].
+
+use std::{fmt::Write, str::FromStr};
+
+use crate::QueryResult;
+
+/// How [`QueryResult::Rows`] is rendered in the result file, see
+/// [`Config::result_format`]. [`QueryResult::Raw`] and [`QueryResult::Error`]
+/// are always written as is.
+///
+/// [`Config::result_format`]: crate::Config#structfield.result_format
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ResultFormat {
+    /// Aligned ASCII table, NULL is rendered as `NULL`:
+    ///
+    /// ``` text
+    /// +----+------+
+    /// | id | name |
+    /// +----+------+
+    /// | 1  | NULL |
+    /// +----+------+
+    /// ```
+    #[default]
+    Table,
+    /// Column names followed by rows in CSV (RFC 4180), separated by
+    /// [`Config::csv_delimiter`]. Cells containing the delimiter, quotes or line
+    /// breaks are quoted, with quotes doubled. NULL is rendered as `NULL`, while a
+    /// `NULL` string is quoted:
+    ///
+    /// ``` text
+    /// id,name
+    /// 1,NULL
+    /// 2,"a, ""b"""
+    /// ```
+    ///
+    /// [`Config::csv_delimiter`]: crate::Config#structfield.csv_delimiter
+    Csv,
+    /// An array with one object per row, each in a line. Cells are strings and
+    /// NULL is `null`:
+    ///
+    /// ``` text
+    /// [
+    ///   {"id":"1","name":null}
+    /// ]
+    /// ```
+    Json,
+}
+
+impl FromStr for ResultFormat {
+    type Err = String;
+
+    /// Parse from `table`, `csv` or `json`, case insensitive.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "table" => Ok(ResultFormat::Table),
+            "csv" => Ok(ResultFormat::Csv),
+            "json" => Ok(ResultFormat::Json),
+            _ => Err(format!("Unknown format {s}, expect table, csv or json")),
+        }
+    }
+}
+
+/// How NULL is rendered, unless it's changed by the `NULL_AS` interceptor.
+pub(crate) const NULL: &str = "NULL";
+
+/// Default delimiter of [`ResultFormat::Csv`].
+pub(crate) const CSV_DELIMITER: char = ',';
+
+/// Render `result` in `format`, `csv_delimiter` is only used by
+/// [`ResultFormat::Csv`].
+pub(crate) fn render(result: &QueryResult, format: ResultFormat, csv_delimiter: char) -> String {
+    render_with_null(result, format, csv_delimiter, NULL)
+}
+
+/// Like [`render`], NULL cells are rendered as `null` in table and CSV.
+pub(crate) fn render_with_null(
+    result: &QueryResult,
+    format: ResultFormat,
+    csv_delimiter: char,
+    null: &str,
+) -> String {
+    let (columns, rows) = match result {
+        QueryResult::Raw(raw) | QueryResult::Error(raw) => return raw.clone(),
+        QueryResult::Rows { columns, rows } => (columns, rows),
+    };
+
+    match format {
+        ResultFormat::Table => render_table(columns, rows, null),
+        ResultFormat::Csv => render_csv(columns, rows, csv_delimiter, null),
+        ResultFormat::Json => render_json(columns, rows),
+    }
+}
+
+fn render_table(columns: &[String], rows: &[Vec<Option<String>>], null: &str) -> String {
+    if columns.is_empty() {
+        return String::new();
+    }
+
+    let mut widths = columns
+        .iter()
+        .map(|column| column.chars().count())
+        .collect::<Vec<_>>();
+    for row in rows {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            let len = cell.as_deref().unwrap_or(null).chars().count();
+            *width = (*width).max(len);
+        }
+    }
+
+    let separator = widths
+        .iter()
+        .map(|width| "-".repeat(width + 2))
+        .collect::<Vec<_>>()
+        .join("+");
+    let separator = format!("+{separator}+");
+    let render_row = |output: &mut String, cells: Vec<&str>| {
+        output.push('|');
+        for (cell, width) in cells.iter().zip(&widths) {
+            let _ = write!(output, " {cell:<width$} |");
+        }
+        output.push('\n');
+    };
+
+    let mut output = String::new();
+    output.push_str(&separator);
+    output.push('\n');
+    render_row(&mut output, columns.iter().map(String::as_str).collect());
+    output.push_str(&separator);
+    output.push('\n');
+    for row in rows {
+        let mut cells = row
+            .iter()
+            .map(|cell| cell.as_deref().unwrap_or(null))
+            .collect::<Vec<_>>();
+        cells.resize(columns.len(), "");
+        render_row(&mut output, cells);
+    }
+    output.push_str(&separator);
+    output
+}
+
+fn render_csv(
+    columns: &[String],
+    rows: &[Vec<Option<String>>],
+    delimiter: char,
+    null: &str,
+) -> String {
+    let quote = |cell: &str| {
+        // Quote `NULL` strings to tell them from NULL values
+        if cell.contains([delimiter, '"', '\n', '\r']) || cell == null {
+            format!("\"{}\"", cell.replace('"', "\"\""))
+        } else {
+            cell.to_string()
+        }
+    };
+
+    let mut lines = vec![columns
+        .iter()
+        .map(|c| quote(c))
+        .collect::<Vec<_>>()
+        .join(&delimiter.to_string())];
+    for row in rows {
+        let line = row
+            .iter()
+            .map(|cell| cell.as_deref().map_or(null.to_string(), quote))
+            .collect::<Vec<_>>()
+            .join(&delimiter.to_string());
+        lines.push(line);
+    }
+    lines.join("\n")
+}
+
+fn render_json(columns: &[String], rows: &[Vec<Option<String>>]) -> String {
+    if rows.is_empty() {
+        return "[]".to_string();
+    }
+
+    let objects = rows
+        .iter()
+        .map(|row| {
+            let fields = columns
+                .iter()
+                .zip(row)
+                .map(|(column, cell)| {
+                    let value = serde_json::to_string(cell).unwrap();
+                    format!("{}:{value}", serde_json::to_string(column).unwrap())
+                })
+                .collect::<Vec<_>>();
+            format!("  {{{}}}", fields.join(","))
+        })
+        .collect::<Vec<_>>();
+    format!("[\n{}\n]", objects.join(",\n"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rows() -> QueryResult {
+        QueryResult::Rows {
+            columns: vec!["id".to_string(), "name".to_string()],
+            rows: vec![
+                vec![Some("1".to_string()), None],
+                vec![Some("22".to_string()), Some("a, \"b\"".to_string())],
+            ],
+        }
+    }
+
+    #[test]
+    fn render_formats() {
+        assert_eq!(
+            render(&rows(), ResultFormat::Table, CSV_DELIMITER),
+            r#"+----+--------+
+| id | name   |
++----+--------+
+| 1  | NULL   |
+| 22 | a, "b" |
++----+--------+"#
+        );
+        assert_eq!(
+            render(&rows(), ResultFormat::Csv, CSV_DELIMITER),
+            "id,name\n1,NULL\n22,\"a, \"\"b\"\"\""
+        );
+        assert_eq!(
+            render(&rows(), ResultFormat::Json, CSV_DELIMITER),
+            "[\n  {\"id\":\"1\",\"name\":null},\n  {\"id\":\"22\",\"name\":\"a, \\\"b\\\"\"}\n]"
+        );
+    }
+
+    /// Parse CSV rendered by [`render_csv`], `NULL` is parsed as None.
+    fn parse_csv(csv: &str, delimiter: char) -> Vec<Vec<Option<String>>> {
+        let mut records = vec![];
+        let mut record = vec![];
+        let mut field = String::new();
+        let (mut quoted, mut in_quotes) = (false, false);
+        let mut chars = csv.chars().peekable();
+        let end_field = |record: &mut Vec<_>, field: &mut String, quoted: &mut bool| {
+            let field = std::mem::take(field);
+            record.push((*quoted || field != NULL).then_some(field));
+            *quoted = false;
+        };
+        while let Some(c) = chars.next() {
+            match c {
+                '"' if in_quotes && chars.peek() == Some(&'"') => {
+                    chars.next();
+                    field.push('"');
+                }
+                '"' => {
+                    in_quotes = !in_quotes;
+                    quoted = true;
+                }
+                c if c == delimiter && !in_quotes => {
+                    end_field(&mut record, &mut field, &mut quoted)
+                }
+                '\n' if !in_quotes => {
+                    end_field(&mut record, &mut field, &mut quoted);
+                    records.push(std::mem::take(&mut record));
+                }
+                c => field.push(c),
+            }
+        }
+        end_field(&mut record, &mut field, &mut quoted);
+        records.push(record);
+        records
+    }
+
+    #[test]
+    fn csv_round_trip() {
+        let cells = vec![
+            vec![Some("1".to_string()), None, Some(String::new())],
+            vec![Some("NULL".to_string()), None, None],
+            vec![
+                Some("a,b;c".to_string()),
+                Some("\"quoted\"".to_string()),
+                Some("multi\nline\r\n".to_string()),
+            ],
+        ];
+        let columns = vec!["id".to_string(), "x;y".to_string(), "z".to_string()];
+        let result = QueryResult::Rows {
+            columns: columns.clone(),
+            rows: cells.clone(),
+        };
+        for delimiter in [',', ';', '\t'] {
+            let csv = render(&result, ResultFormat::Csv, delimiter);
+            let mut records = parse_csv(&csv, delimiter);
+            let header = records.remove(0);
+            assert_eq!(
+                header,
+                columns.iter().cloned().map(Some).collect::<Vec<_>>()
+            );
+            assert_eq!(records, cells, "{csv}");
+            // rendering is stable
+            assert_eq!(csv, render(&result, ResultFormat::Csv, delimiter));
+        }
+        assert_eq!(
+            render(&result, ResultFormat::Csv, ';'),
+            "id;\"x;y\";z\n1;NULL;\n\"NULL\";NULL;NULL\n\"a,b;c\";\"\"\"quoted\"\"\";\"multi\nline\r\n\""
+        );
+    }
+
+    #[test]
+    fn null_token() {
+        let result = QueryResult::Rows {
+            columns: vec!["a".to_string(), "b".to_string()],
+            rows: vec![
+                vec![None, Some("\\N".to_string())],
+                vec![Some(String::new()), None],
+            ],
+        };
+        assert_eq!(
+            render_with_null(&result, ResultFormat::Table, CSV_DELIMITER, "\\N"),
+            "+----+----+\n| a  | b  |\n+----+----+\n| \\N | \\N |\n|    | \\N |\n+----+----+"
+        );
+        assert_eq!(
+            render_with_null(&result, ResultFormat::Csv, CSV_DELIMITER, "\\N"),
+            "a,b\n\\N,\"\\N\"\n,\\N"
+        );
+    }
+
+    #[test]
+    fn raw_is_kept() {
+        let raw = QueryResult::Raw("ok".to_string());
+        assert_eq!(render(&raw, ResultFormat::Json, CSV_DELIMITER), "ok");
+    }
+}
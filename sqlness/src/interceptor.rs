@@ -14,15 +14,84 @@ use crate::{
     },
 };
 
+pub mod allow_variants;
 pub mod arg;
+pub mod capture;
+pub mod case_insensitive;
+pub mod contains;
+pub mod count_rows;
+pub mod db;
+pub mod distinct;
+pub mod empty;
 pub mod env;
+pub mod expect_error;
+pub mod format;
+pub mod hash;
+pub mod hide;
+pub mod json_canon;
+pub mod limit;
+pub mod lines;
+pub mod mask_column;
+pub mod max_duration;
+pub mod null_as;
+pub mod opt;
+pub mod project;
+pub mod redact;
+pub mod repeat;
 pub mod replace;
+pub mod require;
+pub mod retry;
+pub mod round;
+pub mod shell;
+pub mod skip_if;
 pub mod sleep;
 pub mod sort_result;
+pub mod split;
+pub mod stream;
+pub mod strip_ansi;
 pub mod template;
+pub mod timeout;
+pub mod timing;
+pub mod txn;
+pub mod warmup;
 
 pub type InterceptorRef = Box<dyn Interceptor + Send + Sync>;
 
+/// Pre-processes the query and post-processes its result.
+///
+/// Interceptors of one query run in the order they are declared in the case file,
+/// both before and after execution. So stacked interceptors compose like a
+/// pipeline, eg.
+/// ``` sql
+/// -- SQLNESS REPLACE [0-9]+ N
+/// -- SQLNESS SORT_RESULT
+/// SELECT name, id FROM t;
+/// ```
+/// sorts the result with numbers already replaced, while swapping the two lines
+/// sorts by the original numbers and then replaces them.
+///
+/// [`Interceptor::after_execute`] gets the formatted result of each statement,
+/// along with a copy of the context it was executed with. Changes to the context
+/// there are seen by later interceptors of this result only, they don't affect
+/// the execution. The result of a statement goes through, in this order:
+/// 1. formatting, by `FORMAT` and `NULL_AS`;
+/// 2. [`Config::strip_ansi`], [`Config::result_filters`] and
+///    [`Config::max_result_bytes`], for every result;
+/// 3. `after_execute` of all interceptors, built-in or not, in declaration order.
+///    Those working on rows, like `PROJECT`, `MASK_COLUMN` or `COUNT_ROWS`, see the
+///    rows left by the ones before them, or every line as a row of
+///    [`QueryResult::Raw`] once one has changed the result as text, like `REPLACE`,
+///    `SORT_RESULT` or `LIMIT` do;
+/// 4. `CAPTURE` captures the rows left, then `CONTAINS` and `NOT_CONTAINS` check
+///    the final result.
+///
+/// A result matching `EXPECT_ERROR` is replaced without running them, and a
+/// `STREAM` query is compared as it's read instead.
+///
+/// [`Config::strip_ansi`]: crate::Config#structfield.strip_ansi
+/// [`Config::result_filters`]: crate::Config#structfield.result_filters
+/// [`Config::max_result_bytes`]: crate::Config#structfield.max_result_bytes
+/// [`QueryResult::Raw`]: crate::QueryResult::Raw
 #[async_trait::async_trait]
 pub trait Interceptor {
     #[allow(unused_variables)]
@@ -38,16 +107,17 @@ pub trait Interceptor {
     }
 
     #[allow(unused_variables)]
-    fn after_execute(&self, result: &mut String) {}
+    fn after_execute(&self, result: &mut String, context: &mut QueryContext) {}
 
     #[allow(unused_variables)]
-    async fn after_execute_async(&self, result: &mut String) {
-        self.after_execute(result)
+    async fn after_execute_async(&self, result: &mut String, context: &mut QueryContext) {
+        self.after_execute(result, context)
     }
 }
 
 pub type InterceptorFactoryRef = Arc<dyn InterceptorFactory>;
 
+/// Creates interceptors from their context in case files, see [`Registry::register`].
 pub trait InterceptorFactory {
     fn try_new(&self, ctx: &str) -> Result<InterceptorRef>;
 }
@@ -66,6 +136,13 @@ impl Default for Registry {
 }
 
 impl Registry {
+    /// Register a factory for interceptors with `prefix`.
+    ///
+    /// An interceptor line like `-- SQLNESS MYTHING a b` is dispatched by its first
+    /// word, `MYTHING`, to the factory registered with that prefix, which gets the
+    /// rest of the line (`a b`) as the context. Registering an existing prefix
+    /// replaces the factory, including builtin ones. Unknown prefixes fail the case
+    /// with [`SqlnessError::UnknownInterceptor`].
     pub fn register(&mut self, prefix: &str, factory: InterceptorFactoryRef) {
         self.factories.insert(prefix.to_string(), factory);
     }
@@ -86,6 +163,42 @@ impl Registry {
     }
 }
 
+/// Split one line of formatted result into cells.
+///
+/// Lines containing `|` (like ASCII tables) are split on it, other lines are split
+/// on `,`. Cells are trimmed, and the outer `|` of a table row is ignored.
+pub(crate) fn split_columns(line: &str) -> Vec<&str> {
+    let line = line.trim();
+    if line.contains('|') {
+        line.trim_matches('|').split('|').map(str::trim).collect()
+    } else {
+        line.split(',').map(str::trim).collect()
+    }
+}
+
+/// Parse a space separated list of 1-based column numbers into 0-based indices.
+pub(crate) fn parse_columns(prefix: &str, ctx: &str) -> Result<Vec<usize>> {
+    let columns = ctx
+        .split(' ')
+        .filter(|s| !s.is_empty())
+        .map(|column| match column.parse::<usize>() {
+            Ok(column) if column > 0 => Ok(column - 1),
+            _ => Err(SqlnessError::InvalidContext {
+                prefix: prefix.to_string(),
+                msg: format!("Expect column number starting from 1, got:{column}"),
+            }),
+        })
+        .collect::<Result<Vec<_>>>()?;
+    if columns.is_empty() {
+        return Err(SqlnessError::InvalidContext {
+            prefix: prefix.to_string(),
+            msg: "Expect at least one column".to_string(),
+        });
+    }
+
+    Ok(columns)
+}
+
 /// Interceptors builtin sqlness
 fn builtin_interceptors() -> HashMap<String, InterceptorFactoryRef> {
     [
@@ -105,6 +218,10 @@ fn builtin_interceptors() -> HashMap<String, InterceptorFactoryRef> {
             sort_result::PREFIX.to_string(),
             Arc::new(SortResultInterceptorFactory {}) as _,
         ),
+        (
+            sort_result::NUMERIC_PREFIX.to_string(),
+            Arc::new(sort_result::SortResultNumericInterceptorFactory {}) as _,
+        ),
         (
             template::PREFIX.to_string(),
             Arc::new(TemplateInterceptorFactory {}) as _,
@@ -113,6 +230,150 @@ fn builtin_interceptors() -> HashMap<String, InterceptorFactoryRef> {
             sleep::PREFIX.to_string(),
             Arc::new(sleep::SleepInterceptorFactory {}) as _,
         ),
+        (
+            retry::PREFIX.to_string(),
+            Arc::new(retry::RetryInterceptorFactory {}) as _,
+        ),
+        (
+            timeout::PREFIX.to_string(),
+            Arc::new(timeout::TimeoutInterceptorFactory {}) as _,
+        ),
+        (
+            skip_if::PREFIX.to_string(),
+            Arc::new(skip_if::SkipIfInterceptorFactory {}) as _,
+        ),
+        (
+            shell::PREFIX.to_string(),
+            Arc::new(shell::ShellInterceptorFactory {}) as _,
+        ),
+        (
+            require::PREFIX.to_string(),
+            Arc::new(require::RequireInterceptorFactory {}) as _,
+        ),
+        (
+            expect_error::PREFIX.to_string(),
+            Arc::new(expect_error::ExpectErrorInterceptorFactory {}) as _,
+        ),
+        (
+            limit::PREFIX.to_string(),
+            Arc::new(limit::LimitInterceptorFactory {}) as _,
+        ),
+        (
+            hide::PREFIX.to_string(),
+            Arc::new(hide::HideInterceptorFactory {}) as _,
+        ),
+        (
+            format::PREFIX.to_string(),
+            Arc::new(format::FormatInterceptorFactory {}) as _,
+        ),
+        (
+            repeat::PREFIX.to_string(),
+            Arc::new(repeat::RepeatInterceptorFactory {}) as _,
+        ),
+        (
+            capture::PREFIX.to_string(),
+            Arc::new(capture::CaptureInterceptorFactory {}) as _,
+        ),
+        (
+            timing::PREFIX.to_string(),
+            Arc::new(timing::TimingInterceptorFactory {}) as _,
+        ),
+        (
+            project::PREFIX.to_string(),
+            Arc::new(project::ProjectInterceptorFactory {}) as _,
+        ),
+        (
+            project::DROP_PREFIX.to_string(),
+            Arc::new(project::DropColumnInterceptorFactory {}) as _,
+        ),
+        (
+            mask_column::PREFIX.to_string(),
+            Arc::new(mask_column::MaskColumnInterceptorFactory {}) as _,
+        ),
+        (
+            json_canon::PREFIX.to_string(),
+            Arc::new(json_canon::JsonCanonInterceptorFactory {}) as _,
+        ),
+        (
+            round::PREFIX.to_string(),
+            Arc::new(round::RoundInterceptorFactory {}) as _,
+        ),
+        (
+            distinct::PREFIX.to_string(),
+            Arc::new(distinct::DistinctInterceptorFactory {}) as _,
+        ),
+        (
+            count_rows::PREFIX.to_string(),
+            Arc::new(count_rows::CountRowsInterceptorFactory {}) as _,
+        ),
+        (
+            case_insensitive::PREFIX.to_string(),
+            Arc::new(case_insensitive::CaseInsensitiveInterceptorFactory {}) as _,
+        ),
+        (
+            redact::PREFIX.to_string(),
+            Arc::new(redact::RedactInterceptorFactory {}) as _,
+        ),
+        (
+            hash::PREFIX.to_string(),
+            Arc::new(hash::HashInterceptorFactory {}) as _,
+        ),
+        (
+            max_duration::PREFIX.to_string(),
+            Arc::new(max_duration::MaxDurationInterceptorFactory {}) as _,
+        ),
+        (
+            warmup::PREFIX.to_string(),
+            Arc::new(warmup::WarmupInterceptorFactory {}) as _,
+        ),
+        (
+            lines::PREFIX.to_string(),
+            Arc::new(lines::LinesInterceptorFactory {}) as _,
+        ),
+        (
+            empty::PREFIX.to_string(),
+            Arc::new(empty::EmptyInterceptorFactory {}) as _,
+        ),
+        (
+            stream::PREFIX.to_string(),
+            Arc::new(stream::StreamInterceptorFactory {}) as _,
+        ),
+        (
+            db::PREFIX.to_string(),
+            Arc::new(db::DbInterceptorFactory {}) as _,
+        ),
+        (
+            null_as::PREFIX.to_string(),
+            Arc::new(null_as::NullAsInterceptorFactory {}) as _,
+        ),
+        (
+            opt::PREFIX.to_string(),
+            Arc::new(opt::OptInterceptorFactory {}) as _,
+        ),
+        (
+            strip_ansi::PREFIX.to_string(),
+            Arc::new(strip_ansi::StripAnsiInterceptorFactory {}) as _,
+        ),
+        (
+            contains::PREFIX.to_string(),
+            Arc::new(contains::ContainsInterceptorFactory {}) as _,
+        ),
+        (
+            contains::NOT_PREFIX.to_string(),
+            Arc::new(contains::NotContainsInterceptorFactory {}) as _,
+        ),
+        (
+            split::PREFIX.to_string(),
+            Arc::new(split::SplitInterceptorFactory {}) as _,
+        ),
+        (
+            txn::PREFIX.to_string(),
+            Arc::new(txn::TxnInterceptorFactory {}) as _,
+        ),
+        (
+            allow_variants::PREFIX.to_string(),
+            Arc::new(allow_variants::AllowVariantsInterceptorFactory {}) as _,
+        ),
     ]
     .into_iter()
     .map(|(prefix, factory)| (prefix.to_string(), factory))
@@ -0,0 +1,55 @@
+// Copyright 2024 CeresDB Project Authors. Licensed under Apache-2.0.
+
+use crate::case::QueryContext;
+use crate::error::Result;
+use crate::interceptor::{Interceptor, InterceptorFactory, InterceptorRef};
+
+pub const PREFIX: &str = "ALLOW_VARIANTS";
+
+/// Accept any of several recorded results of the query.
+///
+/// # Example
+/// `.sql` file:
+/// ``` sql
+/// -- SQLNESS ALLOW_VARIANTS
+/// SELECT 1.0 / 3;
+/// ```
+///
+/// `.result` file:
+/// ``` sql
+/// -- SQLNESS ALLOW_VARIANTS
+/// SELECT 1.0 / 3;
+///
+/// 0.333
+///
+/// -- VARIANT
+///
+/// 0.3333333333333333
+///
+/// ```
+///
+/// Variants are separated by a `-- VARIANT` line followed by an empty line. The
+/// query passes if its result is the same as any of them, and all variants are kept
+/// in the result file. Otherwise the result is appended as a new variant, so it's
+/// recorded without overwriting existing ones when [`Config::update_result`] is on.
+///
+/// Like `RETRY`, old variants are only known when earlier queries in the file
+/// match their results.
+///
+/// [`Config::update_result`]: crate::Config#structfield.update_result
+#[derive(Debug)]
+pub struct AllowVariantsInterceptor;
+
+impl Interceptor for AllowVariantsInterceptor {
+    fn before_execute(&self, _: &mut Vec<String>, context: &mut QueryContext) {
+        context.settings.allow_variants = true;
+    }
+}
+
+pub struct AllowVariantsInterceptorFactory;
+
+impl InterceptorFactory for AllowVariantsInterceptorFactory {
+    fn try_new(&self, _: &str) -> Result<InterceptorRef> {
+        Ok(Box::new(AllowVariantsInterceptor))
+    }
+}
@@ -1,8 +1,10 @@
 // Copyright 2022 CeresDB Project Authors. Licensed under Apache-2.0.
 
+use std::collections::HashMap;
+
 use crate::case::QueryContext;
 use crate::error::Result;
-use crate::interceptor::{Interceptor, InterceptorFactory, InterceptorRef};
+use crate::interceptor::{env::replace_variables, Interceptor, InterceptorFactory, InterceptorRef};
 
 pub const PREFIX: &str = "ARG";
 
@@ -20,16 +22,47 @@ pub const PREFIX: &str = "ARG";
 /// by spaces.
 ///
 /// It will overwrite existing key-value pair in context if the key name is same.
+///
+/// # Substitution
+/// A name without `=` declares a value from [`Config::args`] to be filled in the
+/// executed query, in the form of `${name}`. Like `ENV`, only declared and present
+/// names are replaced, and the result file keeps the original query:
+/// ``` sql
+/// -- SQLNESS ARG table rows
+/// SELECT * FROM ${table} LIMIT ${rows};
+/// ```
+///
+/// Both forms can be mixed in one interceptor.
+///
+/// [`Config::args`]: crate::Config#structfield.args
 #[derive(Debug)]
 pub struct ArgInterceptor {
     args: Vec<(String, String)>,
+    /// Names to be substituted from [`Config::args`].
+    ///
+    /// [`Config::args`]: crate::Config#structfield.args
+    names: Vec<String>,
 }
 
 impl Interceptor for ArgInterceptor {
-    fn before_execute(&self, _: &mut Vec<String>, context: &mut QueryContext) {
+    fn before_execute(&self, execute_query: &mut Vec<String>, context: &mut QueryContext) {
         for (key, value) in &self.args {
             context.context.insert(key.to_string(), value.to_string());
         }
+
+        let vars = self
+            .names
+            .iter()
+            .filter_map(|name| {
+                let value = context.settings.args.get(name)?;
+                Some((format!("${{{name}}}"), value.clone()))
+            })
+            .collect::<HashMap<_, _>>();
+        if !vars.is_empty() {
+            for line in execute_query {
+                *line = replace_variables(line, &vars);
+            }
+        }
     }
 }
 
@@ -38,7 +71,12 @@ pub struct ArgInterceptorFactory;
 impl InterceptorFactory for ArgInterceptorFactory {
     fn try_new(&self, ctx: &str) -> Result<InterceptorRef> {
         let args = Self::separate_key_value_pairs(ctx);
-        Ok(Box::new(ArgInterceptor { args }))
+        let names = ctx
+            .split(' ')
+            .filter(|s| !s.is_empty() && !s.contains('='))
+            .map(str::to_string)
+            .collect();
+        Ok(Box::new(ArgInterceptor { args, names }))
     }
 }
 
@@ -72,4 +110,24 @@ mod test {
         let args = ArgInterceptorFactory::separate_key_value_pairs(input);
         assert_eq!(args, expected);
     }
+
+    #[test]
+    fn substitute_config_args() {
+        let interceptor = ArgInterceptorFactory
+            .try_new("table key=value table2")
+            .unwrap();
+        let mut context = QueryContext::default();
+        context.settings.args = std::sync::Arc::new(
+            [("table", "t1"), ("table2", "t2"), ("rows", "10")]
+                .into_iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect(),
+        );
+
+        let mut query =
+            vec!["SELECT * FROM ${table}, ${table2}, ${table}_v2 LIMIT ${rows};".to_string()];
+        interceptor.before_execute(&mut query, &mut context);
+        assert_eq!(query, vec!["SELECT * FROM t1, t2, t1_v2 LIMIT ${rows};"]);
+        assert_eq!(context.context["key"], "value");
+    }
 }
@@ -0,0 +1,79 @@
+// Copyright 2024 CeresDB Project Authors. Licensed under Apache-2.0.
+
+use crate::case::QueryContext;
+use crate::error::Result;
+use crate::interceptor::{Interceptor, InterceptorFactory, InterceptorRef};
+use crate::SqlnessError;
+
+pub const PREFIX: &str = "CAPTURE";
+
+/// Store the result of the query into a variable, which can be referenced by
+/// later queries in the same file in the form of `{{name}}`.
+///
+/// # Example
+/// ``` sql
+/// -- SQLNESS CAPTURE last_id
+/// SELECT max(id) FROM t;
+///
+/// SELECT * FROM t WHERE id = {{last_id}};
+/// ```
+///
+/// The query should return a single value: one row of one column for
+/// [`QueryResult::Rows`] (`NULL` is captured as `NULL`), or one line for
+/// [`QueryResult::Raw`]. The case fails with [`SqlnessError::UnexpectedResult`]
+/// otherwise. Variables are substituted in the executed query only, the result file
+/// keeps `{{name}}` as is. Unknown variables are left untouched, and a captured value
+/// is inserted as plain text, a `{{name}}` in it isn't substituted.
+///
+/// The value is still captured if the query is annotated with `HIDE`, but neither
+/// the result nor the captured value is recorded then. Values are only visible in
+/// the same file, unless [`Config::persistent_context`] is on.
+///
+/// [`Config::persistent_context`]: crate::Config#structfield.persistent_context
+/// [`QueryResult::Rows`]: crate::QueryResult::Rows
+/// [`QueryResult::Raw`]: crate::QueryResult::Raw
+/// [`SqlnessError::UnexpectedResult`]: crate::SqlnessError::UnexpectedResult
+#[derive(Debug)]
+pub struct CaptureInterceptor {
+    name: String,
+}
+
+impl Interceptor for CaptureInterceptor {
+    fn before_execute(&self, _: &mut Vec<String>, context: &mut QueryContext) {
+        context.settings.capture = Some(self.name.clone());
+    }
+}
+
+pub struct CaptureInterceptorFactory;
+
+impl InterceptorFactory for CaptureInterceptorFactory {
+    fn try_new(&self, ctx: &str) -> Result<InterceptorRef> {
+        if ctx.is_empty() || !ctx.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+            return Err(SqlnessError::InvalidContext {
+                prefix: PREFIX.to_string(),
+                msg: format!("Expect a variable name of letters, digits and `_`, got {ctx}"),
+            });
+        }
+
+        Ok(Box::new(CaptureInterceptor {
+            name: ctx.to_string(),
+        }))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parse_name() {
+        let interceptor = CaptureInterceptorFactory.try_new("last_id").unwrap();
+        let mut context = QueryContext::default();
+        interceptor.before_execute(&mut vec![], &mut context);
+        assert_eq!(context.settings.capture.as_deref(), Some("last_id"));
+
+        assert!(CaptureInterceptorFactory.try_new("").is_err());
+        assert!(CaptureInterceptorFactory.try_new("a b").is_err());
+        assert!(CaptureInterceptorFactory.try_new("{{a}}").is_err());
+    }
+}
@@ -0,0 +1,50 @@
+// Copyright 2024 CeresDB Project Authors. Licensed under Apache-2.0.
+
+use crate::case::QueryContext;
+use crate::error::Result;
+use crate::interceptor::{Interceptor, InterceptorFactory, InterceptorRef};
+
+pub const PREFIX: &str = "CASE_INSENSITIVE";
+
+/// Ignore letter case when comparing the result of the query.
+///
+/// # Example
+/// `.sql` file:
+/// ``` sql
+/// -- SQLNESS CASE_INSENSITIVE
+/// SHOW CREATE TABLE t;
+/// ```
+///
+/// `.result` file:
+/// ``` sql
+/// -- SQLNESS CASE_INSENSITIVE
+/// SHOW CREATE TABLE t;
+///
+/// CREATE TABLE t (id INT)
+/// ```
+///
+/// A result like `create table t (ID int)` passes as well. When the result only
+/// differs from the recorded one by letter case, the recorded one is kept in the
+/// result file, also with [`Config::update_result`], so it isn't rewritten by each
+/// backend. A result with other differences is written as is.
+///
+/// Like `RETRY`, the recorded result is only known when earlier queries in the file
+/// match their results.
+///
+/// [`Config::update_result`]: crate::Config#structfield.update_result
+#[derive(Debug)]
+pub struct CaseInsensitiveInterceptor;
+
+impl Interceptor for CaseInsensitiveInterceptor {
+    fn before_execute(&self, _: &mut Vec<String>, context: &mut QueryContext) {
+        context.settings.case_insensitive = true;
+    }
+}
+
+pub struct CaseInsensitiveInterceptorFactory;
+
+impl InterceptorFactory for CaseInsensitiveInterceptorFactory {
+    fn try_new(&self, _: &str) -> Result<InterceptorRef> {
+        Ok(Box::new(CaseInsensitiveInterceptor))
+    }
+}
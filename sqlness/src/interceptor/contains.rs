@@ -0,0 +1,150 @@
+// Copyright 2024 CeresDB Project Authors. Licensed under Apache-2.0.
+
+use crate::case::QueryContext;
+use crate::error::Result;
+use crate::interceptor::{Interceptor, InterceptorFactory, InterceptorRef};
+use crate::SqlnessError;
+
+pub const PREFIX: &str = "CONTAINS";
+pub const NOT_PREFIX: &str = "NOT_CONTAINS";
+
+/// Only check that the result contains a substring, instead of the whole result.
+///
+/// # Example
+/// `.sql` file:
+/// ``` sql
+/// -- SQLNESS CONTAINS IndexScan
+/// -- SQLNESS NOT_CONTAINS SeqScan
+/// EXPLAIN SELECT * FROM t WHERE id = 1;
+/// ```
+///
+/// `.result` file:
+/// ``` sql
+/// -- SQLNESS CONTAINS IndexScan
+/// -- SQLNESS NOT_CONTAINS SeqScan
+/// EXPLAIN SELECT * FROM t WHERE id = 1;
+///
+/// IndexScan on t (cost=0.15..8.17)
+/// ```
+///
+/// The substring is the rest of the line, including spaces. Multiple `CONTAINS`
+/// and `NOT_CONTAINS` add up, all of them must hold. `NOT_CONTAINS` requires the
+/// substring to be absent. They are checked on the result of each statement after
+/// all interceptors, and the case fails with [`SqlnessError::UnexpectedResult`]
+/// when one doesn't hold.
+///
+/// The full result is recorded, but it's not compared: when the checks hold, the
+/// recorded result is kept in the result file, unless [`Config::update_result`] is
+/// on. Like `RETRY`, the recorded result is only known when earlier queries in the
+/// file match their results.
+///
+/// [`SqlnessError::UnexpectedResult`]: crate::SqlnessError::UnexpectedResult
+/// [`Config::update_result`]: crate::Config#structfield.update_result
+#[derive(Debug)]
+pub struct ContainsInterceptor {
+    substring: String,
+    negated: bool,
+}
+
+impl Interceptor for ContainsInterceptor {
+    fn before_execute(&self, _: &mut Vec<String>, context: &mut QueryContext) {
+        let substrings = if self.negated {
+            &mut context.settings.not_contains
+        } else {
+            &mut context.settings.contains
+        };
+        substrings.push(self.substring.clone());
+    }
+}
+
+/// Check `result` against substrings of `CONTAINS` and `NOT_CONTAINS`, return an
+/// error message for the first one that doesn't hold.
+pub(crate) fn check(
+    result: &str,
+    contains: &[String],
+    not_contains: &[String],
+) -> std::result::Result<(), String> {
+    if let Some(missing) = contains.iter().find(|s| !result.contains(s.as_str())) {
+        return Err(format!("expect result containing {missing:?}"));
+    }
+    if let Some(present) = not_contains.iter().find(|s| result.contains(s.as_str())) {
+        return Err(format!("expect result not containing {present:?}"));
+    }
+    Ok(())
+}
+
+pub struct ContainsInterceptorFactory;
+
+impl InterceptorFactory for ContainsInterceptorFactory {
+    fn try_new(&self, ctx: &str) -> Result<InterceptorRef> {
+        new_interceptor(PREFIX, ctx, false)
+    }
+}
+
+pub struct NotContainsInterceptorFactory;
+
+impl InterceptorFactory for NotContainsInterceptorFactory {
+    fn try_new(&self, ctx: &str) -> Result<InterceptorRef> {
+        new_interceptor(NOT_PREFIX, ctx, true)
+    }
+}
+
+fn new_interceptor(prefix: &str, ctx: &str, negated: bool) -> Result<InterceptorRef> {
+    if ctx.is_empty() {
+        return Err(SqlnessError::InvalidContext {
+            prefix: prefix.to_string(),
+            msg: "Expect a substring".to_string(),
+        });
+    }
+    Ok(Box::new(ContainsInterceptor {
+        substring: ctx.to_string(),
+        negated,
+    }))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn check_substrings() {
+        let result = "IndexScan on t\n  Filter: id = 1";
+        let strings = |s: &[&str]| s.iter().map(|s| s.to_string()).collect::<Vec<_>>();
+
+        assert!(check(
+            result,
+            &strings(&["IndexScan", "id = 1"]),
+            &strings(&["SeqScan"])
+        )
+        .is_ok());
+        assert_eq!(
+            check(result, &strings(&["IndexScan", "SeqScan"]), &[]),
+            Err("expect result containing \"SeqScan\"".to_string())
+        );
+        assert_eq!(
+            check(result, &[], &strings(&["Filter"])),
+            Err("expect result not containing \"Filter\"".to_string())
+        );
+    }
+
+    #[test]
+    fn collect_substrings() {
+        let mut context = QueryContext::default();
+        for (factory, ctx) in [
+            (
+                &ContainsInterceptorFactory as &dyn InterceptorFactory,
+                "a b",
+            ),
+            (&NotContainsInterceptorFactory, "c"),
+            (&ContainsInterceptorFactory, "d"),
+        ] {
+            factory
+                .try_new(ctx)
+                .unwrap()
+                .before_execute(&mut vec![], &mut context);
+        }
+        assert_eq!(context.settings.contains, vec!["a b", "d"]);
+        assert_eq!(context.settings.not_contains, vec!["c"]);
+        assert!(ContainsInterceptorFactory.try_new("").is_err());
+    }
+}
@@ -0,0 +1,153 @@
+// Copyright 2024 CeresDB Project Authors. Licensed under Apache-2.0.
+
+use std::fmt::Display;
+
+use crate::case::QueryContext;
+use crate::error::Result;
+use crate::interceptor::{Interceptor, InterceptorFactory, InterceptorRef};
+use crate::{QueryResult, SqlnessError};
+
+pub const PREFIX: &str = "COUNT_ROWS";
+
+/// Assert the number of rows returned by the query, instead of recording them.
+///
+/// # Example
+/// `.sql` file:
+/// ``` sql
+/// -- SQLNESS COUNT_ROWS 1000
+/// SELECT * FROM t;
+///
+/// -- SQLNESS COUNT_ROWS >=1000
+/// SELECT * FROM t WHERE ts > now() - INTERVAL '1 day';
+/// ```
+///
+/// `.result` file:
+/// ``` sql
+/// -- SQLNESS COUNT_ROWS 1000
+/// SELECT * FROM t;
+///
+/// -- rows: 1000
+///
+/// -- SQLNESS COUNT_ROWS >=1000
+/// SELECT * FROM t WHERE ts > now() - INTERVAL '1 day';
+///
+/// -- rows: >=1000
+/// ```
+///
+/// The count can be prefixed by `>=`, `>`, `<=` or `<`. The condition is recorded
+/// instead of the rows, and the case fails with [`SqlnessError::UnexpectedResult`]
+/// telling the expected and actual counts if it doesn't hold. Rows of
+/// [`QueryResult::Rows`] are counted, and non-empty lines of [`QueryResult::Raw`].
+/// Error results are kept as is.
+///
+/// [`SqlnessError::UnexpectedResult`]: crate::SqlnessError::UnexpectedResult
+/// [`QueryResult::Rows`]: crate::QueryResult::Rows
+/// [`QueryResult::Raw`]: crate::QueryResult::Raw
+#[derive(Debug)]
+pub struct CountRowsInterceptor {
+    condition: RowCount,
+}
+
+/// Condition on the number of rows, see [`CountRowsInterceptor`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct RowCount {
+    op: &'static str,
+    count: usize,
+}
+
+impl RowCount {
+    const OPS: [&'static str; 4] = [">=", "<=", ">", "<"];
+
+    fn parse(ctx: &str) -> Result<Self> {
+        let ctx = ctx.trim();
+        let op = Self::OPS
+            .into_iter()
+            .find(|op| ctx.starts_with(op))
+            .unwrap_or("");
+        let count = ctx[op.len()..]
+            .trim()
+            .parse()
+            .map_err(|e| SqlnessError::InvalidContext {
+                prefix: PREFIX.to_string(),
+                msg: format!("Expect [>=|>|<=|<]<count>, got {ctx}, err:{e}"),
+            })?;
+        Ok(Self { op, count })
+    }
+
+    fn holds(&self, count: usize) -> bool {
+        match self.op {
+            ">=" => count >= self.count,
+            "<=" => count <= self.count,
+            ">" => count > self.count,
+            "<" => count < self.count,
+            _ => count == self.count,
+        }
+    }
+
+    /// Check the result, and replace it with the recorded line. Return an error
+    /// message if the count doesn't match.
+    pub(crate) fn check(&self, result: QueryResult) -> std::result::Result<QueryResult, String> {
+        let count = match &result {
+            QueryResult::Rows { rows, .. } => rows.len(),
+            QueryResult::Raw(raw) => raw.lines().filter(|l| !l.trim().is_empty()).count(),
+            QueryResult::Error(_) => return Ok(result),
+        };
+        if !self.holds(count) {
+            return Err(format!("expect rows: {self}, got {count}"));
+        }
+        Ok(QueryResult::Raw(format!("-- rows: {self}")))
+    }
+}
+
+impl Display for RowCount {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}{}", self.op, self.count)
+    }
+}
+
+impl Interceptor for CountRowsInterceptor {
+    fn after_execute(&self, result: &mut String, context: &mut QueryContext) {
+        context.map_rows(result, |rows| self.condition.check(rows));
+    }
+}
+
+pub struct CountRowsInterceptorFactory;
+
+impl InterceptorFactory for CountRowsInterceptorFactory {
+    fn try_new(&self, ctx: &str) -> Result<InterceptorRef> {
+        let condition = RowCount::parse(ctx)?;
+        Ok(Box::new(CountRowsInterceptor { condition }))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn check_count() {
+        let rows = |n| QueryResult::Rows {
+            columns: vec!["a".into()],
+            rows: vec![vec![None]; n],
+        };
+        let check = |ctx, result| RowCount::parse(ctx).unwrap().check(result);
+
+        assert_eq!(
+            check("3", rows(3)),
+            Ok(QueryResult::Raw("-- rows: 3".into()))
+        );
+        assert_eq!(
+            check("3", rows(2)),
+            Err("expect rows: 3, got 2".to_string())
+        );
+        assert_eq!(
+            check(">= 2", rows(3)),
+            Ok(QueryResult::Raw("-- rows: >=2".into()))
+        );
+        assert!(check("<2", rows(2)).is_err());
+        assert!(check(">2", QueryResult::Raw("1\n\n2\n3\n".into())).is_ok());
+        assert!(check("0", QueryResult::Error("failed".into())).is_ok());
+        assert!(CountRowsInterceptorFactory.try_new("=1").is_err());
+        assert!(CountRowsInterceptorFactory.try_new("").is_err());
+    }
+}
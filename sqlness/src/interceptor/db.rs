@@ -0,0 +1,74 @@
+// Copyright 2024 CeresDB Project Authors. Licensed under Apache-2.0.
+
+use crate::case::QueryContext;
+use crate::error::Result;
+use crate::interceptor::{Interceptor, InterceptorFactory, InterceptorRef};
+use crate::SqlnessError;
+
+pub const PREFIX: &str = "DB";
+
+/// Execute the query against a named database or schema of the environment,
+/// without a `USE` statement in the result file.
+///
+/// # Example
+/// ``` sql
+/// -- SQLNESS DB analytics
+/// SELECT count(*) FROM events;
+/// ```
+///
+/// The name is passed in [`QueryContext::database`], and it's up to the backend to
+/// run the query there, eg. on a connection to that database. It only applies to
+/// the query it's declared on, the following queries run against the default
+/// database again.
+///
+/// A backend supporting it returns `true` from [`Database::supports_databases`].
+/// For other backends the query isn't executed and the case errors with
+/// [`SqlnessError::UnsupportedDatabase`], instead of silently running against the
+/// wrong database.
+///
+/// [`Database::supports_databases`]: crate::Database::supports_databases
+/// [`SqlnessError::UnsupportedDatabase`]: crate::SqlnessError::UnsupportedDatabase
+#[derive(Debug)]
+pub struct DbInterceptor {
+    database: String,
+}
+
+impl Interceptor for DbInterceptor {
+    fn before_execute(&self, _: &mut Vec<String>, context: &mut QueryContext) {
+        context.settings.database = Some(self.database.clone());
+    }
+}
+
+pub struct DbInterceptorFactory;
+
+impl InterceptorFactory for DbInterceptorFactory {
+    fn try_new(&self, ctx: &str) -> Result<InterceptorRef> {
+        let database = ctx.trim();
+        if database.is_empty() || database.contains(char::is_whitespace) {
+            return Err(SqlnessError::InvalidContext {
+                prefix: PREFIX.to_string(),
+                msg: format!("Expect a database name, got {ctx:?}"),
+            });
+        }
+        Ok(Box::new(DbInterceptor {
+            database: database.to_string(),
+        }))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parse_database() {
+        let mut context = QueryContext::default();
+        DbInterceptorFactory
+            .try_new(" analytics ")
+            .unwrap()
+            .before_execute(&mut vec![], &mut context);
+        assert_eq!(context.database(), Some("analytics"));
+        assert!(DbInterceptorFactory.try_new("").is_err());
+        assert!(DbInterceptorFactory.try_new("a b").is_err());
+    }
+}
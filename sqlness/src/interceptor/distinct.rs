@@ -0,0 +1,124 @@
+// Copyright 2024 CeresDB Project Authors. Licensed under Apache-2.0.
+
+use std::collections::HashSet;
+
+use crate::case::QueryContext;
+use crate::error::Result;
+use crate::interceptor::{Interceptor, InterceptorFactory, InterceptorRef};
+use crate::QueryResult;
+
+pub const PREFIX: &str = "DISTINCT";
+
+/// Remove duplicate rows from the result, keeping the first one of them.
+///
+/// # Example
+/// `.sql` file:
+/// ``` sql
+/// -- SQLNESS DISTINCT
+/// -- SQLNESS SORT_RESULT
+/// SELECT state FROM diagnostics;
+/// ```
+///
+/// `.result` file:
+/// ``` sql
+/// -- SQLNESS DISTINCT
+/// -- SQLNESS SORT_RESULT
+/// SELECT state FROM diagnostics;
+///
+/// idle
+/// running
+/// ```
+///
+/// For [`QueryResult::Rows`] the rows are deduplicated and formatted again, so the
+/// header isn't a row and is always kept. For [`QueryResult::Raw`] every line is a
+/// row, including header lines.
+///
+/// [`QueryResult::Rows`]: crate::QueryResult::Rows
+/// [`QueryResult::Raw`]: crate::QueryResult::Raw
+#[derive(Debug)]
+pub struct DistinctInterceptor;
+
+impl Interceptor for DistinctInterceptor {
+    fn after_execute(&self, result: &mut String, context: &mut QueryContext) {
+        context.map_rows(result, |rows| Ok(distinct_result(rows)));
+    }
+}
+
+/// Remove duplicate rows, see [`DistinctInterceptor`].
+pub(crate) fn distinct_result(result: QueryResult) -> QueryResult {
+    match result {
+        QueryResult::Rows { columns, rows } => {
+            let mut seen = HashSet::new();
+            let rows = rows
+                .into_iter()
+                .filter(|row| seen.insert(row.clone()))
+                .collect();
+            QueryResult::Rows { columns, rows }
+        }
+        QueryResult::Raw(raw) => {
+            let mut seen = HashSet::new();
+            let mut lines = raw
+                .lines()
+                .filter(|line| seen.insert(*line))
+                .collect::<Vec<_>>()
+                .join("\n");
+            if raw.ends_with('\n') {
+                lines.push('\n');
+            }
+            QueryResult::Raw(lines)
+        }
+        result @ QueryResult::Error(_) => result,
+    }
+}
+
+pub struct DistinctInterceptorFactory;
+
+impl InterceptorFactory for DistinctInterceptorFactory {
+    fn try_new(&self, _: &str) -> Result<InterceptorRef> {
+        Ok(Box::new(DistinctInterceptor))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn distinct_rows() {
+        let row = |cells: &[Option<&str>]| {
+            cells
+                .iter()
+                .map(|c| c.map(str::to_string))
+                .collect::<Vec<_>>()
+        };
+        let result = QueryResult::Rows {
+            columns: vec!["a".into(), "b".into()],
+            rows: vec![
+                row(&[Some("2"), None]),
+                row(&[Some("1"), Some("x")]),
+                row(&[Some("2"), None]),
+                row(&[Some("2"), Some("NULL")]),
+                row(&[Some("1"), Some("x")]),
+            ],
+        };
+        assert_eq!(
+            distinct_result(result),
+            QueryResult::Rows {
+                columns: vec!["a".into(), "b".into()],
+                rows: vec![
+                    row(&[Some("2"), None]),
+                    row(&[Some("1"), Some("x")]),
+                    row(&[Some("2"), Some("NULL")]),
+                ],
+            }
+        );
+    }
+
+    #[test]
+    fn distinct_raw() {
+        assert_eq!(
+            distinct_result(QueryResult::Raw("b\na\nb\n\na\n".into())),
+            QueryResult::Raw("b\na\n\n".into())
+        );
+    }
+}
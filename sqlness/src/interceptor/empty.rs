@@ -0,0 +1,92 @@
+// Copyright 2024 CeresDB Project Authors. Licensed under Apache-2.0.
+
+use crate::case::QueryContext;
+use crate::error::Result;
+use crate::interceptor::{Interceptor, InterceptorFactory, InterceptorRef};
+use crate::QueryResult;
+
+pub const PREFIX: &str = "EMPTY";
+
+/// Assert the query returns no rows, recorded as a `-- empty` line.
+///
+/// # Example
+/// `.sql` file:
+/// ``` sql
+/// -- SQLNESS EMPTY
+/// SELECT * FROM t WHERE deleted;
+/// ```
+///
+/// `.result` file:
+/// ``` sql
+/// -- SQLNESS EMPTY
+/// SELECT * FROM t WHERE deleted;
+///
+/// -- empty
+/// ```
+///
+/// [`QueryResult::Rows`] is empty when it has no rows, even with columns, so a
+/// table with only a header is empty. [`QueryResult::Raw`] is empty when all its
+/// lines are blank: a header printed by the database is a line, strip it with a
+/// `REPLACE` declared before `EMPTY`. Otherwise the case fails with
+/// [`SqlnessError::UnexpectedResult`] showing the rows. Error results are kept as
+/// is, like `COUNT_ROWS`.
+///
+/// [`QueryResult::Rows`]: crate::QueryResult::Rows
+/// [`QueryResult::Raw`]: crate::QueryResult::Raw
+/// [`SqlnessError::UnexpectedResult`]: crate::SqlnessError::UnexpectedResult
+#[derive(Debug)]
+pub struct EmptyInterceptor;
+
+impl Interceptor for EmptyInterceptor {
+    fn after_execute(&self, result: &mut String, context: &mut QueryContext) {
+        context.map_rows(result, check_empty);
+    }
+}
+
+/// Replace an empty result with the recorded line, or return an error message
+/// with the rows.
+pub(crate) fn check_empty(result: QueryResult) -> std::result::Result<QueryResult, String> {
+    let count = match &result {
+        QueryResult::Rows { rows, .. } => rows.len(),
+        QueryResult::Raw(raw) => raw.lines().filter(|l| !l.trim().is_empty()).count(),
+        QueryResult::Error(_) => return Ok(result),
+    };
+    if count > 0 {
+        return Err(format!(
+            "expect an empty result, got {count} rows:\n{result}"
+        ));
+    }
+    Ok(QueryResult::Raw("-- empty".to_string()))
+}
+
+pub struct EmptyInterceptorFactory;
+
+impl InterceptorFactory for EmptyInterceptorFactory {
+    fn try_new(&self, _: &str) -> Result<InterceptorRef> {
+        Ok(Box::new(EmptyInterceptor))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn check_rows() {
+        let rows = |n| QueryResult::Rows {
+            columns: vec!["a".into()],
+            rows: vec![vec![Some("1".into())]; n],
+        };
+        let empty = Ok(QueryResult::Raw("-- empty".into()));
+
+        assert_eq!(check_empty(rows(0)), empty);
+        assert_eq!(check_empty(QueryResult::Raw(" \n\n".into())), empty);
+        assert_eq!(
+            check_empty(rows(1)),
+            Err("expect an empty result, got 1 rows:\n+---+\n| a |\n+---+\n| 1 |\n+---+".into())
+        );
+        assert!(check_empty(QueryResult::Raw("a\n".into())).is_err());
+        let error = QueryResult::Error("table not found".into());
+        assert_eq!(check_empty(error.clone()), Ok(error));
+    }
+}
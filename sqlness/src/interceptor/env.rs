@@ -7,6 +7,8 @@ use crate::error::Result;
 use crate::interceptor::{Interceptor, InterceptorFactory, InterceptorRef};
 
 pub const PREFIX: &str = "ENV";
+/// Name of the variable holding the name of the current environment.
+pub const ENV_NAME: &str = "sqlness_env";
 
 /// Read environment variables and fill them in query.
 ///
@@ -21,6 +23,8 @@ pub const PREFIX: &str = "ENV";
 /// safely put secret things in your query.
 ///
 /// Note that only decalred and present environment variables will be replaced.
+/// A variable is only matched as a whole word, `$SECRET` won't touch `$SECRET1`.
+/// When several declared names share a prefix, the longest one is tried first.
 ///
 /// You can either declare multiple env in one intercetor or separate them into
 /// different interceptors. The following two examples are equivalent:
@@ -33,6 +37,20 @@ pub const PREFIX: &str = "ENV";
 /// -- SQLNESS ENV SECRET2
 /// SELECT $SECRET1, $SECRET2;
 /// ````
+///
+/// # Environment name
+/// The name of the environment running the case, ie. its directory under
+/// [`Config::case_dir`], is filled in `{{sqlness_env}}` without declaration, like
+/// values captured by `CAPTURE`:
+/// ``` sql
+/// CREATE SCHEMA test_{{sqlness_env}};
+/// ```
+///
+/// It's part of the metadata of the environment, see [`EnvController::metadata`],
+/// so `SKIP_IF {{sqlness_env}}=local` works as well.
+///
+/// [`Config::case_dir`]: crate::Config#structfield.case_dir
+/// [`EnvController::metadata`]: crate::EnvController::metadata
 #[derive(Debug)]
 pub struct EnvInterceptor {
     /// Environment variables to be replaced.
@@ -42,12 +60,42 @@ pub struct EnvInterceptor {
 impl Interceptor for EnvInterceptor {
     fn before_execute(&self, execute_query: &mut Vec<String>, _: &mut QueryContext) {
         for line in execute_query {
-            for (key, value) in &self.data {
-                let rendered = line.replace(key, value);
-                *line = rendered;
+            *line = replace_variables(line, &self.data);
+        }
+    }
+}
+
+/// Replace every whole-word occurrence of `vars`' keys in `line` with the
+/// corresponding value.
+///
+/// Longer keys are tried first, and a key ending with an identifier character
+/// (`[A-Za-z0-9_]`) only matches when it is not directly followed by another one.
+/// The input is scanned once, so substituted values are never rendered again.
+pub(crate) fn replace_variables(line: &str, vars: &HashMap<String, String>) -> String {
+    let mut keys = vars.keys().filter(|k| !k.is_empty()).collect::<Vec<_>>();
+    keys.sort_by(|a, b| b.len().cmp(&a.len()).then_with(|| a.cmp(b)));
+
+    let mut rendered = String::with_capacity(line.len());
+    let mut remaining = line;
+    'outer: while let Some(c) = remaining.chars().next() {
+        for key in &keys {
+            if let Some(rest) = remaining.strip_prefix(key.as_str()) {
+                if !(key.ends_with(is_ident_char) && rest.starts_with(is_ident_char)) {
+                    rendered.push_str(&vars[key.as_str()]);
+                    remaining = rest;
+                    continue 'outer;
+                }
             }
         }
+        rendered.push(c);
+        remaining = &remaining[c.len_utf8()..];
     }
+
+    rendered
+}
+
+fn is_ident_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '_'
 }
 
 pub struct EnvInterceptorFactory;
@@ -90,4 +138,51 @@ mod test {
         let interceptor = EnvInterceptorFactory::create(input).unwrap();
         assert_eq!(interceptor.data, expected);
     }
+
+    #[test]
+    fn prefix_collision() {
+        let interceptor = EnvInterceptor {
+            data: [("$SECRET".to_string(), "2333".to_string())]
+                .into_iter()
+                .collect(),
+        };
+
+        let mut query = vec!["SELECT $SECRET1, $SECRET, $SECRET_2;".to_string()];
+        interceptor.before_execute(&mut query, &mut QueryContext::default());
+        assert_eq!(query, vec!["SELECT $SECRET1, 2333, $SECRET_2;"]);
+    }
+
+    #[test]
+    fn longer_name_first() {
+        let interceptor = EnvInterceptor {
+            data: [
+                ("$A".to_string(), "short".to_string()),
+                ("$AB".to_string(), "long".to_string()),
+            ]
+            .into_iter()
+            .collect(),
+        };
+
+        for _ in 0..16 {
+            let mut query = vec!["SELECT $AB, $A, $ABC;".to_string()];
+            interceptor.before_execute(&mut query, &mut QueryContext::default());
+            assert_eq!(query, vec!["SELECT long, short, $ABC;"]);
+        }
+    }
+
+    #[test]
+    fn value_not_rendered_again() {
+        let interceptor = EnvInterceptor {
+            data: [
+                ("$A".to_string(), "$B".to_string()),
+                ("$B".to_string(), "b".to_string()),
+            ]
+            .into_iter()
+            .collect(),
+        };
+
+        let mut query = vec!["SELECT $A, $B;".to_string()];
+        interceptor.before_execute(&mut query, &mut QueryContext::default());
+        assert_eq!(query, vec!["SELECT $B, b;"]);
+    }
 }
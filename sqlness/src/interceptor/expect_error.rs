@@ -0,0 +1,53 @@
+// Copyright 2024 CeresDB Project Authors. Licensed under Apache-2.0.
+
+use crate::case::QueryContext;
+use crate::error::Result;
+use crate::interceptor::{Interceptor, InterceptorFactory, InterceptorRef};
+
+pub const PREFIX: &str = "EXPECT_ERROR";
+
+/// Expect the query to fail, without depending on the error message.
+///
+/// # Example
+/// ``` sql
+/// -- SQLNESS EXPECT_ERROR
+/// SELECT * FROM no_such_table;
+///
+/// -- SQLNESS EXPECT_ERROR 42P01
+/// SELECT * FROM no_such_table;
+/// ```
+///
+/// If the query fails, the result is recorded as a `-- EXPECTED ERROR` line instead
+/// of the error message. An optional pattern must be a substring of the error
+/// message, like an error code. The case fails with [`SqlnessError::UnexpectedResult`]
+/// if the query succeeds or the pattern is not found.
+///
+/// Only errors returned as [`QueryResult::Error`] from [`Database::query_result`] are
+/// recognized. The output of [`Database::query`] is always treated as a success, so the
+/// [`Database`] needs to implement [`Database::query_result`] to work with this.
+///
+/// [`SqlnessError::UnexpectedResult`]: crate::SqlnessError::UnexpectedResult
+/// [`QueryResult::Error`]: crate::QueryResult::Error
+/// [`Database`]: crate::Database
+/// [`Database::query`]: crate::Database#tymethod.query
+/// [`Database::query_result`]: crate::Database#method.query_result
+#[derive(Debug)]
+pub struct ExpectErrorInterceptor {
+    pattern: String,
+}
+
+impl Interceptor for ExpectErrorInterceptor {
+    fn before_execute(&self, _: &mut Vec<String>, context: &mut QueryContext) {
+        context.settings.expect_error = Some(self.pattern.clone());
+    }
+}
+
+pub struct ExpectErrorInterceptorFactory;
+
+impl InterceptorFactory for ExpectErrorInterceptorFactory {
+    fn try_new(&self, ctx: &str) -> Result<InterceptorRef> {
+        Ok(Box::new(ExpectErrorInterceptor {
+            pattern: ctx.trim().to_string(),
+        }))
+    }
+}
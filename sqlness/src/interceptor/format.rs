@@ -0,0 +1,60 @@
+// Copyright 2024 CeresDB Project Authors. Licensed under Apache-2.0.
+
+use crate::case::QueryContext;
+use crate::error::Result;
+use crate::interceptor::{Interceptor, InterceptorFactory, InterceptorRef};
+use crate::{ResultFormat, SqlnessError};
+
+pub const PREFIX: &str = "FORMAT";
+
+/// Render the result of this query in the given format, overriding
+/// [`Config::result_format`].
+///
+/// # Example
+/// ``` sql
+/// -- SQLNESS FORMAT json
+/// SELECT * FROM t;
+/// ```
+///
+/// Valid formats are `table`, `csv` and `json`, see [`ResultFormat`]. It only
+/// applies to structured results returned by [`Database::query_result`].
+///
+/// [`Config::result_format`]: crate::Config#structfield.result_format
+/// [`Database::query_result`]: crate::Database#method.query_result
+#[derive(Debug)]
+pub struct FormatInterceptor {
+    format: ResultFormat,
+}
+
+impl Interceptor for FormatInterceptor {
+    fn before_execute(&self, _: &mut Vec<String>, context: &mut QueryContext) {
+        context.settings.format = Some(self.format);
+    }
+}
+
+pub struct FormatInterceptorFactory;
+
+impl InterceptorFactory for FormatInterceptorFactory {
+    fn try_new(&self, ctx: &str) -> Result<InterceptorRef> {
+        let format = ctx.parse().map_err(|msg| SqlnessError::InvalidContext {
+            prefix: PREFIX.to_string(),
+            msg,
+        })?;
+        Ok(Box::new(FormatInterceptor { format }))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parse_format() {
+        let interceptor = FormatInterceptorFactory.try_new("JSON").unwrap();
+        let mut context = QueryContext::default();
+        interceptor.before_execute(&mut vec![], &mut context);
+        assert_eq!(context.settings.format, Some(ResultFormat::Json));
+
+        assert!(FormatInterceptorFactory.try_new("xml").is_err());
+    }
+}
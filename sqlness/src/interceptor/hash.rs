@@ -0,0 +1,146 @@
+// Copyright 2024 CeresDB Project Authors. Licensed under Apache-2.0.
+
+use serde_json::json;
+use sha2::{Digest, Sha256};
+
+use crate::case::QueryContext;
+use crate::error::Result;
+use crate::interceptor::{Interceptor, InterceptorFactory, InterceptorRef};
+use crate::QueryResult;
+
+pub const PREFIX: &str = "HASH";
+
+/// Record the number of rows and a SHA-256 checksum of the result, instead of the
+/// rows themselves.
+///
+/// # Example
+/// `.sql` file:
+/// ``` sql
+/// -- SQLNESS HASH
+/// SELECT * FROM bulk ORDER BY id;
+/// ```
+///
+/// `.result` file:
+/// ``` sql
+/// -- SQLNESS HASH
+/// SELECT * FROM bulk ORDER BY id;
+///
+/// 10000 rows, sha256=5f70bf18a086007016e948b04aed3b82103a36bea41755b6cddfaf10ace3c6ef
+/// ```
+///
+/// It keeps result files small for large results, at the cost of readability: a
+/// changed result only shows a different checksum in the diff, not which rows
+/// changed. Record the full result once to inspect it when it happens.
+///
+/// For [`QueryResult::Rows`] the column names and every row, with `NULL` distinct
+/// from the string `"NULL"`, are hashed regardless of the result format. For
+/// [`QueryResult::Raw`] every non-empty line is a row. Error results are kept as
+/// is.
+///
+/// Rows are hashed in order, so use `ORDER BY` for a stable order, and declare
+/// `MASK_COLUMN` or `PROJECT` before it to leave out nondeterministic columns.
+///
+/// [`QueryResult::Rows`]: crate::QueryResult::Rows
+/// [`QueryResult::Raw`]: crate::QueryResult::Raw
+#[derive(Debug)]
+pub struct HashInterceptor;
+
+impl Interceptor for HashInterceptor {
+    fn after_execute(&self, result: &mut String, context: &mut QueryContext) {
+        context.map_rows(result, |rows| Ok(hash_result(rows)));
+    }
+}
+
+/// Replace the result by its row count and checksum, see [`HashInterceptor`].
+pub(crate) fn hash_result(result: QueryResult) -> QueryResult {
+    let mut hasher = Sha256::new();
+    let count = match &result {
+        QueryResult::Rows { columns, rows } => {
+            // One JSON array for each line, so cells can't be ambiguous.
+            hasher.update(json!(columns).to_string());
+            for row in rows {
+                hasher.update("\n");
+                hasher.update(json!(row).to_string());
+            }
+            rows.len()
+        }
+        QueryResult::Raw(raw) => {
+            let lines = raw.lines().filter(|line| !line.is_empty());
+            let mut count = 0;
+            for line in lines {
+                if count > 0 {
+                    hasher.update("\n");
+                }
+                hasher.update(line);
+                count += 1;
+            }
+            count
+        }
+        QueryResult::Error(_) => return result,
+    };
+
+    QueryResult::Raw(format!("{count} rows, sha256={:x}", hasher.finalize()))
+}
+
+pub struct HashInterceptorFactory;
+
+impl InterceptorFactory for HashInterceptorFactory {
+    fn try_new(&self, _: &str) -> Result<InterceptorRef> {
+        Ok(Box::new(HashInterceptor))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn hash_rows() {
+        let result = |rows: Vec<Vec<Option<&str>>>| QueryResult::Rows {
+            columns: vec!["a".into(), "b".into()],
+            rows: rows
+                .into_iter()
+                .map(|row| row.into_iter().map(|c| c.map(str::to_string)).collect())
+                .collect(),
+        };
+        let hashed = hash_result(result(vec![
+            vec![Some("1"), None],
+            vec![Some("2"), Some("x")],
+        ]));
+        let QueryResult::Raw(hashed) = hashed else {
+            panic!("{hashed:?}");
+        };
+        assert!(hashed.starts_with("2 rows, sha256="), "{hashed}");
+        assert_eq!(hashed.len(), "2 rows, sha256=".len() + 64);
+
+        // same rows hash the same, and any change is detected
+        assert_eq!(
+            hash_result(result(vec![
+                vec![Some("1"), None],
+                vec![Some("2"), Some("x")]
+            ])),
+            QueryResult::Raw(hashed.clone())
+        );
+        for rows in [
+            vec![vec![Some("2"), Some("x")], vec![Some("1"), None]],
+            vec![vec![Some("1"), Some("NULL")], vec![Some("2"), Some("x")]],
+            vec![vec![Some("1"), None], vec![Some("2"), Some("y")]],
+        ] {
+            assert_ne!(hash_result(result(rows)), QueryResult::Raw(hashed.clone()));
+        }
+    }
+
+    #[test]
+    fn hash_raw() {
+        // the hash of "a\nb"
+        assert_eq!(
+            hash_result(QueryResult::Raw("a\n\nb\n".into())),
+            QueryResult::Raw(
+                "2 rows, sha256=7e18f737311b2dc3b2f269dd78396b0351f14fb66efa879f768cb23181883c78"
+                    .into()
+            )
+        );
+        let error = QueryResult::Error("failed".into());
+        assert_eq!(hash_result(error.clone()), error);
+    }
+}
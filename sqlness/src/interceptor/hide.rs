@@ -0,0 +1,47 @@
+// Copyright 2024 CeresDB Project Authors. Licensed under Apache-2.0.
+
+use crate::case::QueryContext;
+use crate::error::Result;
+use crate::interceptor::{Interceptor, InterceptorFactory, InterceptorRef};
+
+pub const PREFIX: &str = "HIDE";
+
+/// Execute the query without recording its result.
+///
+/// # Example
+/// `.sql` file:
+/// ``` sql
+/// -- SQLNESS HIDE
+/// INSERT INTO t VALUES (1), (2), (3);
+/// ```
+///
+/// `.result` file:
+/// ``` sql
+/// -- SQLNESS HIDE
+/// INSERT INTO t VALUES (1), (2), (3);
+///
+/// -- HIDDEN
+/// ```
+///
+/// It's useful for noisy setup queries. To not mask a broken setup, the case still
+/// fails with [`SqlnessError::UnexpectedResult`] if the query returns
+/// [`QueryResult::Error`], unless it's also annotated with `EXPECT_ERROR`.
+///
+/// [`SqlnessError::UnexpectedResult`]: crate::SqlnessError::UnexpectedResult
+/// [`QueryResult::Error`]: crate::QueryResult::Error
+#[derive(Debug)]
+pub struct HideInterceptor;
+
+impl Interceptor for HideInterceptor {
+    fn before_execute(&self, _: &mut Vec<String>, context: &mut QueryContext) {
+        context.settings.hide = true;
+    }
+}
+
+pub struct HideInterceptorFactory;
+
+impl InterceptorFactory for HideInterceptorFactory {
+    fn try_new(&self, _: &str) -> Result<InterceptorRef> {
+        Ok(Box::new(HideInterceptor))
+    }
+}
@@ -0,0 +1,223 @@
+// Copyright 2024 CeresDB Project Authors. Licensed under Apache-2.0.
+
+use serde_json::Value;
+
+use crate::case::QueryContext;
+use crate::error::Result;
+use crate::interceptor::{parse_columns, Interceptor, InterceptorFactory, InterceptorRef};
+use crate::QueryResult;
+
+pub const PREFIX: &str = "JSON_CANON";
+
+/// Rewrite JSON cells in a canonical form, so key order and whitespace don't matter.
+///
+/// Grammar:
+/// ``` text
+/// -- SQLNESS JSON_CANON [<column>...]
+/// ```
+///
+/// # Example
+/// `.sql` file:
+/// ``` sql
+/// -- SQLNESS JSON_CANON 2
+/// SELECT id, doc FROM t;
+/// ```
+///
+/// `.result` file:
+/// ``` sql
+/// -- SQLNESS JSON_CANON 2
+/// SELECT id, doc FROM t;
+///
+/// +----+----------------------------+
+/// | id | doc                        |
+/// +----+----------------------------+
+/// | 1  | {"a":[1,{"b":2}],"c":null} |
+/// +----+----------------------------+
+/// ```
+///
+/// Each cell is parsed as JSON, and written back on a single line without spaces,
+/// with keys of objects sorted at every level. Only objects and arrays are
+/// rewritten: scalars like `1e2`, cells that aren't valid JSON and NULLs are kept
+/// as is. Columns are 1-based, and all columns are rewritten if none is given.
+///
+/// For [`QueryResult::Raw`] every line is a cell, and columns can't be given: the
+/// case fails with [`SqlnessError::UnexpectedResult`], as it does when a column is
+/// out of range.
+///
+/// [`QueryResult::Raw`]: crate::QueryResult::Raw
+/// [`SqlnessError::UnexpectedResult`]: crate::SqlnessError::UnexpectedResult
+#[derive(Debug)]
+pub struct JsonCanonInterceptor {
+    canon: JsonCanon,
+}
+
+/// Columns to canonicalize, 0-based, empty for all columns.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct JsonCanon {
+    columns: Vec<usize>,
+}
+
+impl JsonCanon {
+    /// Apply to a result, return an error message if it isn't possible.
+    pub(crate) fn apply(&self, result: QueryResult) -> std::result::Result<QueryResult, String> {
+        let (columns, mut rows) = match result {
+            QueryResult::Rows { columns, rows } => (columns, rows),
+            QueryResult::Raw(raw) if self.columns.is_empty() => {
+                let mut lines = raw.lines().map(canonicalize).collect::<Vec<_>>().join("\n");
+                if raw.ends_with('\n') {
+                    lines.push('\n');
+                }
+                return Ok(QueryResult::Raw(lines));
+            }
+            QueryResult::Raw(_) => {
+                return Err(
+                    "expect a structured result to canonicalize JSON columns, got a raw one".into(),
+                )
+            }
+            result @ QueryResult::Error(_) => return Ok(result),
+        };
+        if let Some(&index) = self.columns.iter().find(|&&i| i >= columns.len()) {
+            return Err(format!(
+                "column {} is out of range, the result has {} columns",
+                index + 1,
+                columns.len()
+            ));
+        }
+        for row in &mut rows {
+            for (index, cell) in row.iter_mut().enumerate() {
+                if !self.columns.is_empty() && !self.columns.contains(&index) {
+                    continue;
+                }
+                if let Some(cell) = cell {
+                    *cell = canonicalize(cell);
+                }
+            }
+        }
+
+        Ok(QueryResult::Rows { columns, rows })
+    }
+}
+
+/// Canonical form of `cell` if it's a JSON object or array, or `cell` itself.
+fn canonicalize(cell: &str) -> String {
+    match serde_json::from_str::<Value>(cell) {
+        Ok(value @ (Value::Object(_) | Value::Array(_))) => sort_keys(value).to_string(),
+        _ => cell.to_string(),
+    }
+}
+
+/// Sort keys of objects recursively. The map keeps the insertion order if
+/// `preserve_order` of serde_json is enabled, so keys are inserted in order.
+fn sort_keys(value: Value) -> Value {
+    match value {
+        Value::Object(map) => {
+            let mut entries = map.into_iter().collect::<Vec<_>>();
+            entries.sort_by(|a, b| a.0.cmp(&b.0));
+            Value::Object(
+                entries
+                    .into_iter()
+                    .map(|(key, value)| (key, sort_keys(value)))
+                    .collect(),
+            )
+        }
+        Value::Array(values) => Value::Array(values.into_iter().map(sort_keys).collect()),
+        value => value,
+    }
+}
+
+impl Interceptor for JsonCanonInterceptor {
+    fn after_execute(&self, result: &mut String, context: &mut QueryContext) {
+        context.map_rows(result, |rows| self.canon.apply(rows));
+    }
+}
+
+pub struct JsonCanonInterceptorFactory;
+
+impl InterceptorFactory for JsonCanonInterceptorFactory {
+    fn try_new(&self, ctx: &str) -> Result<InterceptorRef> {
+        let columns = if ctx.trim().is_empty() {
+            vec![]
+        } else {
+            parse_columns(PREFIX, ctx)?
+        };
+        Ok(Box::new(JsonCanonInterceptor {
+            canon: JsonCanon { columns },
+        }))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn canon(ctx: &str, result: QueryResult) -> std::result::Result<QueryResult, String> {
+        let interceptor = JsonCanonInterceptorFactory.try_new(ctx).unwrap();
+        QueryContext::after_execute_rows(interceptor.as_ref(), result)
+    }
+
+    #[test]
+    fn canonicalize_cells() {
+        let result = QueryResult::Rows {
+            columns: vec!["a".into(), "b".into()],
+            rows: vec![vec![
+                Some(r#"{ "c": null, "a": [1, {"z": 1, "b": 2}] }"#.into()),
+                Some(r#"{"b": 1, "a": 2}"#.into()),
+            ]],
+        };
+        let row = |a: &str, b: &str| vec![Some(a.to_string()), Some(b.to_string())];
+
+        assert_eq!(
+            canon("", result.clone()).unwrap(),
+            QueryResult::Rows {
+                columns: vec!["a".into(), "b".into()],
+                rows: vec![row(
+                    r#"{"a":[1,{"b":2,"z":1}],"c":null}"#,
+                    r#"{"a":2,"b":1}"#
+                )],
+            }
+        );
+        assert_eq!(
+            canon("2", result).unwrap(),
+            QueryResult::Rows {
+                columns: vec!["a".into(), "b".into()],
+                rows: vec![row(
+                    r#"{ "c": null, "a": [1, {"z": 1, "b": 2}] }"#,
+                    r#"{"a":2,"b":1}"#
+                )],
+            }
+        );
+    }
+
+    #[test]
+    fn invalid_json_is_kept() {
+        let result = QueryResult::Rows {
+            columns: vec!["a".into()],
+            rows: vec![
+                vec![Some("{not json".into())],
+                vec![Some("1e2".into())],
+                vec![None],
+            ],
+        };
+        assert_eq!(canon("", result.clone()).unwrap(), result);
+
+        assert_eq!(
+            canon(
+                "",
+                QueryResult::Raw("{\"b\": 1, \"a\": 2}\nplain text\n".into())
+            )
+            .unwrap(),
+            QueryResult::Raw("{\"a\":2,\"b\":1}\nplain text\n".into())
+        );
+    }
+
+    #[test]
+    fn unexpected_result() {
+        assert!(canon("1", QueryResult::Raw("{}".into())).is_err());
+        let result = QueryResult::Rows {
+            columns: vec!["a".into()],
+            rows: vec![],
+        };
+        assert!(canon("2", result).is_err());
+        assert!(JsonCanonInterceptorFactory.try_new("0").is_err());
+    }
+}
@@ -0,0 +1,85 @@
+// Copyright 2024 CeresDB Project Authors. Licensed under Apache-2.0.
+
+use crate::case::QueryContext;
+use crate::error::Result;
+use crate::interceptor::{Interceptor, InterceptorFactory, InterceptorRef};
+use crate::SqlnessError;
+
+pub const PREFIX: &str = "LIMIT";
+
+/// Marker line appended to a truncated result.
+const TRUNCATED_MARKER: &str = "... (truncated)";
+
+/// Keep only the first N lines of the query result.
+///
+/// # Example
+/// `.sql` file:
+/// ``` sql
+/// -- SQLNESS LIMIT 2
+/// SELECT * from values (1), (2), (3);
+/// ```
+///
+/// `.result` file:
+/// ``` sql
+/// -- SQLNESS LIMIT 2
+/// SELECT * from values (1), (2), (3);
+///
+/// 1
+/// 2
+/// ... (truncated)
+/// ```
+///
+/// It works on the formatted result, so header lines of a table are counted as
+/// well. A result with no more than N lines is kept as is, without the marker.
+#[derive(Debug)]
+pub struct LimitInterceptor {
+    limit: usize,
+}
+
+impl Interceptor for LimitInterceptor {
+    fn after_execute(&self, result: &mut String, _: &mut QueryContext) {
+        if result.lines().count() <= self.limit {
+            return;
+        }
+
+        let mut lines = result.lines().take(self.limit).collect::<Vec<_>>();
+        lines.push(TRUNCATED_MARKER);
+        *result = lines.join("\n");
+    }
+}
+
+pub struct LimitInterceptorFactory;
+
+impl InterceptorFactory for LimitInterceptorFactory {
+    fn try_new(&self, ctx: &str) -> Result<InterceptorRef> {
+        let limit = ctx.parse().map_err(|e| SqlnessError::InvalidContext {
+            prefix: PREFIX.to_string(),
+            msg: format!("Failed to parse line limit {ctx}: {e}"),
+        })?;
+        Ok(Box::new(LimitInterceptor { limit }))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn truncate_result() {
+        let interceptor = LimitInterceptorFactory.try_new("2").unwrap();
+
+        let mut result = "1\n2\n3".to_string();
+        interceptor.after_execute(&mut result, &mut QueryContext::default());
+        assert_eq!(result, "1\n2\n... (truncated)");
+
+        let mut result = "1\n2".to_string();
+        interceptor.after_execute(&mut result, &mut QueryContext::default());
+        assert_eq!(result, "1\n2");
+    }
+
+    #[test]
+    fn invalid_limit() {
+        assert!(LimitInterceptorFactory.try_new("-1").is_err());
+        assert!(LimitInterceptorFactory.try_new("").is_err());
+    }
+}
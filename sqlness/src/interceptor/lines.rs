@@ -0,0 +1,152 @@
+// Copyright 2024 CeresDB Project Authors. Licensed under Apache-2.0.
+
+use std::ops::RangeInclusive;
+
+use crate::case::QueryContext;
+use crate::error::Result;
+use crate::interceptor::{Interceptor, InterceptorFactory, InterceptorRef};
+use crate::SqlnessError;
+
+pub const PREFIX: &str = "LINES";
+
+/// Keep only the given lines of the result, to record the parts of a free-form
+/// output that matter.
+///
+/// Grammar:
+/// ``` text
+/// -- SQLNESS LINES <line>|<first>-<last>...
+/// ```
+///
+/// # Example
+/// `.sql` file:
+/// ``` sql
+/// -- SQLNESS LINES 1-2 4
+/// EXPLAIN SELECT * FROM t WHERE id = 1;
+/// ```
+///
+/// `.result` file:
+/// ``` sql
+/// -- SQLNESS LINES 1-2 4
+/// EXPLAIN SELECT * FROM t WHERE id = 1;
+///
+/// Projection: t.id, t.name
+///   Filter: t.id = 1
+///     TableScan: t
+/// ```
+///
+/// Lines are 1-based and ranges inclusive, separated by spaces or commas. Lines of
+/// multiple `LINES` add up, and they are kept in the order of the result, once
+/// each. It works on the formatted result like `LIMIT`, so header lines of a table
+/// are counted as well. The case fails with [`SqlnessError::UnexpectedResult`]
+/// when a line is beyond the end of the result.
+///
+/// [`SqlnessError::UnexpectedResult`]: crate::SqlnessError::UnexpectedResult
+#[derive(Debug)]
+pub struct LinesInterceptor {
+    ranges: Vec<RangeInclusive<usize>>,
+}
+
+impl Interceptor for LinesInterceptor {
+    fn after_execute(&self, result: &mut String, context: &mut QueryContext) {
+        if context.failure.is_some() {
+            return;
+        }
+        match pick_lines(result, &self.ranges) {
+            Ok(lines) => *result = lines,
+            Err(msg) => context.failure = Some(msg),
+        }
+    }
+}
+
+/// Keep lines of `result` in `ranges`, return an error message if one is out of
+/// range.
+fn pick_lines(
+    result: &str,
+    ranges: &[RangeInclusive<usize>],
+) -> std::result::Result<String, String> {
+    let lines = result.lines().collect::<Vec<_>>();
+    if let Some(last) = ranges.iter().map(|range| *range.end()).max() {
+        if last > lines.len() {
+            return Err(format!(
+                "line {last} is out of range, the result has {} lines",
+                lines.len()
+            ));
+        }
+    }
+
+    Ok(lines
+        .into_iter()
+        .enumerate()
+        .filter(|(i, _)| ranges.iter().any(|range| range.contains(&(i + 1))))
+        .map(|(_, line)| line)
+        .collect::<Vec<_>>()
+        .join("\n"))
+}
+
+pub struct LinesInterceptorFactory;
+
+impl InterceptorFactory for LinesInterceptorFactory {
+    fn try_new(&self, ctx: &str) -> Result<InterceptorRef> {
+        let invalid = |msg: String| SqlnessError::InvalidContext {
+            prefix: PREFIX.to_string(),
+            msg,
+        };
+        let parse = |line: &str| match line.trim().parse::<usize>() {
+            Ok(0) | Err(_) => Err(invalid(format!("Expect a 1-based line, got {line}"))),
+            Ok(line) => Ok(line),
+        };
+        let ranges = ctx
+            .split([' ', ','])
+            .filter(|s| !s.is_empty())
+            .map(|range| {
+                let (first, last) = match range.split_once('-') {
+                    Some((first, last)) => (parse(first)?, parse(last)?),
+                    None => (parse(range)?, parse(range)?),
+                };
+                if first > last {
+                    return Err(invalid(format!(
+                        "Expect <first>-<last> in order, got {range}"
+                    )));
+                }
+                Ok(first..=last)
+            })
+            .collect::<Result<Vec<_>>>()?;
+        if ranges.is_empty() {
+            return Err(invalid("Expect lines to keep".to_string()));
+        }
+        Ok(Box::new(LinesInterceptor { ranges }))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn pick(ctx: &str, result: &str) -> std::result::Result<String, String> {
+        let mut context = QueryContext::default();
+        let mut result = result.to_string();
+        LinesInterceptorFactory
+            .try_new(ctx)
+            .unwrap()
+            .after_execute(&mut result, &mut context);
+        context.failure.map_or(Ok(result), Err)
+    }
+
+    #[test]
+    fn pick_ranges() {
+        let result = "a\nb\nc\nd\ne";
+        assert_eq!(pick("1-2 4", result).unwrap(), "a\nb\nd");
+        assert_eq!(pick("4,2-3,3", result).unwrap(), "b\nc\nd");
+        assert_eq!(
+            pick("5-6", result),
+            Err("line 6 is out of range, the result has 5 lines".to_string())
+        );
+    }
+
+    #[test]
+    fn invalid_ranges() {
+        for ctx in ["", "0", "3-1", "a", "1-"] {
+            assert!(LinesInterceptorFactory.try_new(ctx).is_err(), "{ctx}");
+        }
+    }
+}
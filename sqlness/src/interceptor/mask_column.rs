@@ -0,0 +1,156 @@
+// Copyright 2024 CeresDB Project Authors. Licensed under Apache-2.0.
+
+use crate::case::QueryContext;
+use crate::error::Result;
+use crate::interceptor::{parse_columns, Interceptor, InterceptorFactory, InterceptorRef};
+use crate::QueryResult;
+
+pub const PREFIX: &str = "MASK_COLUMN";
+
+/// Replace values of some columns with a placeholder, eg. IDs from a sequence.
+///
+/// Grammar:
+/// ``` text
+/// -- SQLNESS MASK_COLUMN <column>... [AS <placeholder>]
+/// ```
+///
+/// # Example
+/// `.sql` file:
+/// ``` sql
+/// -- SQLNESS MASK_COLUMN 1
+/// SELECT id, name FROM t;
+/// ```
+///
+/// `.result` file:
+/// ``` sql
+/// -- SQLNESS MASK_COLUMN 1
+/// SELECT id, name FROM t;
+///
+/// +------+------+
+/// | id   | name |
+/// +------+------+
+/// | <id> | a    |
+/// | <id> | b    |
+/// +------+------+
+/// ```
+///
+/// Columns are 1-based. The placeholder defaults to the column name in angle
+/// brackets. NULL cells are kept, so whether a value is present is still checked.
+/// Like `PROJECT`, it requires [`QueryResult::Rows`], and the case fails with
+/// [`SqlnessError::UnexpectedResult`] for [`QueryResult::Raw`] or if a column is
+/// out of range.
+///
+/// [`QueryResult::Rows`]: crate::QueryResult::Rows
+/// [`QueryResult::Raw`]: crate::QueryResult::Raw
+/// [`SqlnessError::UnexpectedResult`]: crate::SqlnessError::UnexpectedResult
+#[derive(Debug)]
+pub struct MaskColumnInterceptor {
+    mask: ColumnMask,
+}
+
+/// Columns to mask, 0-based, and the placeholder.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct ColumnMask {
+    columns: Vec<usize>,
+    placeholder: Option<String>,
+}
+
+impl ColumnMask {
+    /// Apply to a structured result, return an error message if it isn't possible.
+    pub(crate) fn apply(&self, result: QueryResult) -> std::result::Result<QueryResult, String> {
+        let (columns, mut rows) = match result {
+            QueryResult::Rows { columns, rows } => (columns, rows),
+            QueryResult::Raw(_) => {
+                return Err("expect a structured result to mask columns, got a raw one".into())
+            }
+            result @ QueryResult::Error(_) => return Ok(result),
+        };
+        for &index in &self.columns {
+            let column = columns.get(index).ok_or_else(|| {
+                format!(
+                    "column {} is out of range, the result has {} columns",
+                    index + 1,
+                    columns.len()
+                )
+            })?;
+            let placeholder = self
+                .placeholder
+                .clone()
+                .unwrap_or_else(|| format!("<{column}>"));
+            for cell in rows.iter_mut().filter_map(|row| row.get_mut(index)) {
+                if cell.is_some() {
+                    *cell = Some(placeholder.clone());
+                }
+            }
+        }
+
+        Ok(QueryResult::Rows { columns, rows })
+    }
+}
+
+impl Interceptor for MaskColumnInterceptor {
+    fn after_execute(&self, result: &mut String, context: &mut QueryContext) {
+        context.map_rows(result, |rows| self.mask.apply(rows));
+    }
+}
+
+pub struct MaskColumnInterceptorFactory;
+
+impl InterceptorFactory for MaskColumnInterceptorFactory {
+    fn try_new(&self, ctx: &str) -> Result<InterceptorRef> {
+        let (columns, placeholder) = match ctx.split_once(" AS ") {
+            Some((columns, placeholder)) => (columns, Some(placeholder.trim().to_string())),
+            None => (ctx, None),
+        };
+        let columns = parse_columns(PREFIX, columns)?;
+        Ok(Box::new(MaskColumnInterceptor {
+            mask: ColumnMask {
+                columns,
+                placeholder,
+            },
+        }))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn mask_columns() {
+        let rows = || QueryResult::Rows {
+            columns: vec!["id".into(), "name".into(), "seq".into()],
+            rows: vec![
+                vec![Some("1".into()), Some("a".into()), Some("7".into())],
+                vec![Some("2".into()), Some("b".into()), None],
+            ],
+        };
+        let mask = |ctx| {
+            let interceptor = MaskColumnInterceptorFactory.try_new(ctx).unwrap();
+            QueryContext::after_execute_rows(interceptor.as_ref(), rows())
+        };
+
+        assert_eq!(
+            mask("1 3"),
+            Ok(QueryResult::Rows {
+                columns: vec!["id".into(), "name".into(), "seq".into()],
+                rows: vec![
+                    vec![Some("<id>".into()), Some("a".into()), Some("<seq>".into())],
+                    vec![Some("<id>".into()), Some("b".into()), None],
+                ],
+            })
+        );
+        assert_eq!(
+            mask("1 AS ?"),
+            Ok(QueryResult::Rows {
+                columns: vec!["id".into(), "name".into(), "seq".into()],
+                rows: vec![
+                    vec![Some("?".into()), Some("a".into()), Some("7".into())],
+                    vec![Some("?".into()), Some("b".into()), None],
+                ],
+            })
+        );
+        assert!(mask("4").is_err());
+        assert!(MaskColumnInterceptorFactory.try_new("AS x").is_err());
+    }
+}
@@ -0,0 +1,71 @@
+// Copyright 2024 CeresDB Project Authors. Licensed under Apache-2.0.
+
+use std::time::Duration;
+
+use crate::case::QueryContext;
+use crate::error::Result;
+use crate::interceptor::{Interceptor, InterceptorFactory, InterceptorRef};
+use crate::SqlnessError;
+
+pub const PREFIX: &str = "MAX_DURATION";
+
+/// Fail the case if the query takes longer than the given duration.
+///
+/// # Example
+/// ``` sql
+/// -- SQLNESS MAX_DURATION 200ms
+/// SELECT * FROM t WHERE id = 1;
+/// ```
+///
+/// The duration format is the same as `SLEEP` interceptor. It measures the calls
+/// to [`Database`] like `TIMING`, but the duration isn't recorded in the result
+/// file. The case errors with [`SqlnessError::SlowQuery`] when the query is slower,
+/// and the measured duration is reported in [`CaseReport::query_durations`]
+/// either way, eg. to follow the trend in the JSON report.
+///
+/// Durations measured on shared CI machines are noisy, so the threshold should be
+/// generous, like several times the usual duration, to not fail randomly. With
+/// `RETRY` or `REPEAT`, only the last execution is measured.
+///
+/// [`Database`]: crate::Database
+/// [`SqlnessError::SlowQuery`]: crate::SqlnessError::SlowQuery
+/// [`CaseReport::query_durations`]: crate::CaseReport::query_durations
+#[derive(Debug)]
+pub struct MaxDurationInterceptor {
+    duration: Duration,
+}
+
+impl Interceptor for MaxDurationInterceptor {
+    fn before_execute(&self, _: &mut Vec<String>, context: &mut QueryContext) {
+        context.settings.max_duration = Some(self.duration);
+    }
+}
+
+pub struct MaxDurationInterceptorFactory;
+
+impl InterceptorFactory for MaxDurationInterceptorFactory {
+    fn try_new(&self, ctx: &str) -> Result<InterceptorRef> {
+        let duration = duration_str::parse(ctx).map_err(|e| SqlnessError::InvalidContext {
+            prefix: PREFIX.to_string(),
+            msg: format!("Failed to parse duration: {}", e),
+        })?;
+        Ok(Box::new(MaxDurationInterceptor { duration }))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn set_max_duration() {
+        let interceptor = MaxDurationInterceptorFactory.try_new("200ms").unwrap();
+        let mut context = QueryContext::default();
+        interceptor.before_execute(&mut vec![], &mut context);
+        assert_eq!(
+            context.settings.max_duration,
+            Some(Duration::from_millis(200))
+        );
+        assert!(MaxDurationInterceptorFactory.try_new("fast").is_err());
+    }
+}
@@ -0,0 +1,85 @@
+// Copyright 2024 CeresDB Project Authors. Licensed under Apache-2.0.
+
+use crate::case::QueryContext;
+use crate::error::Result;
+use crate::interceptor::{Interceptor, InterceptorFactory, InterceptorRef};
+use crate::SqlnessError;
+
+pub const PREFIX: &str = "NULL_AS";
+
+/// Record NULL cells as the given token instead of `NULL`.
+///
+/// # Example
+/// `.sql` file:
+/// ``` sql
+/// -- SQLNESS NULL_AS \N
+/// SELECT id, name FROM t;
+/// ```
+///
+/// `.result` file:
+/// ``` sql
+/// -- SQLNESS NULL_AS \N
+/// SELECT id, name FROM t;
+///
+/// +----+------+
+/// | id | name |
+/// +----+------+
+/// | 1  | \N   |
+/// +----+------+
+/// ```
+///
+/// The token is the rest of the line, and can't be empty. Only NULLs of
+/// [`QueryResult::Rows`] are rewritten, which are `None` cells: an empty string or
+/// a `NULL` string returned by the database are values, and are kept. With
+/// [`ResultFormat::Csv`] a string equal to the token is quoted to tell it from
+/// NULL, like `NULL` strings are without the interceptor. [`ResultFormat::Json`]
+/// keeps `null`, and [`QueryResult::Raw`] results are written as is, use `REPLACE`
+/// to rewrite how the database prints NULL there.
+///
+/// [`QueryResult::Rows`]: crate::QueryResult::Rows
+/// [`QueryResult::Raw`]: crate::QueryResult::Raw
+/// [`ResultFormat::Csv`]: crate::ResultFormat::Csv
+/// [`ResultFormat::Json`]: crate::ResultFormat::Json
+#[derive(Debug)]
+pub struct NullAsInterceptor {
+    token: String,
+}
+
+impl Interceptor for NullAsInterceptor {
+    fn before_execute(&self, _: &mut Vec<String>, context: &mut QueryContext) {
+        context.settings.null_as = Some(self.token.clone());
+    }
+}
+
+pub struct NullAsInterceptorFactory;
+
+impl InterceptorFactory for NullAsInterceptorFactory {
+    fn try_new(&self, ctx: &str) -> Result<InterceptorRef> {
+        let token = ctx.trim();
+        if token.is_empty() {
+            return Err(SqlnessError::InvalidContext {
+                prefix: PREFIX.to_string(),
+                msg: "Expect a token for NULL".to_string(),
+            });
+        }
+        Ok(Box::new(NullAsInterceptor {
+            token: token.to_string(),
+        }))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parse_token() {
+        let mut context = QueryContext::default();
+        NullAsInterceptorFactory
+            .try_new(" \\N ")
+            .unwrap()
+            .before_execute(&mut vec![], &mut context);
+        assert_eq!(context.settings.null_as.as_deref(), Some("\\N"));
+        assert!(NullAsInterceptorFactory.try_new(" ").is_err());
+    }
+}
@@ -0,0 +1,91 @@
+// Copyright 2024 CeresDB Project Authors. Licensed under Apache-2.0.
+
+use crate::case::QueryContext;
+use crate::error::Result;
+use crate::interceptor::{Interceptor, InterceptorFactory, InterceptorRef};
+use crate::SqlnessError;
+
+pub const PREFIX: &str = "OPT";
+
+/// Pass execution options that aren't SQL, like query tags or resource groups, to
+/// [`Database::query_with_opts`].
+///
+/// # Example
+/// ``` sql
+/// -- SQLNESS OPT resource_group=etl query_tag=nightly
+/// SELECT count(*) FROM events;
+/// ```
+///
+/// Options are `key=value` pairs separated by spaces, the value can't contain
+/// spaces. Multiple `OPT` add up, and a later key overwrites an earlier one. The
+/// result file keeps the interceptor and the query as is.
+///
+/// sqlness doesn't interpret options: what a key means, and whether an unknown one
+/// is ignored or rejected, is up to the backend. A backend implementing
+/// [`Database::query_result`] reads them from [`QueryContext::options`].
+///
+/// [`Database::query_with_opts`]: crate::Database::query_with_opts
+/// [`Database::query_result`]: crate::Database::query_result
+#[derive(Debug)]
+pub struct OptInterceptor {
+    options: Vec<(String, String)>,
+}
+
+impl Interceptor for OptInterceptor {
+    fn before_execute(&self, _: &mut Vec<String>, context: &mut QueryContext) {
+        for (key, value) in &self.options {
+            context.settings.options.insert(key.clone(), value.clone());
+        }
+    }
+}
+
+pub struct OptInterceptorFactory;
+
+impl InterceptorFactory for OptInterceptorFactory {
+    fn try_new(&self, ctx: &str) -> Result<InterceptorRef> {
+        let options = ctx
+            .split_whitespace()
+            .map(|pair| match pair.split_once('=') {
+                Some((key, value)) if !key.is_empty() => Ok((key.to_string(), value.to_string())),
+                _ => Err(SqlnessError::InvalidContext {
+                    prefix: PREFIX.to_string(),
+                    msg: format!("Expect <key>=<value>, got {pair}"),
+                }),
+            })
+            .collect::<Result<Vec<_>>>()?;
+        if options.is_empty() {
+            return Err(SqlnessError::InvalidContext {
+                prefix: PREFIX.to_string(),
+                msg: "Expect at least one <key>=<value>".to_string(),
+            });
+        }
+        Ok(Box::new(OptInterceptor { options }))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn collect_options() {
+        let mut context = QueryContext::default();
+        for ctx in ["resource_group=etl tag=a", "tag=b=c"] {
+            OptInterceptorFactory
+                .try_new(ctx)
+                .unwrap()
+                .before_execute(&mut vec![], &mut context);
+        }
+        assert_eq!(context.options().len(), 2);
+        assert_eq!(context.options()["resource_group"], "etl");
+        assert_eq!(context.options()["tag"], "b=c");
+        assert!(context.context.is_empty());
+    }
+
+    #[test]
+    fn invalid_options() {
+        assert!(OptInterceptorFactory.try_new("").is_err());
+        assert!(OptInterceptorFactory.try_new("a=1 b").is_err());
+        assert!(OptInterceptorFactory.try_new("=1").is_err());
+    }
+}
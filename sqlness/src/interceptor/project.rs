@@ -0,0 +1,157 @@
+// Copyright 2024 CeresDB Project Authors. Licensed under Apache-2.0.
+
+use crate::case::QueryContext;
+use crate::error::Result;
+use crate::interceptor::{parse_columns, Interceptor, InterceptorFactory, InterceptorRef};
+use crate::QueryResult;
+
+pub const PREFIX: &str = "PROJECT";
+pub const DROP_PREFIX: &str = "DROP_COLUMN";
+
+/// Only keep some columns of the result, or drop some of them with `DROP_COLUMN`.
+///
+/// # Example
+/// ``` sql
+/// -- SQLNESS PROJECT 1 2 4
+/// SELECT id, name, addr, value FROM t;
+///
+/// -- SQLNESS DROP_COLUMN 3
+/// SELECT id, name, addr, value FROM t;
+/// ```
+///
+/// Columns are 1-based. `PROJECT` keeps the listed columns in the listed order,
+/// while `DROP_COLUMN` removes them and keeps the others in place.
+///
+/// Columns are only reliable in a structured result, so the query should return
+/// [`QueryResult::Rows`], and the case fails with [`SqlnessError::UnexpectedResult`]
+/// for [`QueryResult::Raw`] or if a column is out of range. Error results are kept
+/// as is. Multiple projections apply each on the columns left by the previous one,
+/// and `CAPTURE` sees the columns left.
+///
+/// [`QueryResult::Rows`]: crate::QueryResult::Rows
+/// [`QueryResult::Raw`]: crate::QueryResult::Raw
+/// [`SqlnessError::UnexpectedResult`]: crate::SqlnessError::UnexpectedResult
+#[derive(Debug)]
+pub struct ProjectInterceptor {
+    projection: Projection,
+}
+
+/// Columns to keep or drop, 0-based.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum Projection {
+    Keep(Vec<usize>),
+    Drop(Vec<usize>),
+}
+
+impl Projection {
+    /// Apply to a structured result, return an error message if it isn't possible.
+    pub(crate) fn apply(&self, result: QueryResult) -> std::result::Result<QueryResult, String> {
+        let (columns, rows) = match result {
+            QueryResult::Rows { columns, rows } => (columns, rows),
+            QueryResult::Raw(_) => {
+                return Err("expect a structured result to project columns, got a raw one".into())
+            }
+            result @ QueryResult::Error(_) => return Ok(result),
+        };
+        let indices = match self {
+            Projection::Keep(indices) | Projection::Drop(indices) => indices,
+        };
+        if let Some(index) = indices.iter().find(|i| **i >= columns.len()) {
+            return Err(format!(
+                "column {} is out of range, the result has {} columns",
+                index + 1,
+                columns.len()
+            ));
+        }
+
+        Ok(QueryResult::Rows {
+            columns: self.project(columns),
+            rows: rows.into_iter().map(|row| self.project(row)).collect(),
+        })
+    }
+
+    fn project<T: Clone>(&self, cells: Vec<T>) -> Vec<T> {
+        match self {
+            Projection::Keep(indices) => indices
+                .iter()
+                .filter_map(|i| cells.get(*i).cloned())
+                .collect(),
+            Projection::Drop(indices) => cells
+                .into_iter()
+                .enumerate()
+                .filter(|(i, _)| !indices.contains(i))
+                .map(|(_, cell)| cell)
+                .collect(),
+        }
+    }
+}
+
+impl Interceptor for ProjectInterceptor {
+    fn after_execute(&self, result: &mut String, context: &mut QueryContext) {
+        context.map_rows(result, |rows| self.projection.apply(rows));
+    }
+}
+
+pub struct ProjectInterceptorFactory;
+
+impl InterceptorFactory for ProjectInterceptorFactory {
+    fn try_new(&self, ctx: &str) -> Result<InterceptorRef> {
+        let columns = parse_columns(PREFIX, ctx)?;
+        Ok(Box::new(ProjectInterceptor {
+            projection: Projection::Keep(columns),
+        }))
+    }
+}
+
+pub struct DropColumnInterceptorFactory;
+
+impl InterceptorFactory for DropColumnInterceptorFactory {
+    fn try_new(&self, ctx: &str) -> Result<InterceptorRef> {
+        let columns = parse_columns(DROP_PREFIX, ctx)?;
+        Ok(Box::new(ProjectInterceptor {
+            projection: Projection::Drop(columns),
+        }))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn rows() -> QueryResult {
+        QueryResult::Rows {
+            columns: vec!["a".into(), "b".into(), "c".into()],
+            rows: vec![vec![Some("1".into()), None, Some("3".into())]],
+        }
+    }
+
+    #[test]
+    fn keep_and_drop() {
+        assert_eq!(
+            Projection::Keep(vec![2, 0]).apply(rows()),
+            Ok(QueryResult::Rows {
+                columns: vec!["c".into(), "a".into()],
+                rows: vec![vec![Some("3".into()), Some("1".into())]],
+            })
+        );
+        assert_eq!(
+            Projection::Drop(vec![0]).apply(rows()),
+            Ok(QueryResult::Rows {
+                columns: vec!["b".into(), "c".into()],
+                rows: vec![vec![None, Some("3".into())]],
+            })
+        );
+    }
+
+    #[test]
+    fn invalid_projection() {
+        assert!(Projection::Keep(vec![3]).apply(rows()).is_err());
+        assert!(Projection::Drop(vec![0])
+            .apply(QueryResult::Raw("1".into()))
+            .is_err());
+        let error = QueryResult::Error("failed".into());
+        assert_eq!(Projection::Keep(vec![3]).apply(error.clone()), Ok(error));
+        assert!(ProjectInterceptorFactory.try_new("0").is_err());
+        assert!(DropColumnInterceptorFactory.try_new("").is_err());
+    }
+}
@@ -0,0 +1,148 @@
+// Copyright 2024 CeresDB Project Authors. Licensed under Apache-2.0.
+
+use regex::Regex;
+
+use crate::case::QueryContext;
+use crate::error::Result;
+use crate::interceptor::{Interceptor, InterceptorFactory, InterceptorRef};
+use crate::SqlnessError;
+
+pub const PREFIX: &str = "REDACT";
+
+/// Replace only the named groups of matched occurrences in the execution result,
+/// keeping the rest of each match.
+///
+/// Grammar:
+/// ``` text
+/// -- SQLNESS REDACT <pattern> [placeholder]
+/// ```
+///
+/// # Example
+/// `.sql` file:
+/// ``` sql
+/// -- SQLNESS REDACT created_at=(?P<ts>\S+)
+/// SHOW JOB 1;
+/// ```
+///
+/// `.result` file:
+/// ``` sql
+/// -- SQLNESS REDACT created_at=(?P<ts>\S+)
+/// SHOW JOB 1;
+///
+/// id=1 created_at=<ts> state=done
+/// ```
+///
+/// Like `REPLACE`, the pattern is a regular expression without spaces, and
+/// multiple `REDACT` and `REPLACE` are evaluated in order. The pattern must have
+/// at least one named group, every named group is replaced by `placeholder`, or by
+/// `<name>` if it's not given. A group nested in another named group is replaced
+/// with the outer one.
+#[derive(Debug)]
+pub struct RedactInterceptor {
+    pattern: Regex,
+    placeholder: Option<String>,
+}
+
+impl Interceptor for RedactInterceptor {
+    fn after_execute(&self, result: &mut String, _: &mut QueryContext) {
+        let names = self.pattern.capture_names().flatten().collect::<Vec<_>>();
+        let mut redacted = String::with_capacity(result.len());
+        let mut last = 0;
+        for captures in self.pattern.captures_iter(result) {
+            let mut groups = names
+                .iter()
+                .filter_map(|name| captures.name(name).map(|m| (m, *name)))
+                .collect::<Vec<_>>();
+            groups.sort_by_key(|(m, _)| (m.start(), std::cmp::Reverse(m.end())));
+            for (m, name) in groups {
+                if m.start() < last {
+                    continue;
+                }
+                redacted.push_str(&result[last..m.start()]);
+                match &self.placeholder {
+                    Some(placeholder) => redacted.push_str(placeholder),
+                    None => redacted.push_str(&format!("<{name}>")),
+                }
+                last = m.end();
+            }
+        }
+        redacted.push_str(&result[last..]);
+        *result = redacted;
+    }
+}
+
+pub struct RedactInterceptorFactory;
+
+impl InterceptorFactory for RedactInterceptorFactory {
+    fn try_new(&self, ctx: &str) -> Result<InterceptorRef> {
+        let mut args = ctx.splitn(2, ' ');
+        let pattern = args.next().unwrap_or_default();
+        if pattern.is_empty() {
+            return Err(SqlnessError::InvalidContext {
+                prefix: PREFIX.to_string(),
+                msg: "Expect <pattern> [placeholder]".to_string(),
+            });
+        }
+        let pattern = Regex::new(pattern).map_err(|e| SqlnessError::InvalidContext {
+            prefix: PREFIX.to_string(),
+            msg: format!("Invalid regexp {pattern}, err:{e}"),
+        })?;
+        if pattern.capture_names().flatten().next().is_none() {
+            return Err(SqlnessError::InvalidContext {
+                prefix: PREFIX.to_string(),
+                msg: format!("Expect a named group like (?P<name>...) in {pattern}"),
+            });
+        }
+        let placeholder = args.next().map(str::to_string);
+        Ok(Box::new(RedactInterceptor {
+            pattern,
+            placeholder,
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn redact(ctx: &str, result: &str) -> String {
+        let interceptor = RedactInterceptorFactory.try_new(ctx).unwrap();
+        let mut result = result.to_string();
+        interceptor.after_execute(&mut result, &mut QueryContext::default());
+        result
+    }
+
+    #[test]
+    fn redact_named_groups() {
+        assert_eq!(
+            redact(
+                r"created_at=(?P<ts>\S+)",
+                "id=1 created_at=2024-01-01T00:00:00Z\nid=2 created_at=2024-01-02T00:00:00Z"
+            ),
+            "id=1 created_at=<ts>\nid=2 created_at=<ts>"
+        );
+        assert_eq!(
+            redact(r"(\w+)=(?P<v>\d+)", "a=1, b=x, c=3"),
+            "a=<v>, b=x, c=<v>"
+        );
+        assert_eq!(
+            redact(r"(?P<host>\w+):(?P<port>\d+) <addr>", "at db:5432 now"),
+            "at <addr>:<addr> now"
+        );
+        assert_eq!(redact(r"id=(?P<id>(?P<digit>\d)\d*)", "id=123"), "id=<id>");
+        // an optional group that doesn't participate is kept
+        assert_eq!(
+            redact(r"took=(?P<ms>\d+)?ms", "took=ms, took=12ms"),
+            "took=ms, took=<ms>ms"
+        );
+    }
+
+    #[test]
+    fn invalid_context() {
+        assert!(RedactInterceptorFactory.try_new("").is_err());
+        assert!(RedactInterceptorFactory.try_new(r"(\d+").is_err());
+        assert!(RedactInterceptorFactory
+            .try_new(r"created_at=(\S+)")
+            .is_err());
+    }
+}
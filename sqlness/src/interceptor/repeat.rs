@@ -0,0 +1,64 @@
+// Copyright 2024 CeresDB Project Authors. Licensed under Apache-2.0.
+
+use crate::case::QueryContext;
+use crate::error::Result;
+use crate::interceptor::{Interceptor, InterceptorFactory, InterceptorRef};
+use crate::SqlnessError;
+
+pub const PREFIX: &str = "REPEAT";
+
+/// Execute the query N times, and only record the output of the last execution.
+///
+/// # Example
+/// ``` sql
+/// -- SQLNESS REPEAT 100
+/// INSERT INTO t VALUES (1) ON CONFLICT DO NOTHING;
+/// ```
+///
+/// If an execution before the last one returns [`QueryResult::Error`], the case fails
+/// immediately with [`SqlnessError::UnexpectedResult`] including the iteration index.
+/// Interceptors running before execution (like `TEMPLATE` or `SLEEP`) are only applied
+/// once.
+///
+/// [`QueryResult::Error`]: crate::QueryResult::Error
+/// [`SqlnessError::UnexpectedResult`]: crate::SqlnessError::UnexpectedResult
+#[derive(Debug)]
+pub struct RepeatInterceptor {
+    times: usize,
+}
+
+impl Interceptor for RepeatInterceptor {
+    fn before_execute(&self, _: &mut Vec<String>, context: &mut QueryContext) {
+        context.settings.repeat = Some(self.times);
+    }
+}
+
+pub struct RepeatInterceptorFactory;
+
+impl InterceptorFactory for RepeatInterceptorFactory {
+    fn try_new(&self, ctx: &str) -> Result<InterceptorRef> {
+        let times = ctx.parse().ok().filter(|times| *times > 0).ok_or_else(|| {
+            SqlnessError::InvalidContext {
+                prefix: PREFIX.to_string(),
+                msg: format!("Expect positive number of times, got {ctx}"),
+            }
+        })?;
+        Ok(Box::new(RepeatInterceptor { times }))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parse_times() {
+        let interceptor = RepeatInterceptorFactory.try_new("3").unwrap();
+        let mut context = QueryContext::default();
+        interceptor.before_execute(&mut vec![], &mut context);
+        assert_eq!(context.settings.repeat, Some(3));
+
+        assert!(RepeatInterceptorFactory.try_new("0").is_err());
+        assert!(RepeatInterceptorFactory.try_new("many").is_err());
+    }
+}
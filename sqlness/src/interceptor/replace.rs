@@ -1,5 +1,6 @@
 // Copyright 2023 CeresDB Project Authors. Licensed under Apache-2.0.
 
+use crate::case::QueryContext;
 use crate::error::Result;
 use crate::interceptor::{Interceptor, InterceptorFactory, InterceptorRef};
 use crate::SqlnessError;
@@ -33,16 +34,18 @@ pub const PREFIX: &str = "REPLACE";
 /// ```
 ///
 /// Multiple `REPLACE` statements are allowed to one query. They will be evaluated in order.
+///
+/// The pattern is compiled when the case file is parsed, an invalid pattern fails
+/// the case with its path and line number.
 #[derive(Debug)]
 pub struct ReplaceInterceptor {
-    pattern: String,
+    pattern: Regex,
     replacement: String,
 }
 
 impl Interceptor for ReplaceInterceptor {
-    fn after_execute(&self, result: &mut String) {
-        let re = Regex::new(&self.pattern).unwrap();
-        let replaced = re.replace_all(result, &self.replacement);
+    fn after_execute(&self, result: &mut String, _: &mut QueryContext) {
+        let replaced = self.pattern.replace_all(result, &self.replacement);
         *result = replaced.to_string();
     }
 }
@@ -66,6 +69,10 @@ impl InterceptorFactory for ReplaceInterceptorFactory {
                 msg: "Pattern shouldn't be empty".to_string(),
             });
         }
+        let pattern = Regex::new(&pattern).map_err(|e| SqlnessError::InvalidContext {
+            prefix: PREFIX.to_string(),
+            msg: format!("Invalid regexp {pattern}, err:{e}"),
+        })?;
         let replacement = args.next().unwrap_or("").to_string();
         Ok(Box::new(ReplaceInterceptor {
             pattern,
@@ -84,12 +91,36 @@ mod tests {
         assert!(interceptor.is_err());
     }
 
+    #[test]
+    fn construct_replace_with_invalid_regex() {
+        let interceptor = ReplaceInterceptorFactory {}.try_new("(\\d+ DURATION");
+        assert!(matches!(
+            interceptor,
+            Err(SqlnessError::InvalidContext { prefix, .. }) if prefix == PREFIX
+        ));
+    }
+
+    #[test]
+    fn replace_in_order() {
+        let first = ReplaceInterceptorFactory {}
+            .try_new(r"(\d+\.\d+)s DURATION")
+            .unwrap();
+        let second = ReplaceInterceptorFactory {}
+            .try_new("DURATION <d>")
+            .unwrap();
+
+        let mut exec_result = "cost 1.25s, 0.5s".to_string();
+        first.after_execute(&mut exec_result, &mut QueryContext::default());
+        second.after_execute(&mut exec_result, &mut QueryContext::default());
+        assert_eq!(exec_result, "cost <d>, <d>".to_string());
+    }
+
     #[test]
     fn replace_without_replacement() {
         let interceptor = ReplaceInterceptorFactory {}.try_new("0").unwrap();
 
         let mut exec_result = "000010101".to_string();
-        interceptor.after_execute(&mut exec_result);
+        interceptor.after_execute(&mut exec_result, &mut QueryContext::default());
         assert_eq!(exec_result, "111".to_string());
     }
 
@@ -98,7 +129,7 @@ mod tests {
         let interceptor = ReplaceInterceptorFactory {}.try_new("00 2").unwrap();
 
         let mut exec_result = "0000010101".to_string();
-        interceptor.after_execute(&mut exec_result);
+        interceptor.after_execute(&mut exec_result, &mut QueryContext::default());
         assert_eq!(exec_result, "22010101".to_string());
     }
 }
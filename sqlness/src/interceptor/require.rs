@@ -0,0 +1,117 @@
+// Copyright 2024 CeresDB Project Authors. Licensed under Apache-2.0.
+
+use std::collections::HashMap;
+
+use crate::case::QueryContext;
+use crate::error::Result;
+use crate::interceptor::{Interceptor, InterceptorFactory, InterceptorRef};
+use crate::SqlnessError;
+
+pub const PREFIX: &str = "REQUIRE";
+/// Name of the metadata holding the features of the current environment, separated
+/// by `,`.
+pub const FEATURES: &str = "sqlness_features";
+
+/// Skip the query when the environment doesn't have the features it requires.
+///
+/// # Example
+/// `config.toml` of the environment:
+/// ``` toml
+/// features = ["json", "cte"]
+/// ```
+///
+/// `.sql` file:
+/// ``` sql
+/// -- SQLNESS REQUIRE cte
+/// WITH t AS (SELECT 1) SELECT * FROM t;
+///
+/// -- SQLNESS REQUIRE json window
+/// SELECT json_agg(x) OVER () FROM t;
+/// ```
+///
+/// Features of an environment are declared by `features` in its config file, and
+/// are available as the `sqlness_features` metadata. Multiple features are all
+/// required. A skipped query isn't executed, and its result is replaced by a
+/// `-- SKIPPED` line.
+///
+/// When `REQUIRE` appears at the top of the file and is separated from the first
+/// query by an empty line, it applies to the whole case instead like `SKIP_IF`, and
+/// the case is reported as skipped.
+#[derive(Debug)]
+pub struct RequireInterceptor {
+    features: Vec<String>,
+}
+
+impl Interceptor for RequireInterceptor {
+    fn before_execute(&self, _: &mut Vec<String>, context: &mut QueryContext) {
+        if missing_feature(&self.features, &context.settings.metadata).is_some() {
+            context.settings.skip = true;
+        }
+    }
+}
+
+/// Parse the features required by `REQUIRE`.
+pub(crate) fn parse_features(ctx: &str) -> Result<Vec<String>> {
+    let features = ctx
+        .split_whitespace()
+        .map(str::to_string)
+        .collect::<Vec<_>>();
+    if features.is_empty() {
+        return Err(SqlnessError::InvalidContext {
+            prefix: PREFIX.to_string(),
+            msg: "Expect at least one feature".to_string(),
+        });
+    }
+
+    Ok(features)
+}
+
+/// Returns the first of `features` the environment doesn't have, `metadata` is the
+/// metadata of the environment.
+pub(crate) fn missing_feature<'a>(
+    features: &'a [String],
+    metadata: &HashMap<String, String>,
+) -> Option<&'a str> {
+    let available = metadata.get(FEATURES).map(String::as_str).unwrap_or("");
+    features
+        .iter()
+        .find(|feature| !available.split(',').any(|f| f == feature.as_str()))
+        .map(String::as_str)
+}
+
+pub struct RequireInterceptorFactory;
+
+impl InterceptorFactory for RequireInterceptorFactory {
+    fn try_new(&self, ctx: &str) -> Result<InterceptorRef> {
+        let features = parse_features(ctx)?;
+        Ok(Box::new(RequireInterceptor { features }))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+
+    use super::*;
+
+    #[test]
+    fn present_and_absent_features() {
+        let skipped = |ctx: &str| {
+            let interceptor = RequireInterceptorFactory.try_new(ctx).unwrap();
+            let mut context = QueryContext::default();
+            context.settings.metadata =
+                Arc::new([(FEATURES.to_string(), "json,cte".to_string())].into());
+            interceptor.before_execute(&mut vec![], &mut context);
+            context.settings.skip
+        };
+        assert!(!skipped("cte"));
+        assert!(!skipped("json cte"));
+        assert!(skipped("window"));
+        assert!(skipped("cte window"));
+        assert!(skipped("js"));
+
+        let features = parse_features("cte").unwrap();
+        assert_eq!(missing_feature(&features, &HashMap::new()), Some("cte"));
+        assert!(RequireInterceptorFactory.try_new(" ").is_err());
+    }
+}
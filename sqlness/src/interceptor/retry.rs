@@ -0,0 +1,100 @@
+// Copyright 2024 CeresDB Project Authors. Licensed under Apache-2.0.
+
+use std::time::Duration;
+
+use crate::case::QueryContext;
+use crate::error::Result;
+use crate::interceptor::{Interceptor, InterceptorFactory, InterceptorRef};
+use crate::SqlnessError;
+
+pub const PREFIX: &str = "RETRY";
+
+/// Re-execute the query until its result matches the expected one.
+///
+/// Grammar:
+/// ``` text
+/// -- SQLNESS RETRY <max-attempts> <delay>
+/// ```
+///
+/// `delay` is optional and defaults to no delay. Its format is the same as `SLEEP`
+/// interceptor.
+///
+/// # Example
+/// ``` sql
+/// -- SQLNESS RETRY 5 1s
+/// SELECT count(*) FROM eventually_consistent_table;
+/// ```
+///
+/// The query is executed at most `max-attempts` times, waiting `delay` between
+/// attempts, until its block in the result file is the same as the old one. The
+/// output of the last attempt is what gets recorded and compared. Interceptors running
+/// before execution (like `TEMPLATE` or `SLEEP`) are only applied once.
+///
+/// Only the annotated query is retried. No retry happens if the query has no
+/// recorded result yet, like in a new case, or an earlier query in this file already
+/// mismatched.
+#[derive(Debug)]
+pub struct RetryInterceptor {
+    attempts: usize,
+    delay: Duration,
+}
+
+impl Interceptor for RetryInterceptor {
+    fn before_execute(&self, _: &mut Vec<String>, context: &mut QueryContext) {
+        context.settings.retry = Some((self.attempts, self.delay));
+    }
+}
+
+pub struct RetryInterceptorFactory;
+
+impl InterceptorFactory for RetryInterceptorFactory {
+    fn try_new(&self, ctx: &str) -> Result<InterceptorRef> {
+        let mut args = ctx.splitn(2, ' ').filter(|s| !s.is_empty());
+        let attempts = args
+            .next()
+            .unwrap_or_default()
+            .parse()
+            .ok()
+            .filter(|attempts| *attempts > 0)
+            .ok_or_else(|| SqlnessError::InvalidContext {
+                prefix: PREFIX.to_string(),
+                msg: "Expect positive number of attempts".to_string(),
+            })?;
+        let delay = match args.next() {
+            Some(delay) => {
+                duration_str::parse(delay).map_err(|e| SqlnessError::InvalidContext {
+                    prefix: PREFIX.to_string(),
+                    msg: format!("Failed to parse duration: {}", e),
+                })?
+            }
+            None => Duration::ZERO,
+        };
+
+        Ok(Box::new(RetryInterceptor { attempts, delay }))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parse_retry() {
+        let interceptor = RetryInterceptorFactory {}.try_new("5 1s").unwrap();
+        let mut context = QueryContext::default();
+        interceptor.before_execute(&mut vec![], &mut context);
+        assert_eq!(context.settings.retry, Some((5, Duration::from_secs(1))));
+
+        let interceptor = RetryInterceptorFactory {}.try_new("3").unwrap();
+        let mut context = QueryContext::default();
+        interceptor.before_execute(&mut vec![], &mut context);
+        assert_eq!(context.settings.retry, Some((3, Duration::ZERO)));
+    }
+
+    #[test]
+    fn invalid_retry() {
+        assert!(RetryInterceptorFactory {}.try_new("").is_err());
+        assert!(RetryInterceptorFactory {}.try_new("0").is_err());
+        assert!(RetryInterceptorFactory {}.try_new("3 soon").is_err());
+    }
+}
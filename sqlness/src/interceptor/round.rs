@@ -0,0 +1,171 @@
+// Copyright 2024 CeresDB Project Authors. Licensed under Apache-2.0.
+
+use std::sync::OnceLock;
+
+use regex::Regex;
+
+use crate::case::QueryContext;
+use crate::error::Result;
+use crate::interceptor::{Interceptor, InterceptorFactory, InterceptorRef};
+use crate::{QueryResult, SqlnessError};
+
+pub const PREFIX: &str = "ROUND";
+
+/// Round floating-point numbers in the result to N decimal places.
+///
+/// # Example
+/// `.sql` file:
+/// ``` sql
+/// -- SQLNESS ROUND 4
+/// SELECT avg(price) FROM t;
+/// ```
+///
+/// `.result` file:
+/// ``` sql
+/// -- SQLNESS ROUND 4
+/// SELECT avg(price) FROM t;
+///
+/// 1.1000
+/// ```
+///
+/// Numbers with a decimal point or an exponent (like `1.0999999999`, `-3.2e-7` or
+/// `1E5`) are written with exactly N decimals, integers and other text are kept as
+/// is. For [`QueryResult::Rows`] a cell is rounded if the whole cell is such a
+/// number, and the rows are formatted again, so tables stay aligned. For
+/// [`QueryResult::Raw`] every such number in the text is rounded, except in dotted
+/// sequences like versions (`1.2.3`).
+///
+/// [`QueryResult::Rows`]: crate::QueryResult::Rows
+/// [`QueryResult::Raw`]: crate::QueryResult::Raw
+#[derive(Debug)]
+pub struct RoundInterceptor {
+    digits: usize,
+}
+
+impl Interceptor for RoundInterceptor {
+    fn after_execute(&self, result: &mut String, context: &mut QueryContext) {
+        context.map_rows(result, |rows| Ok(round_result(rows, self.digits)));
+    }
+}
+
+/// Round numbers in the result, see [`RoundInterceptor`].
+pub(crate) fn round_result(result: QueryResult, digits: usize) -> QueryResult {
+    match result {
+        QueryResult::Rows { columns, rows } => QueryResult::Rows {
+            columns,
+            rows: rows
+                .into_iter()
+                .map(|row| {
+                    row.into_iter()
+                        .map(|cell| cell.map(|cell| round(&cell, digits).unwrap_or(cell)))
+                        .collect()
+                })
+                .collect(),
+        },
+        QueryResult::Raw(raw) => QueryResult::Raw(round_text(&raw, digits)),
+        result @ QueryResult::Error(_) => result,
+    }
+}
+
+/// Round `s` if it's a floating-point number.
+fn round(s: &str, digits: usize) -> Option<String> {
+    let s = s.trim();
+    if !s.contains(['.', 'e', 'E']) || !s.starts_with(|c: char| c.is_ascii_digit() || c == '-') {
+        return None;
+    }
+    let value = s.parse::<f64>().ok().filter(|v| v.is_finite())?;
+    let rounded = format!("{value:.digits$}");
+    // Don't distinguish -0.00 from 0.00
+    match rounded.strip_prefix('-') {
+        Some(abs) if abs.chars().all(|c| c == '0' || c == '.') => Some(abs.to_string()),
+        _ => Some(rounded),
+    }
+}
+
+fn round_text(text: &str, digits: usize) -> String {
+    static NUMBER: OnceLock<Regex> = OnceLock::new();
+    let number = NUMBER.get_or_init(|| {
+        Regex::new(r"-?\b\d+(\.\d+)?([eE][-+]?\d+)?\b").expect("valid number regex")
+    });
+
+    let mut rounded = String::with_capacity(text.len());
+    let mut last = 0;
+    for m in number.find_iter(text) {
+        let dotted = text[..m.start()].ends_with('.') || text[m.end()..].starts_with('.');
+        let Some(value) = round(m.as_str(), digits).filter(|_| !dotted) else {
+            continue;
+        };
+        rounded.push_str(&text[last..m.start()]);
+        rounded.push_str(&value);
+        last = m.end();
+    }
+    rounded.push_str(&text[last..]);
+    rounded
+}
+
+pub struct RoundInterceptorFactory;
+
+impl InterceptorFactory for RoundInterceptorFactory {
+    fn try_new(&self, ctx: &str) -> Result<InterceptorRef> {
+        let digits = ctx
+            .trim()
+            .parse::<usize>()
+            .map_err(|e| SqlnessError::InvalidContext {
+                prefix: PREFIX.to_string(),
+                msg: format!("Expect number of decimal places, got {ctx}, err:{e}"),
+            })?;
+        Ok(Box::new(RoundInterceptor { digits }))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn round_cells() {
+        let result = QueryResult::Rows {
+            columns: vec!["a".into()],
+            rows: vec![
+                vec![Some("1.0999999999".into())],
+                vec![Some("-3.2e-7".into())],
+                vec![Some("1E5".into())],
+                vec![Some("42".into())],
+                vec![Some("1.5 m".into())],
+                vec![None],
+            ],
+        };
+        let cells = match round_result(result, 4) {
+            QueryResult::Rows { rows, .. } => rows.into_iter().flatten().collect::<Vec<_>>(),
+            _ => unreachable!(),
+        };
+        assert_eq!(
+            cells,
+            vec![
+                Some("1.1000".to_string()),
+                Some("0.0000".to_string()),
+                Some("100000.0000".to_string()),
+                Some("42".to_string()),
+                Some("1.5 m".to_string()),
+                None,
+            ]
+        );
+    }
+
+    #[test]
+    fn round_raw() {
+        assert_eq!(
+            round_result(
+                QueryResult::Raw("avg: 1.23456, -2.5e1 rows: 3, version 1.2.3, t1.5".into()),
+                2
+            ),
+            QueryResult::Raw("avg: 1.23, -25.00 rows: 3, version 1.2.3, t1.5".into())
+        );
+    }
+
+    #[test]
+    fn invalid_digits() {
+        assert!(RoundInterceptorFactory.try_new("").is_err());
+        assert!(RoundInterceptorFactory.try_new("-1").is_err());
+    }
+}
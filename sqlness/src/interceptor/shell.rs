@@ -0,0 +1,166 @@
+// Copyright 2024 CeresDB Project Authors. Licensed under Apache-2.0.
+
+use std::io::Read;
+use std::process::{Command, Stdio};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+use crate::case::QueryContext;
+use crate::error::Result;
+use crate::interceptor::{Interceptor, InterceptorFactory, InterceptorRef};
+use crate::SqlnessError;
+
+pub const PREFIX: &str = "SHELL";
+/// How often a running command is checked for exit.
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// Run a shell command before the query, and record its output in the result.
+///
+/// # Example
+/// `.sql` file:
+/// ``` sql
+/// -- SQLNESS SHELL cat /tmp/dump.txt
+/// COPY t TO '/tmp/dump.txt';
+/// ```
+///
+/// `.result` file:
+/// ``` sql
+/// -- SQLNESS SHELL cat /tmp/dump.txt
+/// COPY t TO '/tmp/dump.txt';
+///
+/// 1,a
+/// 2,b
+///
+/// Affected Rows: 2
+/// ```
+///
+/// The command is the rest of the line, run by `sh -c` in the working directory of
+/// the runner. Its stdout is recorded before the result of the query, and commands
+/// of multiple `SHELL` run in order. A command exiting with a non-zero status fails
+/// the case with [`SqlnessError::ShellCommand`], including its stderr. Commands run
+/// off the async task, and are bounded by the timeout of the query like the query
+/// itself, see [`Config::query_timeout`]: a command running longer is killed and
+/// the case fails with [`SqlnessError::Timeout`].
+///
+/// It's disabled unless [`Config::allow_shell`] is on, a case using it fails with
+/// [`SqlnessError::ShellNotAllowed`] otherwise. The command runs with the
+/// privileges of the runner, and values filled in by interceptors declared before
+/// it, like `ENV` or `ARG`, are not escaped: only run cases you trust with it on.
+///
+/// [`SqlnessError::ShellCommand`]: crate::SqlnessError::ShellCommand
+/// [`SqlnessError::ShellNotAllowed`]: crate::SqlnessError::ShellNotAllowed
+/// [`SqlnessError::Timeout`]: crate::SqlnessError::Timeout
+/// [`Config::query_timeout`]: crate::Config#structfield.query_timeout
+/// [`Config::allow_shell`]: crate::Config#structfield.allow_shell
+#[derive(Debug)]
+pub struct ShellInterceptor {
+    command: String,
+}
+
+impl Interceptor for ShellInterceptor {
+    fn before_execute(&self, _: &mut Vec<String>, context: &mut QueryContext) {
+        context.settings.shell.push(self.command.clone());
+    }
+}
+
+/// Run `command` by `sh -c` and return its stdout, it's killed if it's still
+/// running after `timeout`.
+///
+/// It blocks until the command exits, run it by [`util::run_blocking`] in async
+/// code.
+///
+/// [`util::run_blocking`]: crate::util::run_blocking
+pub(crate) fn run(command: &str, timeout: Option<Duration>) -> Result<String> {
+    let failed = |msg: String| SqlnessError::ShellCommand {
+        command: command.to_string(),
+        msg,
+    };
+    let mut child = Command::new("sh")
+        .arg("-c")
+        .arg(command)
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| failed(e.to_string()))?;
+    // Pipes are drained while waiting, so a command writing a lot doesn't block.
+    let stdout = read_pipe(child.stdout.take());
+    let stderr = read_pipe(child.stderr.take());
+
+    let start = Instant::now();
+    let status = loop {
+        if let Some(status) = child.try_wait().map_err(|e| failed(e.to_string()))? {
+            break status;
+        }
+        if timeout.is_some_and(|timeout| start.elapsed() >= timeout) {
+            let _ = child.kill();
+            let _ = child.wait();
+            return Err(SqlnessError::Timeout {
+                case: String::new(),
+                query: command.to_string(),
+                elapsed: start.elapsed(),
+            });
+        }
+        std::thread::sleep(POLL_INTERVAL);
+    };
+    let stdout = stdout.join().unwrap_or_default();
+    if !status.success() {
+        let stderr = stderr.join().unwrap_or_default();
+        return Err(failed(format!(
+            "{status}, stderr: {}",
+            String::from_utf8_lossy(&stderr).trim_end()
+        )));
+    }
+
+    Ok(String::from_utf8_lossy(&stdout).into_owned())
+}
+
+/// Read `pipe` to the end on a helper thread.
+fn read_pipe<R: Read + Send + 'static>(pipe: Option<R>) -> JoinHandle<Vec<u8>> {
+    std::thread::spawn(move || {
+        let mut output = vec![];
+        if let Some(mut pipe) = pipe {
+            let _ = pipe.read_to_end(&mut output);
+        }
+        output
+    })
+}
+
+pub struct ShellInterceptorFactory;
+
+impl InterceptorFactory for ShellInterceptorFactory {
+    fn try_new(&self, ctx: &str) -> Result<InterceptorRef> {
+        if ctx.trim().is_empty() {
+            return Err(SqlnessError::InvalidContext {
+                prefix: PREFIX.to_string(),
+                msg: "Expect a command".to_string(),
+            });
+        }
+        Ok(Box::new(ShellInterceptor {
+            command: ctx.to_string(),
+        }))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn run_command() {
+        assert_eq!(run("echo a; echo b", None).unwrap(), "a\nb\n");
+
+        let err = run("echo out; echo oops >&2; exit 3", None).unwrap_err();
+        let msg = err.to_string();
+        assert!(msg.contains("oops"), "{msg}");
+        assert!(msg.contains('3'), "{msg}");
+        assert!(ShellInterceptorFactory.try_new(" ").is_err());
+    }
+
+    #[test]
+    fn kill_after_timeout() {
+        let start = Instant::now();
+        let err = run("exec sleep 5", Some(Duration::from_millis(100))).unwrap_err();
+        assert!(matches!(err, SqlnessError::Timeout { .. }), "{err}");
+        assert!(start.elapsed() < Duration::from_secs(2));
+    }
+}
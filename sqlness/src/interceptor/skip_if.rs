@@ -0,0 +1,155 @@
+// Copyright 2024 CeresDB Project Authors. Licensed under Apache-2.0.
+
+use std::collections::HashMap;
+use std::fmt::Display;
+
+use crate::case::QueryContext;
+use crate::error::Result;
+use crate::interceptor::{Interceptor, InterceptorFactory, InterceptorRef};
+use crate::SqlnessError;
+
+pub const PREFIX: &str = "SKIP_IF";
+
+/// Skip the query when the condition on an environment variable or metadata holds.
+///
+/// # Example
+/// ``` sql
+/// -- SQLNESS SKIP_IF FEATURE_X=0
+/// SELECT feature_x();
+///
+/// -- SQLNESS SKIP_IF CI
+/// SELECT * FROM local_only;
+///
+/// -- SQLNESS SKIP_IF {{backend_version}}=1.0
+/// SELECT new_feature();
+/// ```
+///
+/// `NAME=VALUE` holds when the variable equals `VALUE`, while a bare `NAME` holds
+/// when the variable is set. A name in the form of `{{name}}` refers to the metadata
+/// of the environment instead, see [`EnvController::metadata`]. A skipped query isn't executed, and its result is
+/// replaced by a `-- SKIPPED` line.
+///
+/// When `SKIP_IF` appears at the top of the file and is separated from the first
+/// query by an empty line, it applies to the whole case instead. The result file of
+/// a skipped case is left untouched and the case is reported as skipped.
+///
+/// [`EnvController::metadata`]: crate::EnvController::metadata
+#[derive(Debug)]
+pub struct SkipIfInterceptor {
+    condition: SkipCondition,
+}
+
+impl Interceptor for SkipIfInterceptor {
+    fn before_execute(&self, _: &mut Vec<String>, context: &mut QueryContext) {
+        if self.condition.holds(&context.settings.metadata) {
+            context.settings.skip = true;
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct SkipCondition {
+    name: String,
+    value: Option<String>,
+}
+
+impl SkipCondition {
+    pub(crate) fn parse(ctx: &str) -> Result<Self> {
+        let (name, value) = match ctx.split_once('=') {
+            Some((name, value)) => (name.trim(), Some(value.trim().to_string())),
+            None => (ctx.trim(), None),
+        };
+        if name.is_empty() || name.contains(char::is_whitespace) {
+            return Err(SqlnessError::InvalidContext {
+                prefix: PREFIX.to_string(),
+                msg: format!("Expect <NAME> or <NAME>=<VALUE>, got {ctx}"),
+            });
+        }
+
+        Ok(Self {
+            name: name.to_string(),
+            value,
+        })
+    }
+
+    /// `metadata` is the metadata of the environment, see [`SkipIfInterceptor`].
+    pub(crate) fn holds(&self, metadata: &HashMap<String, String>) -> bool {
+        let metadata_key = self
+            .name
+            .strip_prefix("{{")
+            .and_then(|name| name.strip_suffix("}}"));
+        let actual = match metadata_key {
+            Some(key) => metadata.get(key).cloned(),
+            None => std::env::var(&self.name).ok(),
+        };
+        match (&self.value, actual) {
+            (Some(value), Some(actual)) => &actual == value,
+            (Some(_), None) => false,
+            (None, actual) => actual.is_some(),
+        }
+    }
+}
+
+impl Display for SkipCondition {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.value {
+            Some(value) => write!(f, "{}={value}", self.name),
+            None => f.write_str(&self.name),
+        }
+    }
+}
+
+pub struct SkipIfInterceptorFactory;
+
+impl InterceptorFactory for SkipIfInterceptorFactory {
+    fn try_new(&self, ctx: &str) -> Result<InterceptorRef> {
+        let condition = SkipCondition::parse(ctx)?;
+        Ok(Box::new(SkipIfInterceptor { condition }))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+
+    use super::*;
+
+    #[test]
+    fn equality_and_presence() {
+        std::env::set_var("SQLNESS_TEST_SKIP_IF", "0");
+
+        let holds = |ctx: &str| {
+            let interceptor = SkipIfInterceptorFactory.try_new(ctx).unwrap();
+            let mut context = QueryContext::default();
+            interceptor.before_execute(&mut vec![], &mut context);
+            context.settings.skip
+        };
+        assert!(holds("SQLNESS_TEST_SKIP_IF=0"));
+        assert!(!holds("SQLNESS_TEST_SKIP_IF=1"));
+        assert!(holds("SQLNESS_TEST_SKIP_IF"));
+        assert!(!holds("SQLNESS_TEST_SKIP_IF_UNSET"));
+    }
+
+    #[test]
+    fn metadata_condition() {
+        let holds = |ctx: &str| {
+            let interceptor = SkipIfInterceptorFactory.try_new(ctx).unwrap();
+            let mut context = QueryContext::default();
+            context.settings.metadata =
+                Arc::new([("version".to_string(), "1.0".to_string())].into());
+            interceptor.before_execute(&mut vec![], &mut context);
+            context.settings.skip
+        };
+        assert!(holds("{{version}}=1.0"));
+        assert!(!holds("{{version}}=2.0"));
+        assert!(holds("{{version}}"));
+        assert!(!holds("{{features}}"));
+    }
+
+    #[test]
+    fn invalid_condition() {
+        assert!(SkipIfInterceptorFactory.try_new("").is_err());
+        assert!(SkipIfInterceptorFactory.try_new("=1").is_err());
+        assert!(SkipIfInterceptorFactory.try_new("A B").is_err());
+    }
+}
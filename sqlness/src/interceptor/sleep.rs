@@ -1,11 +1,10 @@
 // Copyright 2024 CeresDB Project Authors. Licensed under Apache-2.0.
 
-use std::pin::Pin;
-use std::task::Context;
-use std::time::{Duration, Instant};
+use std::time::Duration;
 
 use crate::error::Result;
 use crate::interceptor::{Interceptor, InterceptorFactory, InterceptorRef};
+use crate::util::sleep;
 use crate::SqlnessError;
 
 pub const PREFIX: &str = "SLEEP";
@@ -21,7 +20,9 @@ pub const PREFIX: &str = "SLEEP";
 /// valid duration format:
 /// - `1s` for 1 second
 /// - `1ms` for 1 millisecond
+/// - `1m` for 1 minute
 /// - `1s500ms` for 1.5 seconds
+///
 /// etc. See detailed format in [duration_str](https://docs.rs/duration-str/0.11.2/duration_str/) crate
 ///
 /// Note that this implementation is not accurate and may be affected by the system load.
@@ -32,29 +33,6 @@ pub struct SleepInterceptor {
     duration: Duration,
 }
 
-struct Sleep {
-    now: Instant,
-    duration: Duration,
-}
-impl core::future::Future for Sleep {
-    type Output = ();
-    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> std::task::Poll<Self::Output> {
-        let elapsed = self.now.elapsed();
-        if elapsed < self.duration {
-            let waker = cx.waker().clone();
-            // detach the thread and let it wake the waker later
-            let remaining = self.duration.saturating_sub(elapsed);
-            std::thread::spawn(move || {
-                std::thread::sleep(remaining);
-                waker.wake();
-            });
-            std::task::Poll::Pending
-        } else {
-            std::task::Poll::Ready(())
-        }
-    }
-}
-
 #[async_trait::async_trait]
 impl Interceptor for SleepInterceptor {
     async fn before_execute_async(
@@ -62,12 +40,8 @@ impl Interceptor for SleepInterceptor {
         _execute_query: &mut Vec<String>,
         _context: &mut crate::case::QueryContext,
     ) {
-        // impl a cross-runtime sleep
-        Sleep {
-            now: Instant::now(),
-            duration: self.duration,
-        }
-        .await;
+        // cross-runtime sleep
+        sleep(self.duration).await;
     }
 }
 
@@ -77,7 +51,7 @@ impl InterceptorFactory for SleepInterceptorFactory {
     fn try_new(&self, ctx: &str) -> Result<InterceptorRef> {
         let duration = duration_str::parse(ctx).map_err(|e| SqlnessError::InvalidContext {
             prefix: PREFIX.to_string(),
-            msg: format!("Failed to parse duration: {}", e),
+            msg: format!("Failed to parse duration {ctx}: {e}"),
         })?;
         Ok(Box::new(SleepInterceptor { duration }))
     }
@@ -85,6 +59,8 @@ impl InterceptorFactory for SleepInterceptorFactory {
 
 #[cfg(test)]
 mod test {
+    use std::time::Instant;
+
     use super::*;
 
     #[tokio::test]
@@ -98,4 +74,12 @@ mod test {
         let elasped = now.elapsed().as_millis() as u64;
         assert!(elasped >= 1500);
     }
+
+    #[test]
+    fn parse_duration() {
+        assert!(SleepInterceptorFactory {}.try_new("1m").is_ok());
+
+        let err = SleepInterceptorFactory {}.try_new("soon").err().unwrap();
+        assert!(err.to_string().contains("soon"), "{err}");
+    }
 }
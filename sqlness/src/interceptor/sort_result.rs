@@ -1,23 +1,32 @@
 // Copyright 2023 CeresDB Project Authors. Licensed under Apache-2.0.
 
+use std::cmp::Ordering;
 use std::collections::VecDeque;
 
 use crate::{
+    case::QueryContext,
     error::Result,
-    interceptor::{Interceptor, InterceptorFactory, InterceptorRef},
+    interceptor::{parse_columns, split_columns, Interceptor, InterceptorFactory, InterceptorRef},
     SqlnessError,
 };
 
 pub const PREFIX: &str = "SORT_RESULT";
+pub const NUMERIC_PREFIX: &str = "SORT_RESULT_NUMERIC";
 
 /// Sort the query result in lexicographical order.
 ///
 /// Grammar:
 /// ``` text
-/// -- SQLNESS SORT_RESULT <ignore-head> <ignore-tail>
+/// -- SQLNESS SORT_RESULT <ignore-head> <ignore-tail> [BY <column>...]
 /// ```
 ///
 /// Both `ignore-head` and `ignore-tail` are optional. Default value is 0 (no lines will be ignored).
+/// They can be used to keep header and footer lines in place.
+///
+/// Columns listed after `BY` are 1-based sort keys, compared from left to right. Each
+/// line is split into columns on `|` if it contains one (ASCII tables), otherwise on `,`,
+/// and the cells are trimmed. Missing cells compare as empty strings, and rows with
+/// equal keys are ordered by the whole line. Without `BY` the whole line is the key.
 ///
 /// # Example
 /// `.sql` file:
@@ -35,16 +44,107 @@ pub const PREFIX: &str = "SORT_RESULT";
 /// 2
 /// 3
 /// ```
+///
+/// Sort by the second column, then the first one, while keeping the header:
+/// ``` sql
+/// -- SQLNESS SORT_RESULT 1 0 BY 2 1
+/// SELECT id, name FROM t;
+/// ```
+///
+/// # Numeric order
+/// `SORT_RESULT_NUMERIC` takes the same arguments, plus an optional `NULLS FIRST` or
+/// `NULLS LAST` at the end, and compares keys as numbers so `2` is before `10`:
+/// ``` sql
+/// -- SQLNESS SORT_RESULT_NUMERIC 1 0 BY 2 NULLS FIRST
+/// SELECT name, price FROM t;
+/// ```
+///
+/// Cells that aren't numbers are ordered after all numbers, by string comparison.
+/// Empty and `NULL` cells, as well as missing ones, are NULLs: they are ordered
+/// after everything else by default, or before everything with `NULLS FIRST`.
 #[derive(Debug)]
 pub struct SortResultInterceptor {
     /// How much lines to ignore from the head
     ignore_head: usize,
     /// How much lines to ignore from the tail
     ignore_tail: usize,
+    /// 0-based column indices used as sort keys, empty for whole line.
+    columns: Vec<usize>,
+    /// Compare keys as numbers, see `SORT_RESULT_NUMERIC`.
+    numeric: bool,
+    /// Order NULLs before other keys in numeric order.
+    nulls_first: bool,
+}
+
+/// Sort key of a cell in numeric order.
+#[derive(Debug)]
+enum NumericKey<'a> {
+    Null,
+    Number(f64),
+    Text(&'a str),
+}
+
+impl<'a> NumericKey<'a> {
+    fn new(cell: &'a str) -> Self {
+        if cell.is_empty() || cell.eq_ignore_ascii_case("NULL") {
+            NumericKey::Null
+        } else if let Ok(number) = cell.parse() {
+            NumericKey::Number(number)
+        } else {
+            NumericKey::Text(cell)
+        }
+    }
+
+    fn compare(&self, other: &Self, nulls_first: bool) -> Ordering {
+        let null_order = if nulls_first {
+            Ordering::Less
+        } else {
+            Ordering::Greater
+        };
+        match (self, other) {
+            (NumericKey::Null, NumericKey::Null) => Ordering::Equal,
+            (NumericKey::Null, _) => null_order,
+            (_, NumericKey::Null) => null_order.reverse(),
+            (NumericKey::Number(a), NumericKey::Number(b)) => a.total_cmp(b),
+            (NumericKey::Number(_), NumericKey::Text(_)) => Ordering::Less,
+            (NumericKey::Text(_), NumericKey::Number(_)) => Ordering::Greater,
+            (NumericKey::Text(a), NumericKey::Text(b)) => a.cmp(b),
+        }
+    }
+}
+
+impl SortResultInterceptor {
+    fn sort_numeric(&self, lines: &mut [&str]) {
+        let mut keyed = lines
+            .iter()
+            .map(|line| {
+                let keys = if self.columns.is_empty() {
+                    vec![NumericKey::new(line.trim())]
+                } else {
+                    let cells = split_columns(line);
+                    self.columns
+                        .iter()
+                        .map(|i| NumericKey::new(cells.get(*i).copied().unwrap_or_default()))
+                        .collect()
+                };
+                (keys, *line)
+            })
+            .collect::<Vec<_>>();
+        keyed.sort_by(|(a, a_line), (b, b_line)| {
+            a.iter()
+                .zip(b)
+                .map(|(a, b)| a.compare(b, self.nulls_first))
+                .find(|ordering| ordering.is_ne())
+                .unwrap_or_else(|| a_line.cmp(b_line))
+        });
+        for (line, (_, sorted)) in lines.iter_mut().zip(keyed) {
+            *line = sorted;
+        }
+    }
 }
 
 impl Interceptor for SortResultInterceptor {
-    fn after_execute(&self, result: &mut String) {
+    fn after_execute(&self, result: &mut String, _: &mut QueryContext) {
         let mut lines = result.lines().collect::<VecDeque<_>>();
         let mut head = Vec::with_capacity(self.ignore_head);
         let mut tail = Vec::with_capacity(self.ignore_tail);
@@ -63,7 +163,21 @@ impl Interceptor for SortResultInterceptor {
         tail.reverse();
 
         // sort remaining lines
-        lines.make_contiguous().sort();
+        if self.numeric {
+            self.sort_numeric(lines.make_contiguous());
+        } else if self.columns.is_empty() {
+            lines.make_contiguous().sort();
+        } else {
+            lines.make_contiguous().sort_by_cached_key(|line| {
+                let cells = split_columns(line);
+                let keys = self
+                    .columns
+                    .iter()
+                    .map(|i| cells.get(*i).copied().unwrap_or_default().to_string())
+                    .collect::<Vec<_>>();
+                (keys, line.to_string())
+            });
+        }
 
         let new_lines = head
             .into_iter()
@@ -74,32 +188,65 @@ impl Interceptor for SortResultInterceptor {
     }
 }
 
+/// Parse `<ignore-head> <ignore-tail> [BY <column>...]` from whitespace separated
+/// `tokens`.
+fn parse_sort(prefix: &str, tokens: &[&str], numeric: bool) -> Result<SortResultInterceptor> {
+    let invalid = |msg: String| SqlnessError::InvalidContext {
+        prefix: prefix.to_string(),
+        msg,
+    };
+    let (lines, columns) = match tokens.iter().position(|token| *token == "BY") {
+        Some(by) => (&tokens[..by], Some(&tokens[by + 1..])),
+        None => (tokens, None),
+    };
+    if let Some(extra) = lines.get(2) {
+        return Err(invalid(format!("Unexpected argument {extra}")));
+    }
+
+    let parse_lines = |arg: Option<&&str>| {
+        arg.unwrap_or(&"0")
+            .parse::<usize>()
+            .map_err(|e| invalid(format!("Expect number, err:{e}")))
+    };
+    let ignore_head = parse_lines(lines.first())?;
+    let ignore_tail = parse_lines(lines.get(1))?;
+
+    let columns = match columns {
+        Some(columns) => parse_columns(prefix, &columns.join(" "))?,
+        None => vec![],
+    };
+
+    Ok(SortResultInterceptor {
+        ignore_head,
+        ignore_tail,
+        columns,
+        numeric,
+        nulls_first: false,
+    })
+}
+
 pub struct SortResultInterceptorFactory;
 
 impl InterceptorFactory for SortResultInterceptorFactory {
     fn try_new(&self, ctx: &str) -> Result<InterceptorRef> {
-        let mut args = ctx.splitn(2, ' ').filter(|s| !s.is_empty());
-        let ignore_head =
-            args.next()
-                .unwrap_or("0")
-                .parse()
-                .map_err(|e| SqlnessError::InvalidContext {
-                    prefix: PREFIX.to_string(),
-                    msg: format!("Expect number, err:{e}"),
-                })?;
-        let ignore_tail =
-            args.next()
-                .unwrap_or("0")
-                .parse()
-                .map_err(|e| SqlnessError::InvalidContext {
-                    prefix: PREFIX.to_string(),
-                    msg: format!("Expect number, err:{e}"),
-                })?;
-
-        Ok(Box::new(SortResultInterceptor {
-            ignore_head,
-            ignore_tail,
-        }))
+        let tokens = ctx.split_whitespace().collect::<Vec<_>>();
+        Ok(Box::new(parse_sort(PREFIX, &tokens, false)?))
+    }
+}
+
+pub struct SortResultNumericInterceptorFactory;
+
+impl InterceptorFactory for SortResultNumericInterceptorFactory {
+    fn try_new(&self, ctx: &str) -> Result<InterceptorRef> {
+        let tokens = ctx.split_whitespace().collect::<Vec<_>>();
+        let (tokens, nulls_first) = match tokens.as_slice() {
+            [tokens @ .., "NULLS", "FIRST"] => (tokens, true),
+            [tokens @ .., "NULLS", "LAST"] => (tokens, false),
+            tokens => (tokens, false),
+        };
+        let mut interceptor = parse_sort(NUMERIC_PREFIX, tokens, true)?;
+        interceptor.nulls_first = nulls_first;
+        Ok(Box::new(interceptor))
     }
 }
 
@@ -145,7 +292,7 @@ mod tests {
         ];
 
         for (mut input, expected) in cases {
-            interceptor.after_execute(&mut input);
+            interceptor.after_execute(&mut input, &mut QueryContext::default());
             assert_eq!(input, expected);
         }
     }
@@ -160,7 +307,7 @@ mod tests {
             \n1",
         );
         let expected = exec_result.clone();
-        interceptor.after_execute(&mut exec_result);
+        interceptor.after_execute(&mut exec_result, &mut QueryContext::default());
         assert_eq!(exec_result, expected);
     }
 
@@ -174,7 +321,99 @@ mod tests {
             \n1",
         );
         let expected = exec_result.clone();
-        interceptor.after_execute(&mut exec_result);
+        interceptor.after_execute(&mut exec_result, &mut QueryContext::default());
         assert_eq!(exec_result, expected);
     }
+
+    #[test]
+    fn sort_by_columns() {
+        let interceptor = SortResultInterceptorFactory.try_new("1 0 BY 2 1").unwrap();
+
+        let mut exec_result = String::from(
+            "id,name\
+            \n3,b\
+            \n2,a\
+            \n1,b",
+        );
+        interceptor.after_execute(&mut exec_result, &mut QueryContext::default());
+        assert_eq!(
+            exec_result,
+            "id,name\
+            \n2,a\
+            \n1,b\
+            \n3,b"
+        );
+    }
+
+    #[test]
+    fn sort_table_by_column() {
+        let interceptor = SortResultInterceptorFactory.try_new("BY 2").unwrap();
+
+        let mut exec_result = String::from(
+            "| 1 | c |\
+            \n| 2 | a |\
+            \n| 3 |\
+            \n| 4 | b |",
+        );
+        interceptor.after_execute(&mut exec_result, &mut QueryContext::default());
+        assert_eq!(
+            exec_result,
+            "| 3 |\
+            \n| 2 | a |\
+            \n| 4 | b |\
+            \n| 1 | c |"
+        );
+    }
+
+    #[test]
+    fn construct_with_invalid_column() {
+        assert!(SortResultInterceptorFactory.try_new("BY 0").is_err());
+        assert!(SortResultInterceptorFactory.try_new("BY a").is_err());
+        assert!(SortResultInterceptorFactory.try_new("BY").is_err());
+    }
+
+    #[test]
+    fn construct_with_extra_arguments() {
+        assert!(SortResultInterceptorFactory.try_new("1 2 3").is_err());
+        assert!(SortResultInterceptorFactory.try_new("1 BYTES").is_err());
+        assert!(SortResultInterceptorFactory.try_new("BY 1 x").is_err());
+        assert!(SortResultNumericInterceptorFactory
+            .try_new("BY 1 NULLS")
+            .is_err());
+
+        let interceptor = parse_sort(PREFIX, &["1", "BY", "2"], false).unwrap();
+        assert_eq!((interceptor.ignore_head, interceptor.columns), (1, vec![1]));
+        let tokens = "  1   0  BY  2   1 ".split_whitespace().collect::<Vec<_>>();
+        let interceptor = parse_sort(PREFIX, &tokens, false).unwrap();
+        assert_eq!(
+            (interceptor.ignore_tail, interceptor.columns),
+            (0, vec![1, 0])
+        );
+    }
+
+    #[test]
+    fn sort_numeric() {
+        let sort = |ctx: &str, input: &str| {
+            let interceptor = SortResultNumericInterceptorFactory.try_new(ctx).unwrap();
+            let mut result = input.to_string();
+            interceptor.after_execute(&mut result, &mut QueryContext::default());
+            result
+        };
+
+        assert_eq!(sort("", "10\n2\n1.5\n-3"), "-3\n1.5\n2\n10");
+        assert_eq!(
+            sort(
+                "1 0 BY 2",
+                "name,price\na,10\nb,NULL\nc,2.5\nd,abc\ne,2\nf,"
+            ),
+            "name,price\ne,2\nc,2.5\na,10\nd,abc\nb,NULL\nf,"
+        );
+        assert_eq!(
+            sort("BY 2 NULLS FIRST", "| a | 10 |\n| b | NULL |\n| c | 9.5 |"),
+            "| b | NULL |\n| c | 9.5 |\n| a | 10 |"
+        );
+        assert_eq!(sort("BY 1 NULLS LAST", "\n3\n20"), "3\n20\n");
+
+        assert!(SortResultNumericInterceptorFactory.try_new("BY 0").is_err());
+    }
 }
@@ -0,0 +1,140 @@
+// Copyright 2024 CeresDB Project Authors. Licensed under Apache-2.0.
+
+use crate::case::QueryContext;
+use crate::error::Result;
+use crate::interceptor::{template::DELIMITER, Interceptor, InterceptorFactory, InterceptorRef};
+
+pub const PREFIX: &str = "SPLIT";
+
+/// Execute each statement of a query separately, and record its result under a
+/// `-- statement N` line.
+///
+/// # Example
+/// `.sql` file:
+/// ``` sql
+/// -- SQLNESS DELIMITER $$
+/// -- SQLNESS SPLIT
+/// INSERT INTO t VALUES (1);
+/// SELECT * FROM t;$$
+/// ```
+///
+/// `.result` file:
+/// ``` sql
+/// -- SQLNESS DELIMITER $$
+/// -- SQLNESS SPLIT
+/// INSERT INTO t VALUES (1);
+/// SELECT * FROM t;$$
+///
+/// -- statement 1
+/// Affected Rows: 1
+///
+/// -- statement 2
+/// 1
+/// ```
+///
+/// Statements are separated by `;`, except inside quotes, and sent with their `;`.
+/// When a statement fails, its error is recorded and the following statements are
+/// not executed, each of them is recorded as `-- SKIPPED`. With `EXPECT_ERROR`, a
+/// failure is expected from every statement instead.
+///
+/// It splits the query left by the interceptors declared before it, so declare it
+/// after `TEMPLATE` to split the rendered query. Queries already split by `TEMPLATE`
+/// are numbered separately, after their labels.
+#[derive(Debug)]
+pub struct SplitInterceptor;
+
+impl Interceptor for SplitInterceptor {
+    fn before_execute(&self, execute_query: &mut Vec<String>, context: &mut QueryContext) {
+        let input = execute_query.concat();
+        let segments = input.split(DELIMITER).collect::<Vec<_>>();
+        let mut labels = std::mem::take(&mut context.settings.labels);
+        labels.resize(segments.len(), String::new());
+
+        let mut statements = vec![];
+        for (segment, label) in segments.into_iter().zip(labels) {
+            for (i, statement) in split_statements(segment).into_iter().enumerate() {
+                statements.push(statement);
+                let number = format!("statement {}", i + 1);
+                context.settings.labels.push(if label.is_empty() {
+                    number
+                } else {
+                    format!("{label}, {number}")
+                });
+            }
+        }
+
+        context.settings.split = true;
+        *execute_query = vec![statements.join(DELIMITER)];
+    }
+}
+
+/// Split `sql` on `;` outside quotes, each statement keeps its `;` and is trimmed.
+fn split_statements(sql: &str) -> Vec<String> {
+    let mut statements = vec![];
+    let mut quote = None;
+    let mut start = 0;
+    for (i, c) in sql.char_indices() {
+        match (quote, c) {
+            (None, '\'' | '"' | '`') => quote = Some(c),
+            (Some(q), c) if q == c => quote = None,
+            (None, ';') => {
+                statements.push(&sql[start..=i]);
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    statements.push(&sql[start..]);
+
+    statements
+        .into_iter()
+        .map(str::trim)
+        .filter(|s| !s.is_empty() && *s != ";")
+        .map(str::to_string)
+        .collect()
+}
+
+pub struct SplitInterceptorFactory;
+
+impl InterceptorFactory for SplitInterceptorFactory {
+    fn try_new(&self, _: &str) -> Result<InterceptorRef> {
+        Ok(Box::new(SplitInterceptor))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn split_on_semicolons() {
+        assert_eq!(
+            split_statements("INSERT INTO t VALUES ('a;b');\nSELECT \";\" FROM t;\n\nSELECT 1"),
+            vec![
+                "INSERT INTO t VALUES ('a;b');",
+                "SELECT \";\" FROM t;",
+                "SELECT 1"
+            ]
+        );
+        assert!(split_statements(" ;\n").is_empty());
+    }
+
+    #[test]
+    fn label_statements() {
+        let interceptor = SplitInterceptorFactory.try_new("").unwrap();
+        let mut execute_query = vec![format!("SELECT 1; SELECT 2;{DELIMITER}SELECT 3;")];
+        let mut context = QueryContext::default();
+        context.settings.labels = vec!["v=a".to_string(), "v=b".to_string()];
+        interceptor.before_execute(&mut execute_query, &mut context);
+
+        assert_eq!(
+            execute_query,
+            vec![format!("SELECT 1;{DELIMITER}SELECT 2;{DELIMITER}SELECT 3;")]
+        );
+        assert_eq!(
+            context.settings.labels,
+            vec!["v=a, statement 1", "v=a, statement 2", "v=b, statement 1"]
+        );
+        assert!(context.settings.split);
+    }
+}
@@ -0,0 +1,80 @@
+// Copyright 2024 CeresDB Project Authors. Licensed under Apache-2.0.
+
+use crate::case::QueryContext;
+use crate::error::Result;
+use crate::interceptor::{
+    contains, count_rows, distinct, empty, hash, json_canon, limit, lines, mask_column, project,
+    redact, replace, round, sort_result, strip_ansi, Interceptor, InterceptorFactory,
+    InterceptorRef,
+};
+
+pub const PREFIX: &str = "STREAM";
+
+/// Builtin interceptors changing or checking the result, which a streamed result
+/// doesn't go through.
+pub(crate) const RESULT_INTERCEPTORS: &[&str] = &[
+    replace::PREFIX,
+    redact::PREFIX,
+    sort_result::PREFIX,
+    sort_result::NUMERIC_PREFIX,
+    limit::PREFIX,
+    lines::PREFIX,
+    project::PREFIX,
+    project::DROP_PREFIX,
+    mask_column::PREFIX,
+    round::PREFIX,
+    count_rows::PREFIX,
+    distinct::PREFIX,
+    json_canon::PREFIX,
+    strip_ansi::PREFIX,
+    hash::PREFIX,
+    empty::PREFIX,
+    contains::PREFIX,
+    contains::NOT_PREFIX,
+];
+
+/// Compare a large result with the recorded one chunk by chunk as it arrives from
+/// [`Database::query_stream`], instead of buffering it.
+///
+/// # Example
+/// ``` sql
+/// -- SQLNESS STREAM
+/// SELECT * FROM generate_series(1, 10000000);
+/// ```
+///
+/// Reading stops at the first chunk differing from the recorded result, the
+/// recorded result ends with the chunk and a
+/// `... (stream stopped at the first difference)` line then, and the case fails.
+/// The whole result is read when there's no recorded result or with
+/// [`Config::update_result`]. Backends that don't stream are read in one chunk.
+///
+/// The result is recorded as the backend yields it, and the query is sent as one
+/// statement. So it can't be combined with interceptors changing or checking the
+/// result, like `REPLACE`, `SORT_RESULT` or `CONTAINS`, nor be used with
+/// [`Config::result_filters`], [`Config::strip_ansi`] or
+/// [`Config::max_result_bytes`]: the case fails to parse with
+/// [`SqlnessError::InvalidContext`] then. Interceptors registered by the user
+/// aren't checked, their `after_execute` isn't run on a streamed result.
+///
+/// [`Database::query_stream`]: crate::Database::query_stream
+/// [`Config::update_result`]: crate::Config#structfield.update_result
+/// [`Config::result_filters`]: crate::Config#structfield.result_filters
+/// [`Config::strip_ansi`]: crate::Config#structfield.strip_ansi
+/// [`Config::max_result_bytes`]: crate::Config#structfield.max_result_bytes
+/// [`SqlnessError::InvalidContext`]: crate::SqlnessError::InvalidContext
+#[derive(Debug)]
+pub struct StreamInterceptor;
+
+impl Interceptor for StreamInterceptor {
+    fn before_execute(&self, _: &mut Vec<String>, context: &mut QueryContext) {
+        context.settings.stream = true;
+    }
+}
+
+pub struct StreamInterceptorFactory;
+
+impl InterceptorFactory for StreamInterceptorFactory {
+    fn try_new(&self, _: &str) -> Result<InterceptorRef> {
+        Ok(Box::new(StreamInterceptor))
+    }
+}
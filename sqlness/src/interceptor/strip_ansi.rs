@@ -0,0 +1,118 @@
+// Copyright 2024 CeresDB Project Authors. Licensed under Apache-2.0.
+
+use std::sync::OnceLock;
+
+use regex::Regex;
+
+use crate::case::QueryContext;
+use crate::error::Result;
+use crate::interceptor::{Interceptor, InterceptorFactory, InterceptorRef};
+use crate::QueryResult;
+
+pub const PREFIX: &str = "STRIP_ANSI";
+
+/// Remove ANSI escape sequences, like colors written by a client, from the result.
+///
+/// # Example
+/// `.sql` file:
+/// ``` sql
+/// -- SQLNESS STRIP_ANSI
+/// SELECT status FROM jobs;
+/// ```
+///
+/// `.result` file:
+/// ``` sql
+/// -- SQLNESS STRIP_ANSI
+/// SELECT status FROM jobs;
+///
+/// running
+/// ```
+///
+/// A result like `\x1b[32mrunning\x1b[0m` is recorded as `running`. Sequences are
+/// removed from the cells of [`QueryResult::Rows`], which are formatted again so
+/// tables stay aligned, or from the text of other results. Use
+/// [`Config::strip_ansi`] to remove them from all results.
+///
+/// [`QueryResult::Rows`]: crate::QueryResult::Rows
+/// [`Config::strip_ansi`]: crate::Config#structfield.strip_ansi
+#[derive(Debug)]
+pub struct StripAnsiInterceptor;
+
+impl Interceptor for StripAnsiInterceptor {
+    fn after_execute(&self, result: &mut String, context: &mut QueryContext) {
+        context.map_rows(result, |rows| Ok(strip_result(rows)));
+    }
+}
+
+/// Remove escape sequences from column names and cells, or the text of `result`.
+fn strip_result(result: QueryResult) -> QueryResult {
+    match result {
+        QueryResult::Rows { columns, rows } => QueryResult::Rows {
+            columns: columns.iter().map(|column| strip_ansi(column)).collect(),
+            rows: rows
+                .into_iter()
+                .map(|row| {
+                    row.into_iter()
+                        .map(|cell| cell.map(|cell| strip_ansi(&cell)))
+                        .collect()
+                })
+                .collect(),
+        },
+        QueryResult::Raw(raw) => QueryResult::Raw(strip_ansi(&raw)),
+        QueryResult::Error(error) => QueryResult::Error(strip_ansi(&error)),
+    }
+}
+
+/// Remove CSI sequences like colors and cursor moves, OSC sequences like titles
+/// and hyperlinks, and other two-byte escapes from `text`.
+pub(crate) fn strip_ansi(text: &str) -> String {
+    static ESCAPE: OnceLock<Regex> = OnceLock::new();
+    let escape = ESCAPE.get_or_init(|| {
+        Regex::new(r"\x1b(\[[0-?]*[ -/]*[@-~]|\][^\x07\x1b]*(\x07|\x1b\\)|[@-Z\\-_])")
+            .expect("valid escape regex")
+    });
+
+    escape.replace_all(text, "").into_owned()
+}
+
+pub struct StripAnsiInterceptorFactory;
+
+impl InterceptorFactory for StripAnsiInterceptorFactory {
+    fn try_new(&self, _: &str) -> Result<InterceptorRef> {
+        Ok(Box::new(StripAnsiInterceptor))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn strip_sequences() {
+        assert_eq!(
+            strip_ansi("\x1b[1;32mok\x1b[0m: 1 row\n\x1b[2K\x1b[1Gdone \x1b]0;title\x07!"),
+            "ok: 1 row\ndone !"
+        );
+        assert_eq!(
+            strip_ansi("\x1b]8;;http://a\x1b\\link\x1b]8;;\x1b\\ \x1bMup"),
+            "link up"
+        );
+        assert_eq!(strip_ansi("no [escape] here"), "no [escape] here");
+    }
+
+    #[test]
+    fn strip_cells() {
+        let interceptor = StripAnsiInterceptorFactory.try_new("").unwrap();
+        let result = QueryResult::Rows {
+            columns: vec!["\x1b[1mstatus\x1b[0m".into()],
+            rows: vec![vec![Some("\x1b[32mrunning\x1b[0m".into())], vec![None]],
+        };
+        assert_eq!(
+            QueryContext::after_execute_rows(interceptor.as_ref(), result),
+            Ok(QueryResult::Rows {
+                columns: vec!["status".into()],
+                rows: vec![vec![Some("running".into())], vec![None]],
+            })
+        );
+    }
+}
@@ -5,7 +5,7 @@ use serde_json::Value;
 
 use crate::error::Result;
 use crate::interceptor::{Interceptor, InterceptorFactory, InterceptorRef};
-use crate::SqlnessError;
+use crate::{QueryContext, SqlnessError};
 
 pub struct TemplateInterceptorFactory;
 
@@ -38,11 +38,48 @@ pub const DELIMITER: &str = "__sqlness_delimiter__";
 /// In order to generate multiple queries, you can use the builtin function
 /// `sql_delimiter()` to insert a delimiter.
 ///
+/// # Matrix
+/// If the context isn't a JSON object, it's treated as a variable name followed by a
+/// comma separated list of values:
+/// ``` text
+/// -- SQLNESS TEMPLATE <name> <value1>,<value2>,...
+/// ```
+///
+/// The query is expanded once per value, with every `{name}` replaced by the value,
+/// and each expansion is executed separately. Stacking multiple matrix `TEMPLATE`s
+/// on one query expands the cross product, the first declared variable is the outermost
+/// loop. In the result file, the output of each expansion is preceded by a comment
+/// line listing its bindings:
+///
+/// `.sql` file:
+/// ``` sql
+/// -- SQLNESS TEMPLATE col int,bigint
+/// SELECT CAST(1 AS {col});
+/// ```
+///
+/// `.result` file:
+/// ``` sql
+/// -- SQLNESS TEMPLATE col int,bigint
+/// SELECT CAST(1 AS {col});
+///
+/// -- col=int
+/// 1
+///
+/// -- col=bigint
+/// 1
+/// ```
 #[derive(Debug)]
 pub struct TemplateInterceptor {
     data_bindings: Value,
 }
 
+/// Matrix form of [`TemplateInterceptor`].
+#[derive(Debug)]
+pub struct MatrixTemplateInterceptor {
+    name: String,
+    values: Vec<String>,
+}
+
 fn sql_delimiter() -> std::result::Result<String, minijinja::Error> {
     Ok(DELIMITER.to_string())
 }
@@ -62,8 +99,37 @@ impl Interceptor for TemplateInterceptor {
     }
 }
 
+impl Interceptor for MatrixTemplateInterceptor {
+    fn before_execute(&self, execute_query: &mut Vec<String>, context: &mut QueryContext) {
+        let input = execute_query.concat();
+        let segments = input.split(DELIMITER).collect::<Vec<_>>();
+        let mut labels = std::mem::take(&mut context.settings.labels);
+        labels.resize(segments.len(), String::new());
+
+        let placeholder = format!("{{{}}}", self.name);
+        let mut expanded = Vec::with_capacity(segments.len() * self.values.len());
+        for (segment, label) in segments.into_iter().zip(labels) {
+            for value in &self.values {
+                expanded.push(segment.replace(&placeholder, value));
+                let binding = format!("{}={value}", self.name);
+                context.settings.labels.push(if label.is_empty() {
+                    binding
+                } else {
+                    format!("{label}, {binding}")
+                });
+            }
+        }
+
+        *execute_query = vec![expanded.join(DELIMITER)];
+    }
+}
+
 impl InterceptorFactory for TemplateInterceptorFactory {
     fn try_new(&self, ctx: &str) -> Result<InterceptorRef> {
+        if !ctx.is_empty() && !ctx.starts_with('{') {
+            return Self::create_matrix(ctx);
+        }
+
         let data_bindings = if ctx.is_empty() {
             serde_json::from_str("{}")
         } else {
@@ -78,6 +144,32 @@ impl InterceptorFactory for TemplateInterceptorFactory {
     }
 }
 
+impl TemplateInterceptorFactory {
+    fn create_matrix(ctx: &str) -> Result<InterceptorRef> {
+        let (name, values) = ctx
+            .split_once(' ')
+            .ok_or_else(|| SqlnessError::InvalidContext {
+                prefix: PREFIX.to_string(),
+                msg: "Expect json or <name> <values>".to_string(),
+            })?;
+        let values = values
+            .split(',')
+            .map(|v| v.trim().to_string())
+            .collect::<Vec<_>>();
+        if values.iter().any(|v| v.is_empty()) {
+            return Err(SqlnessError::InvalidContext {
+                prefix: PREFIX.to_string(),
+                msg: format!("Empty value in {ctx}"),
+            });
+        }
+
+        Ok(Box::new(MatrixTemplateInterceptor {
+            name: name.to_string(),
+            values,
+        }))
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -151,4 +243,61 @@ mod tests {
             .to_vec()
         );
     }
+
+    #[test]
+    fn matrix_template() {
+        let interceptor = TemplateInterceptorFactory
+            .try_new("col int, bigint,float")
+            .unwrap();
+
+        let mut input = vec!["SELECT CAST(1 AS {col})".to_string(), ";".to_string()];
+        let mut context = crate::QueryContext::default();
+        interceptor.before_execute(&mut input, &mut context);
+
+        assert_eq!(
+            input,
+            vec![[
+                "SELECT CAST(1 AS int);",
+                "SELECT CAST(1 AS bigint);",
+                "SELECT CAST(1 AS float);"
+            ]
+            .join(DELIMITER)]
+        );
+        assert_eq!(
+            context.settings.labels,
+            vec!["col=int", "col=bigint", "col=float"]
+        );
+    }
+
+    #[test]
+    fn nested_matrix_template() {
+        let outer = TemplateInterceptorFactory.try_new("t t1,t2").unwrap();
+        let inner = TemplateInterceptorFactory.try_new("c a,b").unwrap();
+
+        let mut input = vec!["SELECT {c} FROM {t};".to_string()];
+        let mut context = crate::QueryContext::default();
+        outer.before_execute(&mut input, &mut context);
+        inner.before_execute(&mut input, &mut context);
+
+        assert_eq!(
+            input,
+            vec![[
+                "SELECT a FROM t1;",
+                "SELECT b FROM t1;",
+                "SELECT a FROM t2;",
+                "SELECT b FROM t2;"
+            ]
+            .join(DELIMITER)]
+        );
+        assert_eq!(
+            context.settings.labels,
+            vec!["t=t1, c=a", "t=t1, c=b", "t=t2, c=a", "t=t2, c=b"]
+        );
+    }
+
+    #[test]
+    fn invalid_matrix_template() {
+        assert!(TemplateInterceptorFactory.try_new("col").is_err());
+        assert!(TemplateInterceptorFactory.try_new("col a,,b").is_err());
+    }
 }
@@ -0,0 +1,63 @@
+// Copyright 2024 CeresDB Project Authors. Licensed under Apache-2.0.
+
+use std::time::Duration;
+
+use crate::case::QueryContext;
+use crate::error::Result;
+use crate::interceptor::{Interceptor, InterceptorFactory, InterceptorRef};
+use crate::SqlnessError;
+
+pub const PREFIX: &str = "TIMEOUT";
+
+/// Limit how long the query may run, overriding [`Config::query_timeout`].
+///
+/// # Example
+/// ``` sql
+/// -- SQLNESS TIMEOUT 30s
+/// SELECT * FROM large_table;
+/// ```
+///
+/// The duration format is the same as `SLEEP` interceptor. The case fails with
+/// [`SqlnessError::Timeout`] if the query doesn't finish in time.
+///
+/// [`Config::query_timeout`]: crate::Config#structfield.query_timeout
+#[derive(Debug)]
+pub struct TimeoutInterceptor {
+    duration: Duration,
+}
+
+impl Interceptor for TimeoutInterceptor {
+    fn before_execute(&self, _: &mut Vec<String>, context: &mut QueryContext) {
+        context.settings.timeout = Some(self.duration);
+    }
+}
+
+pub struct TimeoutInterceptorFactory;
+
+impl InterceptorFactory for TimeoutInterceptorFactory {
+    fn try_new(&self, ctx: &str) -> Result<InterceptorRef> {
+        let duration = duration_str::parse(ctx).map_err(|e| SqlnessError::InvalidContext {
+            prefix: PREFIX.to_string(),
+            msg: format!("Failed to parse duration: {}", e),
+        })?;
+        Ok(Box::new(TimeoutInterceptor { duration }))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn set_timeout() {
+        let interceptor = TimeoutInterceptorFactory {}.try_new("1s500ms").unwrap();
+        let mut context = QueryContext::default();
+        interceptor.before_execute(&mut vec![], &mut context);
+        assert_eq!(context.settings.timeout, Some(Duration::from_millis(1500)));
+    }
+
+    #[test]
+    fn invalid_duration() {
+        assert!(TimeoutInterceptorFactory {}.try_new("soon").is_err());
+    }
+}
@@ -0,0 +1,48 @@
+// Copyright 2024 CeresDB Project Authors. Licensed under Apache-2.0.
+
+use crate::case::QueryContext;
+use crate::error::Result;
+use crate::interceptor::{Interceptor, InterceptorFactory, InterceptorRef};
+
+pub const PREFIX: &str = "TIMING";
+
+/// Record how long the query takes in the result file.
+///
+/// # Example
+/// `.sql` file:
+/// ``` sql
+/// -- SQLNESS TIMING
+/// SELECT count(*) FROM large_table;
+/// ```
+///
+/// `.result` file:
+/// ``` sql
+/// -- SQLNESS TIMING
+/// SELECT count(*) FROM large_table;
+///
+/// 1000000
+/// -- elapsed: 12.345ms
+///
+/// ```
+///
+/// The duration covers the calls to [`Database`] only. `-- elapsed: ` lines are
+/// removed from both old and new results before comparing them, so the timing never
+/// affects whether the case passes. It's written for reading only.
+///
+/// [`Database`]: crate::Database
+#[derive(Debug)]
+pub struct TimingInterceptor;
+
+impl Interceptor for TimingInterceptor {
+    fn before_execute(&self, _: &mut Vec<String>, context: &mut QueryContext) {
+        context.settings.timing = true;
+    }
+}
+
+pub struct TimingInterceptorFactory;
+
+impl InterceptorFactory for TimingInterceptorFactory {
+    fn try_new(&self, _: &str) -> Result<InterceptorRef> {
+        Ok(Box::new(TimingInterceptor))
+    }
+}
@@ -0,0 +1,75 @@
+// Copyright 2024 CeresDB Project Authors. Licensed under Apache-2.0.
+
+use crate::case::QueryContext;
+use crate::error::Result;
+use crate::interceptor::{Interceptor, InterceptorFactory, InterceptorRef};
+use crate::SqlnessError;
+
+pub const PREFIX: &str = "TXN";
+
+/// Run the query in an explicit transaction.
+///
+/// # Example
+/// ``` sql
+/// -- SQLNESS TXN
+/// INSERT INTO t VALUES (1);
+///
+/// -- SQLNESS TXN ROLLBACK
+/// DELETE FROM t;
+/// ```
+///
+/// `BEGIN;` is sent to the [`Database`] before the query, and `COMMIT;` (or
+/// `ROLLBACK;` if `ROLLBACK` is given) after it. Their output is not recorded, but the
+/// case fails if they return [`QueryResult::Error`]. If the query itself fails, the
+/// transaction is rolled back.
+///
+/// Only the annotated query is wrapped. When it's expanded to multiple statements
+/// (eg. by `TEMPLATE`), all of them run in the same transaction. Each execution of
+/// `REPEAT` or `RETRY` is a transaction on its own.
+///
+/// [`Database`]: crate::Database
+/// [`QueryResult::Error`]: crate::QueryResult::Error
+#[derive(Debug)]
+pub struct TxnInterceptor {
+    end: TxnEnd,
+}
+
+/// How a transaction started by `TXN` ends.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum TxnEnd {
+    Commit,
+    Rollback,
+}
+
+impl TxnEnd {
+    pub(crate) fn statement(self) -> &'static str {
+        match self {
+            TxnEnd::Commit => "COMMIT;",
+            TxnEnd::Rollback => "ROLLBACK;",
+        }
+    }
+}
+
+impl Interceptor for TxnInterceptor {
+    fn before_execute(&self, _: &mut Vec<String>, context: &mut QueryContext) {
+        context.settings.transaction = Some(self.end);
+    }
+}
+
+pub struct TxnInterceptorFactory;
+
+impl InterceptorFactory for TxnInterceptorFactory {
+    fn try_new(&self, ctx: &str) -> Result<InterceptorRef> {
+        let end = match ctx.to_uppercase().as_str() {
+            "" | "COMMIT" => TxnEnd::Commit,
+            "ROLLBACK" => TxnEnd::Rollback,
+            _ => {
+                return Err(SqlnessError::InvalidContext {
+                    prefix: PREFIX.to_string(),
+                    msg: format!("Expect COMMIT or ROLLBACK, got {ctx}"),
+                })
+            }
+        };
+        Ok(Box::new(TxnInterceptor { end }))
+    }
+}
@@ -0,0 +1,45 @@
+// Copyright 2024 CeresDB Project Authors. Licensed under Apache-2.0.
+
+use crate::case::QueryContext;
+use crate::error::Result;
+use crate::interceptor::{Interceptor, InterceptorFactory, InterceptorRef};
+
+pub const PREFIX: &str = "WARMUP";
+
+/// Execute the query only for its side effects, like warming caches, ignoring its
+/// result and errors.
+///
+/// # Example
+/// `.sql` file:
+/// ``` sql
+/// -- SQLNESS WARMUP
+/// SELECT * FROM t;
+/// ```
+///
+/// `.result` file:
+/// ``` sql
+/// -- SQLNESS WARMUP
+/// SELECT * FROM t;
+///
+/// -- WARMUP
+/// ```
+///
+/// Unlike `HIDE`, which fails the case when the query returns an error, a failed
+/// warmup query is ignored, as are errors from other interceptors like
+/// `EXPECT_ERROR` and timeouts. Unlike `SKIP_IF`, the query is always executed.
+#[derive(Debug)]
+pub struct WarmupInterceptor;
+
+impl Interceptor for WarmupInterceptor {
+    fn before_execute(&self, _: &mut Vec<String>, context: &mut QueryContext) {
+        context.settings.warmup = true;
+    }
+}
+
+pub struct WarmupInterceptorFactory;
+
+impl InterceptorFactory for WarmupInterceptorFactory {
+    fn try_new(&self, _: &str) -> Result<InterceptorRef> {
+        Ok(Box::new(WarmupInterceptor))
+    }
+}
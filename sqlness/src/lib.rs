@@ -11,7 +11,8 @@
 //! - Implement [`EnvController`] and [`Database`]. They provide methods to start
 //!   the server, submit the query and clean up etc.
 //! - Format the result. Implement [`Display`] for your query result to make them
-//!   comparable.
+//!   comparable, or return a [`QueryResult`] from [`Database::query_result`] and
+//!   let sqlness format it.
 //!
 //! And then all you need is to run the runner!
 //!
@@ -53,6 +54,10 @@
 //! different environments). All deeper layers are treated as the same. E.g.,
 //! both `sqlness/local/dml/basic.sql` and `sqlness/local/dml/another-dir/basic.sql`
 //! will be run under the `local` env in the same pass.
+//!
+//! Symbolic links are followed by default (see [`Config::follow_links`]), so a
+//! directory of shared cases can be linked into multiple environments, or an
+//! environment can be a link to another one.
 
 mod case;
 mod config;
@@ -60,12 +65,21 @@ mod database;
 pub mod database_impl;
 mod environment;
 mod error;
+mod format;
 pub mod interceptor;
+mod report;
 mod runner;
+pub mod util;
 
 pub use case::QueryContext;
-pub use config::{Config, ConfigBuilder, DatabaseConfig, DatabaseConfigBuilder};
-pub use database::Database;
+pub use config::{
+    load_env_config, CaseIsolation, Config, ConfigBuilder, DatabaseConfig, DatabaseConfigBuilder,
+};
+pub use database::{Database, QueryResult};
 pub use environment::EnvController;
-pub use error::SqlnessError;
+pub use error::{ResultMismatch, SqlnessError};
+pub use format::ResultFormat;
+pub use report::{
+    CaseReport, CaseStatus, EnvReport, EventCallback, QueryDuration, RunEvent, RunReport,
+};
 pub use runner::Runner;
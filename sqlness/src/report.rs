@@ -0,0 +1,663 @@
+// Copyright 2024 CeresDB Project Authors. Licensed under Apache-2.0.
+
+//! Reports of a run.
+
+use std::io::Write;
+use std::sync::Arc;
+use std::time::Duration;
+
+use serde_json::{json, Value};
+
+use crate::SqlnessError;
+
+/// Version of the JSON report schema, bumped on incompatible changes.
+pub(crate) const JSON_SCHEMA_VERSION: u64 = 1;
+
+/// Outcome of one test case.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CaseStatus {
+    Passed,
+    /// The result mismatched, with the diff from old result to new result.
+    Failed(String),
+    /// The case can't be executed, with the error message.
+    Errored(String),
+    /// The result file is overwritten with the new result, see [`Config::update_result`].
+    ///
+    /// [`Config::update_result`]: crate::Config#structfield.update_result
+    Updated,
+    /// The case is skipped by a `SKIP_IF` condition in its header, or isn't allowed
+    /// in this environment by `ONLY`, with the reason.
+    Skipped(String),
+    /// The case is only listed but not run, see [`Config::dry_run`].
+    ///
+    /// [`Config::dry_run`]: crate::Config#structfield.dry_run
+    Listed,
+    /// The case is in [`Config::quarantine`] and failed or errored, with the diff or
+    /// error message. It doesn't fail the run.
+    ///
+    /// [`Config::quarantine`]: crate::Config#structfield.quarantine
+    Quarantined(String),
+}
+
+impl CaseStatus {
+    /// Whether the case failed or errored.
+    pub fn is_failure(&self) -> bool {
+        matches!(self, CaseStatus::Failed(_) | CaseStatus::Errored(_))
+    }
+}
+
+/// Progress of a run, passed to [`Config::on_event`] as it happens.
+///
+/// Environments and cases are named like in [`EnvReport`] and [`CaseReport`].
+///
+/// [`Config::on_event`]: crate::Config#structfield.on_event
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RunEvent {
+    /// The environment is started, before its cases run.
+    EnvStarted {
+        env: String,
+    },
+    /// The environment is stopped after running its cases.
+    EnvFinished {
+        env: String,
+        duration: Duration,
+    },
+    CaseStarted {
+        env: String,
+        case: String,
+    },
+    CaseFinished {
+        env: String,
+        case: String,
+        status: CaseStatus,
+        duration: Duration,
+    },
+}
+
+/// Callback of [`RunEvent`]s, see [`Config::on_event`].
+///
+/// [`Config::on_event`]: crate::Config#structfield.on_event
+pub type EventCallback = Arc<dyn Fn(&RunEvent) + Send + Sync>;
+
+/// Report of one test case.
+#[derive(Debug, Clone)]
+pub struct CaseReport {
+    /// Case path relative to the environment directory, without extension.
+    pub name: String,
+    pub status: CaseStatus,
+    pub duration: Duration,
+    /// The error behind a failed or errored status, eg.
+    /// [`SqlnessError::ResultMismatch`] with the expected and actual results.
+    pub error: Option<Arc<SqlnessError>>,
+    /// Durations of queries with a `MAX_DURATION` interceptor, in the order of
+    /// queries.
+    pub query_durations: Vec<QueryDuration>,
+    /// The case is run and matches [`Config::quarantine`]. It's only worth noting
+    /// when the status isn't [`CaseStatus::Quarantined`], as the case can leave the
+    /// quarantine.
+    ///
+    /// [`Config::quarantine`]: crate::Config#structfield.quarantine
+    pub quarantined: bool,
+}
+
+/// Duration of a query measured by `MAX_DURATION` interceptor.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct QueryDuration {
+    pub query: String,
+    pub duration: Duration,
+    /// The threshold given to `MAX_DURATION`.
+    pub limit: Duration,
+}
+
+/// Report of cases run in one environment.
+#[derive(Debug, Clone)]
+pub struct EnvReport {
+    pub name: String,
+    /// Name of the backend running this environment, see [`Database::name`].
+    ///
+    /// [`Database::name`]: crate::Database::name
+    pub backend: String,
+    pub cases: Vec<CaseReport>,
+    /// How many cases are filtered out by [`Config::test_filter`].
+    ///
+    /// [`Config::test_filter`]: crate::Config#structfield.test_filter
+    pub filtered: usize,
+    pub duration: Duration,
+    /// Why the environment failed to start, when it's tolerated by
+    /// [`Config::skip_unstartable_envs`]. All its cases are skipped then.
+    ///
+    /// [`Config::skip_unstartable_envs`]: crate::Config#structfield.skip_unstartable_envs
+    pub start_error: Option<String>,
+}
+
+impl EnvReport {
+    fn count(&self, predicate: impl Fn(&CaseStatus) -> bool) -> usize {
+        self.cases
+            .iter()
+            .filter(|case| predicate(&case.status))
+            .count()
+    }
+
+    pub fn passed_count(&self) -> usize {
+        self.count(|s| matches!(s, CaseStatus::Passed))
+    }
+
+    /// Number of failed and errored cases.
+    pub fn failed_count(&self) -> usize {
+        self.count(CaseStatus::is_failure)
+    }
+
+    pub fn errored_count(&self) -> usize {
+        self.count(|s| matches!(s, CaseStatus::Errored(_)))
+    }
+
+    pub fn skipped_count(&self) -> usize {
+        self.count(|s| matches!(s, CaseStatus::Skipped(_)))
+    }
+
+    pub fn updated_count(&self) -> usize {
+        self.count(|s| matches!(s, CaseStatus::Updated))
+    }
+
+    pub fn listed_count(&self) -> usize {
+        self.count(|s| matches!(s, CaseStatus::Listed))
+    }
+
+    /// Number of quarantined cases that failed or errored.
+    pub fn quarantined_count(&self) -> usize {
+        self.count(|s| matches!(s, CaseStatus::Quarantined(_)))
+    }
+}
+
+/// Report of a run, returned by [`Runner::run_with_report`].
+///
+/// [`Runner::run_with_report`]: crate::Runner::run_with_report
+#[derive(Debug, Clone, Default)]
+pub struct RunReport {
+    /// Environments in the order they are run.
+    pub envs: Vec<EnvReport>,
+}
+
+impl RunReport {
+    /// Environments failed to start, see [`EnvReport::start_error`].
+    pub fn unstarted_envs(&self) -> impl Iterator<Item = &EnvReport> {
+        self.envs.iter().filter(|env| env.start_error.is_some())
+    }
+
+    /// All cases with the name of the environment they ran under.
+    pub fn cases(&self) -> impl Iterator<Item = (&str, &CaseReport)> {
+        self.envs
+            .iter()
+            .flat_map(|env| env.cases.iter().map(|case| (env.name.as_str(), case)))
+    }
+
+    pub fn passed_count(&self) -> usize {
+        self.envs.iter().map(EnvReport::passed_count).sum()
+    }
+
+    /// Number of failed and errored cases.
+    pub fn failed_count(&self) -> usize {
+        self.envs.iter().map(EnvReport::failed_count).sum()
+    }
+
+    pub fn errored_count(&self) -> usize {
+        self.envs.iter().map(EnvReport::errored_count).sum()
+    }
+
+    pub fn skipped_count(&self) -> usize {
+        self.envs.iter().map(EnvReport::skipped_count).sum()
+    }
+
+    pub fn updated_count(&self) -> usize {
+        self.envs.iter().map(EnvReport::updated_count).sum()
+    }
+
+    pub fn listed_count(&self) -> usize {
+        self.envs.iter().map(EnvReport::listed_count).sum()
+    }
+
+    pub fn quarantined_count(&self) -> usize {
+        self.envs.iter().map(EnvReport::quarantined_count).sum()
+    }
+
+    /// Quarantined cases that passed, which can be removed from
+    /// [`Config::quarantine`].
+    ///
+    /// [`Config::quarantine`]: crate::Config#structfield.quarantine
+    pub fn passed_quarantined(&self) -> impl Iterator<Item = (&str, &CaseReport)> {
+        self.cases().filter(|(_, case)| {
+            case.quarantined && matches!(case.status, CaseStatus::Passed | CaseStatus::Updated)
+        })
+    }
+
+    /// Total duration of all environments.
+    pub fn duration(&self) -> Duration {
+        self.envs.iter().map(|env| env.duration).sum()
+    }
+
+    /// Whether no case failed or errored, like [`Runner::run`] returning `Ok`.
+    ///
+    /// [`Runner::run`]: crate::Runner::run
+    pub fn is_success(&self) -> bool {
+        self.failed_count() == 0
+    }
+
+    /// Exit code for a driver to pass to [`std::process::exit`], `0` on success and
+    /// `1` otherwise.
+    pub fn exit_code(&self) -> i32 {
+        if self.is_success() {
+            0
+        } else {
+            1
+        }
+    }
+
+    /// One line summary in the form of `3 passed, 1 failed, 0 skipped in 1.25s`.
+    ///
+    /// The format is kept stable to be matched in CI logs. Updated cases count as
+    /// passed, and errored cases as failed.
+    pub fn summary(&self) -> String {
+        format!(
+            "{} passed, {} failed, {} skipped in {:.2}s",
+            self.passed_count() + self.updated_count(),
+            self.failed_count(),
+            self.skipped_count(),
+            self.duration().as_secs_f64()
+        )
+    }
+
+    /// Print [`RunReport::summary`] to stdout.
+    pub fn print_summary(&self) {
+        println!("{}", self.summary());
+    }
+}
+
+/// Write a JUnit XML report. Each environment is a `<testsuite>`, and each case is
+/// a `<testcase>` named by its relative path.
+pub(crate) fn write_junit<W: Write>(envs: &[EnvReport], writer: &mut W) -> std::io::Result<()> {
+    let tests = envs.iter().map(|env| env.cases.len()).sum::<usize>();
+    let errors = envs.iter().map(EnvReport::errored_count).sum::<usize>();
+    let failures = envs.iter().map(EnvReport::failed_count).sum::<usize>() - errors;
+    let skipped = envs
+        .iter()
+        .map(|env| env.skipped_count() + env.quarantined_count())
+        .sum::<usize>();
+    let time = envs.iter().map(|env| env.duration).sum::<Duration>();
+
+    writeln!(writer, r#"<?xml version="1.0" encoding="UTF-8"?>"#)?;
+    writeln!(
+        writer,
+        r#"<testsuites tests="{tests}" failures="{failures}" errors="{errors}" skipped="{skipped}" time="{:.3}">"#,
+        time.as_secs_f64()
+    )?;
+    for env in envs {
+        let errors = env.errored_count();
+        writeln!(
+            writer,
+            r#"  <testsuite name="{}" tests="{}" failures="{}" errors="{errors}" skipped="{}" time="{:.3}">"#,
+            escape_xml(&env.name),
+            env.cases.len(),
+            env.failed_count() - errors,
+            env.skipped_count() + env.quarantined_count(),
+            env.duration.as_secs_f64()
+        )?;
+        writeln!(writer, "    <properties>")?;
+        writeln!(
+            writer,
+            r#"      <property name="backend" value="{}"/>"#,
+            escape_xml(&env.backend)
+        )?;
+        if let Some(error) = &env.start_error {
+            writeln!(
+                writer,
+                r#"      <property name="start_error" value="{}"/>"#,
+                escape_xml(error)
+            )?;
+        }
+        writeln!(writer, "    </properties>")?;
+        for case in &env.cases {
+            write!(
+                writer,
+                r#"    <testcase name="{}" classname="{}" time="{:.3}""#,
+                escape_xml(&case.name),
+                escape_xml(&env.name),
+                case.duration.as_secs_f64()
+            )?;
+            match &case.status {
+                CaseStatus::Passed | CaseStatus::Updated | CaseStatus::Listed => {
+                    writeln!(writer, "/>")?
+                }
+                CaseStatus::Failed(diff) => {
+                    writeln!(writer, ">")?;
+                    writeln!(
+                        writer,
+                        r#"      <failure message="Result mismatched">{}</failure>"#,
+                        escape_xml(diff)
+                    )?;
+                    writeln!(writer, "    </testcase>")?;
+                }
+                CaseStatus::Errored(msg) => {
+                    writeln!(writer, ">")?;
+                    writeln!(writer, r#"      <error message="{}"/>"#, escape_xml(msg))?;
+                    writeln!(writer, "    </testcase>")?;
+                }
+                CaseStatus::Skipped(reason) => {
+                    writeln!(writer, ">")?;
+                    writeln!(
+                        writer,
+                        r#"      <skipped message="{}"/>"#,
+                        escape_xml(reason)
+                    )?;
+                    writeln!(writer, "    </testcase>")?;
+                }
+                CaseStatus::Quarantined(msg) => {
+                    writeln!(writer, ">")?;
+                    writeln!(writer, r#"      <skipped message="Quarantined"/>"#)?;
+                    writeln!(writer, "      <system-out>{}</system-out>", escape_xml(msg))?;
+                    writeln!(writer, "    </testcase>")?;
+                }
+            }
+        }
+        writeln!(writer, "  </testsuite>")?;
+    }
+    writeln!(writer, "</testsuites>")
+}
+
+/// Write a TAP (Test Anything Protocol) stream. Each case is a test point described
+/// by its environment and relative path, and the diff or error of a failed case is
+/// in a YAML diagnostic block.
+pub(crate) fn write_tap<W: Write>(envs: &[EnvReport], writer: &mut W) -> std::io::Result<()> {
+    let tests = envs.iter().map(|env| env.cases.len()).sum::<usize>();
+
+    writeln!(writer, "TAP version 13")?;
+    writeln!(writer, "1..{tests}")?;
+    for (i, (env, case)) in envs
+        .iter()
+        .flat_map(|env| env.cases.iter().map(move |case| (env, case)))
+        .enumerate()
+    {
+        let description = format!("{}/{}", env.name, case.name);
+        let number = i + 1;
+        match &case.status {
+            CaseStatus::Passed => writeln!(writer, "ok {number} - {description}")?,
+            CaseStatus::Updated => writeln!(writer, "ok {number} - {description} # updated")?,
+            CaseStatus::Listed => writeln!(writer, "ok {number} - {description} # SKIP dry run")?,
+            CaseStatus::Skipped(reason) => {
+                writeln!(writer, "ok {number} - {description} # SKIP {reason}")?
+            }
+            CaseStatus::Failed(diff) => {
+                writeln!(writer, "not ok {number} - {description}")?;
+                write_tap_diagnostic(writer, "Result mismatched", "diff", diff)?;
+            }
+            CaseStatus::Errored(msg) => {
+                writeln!(writer, "not ok {number} - {description}")?;
+                write_tap_diagnostic(writer, "Case errored", "error", msg)?;
+            }
+            CaseStatus::Quarantined(msg) => {
+                writeln!(writer, "not ok {number} - {description} # TODO quarantined")?;
+                write_tap_diagnostic(writer, "Case quarantined", "error", msg)?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Write a YAML block with a message and `key` in a literal block scalar.
+fn write_tap_diagnostic<W: Write>(
+    writer: &mut W,
+    message: &str,
+    key: &str,
+    value: &str,
+) -> std::io::Result<()> {
+    writeln!(writer, "  ---")?;
+    writeln!(writer, "  message: {message}")?;
+    writeln!(writer, "  {key}: |")?;
+    for line in value.lines() {
+        writeln!(writer, "    {line}")?;
+    }
+    writeln!(writer, "  ...")
+}
+
+/// Write a JSON report with every case, see [`Config::json_report`] for the schema.
+///
+/// [`Config::json_report`]: crate::Config#structfield.json_report
+pub(crate) fn write_json<W: Write>(envs: &[EnvReport], writer: &mut W) -> std::io::Result<()> {
+    let environments = envs
+        .iter()
+        .map(|env| {
+            json!({
+                "name": env.name,
+                "backend": env.backend,
+                "filtered": env.filtered,
+                "duration_ms": duration_ms(env.duration),
+                "start_error": env.start_error,
+            })
+        })
+        .collect::<Vec<_>>();
+    let cases = envs
+        .iter()
+        .flat_map(|env| env.cases.iter().map(move |case| (env, case)))
+        .map(|(env, case)| {
+            let mut value = json!({
+                "path": case.name,
+                "environment": env.name,
+                "duration_ms": duration_ms(case.duration),
+            });
+            let (status, detail) = match &case.status {
+                CaseStatus::Passed => ("passed", None),
+                CaseStatus::Failed(diff) => {
+                    if let Some(SqlnessError::ResultMismatch(mismatch)) = case.error.as_deref() {
+                        if let Some(query) = &mismatch.query {
+                            value["query"] = query.as_str().into();
+                        }
+                    }
+                    ("failed", Some(("diff", diff)))
+                }
+                CaseStatus::Errored(msg) => ("errored", Some(("error", msg))),
+                CaseStatus::Updated => ("updated", None),
+                CaseStatus::Skipped(reason) => ("skipped", Some(("reason", reason))),
+                CaseStatus::Listed => ("listed", None),
+                CaseStatus::Quarantined(msg) => ("quarantined", Some(("error", msg))),
+            };
+            value["status"] = status.into();
+            if let Some((key, detail)) = detail {
+                value[key] = detail.as_str().into();
+            }
+            if case.quarantined {
+                value["quarantined"] = true.into();
+            }
+            if !case.query_durations.is_empty() {
+                value["query_durations"] = case
+                    .query_durations
+                    .iter()
+                    .map(|d| {
+                        json!({
+                            "query": d.query,
+                            "duration_ms": duration_ms(d.duration),
+                            "limit_ms": duration_ms(d.limit),
+                        })
+                    })
+                    .collect::<Vec<_>>()
+                    .into();
+            }
+            value
+        })
+        .collect::<Vec<_>>();
+    let report = json!({
+        "schema_version": JSON_SCHEMA_VERSION,
+        "environments": environments,
+        "cases": cases,
+    });
+
+    serde_json::to_writer_pretty(&mut *writer, &report)?;
+    writeln!(writer)
+}
+
+fn duration_ms(duration: Duration) -> Value {
+    json!(duration.as_millis() as u64)
+}
+
+fn escape_xml(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn envs() -> Vec<EnvReport> {
+        vec![EnvReport {
+            name: "local".to_string(),
+            backend: "sqlite".to_string(),
+            cases: vec![
+                CaseReport {
+                    name: "dml/insert".to_string(),
+                    status: CaseStatus::Passed,
+                    duration: Duration::from_millis(12),
+                    error: None,
+                    query_durations: vec![QueryDuration {
+                        query: "INSERT INTO t VALUES (1);".to_string(),
+                        duration: Duration::from_millis(5),
+                        limit: Duration::from_millis(200),
+                    }],
+                    quarantined: false,
+                },
+                CaseReport {
+                    name: "dml/select".to_string(),
+                    status: CaseStatus::Failed("-a < b\n+a > b".to_string()),
+                    duration: Duration::from_millis(1),
+                    error: None,
+                    query_durations: vec![],
+                    quarantined: false,
+                },
+                CaseReport {
+                    name: "ddl/create".to_string(),
+                    status: CaseStatus::Errored("Unable to read \"create\"".to_string()),
+                    duration: Duration::ZERO,
+                    error: None,
+                    query_durations: vec![],
+                    quarantined: false,
+                },
+                CaseReport {
+                    name: "ddl/alter".to_string(),
+                    status: CaseStatus::Skipped("CI".to_string()),
+                    duration: Duration::ZERO,
+                    error: None,
+                    query_durations: vec![],
+                    quarantined: false,
+                },
+            ],
+            filtered: 0,
+            duration: Duration::from_millis(20),
+            start_error: None,
+        }]
+    }
+
+    #[test]
+    fn summary() {
+        let report = RunReport { envs: envs() };
+        assert_eq!(report.summary(), "1 passed, 2 failed, 1 skipped in 0.02s");
+        assert!(!report.is_success());
+        assert_eq!(report.exit_code(), 1);
+
+        let mut envs = envs();
+        envs[0].cases.truncate(1);
+        envs[0].cases[0].status = CaseStatus::Updated;
+        let report = RunReport { envs };
+        assert_eq!(report.summary(), "1 passed, 0 failed, 0 skipped in 0.02s");
+        assert_eq!(report.exit_code(), 0);
+    }
+
+    #[test]
+    fn junit_report() {
+        let mut output = vec![];
+        write_junit(&envs(), &mut output).unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            r#"<?xml version="1.0" encoding="UTF-8"?>
+<testsuites tests="4" failures="1" errors="1" skipped="1" time="0.020">
+  <testsuite name="local" tests="4" failures="1" errors="1" skipped="1" time="0.020">
+    <properties>
+      <property name="backend" value="sqlite"/>
+    </properties>
+    <testcase name="dml/insert" classname="local" time="0.012"/>
+    <testcase name="dml/select" classname="local" time="0.001">
+      <failure message="Result mismatched">-a &lt; b
++a &gt; b</failure>
+    </testcase>
+    <testcase name="ddl/create" classname="local" time="0.000">
+      <error message="Unable to read &quot;create&quot;"/>
+    </testcase>
+    <testcase name="ddl/alter" classname="local" time="0.000">
+      <skipped message="CI"/>
+    </testcase>
+  </testsuite>
+</testsuites>
+"#
+        );
+    }
+
+    #[test]
+    fn tap_report() {
+        let mut output = vec![];
+        write_tap(&envs(), &mut output).unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            r#"TAP version 13
+1..4
+ok 1 - local/dml/insert
+not ok 2 - local/dml/select
+  ---
+  message: Result mismatched
+  diff: |
+    -a < b
+    +a > b
+  ...
+not ok 3 - local/ddl/create
+  ---
+  message: Case errored
+  error: |
+    Unable to read "create"
+  ...
+ok 4 - local/ddl/alter # SKIP CI
+"#
+        );
+    }
+
+    #[test]
+    fn json_report() {
+        let mut output = vec![];
+        write_json(&envs(), &mut output).unwrap();
+        let report: Value = serde_json::from_slice(&output).unwrap();
+        assert_eq!(report["schema_version"], 1);
+        assert_eq!(
+            report["environments"],
+            json!([{"name": "local", "backend": "sqlite", "filtered": 0, "duration_ms": 20, "start_error": null}])
+        );
+        assert_eq!(
+            report["cases"],
+            json!([
+                {"path": "dml/insert", "environment": "local", "status": "passed", "duration_ms": 12,
+                    "query_durations": [{"query": "INSERT INTO t VALUES (1);", "duration_ms": 5, "limit_ms": 200}]},
+                {"path": "dml/select", "environment": "local", "status": "failed", "duration_ms": 1,
+                    "diff": "-a < b\n+a > b"},
+                {"path": "ddl/create", "environment": "local", "status": "errored", "duration_ms": 0,
+                    "error": "Unable to read \"create\""},
+                {"path": "ddl/alter", "environment": "local", "status": "skipped", "duration_ms": 0,
+                    "reason": "CI"},
+            ])
+        );
+    }
+}
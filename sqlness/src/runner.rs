@@ -1,19 +1,29 @@
 // Copyright 2022 CeresDB Project Authors. Licensed under Apache-2.0.
 
-use std::fs::{read_dir, OpenOptions};
-use std::io::{Cursor, Read, Seek, Write};
-use std::path::{Path, PathBuf};
-use std::str::FromStr;
-use std::time::Instant;
-
-use prettydiff::basic::{DiffOp, SliceChangeset};
-use prettydiff::diff_lines;
+use std::borrow::Cow;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fs::{read_dir, File, OpenOptions};
+use std::io::{BufWriter, Cursor, IsTerminal, Read, Seek, Write};
+use std::path::{Component, Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use futures::{future, stream, StreamExt};
+use glob::{MatchOptions, Pattern};
+use prettydiff::basic::{diff, DiffOp};
 use regex::Regex;
 use walkdir::WalkDir;
 
-use crate::case::TestCase;
-use crate::error::{Result, SqlnessError};
-use crate::{config::Config, environment::EnvController};
+use crate::case::{strip_timing, TestCase};
+use crate::config::{CaseIsolation, Config, EnvOverrides};
+use crate::error::{Result, ResultMismatch, SqlnessError};
+use crate::interceptor::{env::ENV_NAME, require::FEATURES};
+use crate::report::{self, CaseReport, CaseStatus, EnvReport, QueryDuration, RunEvent, RunReport};
+use crate::{database::Database, environment::EnvController, util, QueryContext, QueryResult};
+
+#[cfg(feature = "watch")]
+mod watch;
 
 /// The entrypoint of this crate.
 ///
@@ -33,158 +43,1020 @@ use crate::{config::Config, environment::EnvController};
 pub struct Runner<E: EnvController> {
     config: Config,
     env_controller: E,
+    /// Seed to shuffle cases, only set when [`Config::randomize`] is on.
+    seed: Option<u64>,
+    /// Serializes prompts of [`Config::interactive`] between concurrent cases.
+    prompt_lock: Mutex<()>,
+    /// Set when the run is aborted from a prompt, remaining cases are skipped.
+    aborted: AtomicBool,
+    /// Cases given to [`Runner::new_with_cases`], `None` to read them from
+    /// [`Config::case_dir`].
+    memory: Option<MemoryCases>,
+    /// Values captured by cases of each environment, see
+    /// [`Config::persistent_context`].
+    variables: Mutex<HashMap<String, HashMap<String, String>>>,
 }
 
 impl<E: EnvController> Runner<E> {
     pub fn new(config: Config, env_controller: E) -> Self {
+        let seed = config.randomize.then(|| {
+            config.seed.unwrap_or_else(|| {
+                SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .map(|d| d.as_nanos() as u64)
+                    .unwrap_or_default()
+            })
+        });
         Self {
             config,
             env_controller,
+            seed,
+            prompt_lock: Mutex::new(()),
+            aborted: AtomicBool::new(false),
+            memory: None,
+            variables: Mutex::default(),
+        }
+    }
+
+    /// Run cases given in memory instead of files under [`Config::case_dir`]. Each
+    /// case is a tuple of its path, content and expected result.
+    ///
+    /// The path is relative to [`Config::case_dir`] and without extension, its first
+    /// component is the environment, like `local/dml/insert`. Cases run through the
+    /// same pipeline as files: they are selected by filters, their queries are
+    /// intercepted, and their results are compared and reported. Results are written
+    /// back in memory instead of result files, see [`Runner::result_of`].
+    ///
+    /// Files included by `INCLUDE` and `SOURCE`, and config files of environments,
+    /// are still read from [`Config::case_dir`].
+    pub fn new_with_cases<P, C, R>(
+        config: Config,
+        env_controller: E,
+        cases: impl IntoIterator<Item = (P, C, R)>,
+    ) -> Self
+    where
+        P: AsRef<Path>,
+        C: Into<String>,
+        R: Into<String>,
+    {
+        let case_dir = Path::new(&config.case_dir).to_path_buf();
+        let mut memory = MemoryCases::default();
+        for (path, content, expected) in cases {
+            let path = case_dir.join(path);
+            memory.cases.insert(path.clone(), content.into());
+            memory
+                .results
+                .get_mut()
+                .unwrap()
+                .insert(path, expected.into());
         }
+
+        let mut runner = Self::new(config, env_controller);
+        runner.memory = Some(memory);
+        runner
+    }
+
+    /// Result of a case given to [`Runner::new_with_cases`], which is the expected
+    /// one until it's written like a result file. `None` for an unknown case, or if
+    /// cases are read from files.
+    pub fn result_of<P: AsRef<Path>>(&self, path: P) -> Option<String> {
+        let memory = self.memory.as_ref()?;
+        let path = Path::new(&self.config.case_dir).join(path);
+        memory.results.lock().unwrap().get(&path).cloned()
     }
 
+    /// Run all cases. Returns [`SqlnessError::RunFailed`] if any case fails.
     pub async fn run(&self) -> Result<()> {
-        let environments = self.collect_env()?;
-        let mut errors = Vec::new();
+        let report = self.run_with_report().await?;
+        match report.failed_count() {
+            0 => Ok(()),
+            count => Err(SqlnessError::RunFailed { count }),
+        }
+    }
+
+    /// Run all cases and return the report. Failed cases are only recorded in the
+    /// report, an error is returned if an environment can't be run.
+    ///
+    /// [`EnvController::before_all`] and [`EnvController::after_all`] are called
+    /// around the run, `after_all` is called even if it returns an error.
+    pub async fn run_with_report(&self) -> Result<RunReport> {
+        self.env_controller.before_all().await;
+        let result = self.run_envs().await;
+        self.env_controller.after_all().await;
+        result
+    }
+
+    async fn run_envs(&self) -> Result<RunReport> {
         let filter = Regex::new(&self.config.env_filter)?;
-        for env in environments {
-            if !filter.is_match(&env) {
+        let mut environments = self.collect_env()?;
+        environments.retain(|env| {
+            let matched = filter.is_match(env);
+            if !matched {
                 println!("Environment({env}) is skipped!");
-                continue;
             }
-            let env_config = self.read_env_config(&env);
-            let config_path = env_config.as_path();
-            let config_path = if config_path.exists() {
-                Some(config_path)
-            } else {
-                None
-            };
-            let db = self.env_controller.start(&env, config_path).await;
-            let run_result = self.run_env(&env, &db).await;
-            self.env_controller.stop(&env, db).await;
+            matched
+        });
+        if let Some(seed) = self.seed {
+            println!("Cases are run in random order with seed {seed}");
+        }
 
-            if let Err(e) = run_result {
-                println!("Environment {env} run failed, error:{e:?}.");
+        let mut errors = Vec::new();
+        let mut reports = Vec::new();
+        // All environments are already run if they are run concurrently.
+        let fail_fast = self.config.fail_fast && !self.config.parallel_envs;
+        let fail_on_first_mismatch =
+            self.config.fail_on_first_mismatch && !self.config.parallel_envs;
+        let mut results = if self.config.parallel_envs {
+            // Results are in the order of `environments`, no matter which finishes first.
+            future::join_all(environments.iter().map(|env| self.start_and_run_env(env)))
+                .await
+                .into_iter()
+        } else {
+            vec![].into_iter()
+        };
+        for env in environments {
+            let (run_result, stop_result) = match results.next() {
+                Some(result) => result,
+                None => self.start_and_run_env(&env).await,
+            };
+            if let Err(e) = stop_result {
+                println!("Environment {env} stop failed, error:{e}.");
+                errors.push(e);
+            }
 
-                if self.config.fail_fast {
-                    return Err(e);
+            match run_result {
+                Ok(report) => {
+                    let count = report.failed_count();
+                    reports.push(report);
+                    if count > 0 {
+                        println!("Environment {env} run failed, {count} cases can't pass.");
+                        if fail_on_first_mismatch {
+                            break;
+                        }
+                    }
                 }
+                Err(e) => {
+                    println!("Environment {env} run failed, error:{e:?}.");
 
-                errors.push(e);
+                    if fail_fast {
+                        self.write_reports(&reports)?;
+                        return Err(e);
+                    }
+
+                    errors.push(e);
+                }
             }
         }
 
+        self.write_reports(&reports)?;
+
         // only return first error
         if let Some(e) = errors.pop() {
             return Err(e);
         }
 
+        Ok(RunReport { envs: reports })
+    }
+
+    /// Start the environment, run its cases and stop it. Return the results of
+    /// running and stopping it.
+    async fn start_and_run_env(&self, env: &str) -> (Result<EnvReport>, Result<()>) {
+        if let Some(reason) = self.unselected_reason(env) {
+            return (self.skip_env(env, reason).await, Ok(()));
+        }
+        if self.config.dry_run {
+            return (self.list_env(env).await, Ok(()));
+        }
+        let db = match self.start_env(env).await {
+            Ok(db) => db,
+            Err(e) if self.config.skip_unstartable_envs => {
+                println!("Environment {env} failed to start, error:{e}");
+                let report = self
+                    .skip_env(env, format!("environment failed to start, {e}"))
+                    .await
+                    .map(|report| EnvReport {
+                        start_error: Some(e.to_string()),
+                        ..report
+                    });
+                return (report, Ok(()));
+            }
+            Err(e) => return (Err(e), Ok(())),
+        };
+        let start = Instant::now();
+        self.emit(RunEvent::EnvStarted {
+            env: env.to_string(),
+        });
+        let run_result = self.run_env(env, &db).await;
+        let stop_result = self.stop_env(env, db).await;
+        self.emit(RunEvent::EnvFinished {
+            env: env.to_string(),
+            duration: start.elapsed(),
+        });
+
+        (run_result, stop_result)
+    }
+
+    /// Run one case file and return its report, the environment is the first
+    /// component of `path` under [`Config::case_dir`]. The environment is started
+    /// and stopped like in [`Runner::run`], but only this case is run.
+    ///
+    /// `path` can be relative to [`Config::case_dir`] or include it, with or
+    /// without the extension, eg. `local/dml/basic.sql`. A relative path is looked
+    /// up in [`Config::extra_case_dirs`] too. Global hooks are called
+    /// like in [`Runner::run_with_report`].
+    pub async fn run_file<P: AsRef<Path>>(&self, path: P) -> Result<CaseReport> {
+        self.env_controller.before_all().await;
+        let result = self.run_one_file(path.as_ref()).await;
+        self.env_controller.after_all().await;
+        result
+    }
+
+    async fn run_one_file(&self, path: &Path) -> Result<CaseReport> {
+        let roots = self.case_roots();
+        let (case_dir, relative) = roots
+            .iter()
+            .find_map(|root| path.strip_prefix(root).ok().map(|r| (*root, r)))
+            .unwrap_or_else(|| {
+                let case_file = path.with_extension(&self.config.test_case_extension);
+                let root = roots
+                    .iter()
+                    .find(|root| root.join(&case_file).is_file())
+                    .unwrap_or(&roots[0]);
+                (*root, path)
+            });
+        let env = relative
+            .components()
+            .next()
+            .and_then(|env| match env {
+                Component::Normal(env) => env.to_str(),
+                _ => None,
+            })
+            .filter(|env| case_dir.join(env).is_dir() && relative.components().count() > 1)
+            .ok_or_else(|| SqlnessError::InvalidCasePath {
+                path: path.to_path_buf(),
+            })?;
+        let env_root = case_dir.join(env);
+        let case_path = case_dir.join(relative).with_extension("");
+
+        let db = self.start_env(env).await?;
+        let start = Instant::now();
+        self.emit(RunEvent::EnvStarted {
+            env: env.to_string(),
+        });
+        let run_result: Result<_> = async {
+            self.wait_ready(env, &db).await?;
+            let dir = case_path.parent().unwrap_or(&env_root);
+            let overrides = self.env_overrides(env)?;
+            let metadata = self.env_metadata(env, &db, &overrides).await;
+            self.env_controller.before_dir(env, dir, &db).await;
+            let report = self
+                .run_reported_case(&db, env, &metadata, &case_path, |e| e)
+                .await;
+            self.env_controller.after_dir(env, dir, &db).await;
+            Ok(report)
+        }
+        .await;
+        let stop_result = self.stop_env(env, db).await;
+        self.emit(RunEvent::EnvFinished {
+            env: env.to_string(),
+            duration: start.elapsed(),
+        });
+
+        let report = run_result?;
+        stop_result?;
+        Ok(report)
+    }
+
+    /// Start the environment with its config file if it exists, see
+    /// [`Config::require_env_config`].
+    async fn start_env(&self, env: &str) -> Result<E::DB> {
+        let env_config = self.read_env_config(env);
+        let config_path = env_config.as_path();
+        let config_path = if config_path.exists() {
+            Some(config_path)
+        } else if self.config.require_env_config {
+            return Err(SqlnessError::MissingEnvConfig {
+                env: env.to_string(),
+                path: env_config,
+            });
+        } else {
+            None
+        };
+        util::catch_panic(
+            self.config.catch_panic,
+            self.env_controller.start(env, config_path),
+        )
+        .await
+        .map_err(|message| SqlnessError::EnvPanic {
+            env: env.to_string(),
+            message,
+        })
+    }
+
+    /// Stop the environment in [`Config::shutdown_timeout`].
+    async fn stop_env(&self, env: &str, db: E::DB) -> Result<()> {
+        let stop = util::catch_panic(self.config.catch_panic, self.env_controller.stop(env, db));
+        let stopped =
+            match self.config.shutdown_timeout {
+                Some(timeout) => util::timeout(timeout, stop).await.ok_or_else(|| {
+                    SqlnessError::ShutdownTimeout {
+                        env: env.to_string(),
+                        timeout,
+                    }
+                })?,
+                None => stop.await,
+            };
+        stopped.map_err(|message| SqlnessError::EnvPanic {
+            env: env.to_string(),
+            message,
+        })
+    }
+
+    /// Write reports enabled in [`Config`].
+    fn write_reports(&self, reports: &[EnvReport]) -> Result<()> {
+        if self.config.dry_run {
+            return Ok(());
+        }
+
+        if let Some(path) = &self.config.junit_report {
+            let mut writer = BufWriter::new(File::create(path)?);
+            report::write_junit(reports, &mut writer)?;
+            writer.flush()?;
+        }
+        if let Some(path) = &self.config.tap_report {
+            let mut writer = BufWriter::new(File::create(path)?);
+            report::write_tap(reports, &mut writer)?;
+            writer.flush()?;
+        }
+        if let Some(path) = &self.config.json_report {
+            let mut writer = BufWriter::new(File::create(path)?);
+            report::write_json(reports, &mut writer)?;
+            writer.flush()?;
+        }
+
         Ok(())
     }
 
+    /// [`Config::parallelism`] of the environment, overridden by its config file.
+    /// Cases run one by one with [`Config::persistent_context`].
+    fn env_parallelism(&self, overrides: &EnvOverrides) -> usize {
+        if self.config.persistent_context {
+            return 1;
+        }
+        overrides.parallelism.unwrap_or(self.config.parallelism)
+    }
+
+    /// Settings overridden by the config file of the environment.
+    fn env_overrides(&self, env: &str) -> Result<EnvOverrides> {
+        let env_config = self.read_env_config(env);
+        if env_config.is_file() {
+            EnvOverrides::load(&env_config)
+        } else {
+            Ok(EnvOverrides::default())
+        }
+    }
+
+    /// Config file of the environment, in the first case directory that has it, see
+    /// [`Config::extra_case_dirs`].
     fn read_env_config(&self, env: &str) -> PathBuf {
-        let mut path_buf = std::path::PathBuf::new();
-        path_buf.push(&self.config.case_dir);
-        path_buf.push(env);
-        path_buf.push(&self.config.env_config_file);
+        let mut paths = self
+            .case_roots()
+            .into_iter()
+            .map(|root| root.join(env).join(&self.config.env_config_file))
+            .collect::<Vec<_>>();
+        let found = paths.iter().position(|path| path.exists()).unwrap_or(0);
+
+        paths.swap_remove(found)
+    }
+
+    /// [`Config::case_dir`] followed by [`Config::extra_case_dirs`], only the former
+    /// for cases in memory.
+    fn case_roots(&self) -> Vec<&Path> {
+        let mut roots = vec![Path::new(&self.config.case_dir)];
+        if self.memory.is_none() {
+            roots.extend(self.config.extra_case_dirs.iter().map(Path::new));
+        }
+        roots
+    }
 
-        path_buf
+    /// Directory of the environment `path` is in.
+    fn env_root(&self, env: &str, path: &Path) -> PathBuf {
+        let roots = self.case_roots();
+        let root = roots
+            .iter()
+            .find(|root| path.starts_with(root.join(env)))
+            .unwrap_or(&roots[0]);
+        root.join(env)
     }
 
     fn collect_env(&self) -> Result<Vec<String>> {
+        if let Some(memory) = &self.memory {
+            return Ok(memory.envs(Path::new(&self.config.case_dir)));
+        }
+
         let mut result = vec![];
 
-        for dir in read_dir(&self.config.case_dir)? {
-            let dir = dir?;
-            if dir.file_type()?.is_dir() {
+        for root in self.case_roots() {
+            for dir in read_dir(root)? {
+                let dir = dir?;
+                // `metadata` follows symbolic links while `file_type` doesn't.
+                let is_dir = if self.config.follow_links {
+                    std::fs::metadata(dir.path())?.is_dir()
+                } else {
+                    dir.file_type()?.is_dir()
+                };
                 let file_name = dir.file_name().to_str().unwrap().to_string();
-                result.push(file_name);
+                if is_dir && !result.contains(&file_name) {
+                    result.push(file_name);
+                }
             }
         }
 
         Ok(result)
     }
 
-    async fn run_env(&self, env: &str, db: &E::DB) -> Result<()> {
-        let case_paths = self.collect_case_paths(env).await?;
-        let mut failed_cases = vec![];
-        let mut errors = vec![];
+    /// Why the environment isn't selected by [`Config::include_envs`] and
+    /// [`Config::exclude_envs`], or `None` if it's selected.
+    fn unselected_reason(&self, env: &str) -> Option<String> {
+        let included = self.config.include_envs.is_empty()
+            || self.config.include_envs.iter().any(|e| e == env);
+        if !included {
+            Some(format!("environment {env} is not in include_envs"))
+        } else if self.config.exclude_envs.iter().any(|e| e == env) {
+            Some(format!("environment {env} is in exclude_envs"))
+        } else {
+            None
+        }
+    }
+
+    /// Report all cases of the environment as skipped without starting it.
+    async fn skip_env(&self, env: &str, reason: String) -> Result<EnvReport> {
+        println!("Environment({env}) is skipped, {reason}");
+        let (case_paths, filtered_count) = self.collect_case_paths(env).await?;
+        let cases = case_paths
+            .iter()
+            .map(|path| CaseReport {
+                name: Self::case_name(&self.env_root(env, path), path),
+                status: CaseStatus::Skipped(reason.clone()),
+                duration: Duration::ZERO,
+                error: None,
+                query_durations: vec![],
+                quarantined: false,
+            })
+            .collect();
+
+        Ok(EnvReport {
+            name: env.to_string(),
+            backend: env.to_string(),
+            cases,
+            filtered: filtered_count,
+            duration: Duration::ZERO,
+            start_error: None,
+        })
+    }
+
+    /// List cases of the environment without running them, see [`Config::dry_run`].
+    async fn list_env(&self, env: &str) -> Result<EnvReport> {
+        let (case_paths, filtered_count) = self.collect_case_paths(env).await?;
+        let cases = case_paths
+            .iter()
+            .map(|path| CaseReport {
+                name: Self::case_name(&self.env_root(env, path), path),
+                status: CaseStatus::Listed,
+                duration: Duration::ZERO,
+                error: None,
+                query_durations: vec![],
+                quarantined: false,
+            })
+            .collect::<Vec<_>>();
+
+        println!("Environment {env} has {} cases to run:", cases.len());
+        for case in &cases {
+            println!("{env}:{}", case.name);
+        }
+        if filtered_count > 0 {
+            println!("{filtered_count} cases are filtered out by test_filter");
+        }
+
+        Ok(EnvReport {
+            name: env.to_string(),
+            backend: env.to_string(),
+            cases,
+            filtered: filtered_count,
+            duration: Duration::ZERO,
+            start_error: None,
+        })
+    }
+
+    async fn run_env(&self, env: &str, db: &E::DB) -> Result<EnvReport> {
+        let (case_paths, filtered_count) = self.collect_case_paths(env).await?;
+        let backend = db.name().unwrap_or(env);
+        let overrides = self.env_overrides(env)?;
+        let parallelism = self.env_parallelism(&overrides);
+        self.variables.lock().unwrap().remove(env);
+        let mut cases = vec![];
         let start = Instant::now();
-        for path in case_paths {
-            let is_success = self.run_single_case(db, &path).await;
-            let case_name = path.as_os_str().to_str().unwrap().to_owned();
-            match is_success {
-                Ok(false) => failed_cases.push(case_name),
-                Ok(true) => {}
-                Err(e) => {
-                    if self.config.fail_fast {
-                        println!("Case {case_name} failed with error {e:?}");
-                        println!("Stopping environment {env} due to previous error.");
-                        break;
-                    } else {
-                        errors.push((case_name, e))
-                    }
-                }
+
+        // Cases all error if the database isn't ready, or are skipped if it's
+        // tolerated.
+        let mut start_error = None;
+        let mut metadata = Arc::default();
+        let groups = match self.wait_ready(env, db).await {
+            Ok(()) => {
+                metadata = self.env_metadata(env, db, &overrides).await;
+                Self::group_by_dir(case_paths)
+            }
+            Err(e) => {
+                println!("{e}");
+                let (status, error) = if self.config.skip_unstartable_envs {
+                    start_error = Some(e.to_string());
+                    let reason = format!("environment failed to start, {e}");
+                    (CaseStatus::Skipped(reason), None)
+                } else {
+                    (CaseStatus::Errored(e.to_string()), Some(Arc::new(e)))
+                };
+                cases.extend(case_paths.iter().map(|path| CaseReport {
+                    name: Self::case_name(&self.env_root(env, path), path),
+                    status: status.clone(),
+                    duration: Duration::ZERO,
+                    error: error.clone(),
+                    query_durations: vec![],
+                    quarantined: false,
+                }));
+                vec![]
+            }
+        };
+
+        for (dir, case_paths) in groups {
+            self.env_controller.before_dir(env, &dir, db).await;
+            let stopped = self
+                .run_cases(db, env, &metadata, case_paths, parallelism, &mut cases)
+                .await;
+            self.env_controller.after_dir(env, &dir, db).await;
+
+            if stopped {
+                println!("Stopping environment {env} due to previous failure.");
+                break;
             }
         }
 
+        let report = EnvReport {
+            name: env.to_string(),
+            backend: backend.to_string(),
+            cases,
+            filtered: filtered_count,
+            duration: start.elapsed(),
+            start_error,
+        };
         println!(
-            "Environment {} run finished, cost:{}ms",
+            "Environment {} run finished on backend {}, cost:{}ms",
             env,
-            start.elapsed().as_millis()
+            backend,
+            report.duration.as_millis()
         );
 
+        if report.filtered > 0 {
+            println!("{} cases are filtered out by test_filter", report.filtered);
+        }
+
+        let updated_cases = Self::case_names(&report, |s| matches!(s, CaseStatus::Updated));
+        if !updated_cases.is_empty() {
+            println!("Updated cases:");
+            println!("{updated_cases:#?}");
+        }
+
+        let skipped_cases = Self::case_names(&report, |s| matches!(s, CaseStatus::Skipped(_)));
+        if !skipped_cases.is_empty() {
+            println!("Skipped cases:");
+            println!("{skipped_cases:#?}");
+        }
+
+        let failed_cases = Self::case_names(&report, |s| matches!(s, CaseStatus::Failed(_)));
         if !failed_cases.is_empty() {
             println!("Failed cases:");
             println!("{failed_cases:#?}");
         }
 
+        let errors = report
+            .cases
+            .iter()
+            .filter_map(|case| match &case.status {
+                CaseStatus::Errored(msg) => Some((case.name.as_str(), msg.as_str())),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
         if !errors.is_empty() {
             println!("Error cases:");
             println!("{errors:#?}");
         }
 
-        let error_count = failed_cases.len() + errors.len();
-        if error_count == 0 {
-            Ok(())
-        } else {
-            Err(SqlnessError::RunFailed { count: error_count })
+        let quarantined_cases =
+            Self::case_names(&report, |s| matches!(s, CaseStatus::Quarantined(_)));
+        if !quarantined_cases.is_empty() {
+            println!("Quarantined cases failed:");
+            println!("{quarantined_cases:#?}");
+        }
+
+        let passed_quarantined = report
+            .cases
+            .iter()
+            .filter(|case| {
+                case.quarantined && matches!(case.status, CaseStatus::Passed | CaseStatus::Updated)
+            })
+            .map(|case| case.name.as_str())
+            .collect::<Vec<_>>();
+        if !passed_quarantined.is_empty() {
+            println!("Quarantined cases passed, consider removing them from quarantine:");
+            println!("{passed_quarantined:#?}");
+        }
+
+        Ok(report)
+    }
+
+    /// Metadata of the environment from [`EnvController::metadata`], with its name
+    /// as `sqlness_env` and the features of its config file as `sqlness_features`.
+    async fn env_metadata(
+        &self,
+        env: &str,
+        db: &E::DB,
+        overrides: &EnvOverrides,
+    ) -> Arc<HashMap<String, String>> {
+        let mut metadata = self.env_controller.metadata(env, db).await;
+        metadata.insert(ENV_NAME.to_string(), env.to_string());
+        metadata.insert(FEATURES.to_string(), overrides.features.join(","));
+        Arc::new(metadata)
+    }
+
+    /// Poll [`Database::is_ready`] with backoff until [`Config::startup_timeout`].
+    async fn wait_ready(&self, env: &str, db: &E::DB) -> Result<()> {
+        let timeout = self.config.startup_timeout;
+        let start = Instant::now();
+        let mut delay = Duration::from_millis(10);
+        loop {
+            let remaining = timeout.saturating_sub(start.elapsed());
+            if util::timeout(remaining, db.is_ready()).await == Some(true) {
+                return Ok(());
+            }
+            let remaining = timeout.saturating_sub(start.elapsed());
+            if remaining.is_zero() {
+                return Err(SqlnessError::NotReady {
+                    env: env.to_string(),
+                    timeout,
+                });
+            }
+            util::sleep(delay.min(remaining)).await;
+            delay = (delay * 2).min(Duration::from_secs(1));
+        }
+    }
+
+    /// Run cases and append their reports to `cases`. Returns true if it stops
+    /// early because of [`Config::fail_on_first_mismatch`].
+    async fn run_cases(
+        &self,
+        db: &E::DB,
+        env: &str,
+        metadata: &Arc<HashMap<String, String>>,
+        case_paths: Vec<PathBuf>,
+        parallelism: usize,
+        cases: &mut Vec<CaseReport>,
+    ) -> bool {
+        let backend = db.name().unwrap_or(env);
+        // `buffered` yields results in the order of `case_paths` regardless of which
+        // case finishes first, so the report is deterministic.
+        let mut reports = stream::iter(case_paths)
+            .map(|path| async move {
+                self.run_reported_case(db, env, metadata, &path, |e| {
+                    let e = SqlnessError::CaseErrored {
+                        case: path.as_os_str().to_str().unwrap().to_string(),
+                        backend: backend.to_string(),
+                        source: Box::new(e),
+                    };
+                    println!("{e}");
+                    e
+                })
+                .await
+            })
+            .buffered(parallelism.max(1));
+        while let Some(report) = reports.next().await {
+            let stop = self.config.fail_on_first_mismatch && report.status.is_failure();
+            cases.push(report);
+
+            if stop {
+                return true;
+            }
+        }
+
+        false
+    }
+
+    /// End the transaction of a case if it's `in_transaction`, committed if the case
+    /// `passed`. Otherwise clean up a failed case, see [`Config::case_isolation`].
+    async fn isolate(
+        &self,
+        db: &E::DB,
+        env: &str,
+        path: &Path,
+        in_transaction: bool,
+        passed: bool,
+    ) -> Result<()> {
+        match (in_transaction, passed) {
+            (true, true) => Self::control(db, "COMMIT;").await,
+            (true, false) => Self::control(db, "ROLLBACK;").await,
+            (false, true) => Ok(()),
+            (false, false) => {
+                if self.config.case_isolation != CaseIsolation::None {
+                    self.env_controller.cleanup_after_case(env, path, db).await;
+                }
+                Ok(())
+            }
+        }
+    }
+
+    /// Execute a statement controlling the transaction of a case.
+    async fn control(db: &E::DB, sql: &str) -> Result<()> {
+        match db
+            .query_result(QueryContext::default(), sql.to_string())
+            .await
+        {
+            QueryResult::Error(msg) => Err(SqlnessError::UnexpectedResult {
+                query: sql.to_string(),
+                msg: format!("query failed: {msg}"),
+            }),
+            _ => Ok(()),
+        }
+    }
+
+    /// Run one case like [`Self::run_single_case`] and report it, with the events of
+    /// [`Config::on_event`]. Errors are wrapped by `wrap`, see [`Self::case_outcome`].
+    async fn run_reported_case(
+        &self,
+        db: &E::DB,
+        env: &str,
+        metadata: &Arc<HashMap<String, String>>,
+        path: &Path,
+        wrap: impl FnOnce(SqlnessError) -> SqlnessError,
+    ) -> CaseReport {
+        let name = Self::case_name(&self.env_root(env, path), path);
+        self.emit(RunEvent::CaseStarted {
+            env: env.to_string(),
+            case: name.clone(),
+        });
+        let timer = Instant::now();
+        let mut query_durations = vec![];
+        let status = self
+            .run_single_case(db, env, metadata, path, &mut query_durations)
+            .await;
+        let duration = timer.elapsed();
+        let (mut status, error) = Self::case_outcome(status, wrap);
+        let quarantined = self.is_quarantined(env, &name);
+        if quarantined {
+            if let CaseStatus::Failed(msg) | CaseStatus::Errored(msg) = &status {
+                status = CaseStatus::Quarantined(msg.clone());
+            }
+        }
+        self.emit(RunEvent::CaseFinished {
+            env: env.to_string(),
+            case: name.clone(),
+            status: status.clone(),
+            duration,
+        });
+
+        CaseReport {
+            name,
+            status,
+            duration,
+            error,
+            query_durations,
+            quarantined,
+        }
+    }
+
+    /// Whether the case matches [`Config::quarantine`].
+    fn is_quarantined(&self, env: &str, name: &str) -> bool {
+        let case = format!("{env}:{name}");
+        self.config.quarantine.iter().any(|p| p.is_match(&case))
+    }
+
+    /// Pass `event` to [`Config::on_event`].
+    fn emit(&self, event: RunEvent) {
+        if let Some(on_event) = &self.config.on_event {
+            on_event(&event);
+        }
+    }
+
+    /// Status of a case and the error behind it. A mismatch is a failure, other
+    /// errors are passed to `wrap` and the case errors.
+    fn case_outcome(
+        result: Result<CaseStatus>,
+        wrap: impl FnOnce(SqlnessError) -> SqlnessError,
+    ) -> (CaseStatus, Option<Arc<SqlnessError>>) {
+        match result {
+            Ok(status) => (status, None),
+            Err(SqlnessError::ResultMismatch(mismatch)) => (
+                CaseStatus::Failed(mismatch.diff.clone()),
+                Some(Arc::new(SqlnessError::ResultMismatch(mismatch))),
+            ),
+            Err(e) => {
+                let e = wrap(e);
+                (CaseStatus::Errored(e.to_string()), Some(Arc::new(e)))
+            }
+        }
+    }
+
+    /// Name of the case in reports, its path relative to the environment directory.
+    fn case_name(env_root: &Path, path: &Path) -> String {
+        path.strip_prefix(env_root)
+            .unwrap_or(path)
+            .to_string_lossy()
+            .replace('\\', "/")
+    }
+
+    /// Group cases by their parent directory, in the order directories first
+    /// appear in `case_paths`.
+    fn group_by_dir(case_paths: Vec<PathBuf>) -> Vec<(PathBuf, Vec<PathBuf>)> {
+        let mut groups: Vec<(PathBuf, Vec<PathBuf>)> = vec![];
+        for path in case_paths {
+            let dir = path.parent().map(Path::to_path_buf).unwrap_or_default();
+            match groups.iter_mut().find(|(d, _)| *d == dir) {
+                Some((_, paths)) => paths.push(path),
+                None => groups.push((dir, vec![path])),
+            }
         }
+        groups
     }
 
-    /// Return true when this case pass, otherwise false.
-    async fn run_single_case(&self, db: &E::DB, path: &Path) -> Result<bool> {
+    fn case_names(report: &EnvReport, predicate: impl Fn(&CaseStatus) -> bool) -> Vec<&str> {
+        report
+            .cases
+            .iter()
+            .filter(|case| predicate(&case.status))
+            .map(|case| case.name.as_str())
+            .collect()
+    }
+
+    /// Run one case and compare its result with the old one.
+    async fn run_single_case(
+        &self,
+        db: &E::DB,
+        env: &str,
+        metadata: &Arc<HashMap<String, String>>,
+        path: &Path,
+        query_durations: &mut Vec<QueryDuration>,
+    ) -> Result<CaseStatus> {
+        if self.aborted.load(Ordering::Relaxed) {
+            return Ok(CaseStatus::Skipped("run aborted".to_string()));
+        }
         let case_path = path.with_extension(&self.config.test_case_extension);
-        let mut case = TestCase::from_file(&case_path, &self.config)?;
-        let result_path = path.with_extension(&self.config.result_extension);
-        let mut result_file = OpenOptions::new()
-            .create(true)
-            .write(true)
-            .read(true)
-            .truncate(false)
-            .open(&result_path)?;
+        let mut case = match &self.memory {
+            Some(memory) => {
+                let content = memory.cases.get(path).map(String::as_str);
+                TestCase::from_reader(
+                    &case_path,
+                    content.unwrap_or_default().as_bytes(),
+                    &self.config,
+                )?
+            }
+            None => TestCase::from_file(&case_path, &self.config)?,
+        };
+        case.set_metadata(metadata.clone());
+        if let Some(envs) = case.only_envs(env) {
+            let reason = format!("ONLY {}", envs.join(" "));
+            println!("Test case {:?} skipped by {reason}", path.as_os_str());
+            return Ok(CaseStatus::Skipped(reason));
+        }
+        if let Some(reason) =
+            case.unselected_reason(&self.config.include_tags, &self.config.exclude_tags)
+        {
+            println!("Test case {:?} skipped, {reason}", path.as_os_str());
+            return Ok(CaseStatus::Skipped(reason));
+        }
+        if let Some(condition) = case.skip_condition() {
+            println!("Test case {:?} skipped by {condition}", path.as_os_str());
+            return Ok(CaseStatus::Skipped(condition.to_string()));
+        }
+        if let Some(feature) = case.missing_feature() {
+            println!(
+                "Test case {:?} skipped, environment lacks feature {feature}",
+                path.as_os_str()
+            );
+            return Ok(CaseStatus::Skipped(format!("REQUIRE {feature}")));
+        }
+        let result_path = self.result_path(env, path);
+        let mut result_file = match &self.memory {
+            Some(memory) => ResultStore::Memory(memory, path.to_path_buf()),
+            None => ResultStore::File(
+                OpenOptions::new()
+                    .create(true)
+                    .write(true)
+                    .read(true)
+                    .truncate(false)
+                    .open(&result_path)?,
+            ),
+        };
 
         // Read old result out for compare later
-        let mut old_result = String::new();
-        result_file.read_to_string(&mut old_result)?;
+        let mut old_result = result_file.read()?;
+        if self.config.normalize_whitespace {
+            old_result = normalize_whitespace(&old_result);
+        }
+        // Timing of queries isn't compared
+        let old_result = strip_timing(&old_result);
 
         // Execute testcase
         let mut new_result = Cursor::new(Vec::new());
         let timer = Instant::now();
-        case.execute(db, &mut new_result).await?;
+        if self.config.persistent_context {
+            let variables = self.variables.lock().unwrap().get(env).cloned();
+            case.set_variables(variables.unwrap_or_default());
+        }
+        let in_transaction =
+            self.config.case_isolation == CaseIsolation::Transaction && db.supports_transactions();
+        if in_transaction {
+            Self::control(db, "BEGIN;").await?;
+        }
+        let executed = case.execute(db, &mut new_result, &old_result).await;
+        *query_durations = case.query_durations();
+        if self.config.persistent_context {
+            let mut variables = self.variables.lock().unwrap();
+            variables.insert(env.to_string(), case.variables().clone());
+        }
+        let mismatched_query = match executed {
+            Ok(mismatched_query) => mismatched_query,
+            Err(e) => {
+                // The error of the case is more relevant than the one of isolation.
+                let _ = self.isolate(db, env, path, in_transaction, false).await;
+                // Also reset after a failed case so it doesn't affect the next ones.
+                db.reset().await;
+                return Err(e);
+            }
+        };
         let elapsed = timer.elapsed();
-
-        // Truncate and write new result back
-        result_file.set_len(0)?;
-        result_file.rewind()?;
-        result_file.write_all(new_result.get_ref())?;
+        let mut new_result = String::from_utf8(new_result.into_inner()).expect("not utf8 string");
+        if self.config.normalize_whitespace {
+            new_result = normalize_whitespace(&new_result);
+        }
 
         // Compare old and new result
-        let new_result = String::from_utf8(new_result.into_inner()).expect("not utf8 string");
-        if let Some(diff) = self.compare(&old_result, &new_result) {
+        let compared_new = strip_timing(&new_result);
+        let (old_result, compared_new) = if self.config.ignore_result_comments {
+            let prefix = self.config.comment_prefix.as_str();
+            (
+                Cow::Owned(strip_comments(&old_result, prefix)),
+                Cow::Owned(strip_comments(&compared_new, prefix)),
+            )
+        } else {
+            (old_result, compared_new)
+        };
+        let context = self.config.diff_context_lines;
+        let diff = unified_diff(&old_result, &compared_new, context, false);
+        let passed = diff.is_none() || self.config.update_result;
+        let isolated = self.isolate(db, env, path, in_transaction, passed).await;
+        db.reset().await;
+        isolated?;
+
+        // Truncate and write new result back, annotations in a matched result are
+        // kept if comments are ignored. In interactive mode a mismatched result is
+        // only written if it's accepted.
+        let interactive = diff.is_some() && !self.config.update_result && self.interactive();
+        if (diff.is_some() || !self.config.ignore_result_comments) && !interactive {
+            result_file.write(&new_result)?;
+        }
+
+        if let Some(diff) = diff {
+            if self.config.update_result {
+                println!("Result updated, path:{result_path:?}");
+                return Ok(CaseStatus::Updated);
+            }
+
             println!("Result unexpected, path:{case_path:?}");
-            println!("{diff}");
-            return Ok(false);
+            if self.use_color() {
+                let colored = unified_diff(&old_result, &compared_new, context, true);
+                println!("{}", colored.unwrap_or_default());
+            } else {
+                println!("{diff}");
+            }
+            if interactive {
+                match self.prompt(&result_path)? {
+                    Answer::Update => {
+                        result_file.write(&new_result)?;
+                        println!("Result updated, path:{result_path:?}");
+                        return Ok(CaseStatus::Updated);
+                    }
+                    Answer::Skip => {}
+                    Answer::Abort => {
+                        println!("Run aborted, remaining cases are skipped.");
+                        self.aborted.store(true, Ordering::Relaxed);
+                    }
+                }
+            }
+            return Err(SqlnessError::ResultMismatch(Box::new(ResultMismatch {
+                case: case_path.to_string_lossy().to_string(),
+                env: env.to_string(),
+                query: mismatched_query,
+                expected: old_result.into_owned(),
+                actual: compared_new.into_owned(),
+                diff,
+            })));
         }
 
         println!(
@@ -193,58 +1065,2217 @@ impl<E: EnvController> Runner<E> {
             elapsed.as_millis()
         );
 
-        Ok(true)
+        Ok(CaseStatus::Passed)
     }
 
-    async fn collect_case_paths(&self, env: &str) -> Result<Vec<PathBuf>> {
-        let mut root = PathBuf::from_str(&self.config.case_dir).unwrap();
-        root.push(env);
+    /// Path of the result file of the case at `path` (without extension), see
+    /// [`Config::per_env_results`].
+    fn result_path(&self, env: &str, path: &Path) -> PathBuf {
+        if self.config.per_env_results {
+            path.with_extension(format!("{env}.{}", self.config.result_extension))
+        } else {
+            path.with_extension(&self.config.result_extension)
+        }
+    }
 
+    /// Collect cases to run in this environment, and how many are filtered out by
+    /// [`Config::test_filter`].
+    async fn collect_case_paths(&self, env: &str) -> Result<(Vec<PathBuf>, usize)> {
         let filter = Regex::new(&self.config.test_filter)?;
+        let case_glob = CaseGlob::new(&self.config.case_glob)?;
         let test_case_extension = self.config.test_case_extension.as_str();
-        let mut cases: Vec<_> = WalkDir::new(&root)
+
+        // Cases with their path relative to the environment directory.
+        let mut cases = vec![];
+        let mut filtered_count = 0;
+        for case_root in self.case_roots() {
+            let root = case_root.join(env);
+            let paths = match &self.memory {
+                Some(memory) => memory.paths(&root, test_case_extension),
+                None if root.is_dir() => self.walk_case_dir(&root),
+                None => continue,
+            };
+            let relative = |path: &PathBuf| {
+                path.strip_prefix(&root)
+                    .unwrap_or(path)
+                    .to_string_lossy()
+                    .replace('\\', "/")
+            };
+            let (matched, filtered): (Vec<_>, Vec<_>) = paths
+                .into_iter()
+                .filter(|path| {
+                    path.extension()
+                        .map(|ext| ext == test_case_extension)
+                        .unwrap_or(false)
+                })
+                .filter(|path| case_glob.matches(path.strip_prefix(&root).unwrap_or(path)))
+                .map(|path| path.with_extension(""))
+                .partition(|path| {
+                    let filename = path
+                        .file_name()
+                        .unwrap_or_default()
+                        .to_str()
+                        .unwrap_or_default();
+                    let relative_path = relative(path);
+                    filter.is_match(&format!("{env}:{filename}"))
+                        || filter.is_match(&format!("{env}:{relative_path}"))
+                });
+            filtered_count += filtered.len();
+            cases.extend(matched.into_iter().map(|path| (relative(&path), path)));
+        }
+
+        // sort the cases by relative path in an os-independent order, the sort is
+        // stable so cases of earlier directories go first on ties.
+        cases.sort_by(|(a, _), (b, _)| a.to_lowercase().cmp(&b.to_lowercase()).then(a.cmp(b)));
+        let mut cases = cases.into_iter().map(|(_, path)| path).collect::<Vec<_>>();
+        if let Some(seed) = self.seed {
+            util::shuffle(&mut cases, seed);
+        }
+
+        Ok((cases, filtered_count))
+    }
+
+    /// All files under `root`.
+    fn walk_case_dir(&self, root: &Path) -> Vec<PathBuf> {
+        let mut paths = vec![];
+        // Canonical paths of visited directories, to not walk into a symbolic link
+        // cycle or the same directory twice.
+        let mut visited = HashSet::new();
+        let mut walker = WalkDir::new(root)
             .follow_links(self.config.follow_links)
-            .into_iter()
-            .filter_map(|entry| {
-                entry
-                    .map_or(None, |entry| Some(entry.path().to_path_buf()))
-                    .filter(|path| {
-                        path.extension()
-                            .map(|ext| ext == test_case_extension)
-                            .unwrap_or(false)
-                    })
+            .into_iter();
+        while let Some(entry) = walker.next() {
+            let Ok(entry) = entry else {
+                continue;
+            };
+            if entry.file_type().is_dir() {
+                let canonical = entry
+                    .path()
+                    .canonicalize()
+                    .unwrap_or_else(|_| entry.path().to_path_buf());
+                if !visited.insert(canonical) {
+                    println!("Directory {:?} is already visited, skipped", entry.path());
+                    walker.skip_current_dir();
+                }
+                continue;
+            }
+            paths.push(entry.into_path());
+        }
+
+        paths
+    }
+
+    /// Whether to prompt on mismatches, see [`Config::interactive`].
+    fn interactive(&self) -> bool {
+        self.config.interactive && std::io::stdin().is_terminal() && std::io::stdout().is_terminal()
+    }
+
+    /// Ask what to do with the mismatched result at `result_path`. The end of
+    /// input is taken as skip.
+    fn prompt(&self, result_path: &Path) -> Result<Answer> {
+        let _guard = self.prompt_lock.lock().unwrap_or_else(|e| e.into_inner());
+        loop {
+            print!("Update {result_path:?}? [u]pdate / [s]kip / [a]bort: ");
+            std::io::stdout().flush()?;
+            let mut line = String::new();
+            if std::io::stdin().read_line(&mut line)? == 0 {
+                return Ok(Answer::Skip);
+            }
+            if let Some(answer) = Answer::parse(&line) {
+                return Ok(answer);
+            }
+        }
+    }
+
+    /// Whether diffs printed to stdout should be colored.
+    fn use_color(&self) -> bool {
+        self.config.diff_color
+            && std::io::stdout().is_terminal()
+            && std::env::var_os("NO_COLOR")
+                .filter(|v| !v.is_empty())
+                .is_none()
+    }
+}
+
+/// Answer to the prompt of [`Config::interactive`].
+#[derive(Debug, PartialEq)]
+enum Answer {
+    Update,
+    Skip,
+    Abort,
+}
+
+impl Answer {
+    fn parse(line: &str) -> Option<Self> {
+        match line.trim().to_lowercase().as_str() {
+            "u" | "update" => Some(Answer::Update),
+            "s" | "skip" => Some(Answer::Skip),
+            "a" | "abort" => Some(Answer::Abort),
+            _ => None,
+        }
+    }
+}
+
+/// Replace the content of `file` with `result`.
+fn write_result(file: &mut File, result: &str) -> std::io::Result<()> {
+    file.set_len(0)?;
+    file.rewind()?;
+    file.write_all(result.as_bytes())
+}
+
+/// Cases given to [`Runner::new_with_cases`].
+#[derive(Default)]
+struct MemoryCases {
+    /// Content of cases, keyed by their paths under [`Config::case_dir`] without
+    /// extension.
+    cases: BTreeMap<PathBuf, String>,
+    /// Results of cases keyed likewise, starting from the expected ones.
+    results: Mutex<HashMap<PathBuf, String>>,
+}
+
+impl MemoryCases {
+    /// Environments of cases, which are the first components of their paths.
+    fn envs(&self, case_dir: &Path) -> Vec<String> {
+        let mut envs = self
+            .cases
+            .keys()
+            .filter_map(|path| {
+                let relative = path.strip_prefix(case_dir).ok()?;
+                let mut components = relative.components();
+                match (components.next(), components.next()) {
+                    (Some(Component::Normal(env)), Some(_)) => env.to_str().map(str::to_string),
+                    _ => None,
+                }
             })
-            .map(|path| path.with_extension(""))
-            .filter(|path| {
-                let filename = path
-                    .file_name()
-                    .unwrap_or_default()
-                    .to_str()
-                    .unwrap_or_default();
-                let filename_with_env = format!("{env}:{filename}");
-                filter.is_match(&filename_with_env)
+            .collect::<Vec<_>>();
+        envs.dedup();
+        envs
+    }
+
+    /// Paths of cases under `root` with `extension`, like files found by walking it.
+    fn paths(&self, root: &Path, extension: &str) -> Vec<PathBuf> {
+        self.cases
+            .keys()
+            .filter(|path| path.starts_with(root))
+            .map(|path| {
+                let mut path = path.clone().into_os_string();
+                path.push(".");
+                path.push(extension);
+                PathBuf::from(path)
             })
-            .collect();
+            .collect()
+    }
+}
 
-        // sort the cases in an os-independent order.
-        cases.sort_by(|a, b| {
-            let a_lower = a.to_string_lossy().to_lowercase();
-            let b_lower = b.to_string_lossy().to_lowercase();
-            a_lower.cmp(&b_lower)
-        });
+/// Where the result of a case is read from and written to.
+enum ResultStore<'a> {
+    File(File),
+    Memory(&'a MemoryCases, PathBuf),
+}
 
-        Ok(cases)
+impl ResultStore<'_> {
+    fn read(&mut self) -> std::io::Result<String> {
+        match self {
+            ResultStore::File(file) => {
+                let mut result = String::new();
+                file.read_to_string(&mut result)?;
+                Ok(result)
+            }
+            ResultStore::Memory(memory, path) => {
+                let results = memory.results.lock().unwrap();
+                Ok(results.get(path).cloned().unwrap_or_default())
+            }
+        }
     }
 
-    /// Compare result, return None if them are the same, else return diff changes
-    fn compare(&self, expected: &str, actual: &str) -> Option<String> {
-        let diff = diff_lines(expected, actual);
-        let diff = diff.diff();
-        let is_different = diff.iter().any(|d| !matches!(d, DiffOp::Equal(_)));
-        if is_different {
-            return Some(format!("{}", SliceChangeset { diff }));
+    fn write(&mut self, result: &str) -> std::io::Result<()> {
+        match self {
+            ResultStore::File(file) => write_result(file, result),
+            ResultStore::Memory(memory, path) => {
+                let mut results = memory.results.lock().unwrap();
+                results.insert(path.clone(), result.to_string());
+                Ok(())
+            }
         }
+    }
+}
 
-        None
+/// Compiled [`Config::case_glob`].
+struct CaseGlob {
+    include: Vec<Pattern>,
+    exclude: Vec<Pattern>,
+}
+
+impl CaseGlob {
+    const OPTIONS: MatchOptions = MatchOptions {
+        case_sensitive: true,
+        require_literal_separator: true,
+        require_literal_leading_dot: false,
+    };
+
+    fn new(patterns: &[String]) -> Result<Self> {
+        let mut glob = Self {
+            include: vec![],
+            exclude: vec![],
+        };
+        for pattern in patterns {
+            let (patterns, raw) = match pattern.strip_prefix('!') {
+                Some(raw) => (&mut glob.exclude, raw),
+                None => (&mut glob.include, pattern.as_str()),
+            };
+            let compiled = Pattern::new(raw).map_err(|source| SqlnessError::InvalidGlob {
+                pattern: pattern.clone(),
+                source,
+            })?;
+            patterns.push(compiled);
+        }
+        Ok(glob)
+    }
+
+    /// Whether the file at `path` relative to the environment directory is a case.
+    fn matches(&self, path: &Path) -> bool {
+        let included = self.include.is_empty()
+            || self
+                .include
+                .iter()
+                .any(|p| p.matches_path_with(path, Self::OPTIONS));
+        included
+            && !self
+                .exclude
+                .iter()
+                .any(|p| p.matches_path_with(path, Self::OPTIONS))
+    }
+}
+
+const COLOR_RED: &str = "\x1b[31m";
+const COLOR_GREEN: &str = "\x1b[32m";
+const COLOR_CYAN: &str = "\x1b[36m";
+const COLOR_RESET: &str = "\x1b[0m";
+
+/// Render a line-based unified diff from `expected` to `actual`, with `context`
+/// lines around each hunk. Return None if there is no difference.
+fn unified_diff(expected: &str, actual: &str, context: usize, color: bool) -> Option<String> {
+    let expected = expected.lines().collect::<Vec<_>>();
+    let actual = actual.lines().collect::<Vec<_>>();
+
+    let mut lines = Vec::new();
+    for op in diff(&expected, &actual) {
+        match op {
+            DiffOp::Equal(a) => lines.extend(a.iter().map(|l| (' ', *l))),
+            DiffOp::Remove(a) => lines.extend(a.iter().map(|l| ('-', *l))),
+            DiffOp::Insert(b) => lines.extend(b.iter().map(|l| ('+', *l))),
+            DiffOp::Replace(a, b) => {
+                lines.extend(a.iter().map(|l| ('-', *l)));
+                lines.extend(b.iter().map(|l| ('+', *l)));
+            }
+        }
+    }
+
+    let changes = lines
+        .iter()
+        .enumerate()
+        .filter(|(_, (tag, _))| *tag != ' ')
+        .map(|(i, _)| i)
+        .collect::<Vec<_>>();
+    if changes.is_empty() {
+        return None;
+    }
+
+    let paint = |code: &str, line: String| {
+        if color {
+            format!("{code}{line}{COLOR_RESET}")
+        } else {
+            line
+        }
+    };
+    let count =
+        |lines: &[(char, &str)], skip: char| lines.iter().filter(|(t, _)| *t != skip).count();
+
+    let mut out = vec!["--- expected".to_string(), "+++ actual".to_string()];
+    let mut i = 0;
+    while i < changes.len() {
+        // merge changes whose context overlaps into one hunk
+        let mut last = changes[i];
+        let start = last.saturating_sub(context);
+        i += 1;
+        while i < changes.len() && changes[i] - last <= 2 * context + 1 {
+            last = changes[i];
+            i += 1;
+        }
+        let end = (last + context + 1).min(lines.len());
+        let hunk = &lines[start..end];
+
+        let (old_len, new_len) = (count(hunk, '+'), count(hunk, '-'));
+        let old_start = count(&lines[..start], '+') + usize::from(old_len > 0);
+        let new_start = count(&lines[..start], '-') + usize::from(new_len > 0);
+        out.push(paint(
+            COLOR_CYAN,
+            format!("@@ -{old_start},{old_len} +{new_start},{new_len} @@"),
+        ));
+        for (tag, line) in hunk {
+            let line = format!("{tag}{line}");
+            out.push(match tag {
+                '-' => paint(COLOR_RED, line),
+                '+' => paint(COLOR_GREEN, line),
+                _ => line,
+            });
+        }
+    }
+
+    Some(out.join("\n"))
+}
+
+/// Remove lines starting with `prefix`, see [`Config::ignore_result_comments`].
+fn strip_comments(s: &str, prefix: &str) -> String {
+    s.split_inclusive('\n')
+        .filter(|line| !line.starts_with(prefix))
+        .collect()
+}
+
+/// Strip trailing whitespace of each line and normalize line endings to `\n`.
+fn normalize_whitespace(s: &str) -> String {
+    s.split('\n')
+        .map(str::trim_end)
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fmt::Display;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::Duration;
+
+    use async_trait::async_trait;
+
+    use super::*;
+    use crate::{util, ConfigBuilder, Database, QueryContext};
+
+    /// Echo the query back, and track the max number of concurrent queries.
+    #[derive(Default)]
+    pub(super) struct EchoDB {
+        running: AtomicUsize,
+        max_running: AtomicUsize,
+    }
+
+    #[async_trait]
+    impl Database for EchoDB {
+        async fn query(&self, _: QueryContext, query: String) -> Box<dyn Display> {
+            let running = self.running.fetch_add(1, Ordering::SeqCst) + 1;
+            self.max_running.fetch_max(running, Ordering::SeqCst);
+            util::sleep(Duration::from_millis(50)).await;
+            self.running.fetch_sub(1, Ordering::SeqCst);
+            Box::new(query)
+        }
+
+        fn name(&self) -> Option<&str> {
+            Some("echo")
+        }
+    }
+
+    /// Start [`EchoDB`] for every environment.
+    struct EchoController;
+
+    #[async_trait]
+    impl EnvController for EchoController {
+        type DB = EchoDB;
+
+        async fn start(&self, _: &str, _: Option<&Path>) -> Self::DB {
+            EchoDB::default()
+        }
+
+        async fn stop(&self, _: &str, _: Self::DB) {}
+    }
+
+    /// Panic when a query contains `panic`, or when starting `broken` environment.
+    struct PanicController;
+
+    struct PanicDB;
+
+    #[async_trait]
+    impl Database for PanicDB {
+        async fn query(&self, _: QueryContext, query: String) -> Box<dyn Display> {
+            if query.contains("panic") {
+                panic!("boom");
+            }
+            Box::new(query)
+        }
+    }
+
+    #[async_trait]
+    impl EnvController for PanicController {
+        type DB = PanicDB;
+
+        async fn start(&self, env: &str, _: Option<&Path>) -> Self::DB {
+            if env == "broken" {
+                panic!("broken env");
+            }
+            PanicDB
+        }
+
+        async fn stop(&self, _: &str, _: Self::DB) {}
+    }
+
+    /// Record calls to directory hooks.
+    #[derive(Default)]
+    struct HookController {
+        calls: std::sync::Mutex<Vec<String>>,
+    }
+
+    #[async_trait]
+    impl EnvController for HookController {
+        type DB = EchoDB;
+
+        async fn start(&self, _: &str, _: Option<&Path>) -> Self::DB {
+            EchoDB::default()
+        }
+
+        async fn stop(&self, _: &str, _: Self::DB) {}
+
+        async fn before_dir(&self, env: &str, dir: &Path, _: &Self::DB) {
+            let dir = dir.file_name().unwrap().to_str().unwrap();
+            self.calls
+                .lock()
+                .unwrap()
+                .push(format!("before {env} {dir}"));
+        }
+
+        async fn after_dir(&self, env: &str, dir: &Path, _: &Self::DB) {
+            let dir = dir.file_name().unwrap().to_str().unwrap();
+            self.calls
+                .lock()
+                .unwrap()
+                .push(format!("after {env} {dir}"));
+        }
+    }
+
+    /// Record queries and cleanups of failed cases, in a log shared by its databases.
+    #[derive(Default)]
+    struct IsolationController {
+        transactions: bool,
+        log: Arc<std::sync::Mutex<Vec<String>>>,
+    }
+
+    struct LogDB {
+        transactions: bool,
+        log: Arc<std::sync::Mutex<Vec<String>>>,
+    }
+
+    #[async_trait]
+    impl Database for LogDB {
+        async fn query(&self, _: QueryContext, query: String) -> Box<dyn Display> {
+            self.log.lock().unwrap().push(query.clone());
+            Box::new(query)
+        }
+
+        fn supports_transactions(&self) -> bool {
+            self.transactions
+        }
+    }
+
+    #[async_trait]
+    impl EnvController for IsolationController {
+        type DB = LogDB;
+
+        async fn start(&self, _: &str, _: Option<&Path>) -> Self::DB {
+            LogDB {
+                transactions: self.transactions,
+                log: self.log.clone(),
+            }
+        }
+
+        async fn stop(&self, _: &str, _: Self::DB) {}
+
+        async fn cleanup_after_case(&self, env: &str, case: &Path, _: &Self::DB) {
+            let case = case.file_name().unwrap().to_str().unwrap();
+            self.log
+                .lock()
+                .unwrap()
+                .push(format!("cleanup {env} {case}"));
+        }
+    }
+
+    /// Record calls to global hooks, and starts and stops of environments.
+    #[derive(Default)]
+    struct GlobalHookController {
+        calls: std::sync::Mutex<Vec<String>>,
+    }
+
+    #[async_trait]
+    impl EnvController for GlobalHookController {
+        type DB = EchoDB;
+
+        async fn start(&self, env: &str, _: Option<&Path>) -> Self::DB {
+            self.calls.lock().unwrap().push(format!("start {env}"));
+            EchoDB::default()
+        }
+
+        async fn stop(&self, env: &str, _: Self::DB) {
+            self.calls.lock().unwrap().push(format!("stop {env}"));
+        }
+
+        async fn before_all(&self) {
+            self.calls.lock().unwrap().push("before all".to_string());
+        }
+
+        async fn after_all(&self) {
+            self.calls.lock().unwrap().push("after all".to_string());
+        }
+    }
+
+    /// Take the given duration to stop.
+    struct SlowStopController(Duration);
+
+    #[async_trait]
+    impl EnvController for SlowStopController {
+        type DB = EchoDB;
+
+        async fn start(&self, _: &str, _: Option<&Path>) -> Self::DB {
+            EchoDB::default()
+        }
+
+        async fn stop(&self, _: &str, _: Self::DB) {
+            util::sleep(self.0).await;
+        }
+    }
+
+    /// Ready after being polled the given times, or never if `None`.
+    #[derive(Default)]
+    struct StartingDB {
+        ready_after: Option<usize>,
+        polled: AtomicUsize,
+    }
+
+    #[async_trait]
+    impl Database for StartingDB {
+        async fn query(&self, _: QueryContext, query: String) -> Box<dyn Display> {
+            Box::new(query)
+        }
+
+        async fn is_ready(&self) -> bool {
+            let polled = self.polled.fetch_add(1, Ordering::SeqCst) + 1;
+            self.ready_after.is_some_and(|n| polled >= n)
+        }
+    }
+
+    struct StartingController(Option<usize>);
+
+    #[async_trait]
+    impl EnvController for StartingController {
+        type DB = StartingDB;
+
+        async fn start(&self, _: &str, _: Option<&Path>) -> Self::DB {
+            StartingDB {
+                ready_after: self.0,
+                ..Default::default()
+            }
+        }
+
+        async fn stop(&self, _: &str, db: Self::DB) {
+            if let Some(n) = self.0 {
+                assert_eq!(db.polled.load(Ordering::SeqCst), n);
+            }
+        }
+    }
+
+    /// Record the file name of the config passed to `start`.
+    #[derive(Default)]
+    struct ConfigController {
+        configs: std::sync::Mutex<Vec<(String, Option<String>)>>,
+    }
+
+    #[async_trait]
+    impl EnvController for ConfigController {
+        type DB = EchoDB;
+
+        async fn start(&self, env: &str, config: Option<&Path>) -> Self::DB {
+            let config = config.map(|c| c.file_name().unwrap().to_str().unwrap().to_string());
+            self.configs.lock().unwrap().push((env.to_string(), config));
+            EchoDB::default()
+        }
+
+        async fn stop(&self, _: &str, _: Self::DB) {}
+    }
+
+    /// Track the max number of environments running at the same time.
+    #[derive(Default)]
+    struct CountingController {
+        running: AtomicUsize,
+        max_running: AtomicUsize,
+    }
+
+    #[async_trait]
+    impl EnvController for CountingController {
+        type DB = EchoDB;
+
+        async fn start(&self, _: &str, _: Option<&Path>) -> Self::DB {
+            let running = self.running.fetch_add(1, Ordering::SeqCst) + 1;
+            self.max_running.fetch_max(running, Ordering::SeqCst);
+            EchoDB::default()
+        }
+
+        async fn stop(&self, _: &str, _: Self::DB) {
+            self.running.fetch_sub(1, Ordering::SeqCst);
+        }
+    }
+
+    /// Create a case directory with given files under a clean temp directory.
+    pub(super) fn case_dir(name: &str, files: &[(&str, &str)]) -> String {
+        let root = std::env::temp_dir().join(format!("sqlness-{name}-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&root);
+        for (path, content) in files {
+            let path = root.join(path);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, content).unwrap();
+        }
+        root.to_str().unwrap().to_string()
+    }
+
+    #[tokio::test]
+    async fn run_cases_concurrently() {
+        let files = (0..8)
+            .map(|i| (format!("env/case{i}.sql"), format!("SELECT {i};\n")))
+            .collect::<Vec<_>>();
+        let files = files
+            .iter()
+            .map(|(p, c)| (p.as_str(), c.as_str()))
+            .collect::<Vec<_>>();
+        let config = ConfigBuilder::default()
+            .case_dir(case_dir("parallel", &files))
+            .parallelism(4)
+            .build()
+            .unwrap();
+        let runner = Runner::new(config, EchoController);
+
+        // result files are created in the first run
+        let db = EchoDB::default();
+        assert_eq!(runner.run_env("env", &db).await.unwrap().failed_count(), 8);
+        assert_eq!(runner.run_env("env", &db).await.unwrap().failed_count(), 0);
+        let max_running = db.max_running.load(Ordering::SeqCst);
+        assert!(max_running > 1 && max_running <= 4, "{max_running}");
+    }
+
+    #[tokio::test]
+    async fn env_parallelism() {
+        let mut files = vec![
+            (
+                "local/config.toml".to_string(),
+                "parallelism = 4\n".to_string(),
+            ),
+            (
+                "remote/config.toml".to_string(),
+                "addr = \"remote\"\nparallelism = 1\n".to_string(),
+            ),
+        ];
+        for env in ["local", "remote"] {
+            files.extend((0..8).map(|i| (format!("{env}/case{i}.sql"), format!("SELECT {i};\n"))));
+        }
+        let files = files
+            .iter()
+            .map(|(p, c)| (p.as_str(), c.as_str()))
+            .collect::<Vec<_>>();
+        let config = ConfigBuilder::default()
+            .case_dir(case_dir("env-parallelism", &files))
+            .parallelism(2)
+            .build()
+            .unwrap();
+        let runner = &Runner::new(config, EchoController);
+
+        let max_running = |env| async move {
+            let db = EchoDB::default();
+            runner.run_env(env, &db).await.unwrap();
+            db.max_running.load(Ordering::SeqCst)
+        };
+        let local = max_running("local").await;
+        assert!(local > 2 && local <= 4, "{local}");
+        assert_eq!(max_running("remote").await, 1);
+    }
+
+    #[tokio::test]
+    async fn persistent_context() {
+        let root = case_dir(
+            "persistent-context",
+            &[
+                ("env/a.sql", "-- SQLNESS CAPTURE id\nSELECT 42;\n"),
+                ("env/b.sql", "SELECT '{{id}}';\n"),
+            ],
+        );
+        let result = |persistent| {
+            let root = root.clone();
+            async move {
+                let config = ConfigBuilder::default()
+                    .case_dir(root.clone())
+                    .persistent_context(persistent)
+                    .build()
+                    .unwrap();
+                Runner::new(config, EchoController)
+                    .run_env("env", &EchoDB::default())
+                    .await
+                    .unwrap();
+                std::fs::read_to_string(Path::new(&root).join("env/b.result")).unwrap()
+            }
+        };
+
+        assert_eq!(
+            result(false).await,
+            "SELECT '{{id}}';\n\nSELECT '{{id}}';\n\n"
+        );
+        assert_eq!(
+            result(true).await,
+            "SELECT '{{id}}';\n\nSELECT 'SELECT 42;';\n\n"
+        );
+    }
+
+    #[tokio::test]
+    async fn case_isolation() {
+        let root = case_dir(
+            "case-isolation",
+            &[
+                ("env/a.sql", "SELECT 1;\n"),
+                ("env/a.result", "SELECT 1;\n\nSELECT 1;\n\n"),
+                ("env/b.sql", "SELECT 2;\n"),
+                ("env/b.result", "SELECT 2;\n\nSELECT 0;\n\n"),
+            ],
+        );
+        let log = |isolation, transactions| {
+            let root = root.clone();
+            async move {
+                let config = ConfigBuilder::default()
+                    .case_dir(root)
+                    .case_isolation(isolation)
+                    .build()
+                    .unwrap();
+                let controller = IsolationController {
+                    transactions,
+                    ..Default::default()
+                };
+                let log = controller.log.clone();
+                let runner = Runner::new(config, controller);
+                let report = runner.run_with_report().await.unwrap();
+                assert_eq!(report.failed_count(), 1);
+                // keep the mismatched result for the next run
+                std::fs::write(
+                    Path::new(&runner.config.case_dir).join("env/b.result"),
+                    "SELECT 2;\n\nSELECT 0;\n\n",
+                )
+                .unwrap();
+                let log = log.lock().unwrap().clone();
+                log
+            }
+        };
+
+        assert_eq!(
+            log(CaseIsolation::None, true).await,
+            vec!["SELECT 1;", "SELECT 2;"]
+        );
+        assert_eq!(
+            log(CaseIsolation::Transaction, true).await,
+            vec![
+                "BEGIN;",
+                "SELECT 1;",
+                "COMMIT;",
+                "BEGIN;",
+                "SELECT 2;",
+                "ROLLBACK;"
+            ]
+        );
+        // fall back to cleanup without transactions
+        assert_eq!(
+            log(CaseIsolation::Transaction, false).await,
+            vec!["SELECT 1;", "SELECT 2;", "cleanup env b"]
+        );
+        assert_eq!(
+            log(CaseIsolation::Cleanup, true).await,
+            vec!["SELECT 1;", "SELECT 2;", "cleanup env b"]
+        );
+    }
+
+    /// Return relative paths of collected cases and the filtered count.
+    async fn collect_cases(root: &str, filter: &str) -> (Vec<String>, usize) {
+        let config = ConfigBuilder::default()
+            .case_dir(root.to_string())
+            .test_filter(filter.to_string())
+            .build()
+            .unwrap();
+        let runner = Runner::new(config, EchoController);
+        let (cases, filtered) = runner.collect_case_paths("local").await.unwrap();
+        let cases = cases
+            .iter()
+            .map(|p| p.strip_prefix(root).unwrap().to_str().unwrap().to_string())
+            .collect();
+        (cases, filtered)
+    }
+
+    #[tokio::test]
+    async fn run_envs_concurrently() {
+        let root = case_dir(
+            "parallel-envs",
+            &[
+                ("local/a.sql", "SELECT 1;\n"),
+                ("remote/a.sql", "SELECT 2;\n"),
+                ("remote/a.result", "SELECT 2;\n\nSELECT 2;\n\n"),
+            ],
+        );
+        let config = ConfigBuilder::default()
+            .case_dir(root)
+            .parallel_envs(true)
+            .fail_on_first_mismatch(true)
+            .build()
+            .unwrap();
+        let runner = Runner::new(config, CountingController::default());
+
+        // failure of `local` doesn't stop `remote`
+        let report = runner.run_with_report().await.unwrap();
+        let mut envs = report
+            .envs
+            .iter()
+            .map(|env| (env.name.as_str(), env.failed_count()))
+            .collect::<Vec<_>>();
+        envs.sort();
+        assert_eq!(envs, vec![("local", 1), ("remote", 0)]);
+        assert_eq!(runner.env_controller.max_running.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn fail_on_first_mismatch() {
+        let root = case_dir(
+            "fail-fast",
+            &[("env/a.sql", "SELECT 1;\n"), ("env/b.sql", "SELECT 2;\n")],
+        );
+        let config = ConfigBuilder::default()
+            .case_dir(root.clone())
+            .fail_on_first_mismatch(true)
+            .build()
+            .unwrap();
+        let runner = Runner::new(config, EchoController);
+
+        // `a` has no result file thus fails, `b` is not executed
+        assert!(runner.run().await.is_err());
+        assert!(Path::new(&root).join("env/a.result").exists());
+        assert!(!Path::new(&root).join("env/b.result").exists());
+    }
+
+    #[tokio::test]
+    async fn continue_after_timeout() {
+        let root = case_dir(
+            "continue-after-timeout",
+            &[
+                ("env/a.sql", "-- SQLNESS TIMEOUT 10ms\nSELECT 1;\n"),
+                ("env/b.sql", "SELECT 2;\n"),
+                ("env/b.result", "SELECT 2;\n\nSELECT 2;\n\n"),
+            ],
+        );
+        let config = ConfigBuilder::default().case_dir(root).build().unwrap();
+        let report = Runner::new(config, EchoController)
+            .run_with_report()
+            .await
+            .unwrap();
+
+        let statuses = report
+            .cases()
+            .map(|(_, case)| case.status.clone())
+            .collect::<Vec<_>>();
+        assert!(
+            matches!(&statuses[0], CaseStatus::Errored(e) if e.contains("timeout")),
+            "{statuses:?}"
+        );
+        assert_eq!(statuses[1], CaseStatus::Passed);
+    }
+
+    #[tokio::test]
+    async fn dir_hooks() {
+        let root = case_dir(
+            "dir-hooks",
+            &[
+                ("env/ddl/a.sql", "SELECT 1;\n"),
+                ("env/ddl/b.sql", "SELECT 2;\n"),
+                ("env/dml/a.sql", "SELECT 3;\n"),
+            ],
+        );
+        let config = |stop| {
+            ConfigBuilder::default()
+                .case_dir(root.clone())
+                .fail_on_first_mismatch(stop)
+                .build()
+                .unwrap()
+        };
+
+        // `ddl/a` fails without result file, but `ddl` is still left
+        let runner = Runner::new(config(true), HookController::default());
+        assert!(runner.run().await.is_err());
+        assert_eq!(
+            *runner.env_controller.calls.lock().unwrap(),
+            vec!["before env ddl", "after env ddl"]
+        );
+
+        let runner = Runner::new(config(false), HookController::default());
+        assert!(runner.run().await.is_err());
+        assert_eq!(
+            *runner.env_controller.calls.lock().unwrap(),
+            vec![
+                "before env ddl",
+                "after env ddl",
+                "before env dml",
+                "after env dml"
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn global_hooks() {
+        let root = case_dir(
+            "global-hooks",
+            &[
+                ("local/a.sql", "SELECT 1;\n"),
+                ("remote/a.sql", "SELECT 1;\n"),
+            ],
+        );
+        let config = |env_filter: &str| {
+            ConfigBuilder::default()
+                .case_dir(root.clone())
+                .env_filter(env_filter.to_string())
+                .build()
+                .unwrap()
+        };
+
+        let runner = Runner::new(config(".*"), GlobalHookController::default());
+        runner.run_with_report().await.unwrap();
+        let mut calls = runner.env_controller.calls.into_inner().unwrap();
+        assert_eq!(calls.remove(0), "before all");
+        assert_eq!(calls.pop().unwrap(), "after all");
+        calls.sort();
+        assert_eq!(
+            calls,
+            vec!["start local", "start remote", "stop local", "stop remote"]
+        );
+
+        // `after_all` is still called when the run fails
+        let runner = Runner::new(config("("), GlobalHookController::default());
+        assert!(runner.run().await.is_err());
+        assert_eq!(
+            *runner.env_controller.calls.lock().unwrap(),
+            vec!["before all", "after all"]
+        );
+
+        let runner = Runner::new(config(".*"), GlobalHookController::default());
+        runner.run_file("local/a.sql").await.unwrap();
+        assert_eq!(
+            *runner.env_controller.calls.lock().unwrap(),
+            vec!["before all", "start local", "stop local", "after all"]
+        );
+    }
+
+    #[tokio::test]
+    async fn run_events() {
+        let root = case_dir(
+            "events",
+            &[
+                ("env/a.sql", "SELECT 1;\n"),
+                ("env/a.result", "SELECT 1;\n\nSELECT 1;\n\n"),
+                ("env/b.sql", "SELECT 2;\n"),
+            ],
+        );
+        let events = Arc::new(std::sync::Mutex::new(vec![]));
+        let recorded = events.clone();
+        let config = ConfigBuilder::default()
+            .case_dir(root)
+            .on_event(Some(Arc::new(move |event: &RunEvent| {
+                recorded.lock().unwrap().push(event.clone())
+            })))
+            .build()
+            .unwrap();
+        Runner::new(config, EchoController)
+            .run_with_report()
+            .await
+            .unwrap();
+
+        // Durations vary, only compare them with zero
+        let events = events
+            .lock()
+            .unwrap()
+            .iter()
+            .cloned()
+            .map(|event| match event {
+                RunEvent::EnvFinished { env, .. } => RunEvent::EnvFinished {
+                    env,
+                    duration: Duration::ZERO,
+                },
+                RunEvent::CaseFinished {
+                    env, case, status, ..
+                } => RunEvent::CaseFinished {
+                    env,
+                    case,
+                    status: match status {
+                        CaseStatus::Failed(_) => CaseStatus::Failed(String::new()),
+                        status => status,
+                    },
+                    duration: Duration::ZERO,
+                },
+                event => event,
+            })
+            .collect::<Vec<_>>();
+        let env = || "env".to_string();
+        assert_eq!(
+            events,
+            vec![
+                RunEvent::EnvStarted { env: env() },
+                RunEvent::CaseStarted {
+                    env: env(),
+                    case: "a".to_string()
+                },
+                RunEvent::CaseFinished {
+                    env: env(),
+                    case: "a".to_string(),
+                    status: CaseStatus::Passed,
+                    duration: Duration::ZERO
+                },
+                RunEvent::CaseStarted {
+                    env: env(),
+                    case: "b".to_string()
+                },
+                RunEvent::CaseFinished {
+                    env: env(),
+                    case: "b".to_string(),
+                    status: CaseStatus::Failed(String::new()),
+                    duration: Duration::ZERO
+                },
+                RunEvent::EnvFinished {
+                    env: env(),
+                    duration: Duration::ZERO
+                },
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn shutdown_timeout() {
+        let root = case_dir(
+            "shutdown-timeout",
+            &[
+                ("env/case.sql", "SELECT 1;\n"),
+                ("env/case.result", "SELECT 1;\n\nSELECT 1;\n\n"),
+            ],
+        );
+        let runner = |stop| {
+            let config = ConfigBuilder::default()
+                .case_dir(root.clone())
+                .shutdown_timeout(Some(Duration::from_millis(100)))
+                .build()
+                .unwrap();
+            Runner::new(config, SlowStopController(stop))
+        };
+
+        let err = runner(Duration::from_secs(10))
+            .run_with_report()
+            .await
+            .unwrap_err();
+        assert!(
+            matches!(&err, SqlnessError::ShutdownTimeout { env, .. } if env == "env"),
+            "{err:?}"
+        );
+        assert!(runner(Duration::ZERO).run_with_report().await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn run_with_report() {
+        let root = case_dir(
+            "run-report",
+            &[
+                ("env/pass.sql", "SELECT 1;\n"),
+                ("env/pass.result", "SELECT 1;\n\nSELECT 1;\n\n"),
+                ("env/fail.sql", "SELECT 2;\n"),
+                (
+                    "env/skip.sql",
+                    "-- SQLNESS SKIP_IF SQLNESS_TEST_RUN_REPORT\n\nSELECT 3;\n",
+                ),
+            ],
+        );
+        std::env::set_var("SQLNESS_TEST_RUN_REPORT", "1");
+        let config = ConfigBuilder::default().case_dir(root).build().unwrap();
+        let runner = Runner::new(config, EchoController);
+
+        let report = runner.run_with_report().await.unwrap();
+        assert_eq!(
+            (
+                report.passed_count(),
+                report.failed_count(),
+                report.skipped_count(),
+                report.updated_count()
+            ),
+            (1, 1, 1, 0)
+        );
+        let cases = report
+            .cases()
+            .map(|(env, case)| format!("{env}:{}", case.name))
+            .collect::<Vec<_>>();
+        assert_eq!(cases, vec!["env:fail", "env:pass", "env:skip"]);
+        assert_eq!(report.envs[0].backend, "echo");
+        // result file of `fail` is written in the last run
+        assert!(runner.run().await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn extra_case_dirs() {
+        let core = case_dir(
+            "extra-case-dirs-core",
+            &[
+                ("env/b.sql", "SELECT 'core b';\n"),
+                ("env/c.sql", "SELECT 'core c';\n"),
+            ],
+        );
+        let ext = case_dir(
+            "extra-case-dirs-ext",
+            &[
+                ("env/config.toml", ""),
+                ("env/a.sql", "SELECT 'ext a';\n"),
+                ("env/c.sql", "SELECT 'ext c';\n"),
+                ("other/d.sql", "SELECT 'ext d';\n"),
+            ],
+        );
+        let config = ConfigBuilder::default()
+            .case_dir(core.clone())
+            .extra_case_dirs(vec![ext.clone()])
+            .build()
+            .unwrap();
+        let runner = Runner::new(config, EchoController);
+        assert_eq!(
+            runner.read_env_config("env"),
+            Path::new(&ext).join("env/config.toml")
+        );
+
+        let report = runner.run_with_report().await.unwrap();
+        let mut cases = report
+            .cases()
+            .map(|(env, case)| format!("{env}:{}", case.name))
+            .collect::<Vec<_>>();
+        cases.sort();
+        assert_eq!(cases, vec!["env:a", "env:b", "env:c", "env:c", "other:d"]);
+        assert_eq!(report.envs.len(), 2);
+        let (paths, _) = runner.collect_case_paths("env").await.unwrap();
+        assert_eq!(
+            paths,
+            vec![
+                Path::new(&ext).join("env/a"),
+                Path::new(&core).join("env/b"),
+                Path::new(&core).join("env/c"),
+                Path::new(&ext).join("env/c"),
+            ]
+        );
+        // results are written beside their cases
+        assert_eq!(
+            std::fs::read_to_string(Path::new(&ext).join("env/c.result")).unwrap(),
+            "SELECT 'ext c';\n\nSELECT 'ext c';\n\n"
+        );
+        assert!(Path::new(&core).join("env/c.result").exists());
+        assert_eq!(
+            runner.run_file("other/d").await.unwrap().status,
+            CaseStatus::Passed
+        );
+    }
+
+    #[tokio::test]
+    async fn quarantine() {
+        let root = case_dir(
+            "quarantine",
+            &[
+                ("env/flaky.sql", "SELECT 1;\n"),
+                ("env/flaky.result", "SELECT 1;\n\n2\n\n"),
+                ("env/fixed.sql", "SELECT 2;\n"),
+                ("env/fixed.result", "SELECT 2;\n\nSELECT 2;\n\n"),
+                ("env/broken.sql", "SELECT 3;\n"),
+                ("env/broken.result", "SELECT 3;\n\n3\n\n"),
+            ],
+        );
+        let config = ConfigBuilder::default()
+            .case_dir(root)
+            .quarantine(vec![Regex::new("env:f").unwrap()])
+            .build()
+            .unwrap();
+        let runner = Runner::new(config, EchoController);
+
+        let report = runner.run_with_report().await.unwrap();
+        let status = |name: &str| {
+            let case = report.cases().find(|(_, c)| c.name == name).unwrap().1;
+            (case.status.clone(), case.quarantined)
+        };
+        assert!(matches!(
+            status("flaky"),
+            (CaseStatus::Quarantined(_), true)
+        ));
+        assert_eq!(status("fixed"), (CaseStatus::Passed, true));
+        assert!(matches!(status("broken"), (CaseStatus::Failed(_), false)));
+        assert_eq!((report.failed_count(), report.quarantined_count()), (1, 1));
+        let passed = report
+            .passed_quarantined()
+            .map(|(env, case)| format!("{env}:{}", case.name))
+            .collect::<Vec<_>>();
+        assert_eq!(passed, vec!["env:fixed"]);
+    }
+
+    #[tokio::test]
+    async fn require_features() {
+        let case = "-- SQLNESS REQUIRE cte\n\nSELECT 1;\n";
+        let root = case_dir(
+            "require",
+            &[
+                ("full/config.toml", "features = [\"json\", \"cte\"]\n"),
+                ("full/a.sql", case),
+                (
+                    "full/a.result",
+                    "-- SQLNESS REQUIRE cte\nSELECT 1;\n\nSELECT 1;\n\n",
+                ),
+                ("basic/config.toml", "features = [\"json\"]\n"),
+                ("basic/a.sql", case),
+            ],
+        );
+        let config = ConfigBuilder::default()
+            .case_dir(root.clone())
+            .build()
+            .unwrap();
+        let report = Runner::new(config, EchoController)
+            .run_with_report()
+            .await
+            .unwrap();
+        let status = |env: &str| {
+            report
+                .cases()
+                .find(|(e, _)| *e == env)
+                .unwrap()
+                .1
+                .status
+                .clone()
+        };
+        assert_eq!(status("full"), CaseStatus::Passed);
+        assert_eq!(
+            status("basic"),
+            CaseStatus::Skipped("REQUIRE cte".to_string())
+        );
+        assert!(!Path::new(&root).join("basic/a.result").exists());
+    }
+
+    #[tokio::test]
+    async fn reset_after_each_case() {
+        /// Keep a session variable set by `SET`, until it's reset.
+        #[derive(Default)]
+        struct SessionDB {
+            session: std::sync::Mutex<Option<String>>,
+        }
+
+        #[async_trait]
+        impl Database for SessionDB {
+            async fn query(&self, _: QueryContext, query: String) -> Box<dyn Display> {
+                let mut session = self.session.lock().unwrap();
+                if let Some(value) = query.strip_prefix("SET ") {
+                    *session = Some(value.to_string());
+                }
+                Box::new(format!("{session:?}"))
+            }
+
+            async fn reset(&self) {
+                *self.session.lock().unwrap() = None;
+            }
+        }
+
+        struct SessionController;
+
+        #[async_trait]
+        impl EnvController for SessionController {
+            type DB = SessionDB;
+
+            async fn start(&self, _: &str, _: Option<&Path>) -> Self::DB {
+                SessionDB::default()
+            }
+
+            async fn stop(&self, _: &str, _: Self::DB) {}
+        }
+
+        let root = case_dir(
+            "reset",
+            &[
+                (
+                    "env/a.sql",
+                    "SET a;
+SELECT 1;
+",
+                ),
+                (
+                    "env/b.sql",
+                    "SELECT 1;
+",
+                ),
+            ],
+        );
+        let config = ConfigBuilder::default()
+            .case_dir(root.clone())
+            .build()
+            .unwrap();
+        Runner::new(config, SessionController)
+            .run_env("env", &SessionDB::default())
+            .await
+            .unwrap();
+        let result = |case| std::fs::read_to_string(Path::new(&root).join(case)).unwrap();
+        assert_eq!(
+            result("env/a.result"),
+            "SET a;\n\nSome(\"a;\")\n\nSELECT 1;\n\nSome(\"a;\")\n\n"
+        );
+        assert_eq!(result("env/b.result"), "SELECT 1;\n\nNone\n\n");
+    }
+
+    #[tokio::test]
+    async fn env_name() {
+        let case = "CREATE SCHEMA test_{{sqlness_env}};\n";
+        let root = case_dir("env-name", &[("local/a.sql", case), ("remote/a.sql", case)]);
+        let config = ConfigBuilder::default()
+            .case_dir(root.clone())
+            .build()
+            .unwrap();
+        Runner::new(config, EchoController)
+            .run_with_report()
+            .await
+            .unwrap();
+        let result = |case| std::fs::read_to_string(Path::new(&root).join(case)).unwrap();
+        assert_eq!(
+            result("local/a.result"),
+            "CREATE SCHEMA test_{{sqlness_env}};\n\nCREATE SCHEMA test_local;\n\n"
+        );
+        assert_eq!(
+            result("remote/a.result"),
+            "CREATE SCHEMA test_{{sqlness_env}};\n\nCREATE SCHEMA test_remote;\n\n"
+        );
+    }
+
+    #[tokio::test]
+    async fn env_metadata() {
+        /// Report the environment name as `backend_version`.
+        struct VersionController;
+
+        #[async_trait]
+        impl EnvController for VersionController {
+            type DB = EchoDB;
+
+            async fn start(&self, _: &str, _: Option<&Path>) -> Self::DB {
+                EchoDB::default()
+            }
+
+            async fn stop(&self, _: &str, _: Self::DB) {}
+
+            async fn metadata(&self, env: &str, _: &Self::DB) -> HashMap<String, String> {
+                [("backend_version".to_string(), env.to_string())].into()
+            }
+        }
+
+        let case = "-- SQLNESS SKIP_IF {{backend_version}}=v1
+SELECT 'new';
+SELECT '{{backend_version}}';
+";
+        let root = case_dir("metadata", &[("v1/a.sql", case), ("v2/a.sql", case)]);
+        let config = ConfigBuilder::default()
+            .case_dir(root.clone())
+            .build()
+            .unwrap();
+        Runner::new(config, VersionController)
+            .run_with_report()
+            .await
+            .unwrap();
+        let result = |case| std::fs::read_to_string(Path::new(&root).join(case)).unwrap();
+        assert_eq!(
+            result("v1/a.result"),
+            "-- SQLNESS SKIP_IF {{backend_version}}=v1
+SELECT 'new';
+
+-- SKIPPED
+
+SELECT '{{backend_version}}';
+
+SELECT 'v1';
+
+"
+        );
+        assert_eq!(
+            result("v2/a.result"),
+            "-- SQLNESS SKIP_IF {{backend_version}}=v1
+SELECT 'new';
+
+SELECT 'new';
+
+SELECT '{{backend_version}}';
+
+SELECT 'v2';
+
+"
+        );
+    }
+
+    #[test]
+    fn parse_answer() {
+        assert_eq!(Answer::parse("u\n"), Some(Answer::Update));
+        assert_eq!(Answer::parse(" Skip "), Some(Answer::Skip));
+        assert_eq!(Answer::parse("A"), Some(Answer::Abort));
+        assert_eq!(Answer::parse(""), None);
+        assert_eq!(Answer::parse("yes"), None);
+    }
+
+    #[tokio::test]
+    async fn include_and_exclude_envs() {
+        let root = case_dir(
+            "select-envs",
+            &[
+                ("local/a.sql", "SELECT 1;\n"),
+                ("local/a.result", "SELECT 1;\n\nSELECT 1;\n\n"),
+                ("remote/a.sql", "SELECT 1;\n"),
+                ("cluster/a.sql", "SELECT 1;\n"),
+            ],
+        );
+        let config = ConfigBuilder::default()
+            .case_dir(root)
+            .include_envs(vec!["local".to_string(), "remote".to_string()])
+            .exclude_envs(vec!["remote".to_string()])
+            .build()
+            .unwrap();
+        let mut report = Runner::new(config, EchoController)
+            .run_with_report()
+            .await
+            .unwrap();
+        report.envs.sort_by(|a, b| a.name.cmp(&b.name));
+        let statuses = report
+            .cases()
+            .map(|(env, case)| (env, case.status.clone()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            statuses,
+            vec![
+                (
+                    "cluster",
+                    CaseStatus::Skipped("environment cluster is not in include_envs".into())
+                ),
+                ("local", CaseStatus::Passed),
+                (
+                    "remote",
+                    CaseStatus::Skipped("environment remote is in exclude_envs".into())
+                ),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn include_and_exclude_tags() {
+        let result = "SELECT 1;\n\nSELECT 1;\n\n";
+        let root = case_dir(
+            "select-tags",
+            &[
+                ("env/file.sql", "-- SQLNESS TAG smoke\n\nSELECT 1;\n"),
+                (
+                    "env/file.result",
+                    "-- SQLNESS TAG smoke\nSELECT 1;\n\nSELECT 1;\n\n",
+                ),
+                (
+                    "env/query.sql",
+                    "SELECT 1;\n\n-- SQLNESS TAG smoke slow\nSELECT 1;\n",
+                ),
+                ("env/untagged.sql", "SELECT 1;\n"),
+                ("env/untagged.result", result),
+            ],
+        );
+        let statuses = |include: &[&str], exclude: &[&str]| {
+            let config = ConfigBuilder::default()
+                .case_dir(root.clone())
+                .include_tags(include.iter().map(|t| t.to_string()).collect())
+                .exclude_tags(exclude.iter().map(|t| t.to_string()).collect())
+                .build()
+                .unwrap();
+            async move {
+                let report = Runner::new(config, EchoController)
+                    .run_with_report()
+                    .await
+                    .unwrap();
+                report
+                    .cases()
+                    .map(|(_, case)| (case.name.clone(), case.status.clone()))
+                    .collect::<Vec<_>>()
+            }
+        };
+
+        let skipped = |reason: &str| CaseStatus::Skipped(reason.to_string());
+        assert_eq!(
+            statuses(&["smoke"], &["slow"]).await,
+            vec![
+                ("file".to_string(), CaseStatus::Passed),
+                ("query".to_string(), skipped("tag slow is in exclude_tags")),
+                ("untagged".to_string(), skipped("no tag in include_tags")),
+            ]
+        );
+        assert_eq!(
+            statuses(&[], &["smoke"]).await,
+            vec![
+                ("file".to_string(), skipped("tag smoke is in exclude_tags")),
+                ("query".to_string(), skipped("tag smoke is in exclude_tags")),
+                ("untagged".to_string(), CaseStatus::Passed),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn skip_unstartable_envs() {
+        let root = case_dir(
+            "unstartable",
+            &[
+                ("env/a.sql", "SELECT 1;\n"),
+                ("env/a.result", "SELECT 1;\n\nSELECT 1;\n\n"),
+                ("broken/a.sql", "SELECT 1;\n"),
+            ],
+        );
+        let runner = |skip| {
+            let config = ConfigBuilder::default()
+                .case_dir(root.clone())
+                .skip_unstartable_envs(skip)
+                .build()
+                .unwrap();
+            Runner::new(config, PanicController)
+        };
+        assert!(runner(false).run_with_report().await.is_err());
+
+        let mut report = runner(true).run_with_report().await.unwrap();
+        report.envs.sort_by(|a, b| a.name.cmp(&b.name));
+        let unstarted = report
+            .unstarted_envs()
+            .map(|env| (env.name.as_str(), env.start_error.as_deref()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            unstarted,
+            vec![(
+                "broken",
+                Some("Environment broken panicked, message:broken env")
+            )]
+        );
+        assert_eq!(report.envs[0].skipped_count(), 1);
+        assert_eq!(report.envs[1].passed_count(), 1);
+        assert_eq!(report.failed_count(), 0);
+    }
+
+    #[tokio::test]
+    async fn catch_panic() {
+        let root = case_dir(
+            "catch-panic",
+            &[
+                (
+                    "env/a.sql",
+                    "SELECT panic;
+",
+                ),
+                (
+                    "env/b.sql",
+                    "SELECT 1;
+",
+                ),
+                (
+                    "env/b.result",
+                    "SELECT 1;
+
+SELECT 1;
+
+",
+                ),
+                (
+                    "broken/a.sql",
+                    "SELECT 1;
+",
+                ),
+            ],
+        );
+        let config = ConfigBuilder::default()
+            .case_dir(root.clone())
+            .env_filter("env".to_string())
+            .build()
+            .unwrap();
+        let report = Runner::new(config, PanicController)
+            .run_with_report()
+            .await
+            .unwrap();
+        let statuses = report
+            .cases()
+            .map(|(_, case)| case.status.clone())
+            .collect::<Vec<_>>();
+        assert!(
+            matches!(&statuses[0], CaseStatus::Errored(e)
+                if e.contains("query:SELECT panic;, message:boom")),
+            "{statuses:?}"
+        );
+        assert_eq!(statuses[1], CaseStatus::Passed);
+
+        let config = ConfigBuilder::default()
+            .case_dir(root)
+            .env_filter("broken".to_string())
+            .build()
+            .unwrap();
+        let err = Runner::new(config, PanicController)
+            .run_with_report()
+            .await
+            .unwrap_err();
+        assert!(
+            matches!(&err, SqlnessError::EnvPanic { env, message }
+                if env == "broken" && message == "broken env"),
+            "{err:?}"
+        );
+    }
+
+    #[tokio::test]
+    async fn result_naming() {
+        let root = case_dir(
+            "result-naming",
+            &[
+                ("local/a.sql", "SELECT 1;\n"),
+                ("remote/a.sql", "SELECT 1;\n"),
+            ],
+        );
+        let root_path = Path::new(&root);
+        let runner = |per_env_results| {
+            let config = ConfigBuilder::default()
+                .case_dir(root.clone())
+                .result_extension("out".to_string())
+                .per_env_results(per_env_results)
+                .update_result(true)
+                .build()
+                .unwrap();
+            Runner::new(config, EchoController)
+        };
+
+        runner(false).run().await.unwrap();
+        for path in ["local/a.out", "remote/a.out"] {
+            assert!(root_path.join(path).exists(), "{path}");
+        }
+
+        runner(true).run().await.unwrap();
+        for path in ["local/a.local.out", "remote/a.remote.out"] {
+            let result = std::fs::read_to_string(root_path.join(path)).unwrap();
+            assert_eq!(result, "SELECT 1;\n\nSELECT 1;\n\n", "{path}");
+        }
+        // recorded results are compared in later runs
+        let report = runner(true).run_with_report().await.unwrap();
+        assert_eq!(report.passed_count(), 2);
+    }
+
+    #[tokio::test]
+    async fn only_envs() {
+        let root = case_dir(
+            "only-envs",
+            &[
+                ("local/a.sql", "-- SQLNESS ONLY remote\nSELECT 1;\n"),
+                ("remote/a.sql", "-- SQLNESS ONLY remote\nSELECT 1;\n"),
+            ],
+        );
+        let config = ConfigBuilder::default()
+            .case_dir(root.clone())
+            .update_result(true)
+            .build()
+            .unwrap();
+        let runner = Runner::new(config, EchoController);
+
+        let mut report = runner.run_with_report().await.unwrap();
+        report.envs.sort_by(|a, b| a.name.cmp(&b.name));
+        let cases = report
+            .cases()
+            .map(|(env, case)| (env, case.status.clone()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            cases,
+            vec![
+                ("local", CaseStatus::Skipped("ONLY remote".to_string())),
+                ("remote", CaseStatus::Updated),
+            ]
+        );
+        assert!(!Path::new(&root).join("local/a.result").exists());
+    }
+
+    #[tokio::test]
+    async fn wait_ready() {
+        let root = case_dir(
+            "wait-ready",
+            &[
+                ("env/a.sql", "SELECT 1;\n"),
+                ("env/a.result", "SELECT 1;\n\nSELECT 1;\n\n"),
+            ],
+        );
+        let runner = |ready_after| {
+            let config = ConfigBuilder::default()
+                .case_dir(root.clone())
+                .startup_timeout(Duration::from_millis(200))
+                .build()
+                .unwrap();
+            Runner::new(config, StartingController(ready_after))
+        };
+
+        let report = runner(Some(3)).run_with_report().await.unwrap();
+        assert_eq!(report.passed_count(), 1);
+
+        let report = runner(None).run_with_report().await.unwrap();
+        let status = &report.envs[0].cases[0].status;
+        assert!(
+            matches!(status, CaseStatus::Errored(msg) if msg.contains("not ready")),
+            "{status:?}"
+        );
+    }
+
+    #[tokio::test]
+    async fn run_file() {
+        let root = case_dir(
+            "run-file",
+            &[
+                ("local/dml/a.sql", "-- SQLNESS REPLACE 1 2\nSELECT 1;\n"),
+                ("local/dml/b.sql", "SELECT 2;\n"),
+            ],
+        );
+        let config = ConfigBuilder::default()
+            .case_dir(root.clone())
+            .build()
+            .unwrap();
+        let runner = Runner::new(config, HookController::default());
+
+        let report = runner.run_file("local/dml/a.sql").await.unwrap();
+        assert_eq!(report.name, "dml/a");
+        assert!(report.status.is_failure());
+        let result = std::fs::read_to_string(Path::new(&root).join("local/dml/a.result")).unwrap();
+        assert_eq!(result, "-- SQLNESS REPLACE 1 2\nSELECT 1;\n\nSELECT 2;\n\n");
+        assert!(!Path::new(&root).join("local/dml/b.result").exists());
+        assert_eq!(
+            *runner.env_controller.calls.lock().unwrap(),
+            vec!["before local dml", "after local dml"]
+        );
+
+        let path = Path::new(&root).join("local/dml/a");
+        let report = runner.run_file(&path).await.unwrap();
+        assert_eq!(report.status, CaseStatus::Passed);
+
+        for path in ["remote/a.sql", "local"] {
+            assert!(matches!(
+                runner.run_file(path).await,
+                Err(SqlnessError::InvalidCasePath { .. })
+            ));
+        }
+    }
+
+    #[tokio::test]
+    async fn env_config_file() {
+        let root = case_dir(
+            "env-config-file",
+            &[
+                ("ci/config.toml", ""),
+                ("ci/config.ci.toml", ""),
+                ("dev/config.toml", ""),
+            ],
+        );
+        let runner = |require_env_config| {
+            let config = ConfigBuilder::default()
+                .case_dir(root.clone())
+                .env_filter("ci|dev".to_string())
+                .env_config_file("config.ci.toml".to_string())
+                .require_env_config(require_env_config)
+                .build()
+                .unwrap();
+            Runner::new(config, ConfigController::default())
+        };
+
+        let lenient = runner(false);
+        lenient.run().await.unwrap();
+        let mut configs = lenient.env_controller.configs.lock().unwrap().clone();
+        configs.sort();
+        assert_eq!(
+            configs,
+            vec![
+                ("ci".to_string(), Some("config.ci.toml".to_string())),
+                ("dev".to_string(), None),
+            ]
+        );
+
+        let err = runner(true).run().await.unwrap_err();
+        assert!(
+            matches!(&err, SqlnessError::MissingEnvConfig { env, .. } if env == "dev"),
+            "{err:?}"
+        );
+    }
+
+    #[tokio::test]
+    async fn dry_run() {
+        let root = case_dir(
+            "dry-run",
+            &[
+                ("env/dml/a.sql", "SELECT 1;\n"),
+                ("env/dml/b.sql", "SELECT 2;\n"),
+                ("other/c.sql", "SELECT 3;\n"),
+            ],
+        );
+        let config = ConfigBuilder::default()
+            .case_dir(root.clone())
+            .env_filter("env".to_string())
+            .test_filter("a".to_string())
+            .dry_run(true)
+            .build()
+            .unwrap();
+        let runner = Runner::new(config, CountingController::default());
+
+        let report = runner.run_with_report().await.unwrap();
+        let cases = report
+            .cases()
+            .map(|(env, case)| (env, case.name.as_str(), &case.status))
+            .collect::<Vec<_>>();
+        assert_eq!(cases, vec![("env", "dml/a", &CaseStatus::Listed)]);
+        assert_eq!(report.envs[0].filtered, 1);
+        assert_eq!(runner.env_controller.max_running.load(Ordering::SeqCst), 0);
+        assert!(!Path::new(&root).join("env/dml/a.result").exists());
+    }
+
+    #[tokio::test]
+    async fn write_junit_report() {
+        let root = case_dir("junit", &[("env/dml/a.sql", "SELECT 1;\n")]);
+        let report_path = Path::new(&root).join("junit.xml");
+        let config = ConfigBuilder::default()
+            .case_dir(root.clone())
+            .junit_report(Some(report_path.to_str().unwrap().to_string()))
+            .build()
+            .unwrap();
+        let runner = Runner::new(config, EchoController);
+
+        assert!(runner.run().await.is_err());
+        let report = std::fs::read_to_string(&report_path).unwrap();
+        assert!(report.contains(r#"<testsuite name="env" tests="1" failures="1""#));
+        assert!(report.contains(r#"<testcase name="dml/a" classname="env""#));
+        assert!(report.contains("+SELECT 1;"));
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn follow_links() {
+        let root = case_dir("follow-links", &[("shared/a.sql", ""), ("local/b.sql", "")]);
+        let root_path = Path::new(&root);
+        let link = |target: &str, link: &str| {
+            std::os::unix::fs::symlink(root_path.join(target), root_path.join(link)).unwrap();
+        };
+        link("shared", "local/shared");
+        // cycle back to the environment directory
+        link("local", "local/shared/cycle");
+        link("local", "linked");
+
+        assert_eq!(
+            collect_cases(&root, ".*").await,
+            (vec!["local/b".into(), "local/shared/a".into()], 0)
+        );
+
+        let runner = |follow_links| {
+            let config = ConfigBuilder::default()
+                .case_dir(root.clone())
+                .follow_links(follow_links)
+                .build()
+                .unwrap();
+            Runner::new(config, EchoController)
+        };
+        let mut envs = runner(true).collect_env().unwrap();
+        envs.sort();
+        assert_eq!(envs, vec!["linked", "local", "shared"]);
+
+        let runner = runner(false);
+        let mut envs = runner.collect_env().unwrap();
+        envs.sort();
+        assert_eq!(envs, vec!["local", "shared"]);
+        let (cases, _) = runner.collect_case_paths("local").await.unwrap();
+        assert_eq!(cases, vec![root_path.join("local/b")]);
+    }
+
+    #[tokio::test]
+    async fn case_glob() {
+        let root = case_dir(
+            "case-glob",
+            &[
+                ("local/a.sql", ""),
+                ("local/a.slt", ""),
+                ("local/dml/b.sql", ""),
+                ("local/helpers/c.sql", ""),
+                ("local/helpers/readme.md", ""),
+            ],
+        );
+        let env_root = Path::new(&root).join("local");
+        let collect = |patterns: &[&str]| {
+            let config = ConfigBuilder::default()
+                .case_dir(root.clone())
+                .case_glob(patterns.iter().map(|p| p.to_string()).collect())
+                .build()
+                .unwrap();
+            let env_root = env_root.clone();
+            async move {
+                let runner = Runner::new(config, EchoController);
+                let (cases, _) = runner.collect_case_paths("local").await?;
+                let names = cases
+                    .iter()
+                    .map(|p| Runner::<EchoController>::case_name(&env_root, p));
+                Ok::<_, SqlnessError>(names.collect::<Vec<_>>())
+            }
+        };
+
+        assert_eq!(collect(&[]).await.unwrap(), ["a", "dml/b", "helpers/c"]);
+        assert_eq!(
+            collect(&["**/*.sql"]).await.unwrap(),
+            ["a", "dml/b", "helpers/c"]
+        );
+        assert_eq!(collect(&["*.sql"]).await.unwrap(), ["a"]);
+        assert_eq!(
+            collect(&["**/*.sql", "!helpers/**"]).await.unwrap(),
+            ["a", "dml/b"]
+        );
+        assert_eq!(collect(&["!dml/*"]).await.unwrap(), ["a", "helpers/c"]);
+        assert!(matches!(
+            collect(&["[a"]).await,
+            Err(SqlnessError::InvalidGlob { .. })
+        ));
+    }
+
+    #[tokio::test]
+    async fn filter_cases() {
+        let root = case_dir(
+            "filter",
+            &[
+                ("local/dml/basic.sql", ""),
+                ("local/ddl/basic.sql", ""),
+                ("local/ddl/create.sql", ""),
+            ],
+        );
+        assert_eq!(
+            collect_cases(&root, "local:basic").await,
+            (vec!["local/ddl/basic".into(), "local/dml/basic".into()], 1)
+        );
+        assert_eq!(
+            collect_cases(&root, "local:ddl/").await,
+            (vec!["local/ddl/basic".into(), "local/ddl/create".into()], 1)
+        );
+        assert_eq!(collect_cases(&root, "remote:.*").await, (vec![], 3));
+    }
+
+    #[tokio::test]
+    async fn case_order() {
+        let files = ["b.sql", "A.sql", "a.sql", "dir/c.sql", "c.sql", "B.sql"];
+        let files = files.map(|f| (format!("local/{f}"), String::new()));
+        let files = files
+            .iter()
+            .map(|(p, c)| (p.as_str(), c.as_str()))
+            .collect::<Vec<_>>();
+        let root = case_dir("case-order", &files);
+        let sorted =
+            ["A", "a", "B", "b", "c", "dir/c"].map(|c| Path::new(&root).join("local").join(c));
+        let collect = |randomize, seed| {
+            let config = ConfigBuilder::default()
+                .case_dir(root.clone())
+                .randomize(randomize)
+                .seed(seed)
+                .build()
+                .unwrap();
+            async move {
+                let runner = Runner::new(config, EchoController);
+                runner.collect_case_paths("local").await.unwrap().0
+            }
+        };
+        assert_eq!(collect(false, Some(1)).await, sorted);
+
+        let shuffled = collect(true, Some(1)).await;
+        assert_eq!(shuffled, collect(true, Some(1)).await);
+        assert_ne!(shuffled, sorted);
+        let mut resorted = shuffled.clone();
+        resorted.sort_by_key(|p| sorted.iter().position(|s| s == p));
+        assert_eq!(resorted, sorted);
+    }
+
+    #[tokio::test]
+    async fn normalize_whitespace_comparison() {
+        for normalize in [true, false] {
+            let root = case_dir(
+                &format!("whitespace-{normalize}"),
+                &[
+                    ("env/a.sql", "SELECT 1;\n"),
+                    ("env/a.result", "SELECT 1;  \r\n\r\nSELECT 1;\t\r\n\n"),
+                ],
+            );
+            let config = ConfigBuilder::default()
+                .case_dir(root.clone())
+                .normalize_whitespace(normalize)
+                .build()
+                .unwrap();
+            let runner = Runner::new(config, EchoController);
+
+            assert_eq!(runner.run().await.is_ok(), normalize);
+            let result = std::fs::read_to_string(Path::new(&root).join("env/a.result")).unwrap();
+            assert_eq!(result, "SELECT 1;\n\nSELECT 1;\n\n");
+        }
+    }
+
+    #[tokio::test]
+    async fn ignore_result_comments() {
+        for ignore in [true, false] {
+            let annotated = "-- the answer\nSELECT 1;\n\nSELECT 1;\n-- checked by hand\n\n";
+            let root = case_dir(
+                &format!("ignore-comments-{ignore}"),
+                &[("env/a.sql", "SELECT 1;\n"), ("env/a.result", annotated)],
+            );
+            let config = ConfigBuilder::default()
+                .case_dir(root.clone())
+                .ignore_result_comments(ignore)
+                .build()
+                .unwrap();
+            let runner = Runner::new(config, EchoController);
+
+            assert_eq!(runner.run().await.is_ok(), ignore);
+            let result = std::fs::read_to_string(Path::new(&root).join("env/a.result")).unwrap();
+            if ignore {
+                assert_eq!(result, annotated);
+            } else {
+                assert_eq!(result, "SELECT 1;\n\nSELECT 1;\n\n");
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn diff_context_lines() {
+        let root = case_dir(
+            "diff-context",
+            &[
+                ("env/a.sql", "SELECT 1;\nSELECT 2;\nSELECT 3;\n"),
+                (
+                    "env/a.result",
+                    "SELECT 1;\n\nSELECT 1;\n\nSELECT 2;\n\nSELECT 0;\n\nSELECT 3;\n\nSELECT 3;\n\n",
+                ),
+            ],
+        );
+        let config = ConfigBuilder::default()
+            .case_dir(root.clone())
+            .diff_context_lines(0)
+            .build()
+            .unwrap();
+        let runner = Runner::new(config, EchoController);
+
+        let report = runner.run_with_report().await.unwrap();
+        let CaseStatus::Failed(diff) = &report.envs[0].cases[0].status else {
+            panic!("{report:?}");
+        };
+        assert_eq!(
+            diff,
+            "--- expected\n+++ actual\n@@ -7,1 +7,1 @@\n-SELECT 0;\n+SELECT 2;"
+        );
+    }
+
+    #[tokio::test]
+    async fn result_mismatch() {
+        let root = case_dir(
+            "mismatch",
+            &[
+                ("env/a.sql", "SELECT 1;\nSELECT 2;\n"),
+                (
+                    "env/a.result",
+                    "SELECT 1;\n\nSELECT 1;\n\nSELECT 2;\n\nSELECT 0;\n\n",
+                ),
+            ],
+        );
+        let config = ConfigBuilder::default().case_dir(root).build().unwrap();
+        let report = Runner::new(config, EchoController)
+            .run_with_report()
+            .await
+            .unwrap();
+        let case = &report.envs[0].cases[0];
+        let Some(SqlnessError::ResultMismatch(mismatch)) = case.error.as_deref() else {
+            panic!("{case:?}");
+        };
+        assert_eq!(mismatch.env, "env");
+        assert_eq!(mismatch.query.as_deref(), Some("SELECT 2;"));
+        assert_eq!(
+            mismatch.expected,
+            "SELECT 1;\n\nSELECT 1;\n\nSELECT 2;\n\nSELECT 0;\n\n"
+        );
+        assert_eq!(
+            mismatch.actual,
+            "SELECT 1;\n\nSELECT 1;\n\nSELECT 2;\n\nSELECT 2;\n\n"
+        );
+        assert_eq!(case.status, CaseStatus::Failed(mismatch.diff.clone()));
+        assert!(mismatch.to_string().ends_with(&mismatch.diff));
+    }
+
+    #[tokio::test]
+    async fn in_memory_cases() {
+        let config = ConfigBuilder::default()
+            .case_dir("in-memory".to_string())
+            .test_filter("(a|b|c)$".to_string())
+            .build()
+            .unwrap();
+        let runner = Runner::new_with_cases(
+            config,
+            EchoController,
+            [
+                ("env/a", "SELECT 1;\n", "SELECT 1;\n\nSELECT 1;\n\n"),
+                ("env/dml/b", "SELECT 2;\n", "SELECT 2;\n\nSELECT 0;\n\n"),
+                ("env/d", "SELECT 4;\n", ""),
+                ("other/c", "SELECT 3;\n", ""),
+            ],
+        );
+        let report = runner.run_with_report().await.unwrap();
+
+        let cases = |env: usize| {
+            report.envs[env]
+                .cases
+                .iter()
+                .map(|c| (c.name.as_str(), c.status.is_failure()))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(report.envs.len(), 2);
+        assert_eq!(report.envs[0].name, "env");
+        assert_eq!(cases(0), vec![("a", false), ("dml/b", true)]);
+        assert_eq!(report.envs[0].filtered, 1);
+        assert_eq!(report.envs[1].name, "other");
+        assert_eq!(cases(1), vec![("c", true)]);
+
+        // results are written in memory like result files
+        assert_eq!(
+            runner.result_of("env/dml/b").unwrap(),
+            "SELECT 2;\n\nSELECT 2;\n\n"
+        );
+        assert_eq!(
+            runner.result_of("other/c").unwrap(),
+            "SELECT 3;\n\nSELECT 3;\n\n"
+        );
+        assert_eq!(runner.result_of("env/d").unwrap(), "");
+        assert_eq!(runner.result_of("env/e"), None);
+        assert!(!Path::new("in-memory").exists());
+    }
+
+    #[test]
+    fn unified_diff_same() {
+        assert_eq!(unified_diff("a\nb", "a\nb", 3, false), None);
+    }
+
+    #[test]
+    fn unified_diff_hunks() {
+        let expected = (1..=10).map(|i| i.to_string()).collect::<Vec<_>>();
+        let mut actual = expected.clone();
+        actual[1] = "two".to_string();
+        actual.remove(8);
+
+        let diff = unified_diff(&expected.join("\n"), &actual.join("\n"), 1, false).unwrap();
+        assert_eq!(
+            diff,
+            "--- expected\
+            \n+++ actual\
+            \n@@ -1,3 +1,3 @@\
+            \n 1\
+            \n-2\
+            \n+two\
+            \n 3\
+            \n@@ -8,3 +8,2 @@\
+            \n 8\
+            \n-9\
+            \n 10"
+        );
+    }
+
+    #[test]
+    fn unified_diff_colored() {
+        let diff = unified_diff("", "a", 3, true).unwrap();
+        assert_eq!(
+            diff,
+            format!(
+                "--- expected\n+++ actual\n{COLOR_CYAN}@@ -0,0 +1,1 @@{COLOR_RESET}\n{COLOR_GREEN}+a{COLOR_RESET}"
+            )
+        );
     }
 }
@@ -0,0 +1,328 @@
+// Copyright 2024 CeresDB Project Authors. Licensed under Apache-2.0.
+
+//! Watch mode, see [`Runner::watch`].
+
+use std::collections::{BTreeMap, HashMap};
+use std::future::Future;
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime};
+
+use futures::future::{select, Either};
+use regex::Regex;
+use walkdir::WalkDir;
+
+use super::Runner;
+use crate::error::Result;
+use crate::report::{EnvReport, RunReport};
+use crate::{util, Database, EnvController};
+
+/// How often case files are checked for changes.
+const POLL_INTERVAL: Duration = Duration::from_millis(300);
+
+/// Case files and the directories holding them, as of the last check.
+#[derive(Default)]
+struct Snapshot {
+    /// Modification time and size of case files, by their environment and path
+    /// without extension.
+    cases: HashMap<(String, PathBuf), (SystemTime, u64)>,
+    /// Modification times of the case roots and all directories under them, a
+    /// case is added, removed or renamed when one changes.
+    dirs: HashMap<PathBuf, SystemTime>,
+}
+
+/// An environment started by the watch, it's kept until the watch stops.
+struct StartedEnv<DB> {
+    db: DB,
+    metadata: Arc<HashMap<String, String>>,
+}
+
+impl<E: EnvController> Runner<E> {
+    /// Watch case files and rerun the changed ones, until `stop` completes, eg. on
+    /// `tokio::signal::ctrl_c()`. It's enabled by the `watch` feature.
+    ///
+    /// Cases selected by the filters in [`Config::case_dir`] and
+    /// [`Config::extra_case_dirs`] are checked for modification every 300ms, new
+    /// cases included. A check only reads the modification time and size of known
+    /// case files and directories, cases are collected again when a directory
+    /// changes. Changes made from the first time `watch` is polled are seen, the
+    /// first check is done before `stop` is polled. Rapid edits are debounced: a run
+    /// starts once a check finds no more changes, and a summary is printed after it
+    /// like [`RunReport::print_summary`]. Result files and other files aren't
+    /// watched.
+    ///
+    /// To exit on Ctrl-C, pass a future completing on SIGINT as `stop`, like
+    /// `tokio::signal::ctrl_c()`, see the `watch` example.
+    ///
+    /// An environment is started when one of its cases first runs, and kept for the
+    /// following runs. When `stop` completes, the run in progress finishes, all
+    /// started environments are stopped and [`EnvController::after_all`] is called.
+    /// An error is only returned if cases can't be collected or an environment
+    /// fails to stop, a failed case or environment start is printed and the watch
+    /// goes on.
+    ///
+    /// [`Config::case_dir`]: crate::Config#structfield.case_dir
+    /// [`Config::extra_case_dirs`]: crate::Config#structfield.extra_case_dirs
+    pub async fn watch<F: Future<Output = ()>>(&self, stop: F) -> Result<()> {
+        self.env_controller.before_all().await;
+        let mut started = BTreeMap::new();
+        let result = self.watch_until(Box::pin(stop), &mut started).await;
+
+        let mut stop_result = Ok(());
+        for (env, started) in started {
+            if let Err(e) = self.stop_env(&env, started.db).await {
+                println!("Environment {env} stop failed, error:{e}.");
+                stop_result = Err(e);
+            }
+        }
+        self.env_controller.after_all().await;
+        result.and(stop_result)
+    }
+
+    async fn watch_until<F: Future<Output = ()>>(
+        &self,
+        mut stop: Pin<Box<F>>,
+        started: &mut BTreeMap<String, StartedEnv<E::DB>>,
+    ) -> Result<()> {
+        // Taken before `stop` is first polled, so it doesn't miss changes made by it.
+        let mut snapshot = self.snapshot().await?;
+        println!("Watching {} cases for changes", snapshot.cases.len());
+        loop {
+            let mut changed = vec![];
+            // Wait for a change, and then until the files settle.
+            loop {
+                if let Either::Left(_) = select(stop.as_mut(), util::sleep(POLL_INTERVAL)).await {
+                    return Ok(());
+                }
+                let current = self.refresh(&snapshot).await?;
+                let more = Self::changed(&snapshot, &current);
+                snapshot = current;
+                if more.is_empty() && !changed.is_empty() {
+                    break;
+                }
+                for case in more {
+                    if !changed.contains(&case) {
+                        changed.push(case);
+                    }
+                }
+            }
+            changed.sort();
+
+            let report = self.run_changed(changed, started).await;
+            report.print_summary();
+        }
+    }
+
+    /// Collect the cases to run and the directories holding them.
+    async fn snapshot(&self) -> Result<Snapshot> {
+        let mut snapshot = Snapshot::default();
+        for root in self.case_roots() {
+            let walk = WalkDir::new(root).follow_links(self.config.follow_links);
+            for entry in walk.into_iter().filter_map(|entry| entry.ok()) {
+                if entry.file_type().is_dir() {
+                    if let Some(modified) = entry.metadata().ok().and_then(|m| m.modified().ok()) {
+                        snapshot.dirs.insert(entry.into_path(), modified);
+                    }
+                }
+            }
+        }
+
+        let filter = Regex::new(&self.config.env_filter)?;
+        for env in self.collect_env()? {
+            if !filter.is_match(&env) || self.unselected_reason(&env).is_some() {
+                continue;
+            }
+            let (case_paths, _) = self.collect_case_paths(&env).await?;
+            for path in case_paths {
+                if let Some(stat) = self.stat_case(&path) {
+                    snapshot.cases.insert((env.clone(), path), stat);
+                }
+            }
+        }
+        Ok(snapshot)
+    }
+
+    /// Check the cases of `previous` again, they are only collected again if a
+    /// directory changed.
+    async fn refresh(&self, previous: &Snapshot) -> Result<Snapshot> {
+        let dirs_changed = previous.dirs.iter().any(|(dir, modified)| {
+            std::fs::metadata(dir).and_then(|m| m.modified()).ok() != Some(*modified)
+        });
+        if dirs_changed {
+            return self.snapshot().await;
+        }
+
+        let cases = previous
+            .cases
+            .keys()
+            .filter_map(|case| Some((case.clone(), self.stat_case(&case.1)?)))
+            .collect();
+        Ok(Snapshot {
+            cases,
+            dirs: previous.dirs.clone(),
+        })
+    }
+
+    /// Modification time and size of the case file of `path`.
+    fn stat_case(&self, path: &Path) -> Option<(SystemTime, u64)> {
+        let metadata =
+            std::fs::metadata(path.with_extension(&self.config.test_case_extension)).ok()?;
+        Some((metadata.modified().ok()?, metadata.len()))
+    }
+
+    /// Cases new or modified in `current`.
+    fn changed(previous: &Snapshot, current: &Snapshot) -> Vec<(String, PathBuf)> {
+        current
+            .cases
+            .iter()
+            .filter(|(case, stat)| previous.cases.get(*case) != Some(*stat))
+            .map(|(case, _)| case.clone())
+            .collect()
+    }
+
+    /// Run the changed cases, starting their environments if they aren't yet.
+    async fn run_changed(
+        &self,
+        changed: Vec<(String, PathBuf)>,
+        started: &mut BTreeMap<String, StartedEnv<E::DB>>,
+    ) -> RunReport {
+        let mut by_env = BTreeMap::<_, Vec<_>>::new();
+        for (env, path) in changed {
+            by_env.entry(env).or_default().push(path);
+        }
+
+        let mut envs = vec![];
+        for (env, paths) in by_env {
+            if !started.contains_key(&env) {
+                match self.start_watched_env(&env).await {
+                    Ok(env_started) => {
+                        started.insert(env.clone(), env_started);
+                    }
+                    Err(e) => {
+                        println!("Environment {env} failed to start, error:{e}.");
+                        continue;
+                    }
+                }
+            }
+            let StartedEnv { db, metadata } = &started[&env];
+
+            let start = Instant::now();
+            let mut cases = vec![];
+            for path in paths {
+                let dir = path.parent().unwrap_or(&path);
+                self.env_controller.before_dir(&env, dir, db).await;
+                cases.push(
+                    self.run_reported_case(db, &env, metadata, &path, |e| e)
+                        .await,
+                );
+                self.env_controller.after_dir(&env, dir, db).await;
+            }
+            envs.push(EnvReport {
+                name: env.clone(),
+                backend: db.name().unwrap_or(&env).to_string(),
+                cases,
+                filtered: 0,
+                duration: start.elapsed(),
+                start_error: None,
+            });
+        }
+
+        RunReport { envs }
+    }
+
+    async fn start_watched_env(&self, env: &str) -> Result<StartedEnv<E::DB>> {
+        let db = self.start_env(env).await?;
+        let metadata = match self.wait_ready(env, &db).await {
+            Ok(()) => match self.env_overrides(env) {
+                Ok(overrides) => Ok(self.env_metadata(env, &db, &overrides).await),
+                Err(e) => Err(e),
+            },
+            Err(e) => Err(e),
+        };
+        match metadata {
+            Ok(metadata) => Ok(StartedEnv { db, metadata }),
+            Err(e) => {
+                let _ = self.stop_env(env, db).await;
+                Err(e)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::Path;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use async_trait::async_trait;
+
+    use super::*;
+    use crate::runner::tests::{case_dir, EchoDB};
+    use crate::ConfigBuilder;
+
+    /// Count starts and stops of environments.
+    #[derive(Default)]
+    struct CountingController {
+        started: AtomicUsize,
+        stopped: AtomicUsize,
+    }
+
+    #[async_trait]
+    impl EnvController for CountingController {
+        type DB = EchoDB;
+
+        async fn start(&self, _: &str, _: Option<&Path>) -> Self::DB {
+            self.started.fetch_add(1, Ordering::SeqCst);
+            EchoDB::default()
+        }
+
+        async fn stop(&self, _: &str, _: Self::DB) {
+            self.stopped.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+
+    #[tokio::test]
+    async fn rerun_changed_cases() {
+        let root = case_dir(
+            "watch",
+            &[("env/a.sql", "SELECT 1;\n"), ("env/b.sql", "SELECT 2;\n")],
+        );
+        let root = Path::new(&root);
+        let config = ConfigBuilder::default()
+            .case_dir(root.to_str().unwrap().to_string())
+            .build()
+            .unwrap();
+        let runner = Runner::new(config, CountingController::default());
+
+        let result = |case: &str| std::fs::read_to_string(root.join(case)).ok();
+        let wait_for = |case: &'static str, expected: &'static str| async move {
+            let deadline = Instant::now() + Duration::from_secs(10);
+            while result(case).as_deref() != Some(expected) && Instant::now() < deadline {
+                util::sleep(POLL_INTERVAL).await;
+            }
+        };
+        // `stop` is first polled after the first snapshot, so the edit is seen. The
+        // watch stops once the edited and the new cases reran.
+        let edit = async {
+            std::fs::write(root.join("env/a.sql"), "SELECT 30;\n").unwrap();
+            wait_for("env/a.result", "SELECT 30;\n\nSELECT 30;\n\n").await;
+            std::fs::write(root.join("env/c.sql"), "SELECT 4;\n").unwrap();
+            wait_for("env/c.result", "SELECT 4;\n\nSELECT 4;\n\n").await;
+        };
+        runner.watch(edit).await.unwrap();
+
+        assert_eq!(
+            result("env/a.result").as_deref(),
+            Some("SELECT 30;\n\nSELECT 30;\n\n")
+        );
+        assert_eq!(
+            result("env/c.result").as_deref(),
+            Some("SELECT 4;\n\nSELECT 4;\n\n")
+        );
+        assert!(!root.join("env/b.result").exists());
+        let controller = &runner.env_controller;
+        assert_eq!(controller.started.load(Ordering::SeqCst), 1);
+        assert_eq!(controller.stopped.load(Ordering::SeqCst), 1);
+    }
+}
@@ -0,0 +1,304 @@
+// Copyright 2024 CeresDB Project Authors. Licensed under Apache-2.0.
+
+//! Runtime-agnostic async helpers, and helpers for [`EnvController`]
+//! implementations to allocate ports and data directories for their environments.
+//!
+//! [`EnvController`]: crate::EnvController
+
+use std::any::Any;
+use std::collections::HashSet;
+use std::future::Future;
+use std::io;
+use std::net::{Ipv4Addr, TcpListener};
+use std::panic::AssertUnwindSafe;
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll, Waker};
+use std::time::{Duration, Instant};
+
+use futures::future::{select, Either};
+use futures::FutureExt;
+
+/// A future that completes after the deadline, without relying on any runtime.
+///
+/// A helper thread is spawned on first poll to wake the task up when the deadline
+/// is reached.
+pub(crate) struct Sleep {
+    deadline: Instant,
+    waker: Option<Arc<Mutex<Waker>>>,
+}
+
+impl Future for Sleep {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let now = Instant::now();
+        if now >= self.deadline {
+            return Poll::Ready(());
+        }
+
+        match &self.waker {
+            Some(waker) => *waker.lock().unwrap() = cx.waker().clone(),
+            None => {
+                let waker = Arc::new(Mutex::new(cx.waker().clone()));
+                let remaining = self.deadline - now;
+                let thread_waker = waker.clone();
+                // detach the thread and let it wake the waker later
+                std::thread::spawn(move || {
+                    std::thread::sleep(remaining);
+                    thread_waker.lock().unwrap().wake_by_ref();
+                });
+                self.waker = Some(waker);
+            }
+        }
+
+        Poll::Pending
+    }
+}
+
+/// Sleep for at least the given duration.
+pub(crate) fn sleep(duration: Duration) -> Sleep {
+    Sleep {
+        deadline: Instant::now() + duration,
+        waker: None,
+    }
+}
+
+/// Wait for `future` for at most `duration`. Return None if it doesn't complete in time.
+pub(crate) async fn timeout<F: Future>(duration: Duration, future: F) -> Option<F::Output> {
+    match select(Box::pin(future), sleep(duration)).await {
+        Either::Left((output, _)) => Some(output),
+        Either::Right(_) => None,
+    }
+}
+
+/// Run `f` on a helper thread, and wait for it without blocking the task, like
+/// [`Sleep`] without relying on any runtime. A panic of `f` is resumed here.
+pub(crate) async fn run_blocking<T, F>(f: F) -> T
+where
+    T: Send + 'static,
+    F: FnOnce() -> T + Send + 'static,
+{
+    let (tx, rx) = futures::channel::oneshot::channel();
+    std::thread::spawn(move || {
+        let _ = tx.send(std::panic::catch_unwind(AssertUnwindSafe(f)));
+    });
+    match rx.await.expect("helper thread is gone") {
+        Ok(output) => output,
+        Err(payload) => std::panic::resume_unwind(payload),
+    }
+}
+
+/// Wait for `future`, and return the panic message instead if it panics and
+/// `catch` is on.
+pub(crate) async fn catch_panic<F: Future>(
+    catch: bool,
+    future: F,
+) -> std::result::Result<F::Output, String> {
+    if !catch {
+        return Ok(future.await);
+    }
+    AssertUnwindSafe(future)
+        .catch_unwind()
+        .await
+        .map_err(|payload| panic_message(payload.as_ref()))
+}
+
+fn panic_message(payload: &(dyn Any + Send)) -> String {
+    if let Some(msg) = payload.downcast_ref::<&str>() {
+        msg.to_string()
+    } else if let Some(msg) = payload.downcast_ref::<String>() {
+        msg.clone()
+    } else {
+        "unknown panic".to_string()
+    }
+}
+
+/// Shuffle `items` in place, the order only depends on `seed`.
+pub(crate) fn shuffle<T>(items: &mut [T], seed: u64) {
+    // splitmix64, good enough to shuffle cases
+    let mut state = seed;
+    let mut next = || {
+        state = state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    };
+    for i in (1..items.len()).rev() {
+        let j = (next() % (i as u64 + 1)) as usize;
+        items.swap(i, j);
+    }
+}
+
+/// Ports handed out by [`alloc_free_port`] and not released yet.
+static ALLOCATED_PORTS: Mutex<Option<HashSet<u16>>> = Mutex::new(None);
+
+/// A free TCP port on localhost, reserved in this process until it's dropped.
+///
+/// The port is released to the OS as soon as it's allocated, so another process
+/// may still take it, but it's never handed out twice by [`alloc_free_port`]
+/// while the guard is alive, eg. to environments started concurrently.
+#[derive(Debug)]
+pub struct PortGuard {
+    port: u16,
+}
+
+impl PortGuard {
+    pub fn port(&self) -> u16 {
+        self.port
+    }
+}
+
+impl Drop for PortGuard {
+    fn drop(&mut self) {
+        if let Some(ports) = ALLOCATED_PORTS.lock().unwrap().as_mut() {
+            ports.remove(&self.port);
+        }
+    }
+}
+
+/// Find a free TCP port on localhost, see [`PortGuard`].
+///
+/// ``` rust
+/// let port = sqlness::util::alloc_free_port().unwrap();
+/// let addr = format!("127.0.0.1:{}", port.port());
+/// ```
+pub fn alloc_free_port() -> io::Result<PortGuard> {
+    // Give up after a while instead of looping forever if the OS keeps returning
+    // reserved ports.
+    for _ in 0..64 {
+        let port = TcpListener::bind((Ipv4Addr::LOCALHOST, 0))?
+            .local_addr()?
+            .port();
+        let mut ports = ALLOCATED_PORTS.lock().unwrap();
+        if ports.get_or_insert_with(HashSet::new).insert(port) {
+            return Ok(PortGuard { port });
+        }
+    }
+
+    Err(io::Error::new(
+        io::ErrorKind::AddrInUse,
+        "no free port which isn't allocated yet",
+    ))
+}
+
+/// Number of directories created by [`scoped_tempdir`] in this process.
+static TEMPDIR_COUNT: AtomicUsize = AtomicUsize::new(0);
+
+/// A directory created by [`scoped_tempdir`], removed with its content when it's
+/// dropped.
+#[derive(Debug)]
+pub struct TempDirGuard {
+    path: PathBuf,
+}
+
+impl TempDirGuard {
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for TempDirGuard {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.path);
+    }
+}
+
+/// Create an empty directory under [`std::env::temp_dir`], named
+/// `<prefix>-<pid>-<n>` where `n` counts directories created by this process, eg.
+/// for the data of a database. It's removed when the guard is dropped.
+///
+/// ``` rust
+/// let dir = sqlness::util::scoped_tempdir("sqlness-data").unwrap();
+/// assert!(dir.path().is_dir());
+/// ```
+///
+/// A directory left by a previous process with the same pid is removed first.
+pub fn scoped_tempdir(prefix: &str) -> io::Result<TempDirGuard> {
+    let n = TEMPDIR_COUNT.fetch_add(1, Ordering::Relaxed);
+    let path = std::env::temp_dir().join(format!("{prefix}-{}-{n}", std::process::id()));
+    if path.exists() {
+        std::fs::remove_dir_all(&path)?;
+    }
+    std::fs::create_dir_all(&path)?;
+
+    Ok(TempDirGuard { path })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[tokio::test]
+    async fn timeout_expired() {
+        let now = Instant::now();
+        let output = timeout(Duration::from_millis(100), sleep(Duration::from_secs(10))).await;
+        assert!(output.is_none());
+        assert!(now.elapsed() < Duration::from_secs(10));
+    }
+
+    #[tokio::test]
+    async fn timeout_completed() {
+        let output = timeout(Duration::from_secs(10), async { 42 }).await;
+        assert_eq!(output, Some(42));
+    }
+
+    #[test]
+    fn shuffle_by_seed() {
+        let shuffled = |seed| {
+            let mut items = (0..20).collect::<Vec<_>>();
+            shuffle(&mut items, seed);
+            items
+        };
+        assert_eq!(shuffled(42), shuffled(42));
+        assert_ne!(shuffled(42), shuffled(43));
+
+        let mut items = shuffled(42);
+        items.sort();
+        assert_eq!(items, (0..20).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn concurrent_ports_are_distinct() {
+        let threads = (0..8)
+            .map(|_| {
+                std::thread::spawn(|| {
+                    (0..8)
+                        .map(|_| alloc_free_port().unwrap())
+                        .collect::<Vec<_>>()
+                })
+            })
+            .collect::<Vec<_>>();
+        let guards = threads
+            .into_iter()
+            .flat_map(|t| t.join().unwrap())
+            .collect::<Vec<_>>();
+        let ports = guards.iter().map(PortGuard::port).collect::<HashSet<_>>();
+        assert_eq!(ports.len(), guards.len());
+
+        let port = guards[0].port();
+        drop(guards);
+        assert!(!ALLOCATED_PORTS
+            .lock()
+            .unwrap()
+            .as_ref()
+            .unwrap()
+            .contains(&port));
+    }
+
+    #[test]
+    fn remove_tempdir_on_drop() {
+        let first = scoped_tempdir("sqlness-util").unwrap();
+        let second = scoped_tempdir("sqlness-util").unwrap();
+        assert_ne!(first.path(), second.path());
+        std::fs::write(first.path().join("data"), "1").unwrap();
+
+        let path = first.path().to_path_buf();
+        drop(first);
+        assert!(!path.exists());
+        assert!(second.path().is_dir());
+    }
+}